@@ -1,5 +1,5 @@
 use anyhow::{Context, Result};
-use cgmath::{prelude::*, Deg, Rad};
+use cgmath::{prelude::*, Deg};
 use easy_imgui_window::{
     easy_imgui::{self as imgui, vec2, Color, MouseButton, Vector2},
     easy_imgui_renderer::{
@@ -14,7 +14,7 @@ use easy_imgui_window::{
 };
 use image::{DynamicImage, EncodableLayout, GenericImage, GenericImageView};
 use lazy_static::lazy_static;
-use std::io::{Read, Write};
+use std::io::Read;
 use std::{
     cell::RefCell,
     path::{Path, PathBuf},
@@ -33,6 +33,8 @@ fn to_cgv2(v: imgui::Vector2) -> cgmath::Vector2<f32> {
 mod imgui_filedialog;
 mod paper;
 mod pdf_metrics;
+mod printable;
+mod soft_render;
 mod util_3d;
 mod util_gl;
 
@@ -60,13 +62,20 @@ lazy_static! {
     };
 }
 const FONT_SIZE: f32 = 3.0;
+// This is eye-balled, depending on the particular font
+const FONT_TEXT_LINE_SCALE: f32 = 0.80;
 
 use paper::{
-    import::import_model_file, EdgeIdPosition, FlapStyle, FoldStyle, IslandKey, PaperOptions,
-    Papercraft,
+    import::{
+        import_model_file, import_model_reader, ImportOptions, ImportReport, SimplifyOptions,
+    },
+    AlignmentMarks, Conflict, EdgeIdPosition, EdgeIndex, FlapOverride, FlapShape, FlapStyle,
+    FoldStyle, IslandPart, PaperOptions, Papercraft, PartIndex,
 };
+use printable::{PageContent, PageRenderer, Printable, PrintableFormat, PrintableText, TextAlign};
+use soft_render::SoftRenderer;
 use util_3d::{Matrix3, Vector3};
-use util_gl::{MVertex2DLine, UniformQuad, Uniforms2D, Uniforms3D};
+use util_gl::{UniformQuad, Uniforms2D, Uniforms3D};
 
 use clap::Parser;
 
@@ -74,7 +83,10 @@ use clap::Parser;
 #[command(author, version, about, long_about = None)]
 /// Long
 struct Cli {
-    #[arg(value_name = "MODEL_FILE")]
+    #[arg(
+        value_name = "MODEL_FILE",
+        help = "Model to open, with --export it can be \"-\" to read it from stdin"
+    )]
     name: Option<PathBuf>,
 
     #[arg(
@@ -89,6 +101,57 @@ struct Cli {
         help = "Prevents editing of the model, useful as reference to build a real model"
     )]
     read_only: bool,
+
+    #[arg(
+        long,
+        help = "Draws the printable pages without GL, slower but the same in every machine"
+    )]
+    software_render: bool,
+
+    #[arg(
+        long,
+        value_name = "OUTPUT_FILE",
+        requires = "name",
+        help = "Generates the printable file from the model and exits, without opening a window"
+    )]
+    export: Option<PathBuf>,
+
+    #[arg(
+        long,
+        requires = "export",
+        help = "Pages to export, such as \"1,3-5\", by default all of them"
+    )]
+    pages: Option<String>,
+
+    #[arg(
+        long,
+        requires = "export",
+        help = "Resolution of the exported images, instead of the one in the model"
+    )]
+    dpi: Option<u32>,
+
+    #[arg(
+        long,
+        value_enum,
+        requires = "export",
+        help = "Format of the exported file, by default it is guessed from the file extension"
+    )]
+    format: Option<PrintableFormat>,
+
+    #[arg(
+        long,
+        requires = "export",
+        help = "Welds the vertices and fixes the faces of the model when importing it"
+    )]
+    cleanup: bool,
+
+    #[arg(
+        long,
+        value_name = "FACES",
+        requires = "export",
+        help = "Simplifies the model when importing it, down to this number of faces"
+    )]
+    simplify: Option<usize>,
 }
 
 fn main() {
@@ -96,6 +159,14 @@ fn main() {
 
     let cli = Cli::parse();
 
+    if let Some(output) = &cli.export {
+        if let Err(e) = export_printable(&cli, output) {
+            eprintln!("{e:?}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let event_loop = EventLoopBuilder::new().build().unwrap();
     let window = MainWindow::new(&event_loop, "Papercraft").unwrap();
 
@@ -172,6 +243,14 @@ fn main() {
         options_opened: None,
         options_applied: None,
         about_visible: false,
+        problems: None,
+        import_options: ImportOptions::default(),
+        software_render: cli.software_render,
+        import_report: None,
+        simplify_opened: None,
+        pre_simplify: None,
+        transform_opened: None,
+        flap_inspector: None,
         option_button_height: 0.0,
         file_dialog: None,
         file_action: None,
@@ -229,6 +308,70 @@ fn main() {
         .unwrap();
 }
 
+// Batch mode: there is no window and no GL context, so the pages are drawn by the software renderer
+fn export_printable(cli: &Cli, output: &Path) -> Result<()> {
+    let Some(name) = &cli.name else {
+        anyhow::bail!("A model file is required to export");
+    };
+    let import_options = ImportOptions {
+        cleanup: cli.cleanup,
+        simplify: cli.simplify.map(|target_faces| SimplifyOptions {
+            target_faces,
+            max_error: None,
+        }),
+        ..ImportOptions::default()
+    };
+    let (mut papercraft, _, report) = if name.as_os_str() == "-" {
+        // The model comes from a pipe, so it must be read fully to be seekable
+        let mut data = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut data)
+            .context("Error reading the model from stdin")?;
+        import_model_reader(
+            std::io::Cursor::new(data),
+            Path::new("stdin"),
+            &import_options,
+        )?
+    } else {
+        import_model_file(name, &import_options)?
+    };
+    if !report.is_empty() {
+        eprintln!("Warning: the model has some issues:");
+        for line in import_report_summary(&report) {
+            eprintln!("  {line}");
+        }
+    }
+    if let Some(dpi) = cli.dpi {
+        let mut options = papercraft.options().clone();
+        options.resolution = dpi;
+        papercraft.set_options(options, false);
+    }
+    // The names are printed in the pages
+    papercraft.rebuild_island_names();
+    let conflicts = papercraft.find_conflicts();
+    if !conflicts.is_empty() {
+        eprintln!("Warning: the layout has {} problems:", conflicts.len());
+        for conflict in &conflicts {
+            eprintln!("  {}", conflict_description(&papercraft, conflict));
+        }
+    }
+
+    let title = match name.file_name() {
+        Some(f) => format!("{} - Papercraft", f.to_string_lossy()),
+        None => String::from("Papercraft"),
+    };
+    let mut printable = Printable::new(&papercraft, title, FONT_TEXT_LINE_SCALE);
+    if let Some(pages) = &cli.pages {
+        let pages = printable::parse_page_list(pages, papercraft.options().pages)?;
+        printable.set_pages(pages);
+    }
+    let mut renderer = SoftRenderer::new(&papercraft)?;
+    printable
+        .generate(&mut renderer, cli.format, output)
+        .with_context(|| format!("Error exporting to {}", output.display()))?;
+    Ok(())
+}
+
 fn build_gl_fixs(gl: &GlContext) -> Result<GLFixedObjects> {
     let prg_scene_solid =
         util_gl::program_from_source(gl, include_str!("shaders/scene_solid.glsl"))
@@ -346,6 +489,7 @@ enum FileAction {
     ImportModel,
     UpdateObj,
     ExportObj,
+    ExportPdo,
     GeneratePrintable,
 }
 
@@ -356,7 +500,7 @@ impl FileAction {
             FileAction::SaveAsCraft => "Saving...",
             FileAction::ImportModel => "Importing...",
             FileAction::UpdateObj => "Updating...",
-            FileAction::ExportObj => "Exporting...",
+            FileAction::ExportObj | FileAction::ExportPdo => "Exporting...",
             FileAction::GeneratePrintable => "Generating...",
         }
     }
@@ -392,6 +536,17 @@ struct GlobalContext {
     // false, when doing an "Undo".
     option_button_height: f32,
     about_visible: bool,
+    problems: Option<Vec<Conflict>>, // Some if the "Problems" window is visible
+    import_options: ImportOptions,
+    // Draw the printable pages without GL, it is not saved with the model
+    software_render: bool,
+    import_report: Option<ImportReport>, // Some if the "Import report" window is visible
+    simplify_opened: Option<SimplifyOptions>,
+    // The model before the last simplification, until another one is loaded
+    pre_simplify: Option<PapercraftContext>,
+    transform_opened: Option<TransformSize>,
+    // The selected flaps and their overrides, as edited in the inspector
+    flap_inspector: Option<(Vec<EdgeIndex>, FlapOverride)>,
     file_dialog: Option<(imgui_filedialog::FileDialog, &'static str, FileAction)>,
     file_action: Option<(FileAction, PathBuf)>,
     last_path: String,
@@ -420,10 +575,12 @@ struct MenuActions {
     import_model: BoolWithConfirm,
     update_model: BoolWithConfirm,
     export_obj: bool,
-    generate_printable: bool,
+    export_pdo: bool,
+    generate_printable: BoolWithConfirm,
     quit: BoolWithConfirm,
     reset_views: bool,
     undo: bool,
+    redo: bool,
 }
 
 #[allow(clippy::collapsible_if)]
@@ -551,6 +708,302 @@ impl GlobalContext {
                 //TODO: list third party SW
             });
     }
+    fn build_problems(&mut self, ui: &Ui) {
+        let Some(problems) = self.problems.take() else {
+            return;
+        };
+        let mut visible = true;
+        let mut clicked = None;
+        ui.set_next_window_size(vec2(400.0, 300.0), imgui::Cond::Once);
+        ui.window_config("Problems###problems")
+            .open(&mut visible)
+            .with(|| {
+                if problems.is_empty() {
+                    ui.text("No problems found.");
+                    return;
+                }
+                ui.text(&format!(
+                    "{} problems found, click to show:",
+                    problems.len()
+                ));
+                ui.separator();
+                ui.child_config("list").with(|| {
+                    for (i, conflict) in problems.iter().enumerate() {
+                        let text = conflict_description(self.data.papercraft(), conflict);
+                        if ui.selectable_config(format!("{text}###{i}")).build() {
+                            clicked = Some(i);
+                        }
+                    }
+                });
+            });
+        if let Some(i) = clicked {
+            let flags = self
+                .data
+                .show_conflict(to_cgv2(self.sz_paper), &problems[i]);
+            self.add_rebuild(flags);
+        }
+        if visible {
+            self.problems = Some(problems);
+        }
+    }
+    fn build_import_report(&mut self, ui: &Ui) {
+        let Some(report) = self.import_report.take() else {
+            return;
+        };
+        let mut visible = true;
+        let mut flags = RebuildFlags::empty();
+        ui.set_next_window_size(vec2(400.0, 300.0), imgui::Cond::Once);
+        ui.window_config("Import report###import_report")
+            .open(&mut visible)
+            .with(|| {
+                for line in import_report_summary(&report) {
+                    ui.text(&line);
+                }
+                if !self.import_options.cleanup {
+                    ui.text("Enable \"File > Clean up imported models\" to fix some of these.");
+                }
+                ui.separator();
+                ui.child_config("list").with(|| {
+                    let edge_lists = [
+                        ("Non-manifold edge", &report.non_manifold_edges),
+                        ("Inverted edge", &report.inverted_edges),
+                    ];
+                    for (text, edges) in edge_lists {
+                        for &i_edge in edges {
+                            let i = usize::from(i_edge);
+                            if ui
+                                .selectable_config(format!("{text} {i}###{text}{i}"))
+                                .build()
+                            {
+                                flags = self.data.show_edge(i_edge);
+                            }
+                        }
+                    }
+                    for &i_face in &report.flipped_faces {
+                        let i = usize::from(i_face);
+                        if ui
+                            .selectable_config(format!("Flipped face {i}###flipped{i}"))
+                            .build()
+                        {
+                            flags = self.data.show_face(i_face);
+                        }
+                    }
+                });
+            });
+        self.add_rebuild(flags);
+        if visible {
+            self.import_report = Some(report);
+        }
+    }
+    fn build_simplify_dialog(&mut self, ui: &Ui) {
+        let Some(mut options) = self.simplify_opened.take() else {
+            return;
+        };
+        let mut visible = true;
+        let mut apply = false;
+        let mut cancel = false;
+        ui.window_config("Simplify model###simplify")
+            .open(&mut visible)
+            .flags(imgui::WindowFlags::NoResize | imgui::WindowFlags::AlwaysAutoResize)
+            .with(|| {
+                let font_sz = ui.get_font_size();
+                let num_faces = self.data.papercraft().model().num_faces();
+                ui.text(&format!("The model has {num_faces} faces."));
+
+                ui.set_next_item_width(font_sz * 5.5);
+                let mut i = options.target_faces as _;
+                ui.input_int_config("Target faces", &mut i).build();
+                options.target_faces = i.max(1) as _;
+
+                let mut limit = options.max_error.is_some();
+                ui.checkbox("Limit the error", &mut limit);
+                // The error is relative to the size of the model, shown as a percentage
+                let mut error = options.max_error.unwrap_or(0.01) * 100.0;
+                ui.same_line();
+                ui.with_disabled(!limit, || {
+                    ui.set_next_item_width(font_sz * 4.0);
+                    ui.input_float_config("% of the model size", &mut error)
+                        .display_format(imgui::FloatFormat::G)
+                        .build();
+                });
+                options.max_error = limit.then_some(error.max(0.0) / 100.0);
+
+                ui.text("It can be reverted with \"Revert simplification\" in the Edit menu.");
+                ui.separator();
+                if ui.button_config("Simplify").size(vec2(100.0, 0.0)).build() {
+                    apply = true;
+                }
+                ui.same_line();
+                if ui.button_config("Cancel").size(vec2(100.0, 0.0)).build() {
+                    cancel = true;
+                }
+            });
+        if apply {
+            if let Err(e) = self.simplify_model(&options) {
+                self.error_message = Some(format!("{e:?}"));
+                ui.open_popup("Error");
+            }
+        } else if visible && !cancel {
+            self.simplify_opened = Some(options);
+        }
+    }
+    fn build_transform_dialog(&mut self, ui: &Ui) {
+        let Some(mut size) = self.transform_opened.take() else {
+            return;
+        };
+        let mut visible = true;
+        let mut transform = None;
+        let mut new_scale = None;
+        ui.window_config("Transform model###transform")
+            .open(&mut visible)
+            .flags(imgui::WindowFlags::NoResize | imgui::WindowFlags::AlwaysAutoResize)
+            .with(|| {
+                let font_sz = ui.get_font_size();
+                for (label, build) in [
+                    ("Rotate 90°:", rotation_90 as fn(usize) -> Matrix3),
+                    ("Mirror:", mirror),
+                ] {
+                    ui.text(label);
+                    for (axis, name) in ["X", "Y", "Z"].into_iter().enumerate() {
+                        ui.same_line_ex(font_sz * (7.0 + 3.0 * axis as f32), 0.0);
+                        if ui
+                            .button_config(format!("{name}###{label}{axis}"))
+                            .size(vec2(font_sz * 2.5, 0.0))
+                            .build()
+                        {
+                            transform = Some(build(axis));
+                        }
+                    }
+                }
+                ui.separator();
+
+                let Vector3 { x, y, z } = self.real_size();
+                ui.text(&format!("Real size (mm): {x:.0} x {y:.0} x {z:.0}"));
+                ui.set_next_item_width(font_sz * 6.0);
+                ui.combo(
+                    "##axis",
+                    0..3,
+                    |axis| ["Width", "Height", "Depth"][axis],
+                    &mut size.axis,
+                );
+                ui.same_line();
+                ui.set_next_item_width(font_sz * 5.5);
+                ui.input_float_config("mm", &mut size.size)
+                    .display_format(imgui::FloatFormat::G)
+                    .build();
+                size.size = size.size.max(0.0);
+                ui.same_line();
+                let model_size = self.model_size()[size.axis];
+                ui.with_disabled(model_size == 0.0 || size.size == 0.0, || {
+                    if ui.button_config("Set size").build() {
+                        new_scale = Some(size.size / model_size);
+                    }
+                });
+            });
+        if let Some(mx) = transform {
+            let undo = self.data.transform_model(&mx);
+            self.data.push_undo_action(undo);
+            self.add_rebuild(RebuildFlags::all());
+        }
+        if let Some(scale) = new_scale {
+            let mut options = self.data.papercraft().options().clone();
+            options.scale = scale;
+            // If the "Options" window is opened, just overwrite the value
+            if let Some(o) = self.options_opened.as_mut() {
+                o.scale = scale;
+            }
+            self.options_applied = Some((options, true));
+        }
+        if visible {
+            self.transform_opened = Some(size);
+        }
+    }
+    fn build_flap_inspector(&mut self, ui: &Ui) {
+        let edges = self.data.selected_flaps();
+        if self.data.ui.mode != MouseMode::Flap || edges.is_empty() {
+            self.flap_inspector = None;
+            return;
+        }
+        // Reload the values if the selection changes
+        let mut edit = match self.flap_inspector.take() {
+            Some((sel, edit)) if sel == edges => edit,
+            _ => self.data.papercraft().flap_override(edges[0]),
+        };
+        let mut visible = true;
+        let mut apply = None;
+        ui.window_config("Flap###flap_inspector")
+            .open(&mut visible)
+            .flags(imgui::WindowFlags::NoResize | imgui::WindowFlags::AlwaysAutoResize)
+            .with(|| {
+                let font_sz = ui.get_font_size();
+                let options = self.data.papercraft().options();
+                match edges.len() {
+                    1 => ui.text("1 edge selected."),
+                    n => ui.text(&format!("{n} edges selected.")),
+                }
+                ui.text("Unchecked values are taken from the document options.");
+                ui.separator();
+
+                let mut on = edit.width.is_some();
+                ui.checkbox("##width", &mut on);
+                let mut width = edit.width.unwrap_or(options.flap_width);
+                ui.same_line();
+                ui.with_disabled(!on, || {
+                    ui.set_next_item_width(font_sz * 8.0);
+                    ui.input_float_config("Width", &mut width)
+                        .display_format(imgui::FloatFormat::G)
+                        .build();
+                });
+                edit.width = on.then_some(width.max(0.0));
+
+                let mut on = edit.angle.is_some();
+                ui.checkbox("##angle", &mut on);
+                let mut angle = edit.angle.unwrap_or(options.flap_angle);
+                ui.same_line();
+                ui.with_disabled(!on, || {
+                    ui.set_next_item_width(font_sz * 8.0);
+                    ui.input_float_config("Angle", &mut angle)
+                        .display_format(imgui::FloatFormat::G)
+                        .build();
+                });
+                edit.angle = on.then_some(angle.clamp(0.0, 180.0));
+
+                let mut on = edit.shape.is_some();
+                ui.checkbox("##shape", &mut on);
+                let mut shape = edit.shape.unwrap_or(options.flap_shape);
+                ui.same_line();
+                ui.with_disabled(!on, || {
+                    ui.set_next_item_width(font_sz * 8.0);
+                    ui.combo(
+                        "Shape",
+                        FLAP_SHAPES.iter().copied(),
+                        fmt_flap_shape,
+                        &mut shape,
+                    );
+                });
+                edit.shape = on.then_some(shape);
+
+                ui.separator();
+                if ui.button_config("Apply").size(vec2(100.0, 0.0)).build() {
+                    apply = Some(edit);
+                }
+                ui.same_line();
+                if ui.button_config("Reset").size(vec2(100.0, 0.0)).build() {
+                    edit = FlapOverride::default();
+                    apply = Some(edit);
+                }
+            });
+        if let Some(flap_override) = apply {
+            let flags = self.data.set_flap_overrides(&edges, flap_override);
+            self.add_rebuild(flags);
+        }
+        if visible {
+            self.flap_inspector = Some((edges, edit));
+        } else {
+            let flags = self.data.clear_selected_flaps();
+            self.add_rebuild(flags);
+        }
+    }
     // Returns true if the action has just been done successfully
     fn build_modal_wait_message_and_run_file_action(&mut self, ui: &Ui) -> bool {
         let mut ok = false;
@@ -846,7 +1299,7 @@ impl GlobalContext {
         let status_text = match self.data.ui.mode {
             MouseMode::Face => "Face mode. Click to select a piece. Drag on paper to move it. Shift-drag on paper to rotate it.",
             MouseMode::Edge => "Edge mode. Click on an edge to split/join pieces. Shift-click to join a full strip of quads.",
-            MouseMode::Flap => "Flap mode. Click on an edge to swap the side of a flap. Shift-click to hide a flap.",
+            MouseMode::Flap => "Flap mode. Click on an edge to swap the side of a flap. Shift-click to hide a flap. Ctrl-click to edit the flap.",
             MouseMode::ReadOnly => "View mode. Click to highlight a piece. Move the mouse over an edge to highlight the matching pair.",
         };
         ui.text(status_text);
@@ -856,6 +1309,11 @@ impl GlobalContext {
         self.build_modal_wait_message_and_run_file_action(ui);
         self.build_confirm_message(ui, &mut menu_actions);
         self.build_about(ui);
+        self.build_problems(ui);
+        self.build_import_report(ui);
+        self.build_simplify_dialog(ui);
+        self.build_transform_dialog(ui);
+        self.build_flap_inspector(ui);
 
         menu_actions
     }
@@ -918,17 +1376,18 @@ impl GlobalContext {
                 )
             })
             .count();
-        let bbox = util_3d::bounding_box_3d(
-            self.data
-                .papercraft()
-                .model()
-                .vertices()
-                .map(|(_, v)| v.pos()),
-        );
-        let model_size = (bbox.1 - bbox.0) * options.scale;
-        let Vector3 { x, y, z } = model_size;
+        let Vector3 { x, y, z } = self.model_size() * options.scale;
         ui.text(&format!("Number of pieces: {n_pieces}\nNumber of flaps: {n_flaps}\nReal size (mm): {x:.0} x {y:.0} x {z:.0}"));
     }
+    // Size of the model, before scaling
+    fn model_size(&self) -> Vector3 {
+        let (v_min, v_max) = self.data.papercraft().model().bounding_box();
+        v_max - v_min
+    }
+    // Size of the finished model, in mm
+    fn real_size(&self) -> Vector3 {
+        self.model_size() * self.data.papercraft().options().scale
+    }
 
     fn build_full_options_inner_dialog(
         &mut self,
@@ -955,6 +1414,29 @@ impl GlobalContext {
                             ui.same_line_ex(0.0, font_sz * 3.0);
                             ui.checkbox("Texture filter", &mut options.tex_filter);
                         });
+                        ui.with_disabled(
+                            !self.data.papercraft().model().has_textures() || !options.texture,
+                            || {
+                                ui.same_line_ex(0.0, font_sz * 3.0);
+                                ui.set_next_item_width(font_sz * 5.5);
+                                ui.input_float_config("Bleed (mm)", &mut options.bleed)
+                                    .display_format(imgui::FloatFormat::G)
+                                    .build();
+                                options.bleed = options.bleed.clamp(0.0, 10.0);
+                            },
+                        );
+                        let Vector3 { x, y, z } = self.model_size() * options.scale;
+                        ui.text(&format!("Real size (mm): {x:.0} x {y:.0} x {z:.0}"));
+                        ui.checkbox(
+                            "Save the undo history in the file",
+                            &mut options.save_undo_history,
+                        );
+                        ui.with_disabled(self.data.papercraft().model().num_parts() == 0, || {
+                            ui.checkbox(
+                                "Prefix the island names with the part name",
+                                &mut options.island_name_with_part,
+                            );
+                        });
 
                         ui.tree_node_config("Flaps").with(|| {
                             static FLAP_STYLES: &[FlapStyle] = &[
@@ -999,6 +1481,14 @@ impl GlobalContext {
                                 .display_format(imgui::FloatFormat::G)
                                 .build();
                             options.flap_angle = options.flap_angle.clamp(0.0, 180.0);
+
+                            ui.set_next_item_width(font_sz * 8.0);
+                            ui.combo(
+                                "Shape",
+                                FLAP_SHAPES.iter().copied(),
+                                fmt_flap_shape,
+                                &mut options.flap_shape,
+                            );
                         });
                         ui.tree_node_config("Folds").with(|| {
                             static FOLD_STYLES: &[FoldStyle] = &[
@@ -1056,6 +1546,22 @@ impl GlobalContext {
                             .display_format(imgui::FloatFormat::G)
                             .build();
                             options.hidden_line_angle = options.hidden_line_angle.clamp(0.0, 180.0);
+
+                            ui.same_line_ex(0.0, font_sz * 1.5);
+                            ui.set_next_item_width(font_sz * 5.5);
+                            ui.input_float_config(
+                                "Paper thickness (mm)",
+                                &mut options.paper_thickness,
+                            )
+                            .display_format(imgui::FloatFormat::G)
+                            .build();
+                            options.paper_thickness = options.paper_thickness.clamp(0.0, 20.0);
+
+                            // Turning it on cuts all the folds, that can be undone
+                            ui.checkbox(
+                                "Thick board (each face is a panel, with its bevel angles)",
+                                &mut options.thick_board,
+                            );
                         });
                         ui.tree_node_config("Information").with(|| {
                             self.build_read_only_options_inner_dialog(ui, &options);
@@ -1087,6 +1593,24 @@ impl GlobalContext {
                         ui.set_next_item_width(font_sz * 11.0);
                         ui.checkbox("Print page number", &mut options.show_page_number);
 
+                        ui.same_line_ex(0.0, font_sz * 3.0);
+                        ui.checkbox("Vector PDF", &mut options.vector_pdf);
+
+                        ui.checkbox("Single DXF file", &mut options.dxf_whole_canvas);
+                        ui.same_line_ex(0.0, font_sz * 3.0);
+
+                        ui.set_next_item_width(font_sz * 5.5);
+                        ui.input_float_config("Packing spacing (mm)", &mut options.pack_spacing)
+                            .display_format(imgui::FloatFormat::G)
+                            .build();
+                        options.pack_spacing = options.pack_spacing.clamp(0.0, 50.0);
+
+                        ui.checkbox("Print & cut marks", &mut options.print_and_cut);
+                        ui.same_line_ex(0.0, font_sz * 3.0);
+                        ui.with_disabled(!options.print_and_cut, || {
+                            ui.checkbox("Score creases", &mut options.score_creases);
+                        });
+
                         static EDGE_ID_POSITIONS: &[EdgeIdPosition] = &[
                             EdgeIdPosition::None,
                             EdgeIdPosition::Outside,
@@ -1119,6 +1643,26 @@ impl GlobalContext {
                             .build();
                             options.edge_id_font_size = options.edge_id_font_size.clamp(1.0, 72.0);
                         });
+
+                        static ALIGNMENT_MARKS: &[AlignmentMarks] = &[
+                            AlignmentMarks::None,
+                            AlignmentMarks::Midpoint,
+                            AlignmentMarks::Fractions,
+                        ];
+                        fn fmt_alignment_marks(s: AlignmentMarks) -> &'static str {
+                            match s {
+                                AlignmentMarks::None => "None",
+                                AlignmentMarks::Midpoint => "Midpoint",
+                                AlignmentMarks::Fractions => "Fractions",
+                            }
+                        }
+                        ui.set_next_item_width(font_sz * 6.0);
+                        ui.combo(
+                            "Alignment marks",
+                            ALIGNMENT_MARKS.iter().copied(),
+                            fmt_alignment_marks,
+                            &mut options.alignment_marks,
+                        );
                     });
                 ui.tree_node_config("Paper size")
                     .flags(imgui::TreeNodeFlags::Framed)
@@ -1261,6 +1805,13 @@ impl GlobalContext {
             BoolWithConfirm::Confirmed
         }
     }
+    fn check_conflicts(&mut self) -> BoolWithConfirm {
+        if self.data.find_conflicts().is_empty() {
+            BoolWithConfirm::Confirmed
+        } else {
+            BoolWithConfirm::Requested
+        }
+    }
     fn build_menu_and_file_dialog(&mut self, ui: &Ui) -> MenuActions {
         let mut menu_actions = MenuActions::default();
 
@@ -1284,12 +1835,29 @@ impl GlobalContext {
                     if ui.menu_item_config("Update with new model...").build() {
                         menu_actions.update_model = self.check_modified();
                     }
+                    if ui
+                        .menu_item_config("Clean up imported models")
+                        .selected(self.import_options.cleanup)
+                        .build()
+                    {
+                        self.import_options.cleanup ^= true;
+                    }
                 }
                 if ui.menu_item_config("Export OBJ...").build() {
                     menu_actions.export_obj = true;
                 }
+                if ui.menu_item_config("Export Pepakura...").build() {
+                    menu_actions.export_pdo = true;
+                }
                 if ui.menu_item_config("Generate Printable...").build() {
-                    menu_actions.generate_printable = true;
+                    menu_actions.generate_printable = self.check_conflicts();
+                }
+                if ui
+                    .menu_item_config("Software page renderer")
+                    .selected(self.software_render)
+                    .build()
+                {
+                    self.software_render ^= true;
                 }
                 ui.separator();
                 if ui.menu_item_config("Quit").shortcut("Ctrl+Q").build() {
@@ -1306,6 +1874,14 @@ impl GlobalContext {
                     {
                         menu_actions.undo = true;
                     }
+                    if ui
+                        .menu_item_config("Redo")
+                        .shortcut("Ctrl+Y")
+                        .enabled(self.data.can_redo())
+                        .build()
+                    {
+                        menu_actions.redo = true;
+                    }
                     ui.separator();
                 }
 
@@ -1351,10 +1927,91 @@ impl GlobalContext {
                     ui.separator();
 
                     if ui.menu_item_config("Repack pieces").build() {
-                        let undo = self.data.pack_islands();
+                        let undo = self.data.pack_islands(false);
+                        self.data.push_undo_action(undo);
+                        self.add_rebuild(RebuildFlags::PAPER | RebuildFlags::SELECTION);
+                    }
+                    if ui.menu_item_config("Repack pieces in their pages").build() {
+                        let undo = self.data.pack_islands(true);
                         self.data.push_undo_action(undo);
                         self.add_rebuild(RebuildFlags::PAPER | RebuildFlags::SELECTION);
                     }
+                    if ui.menu_item_config("Auto unfold").build() {
+                        let undo = self.data.auto_unfold();
+                        self.data.push_undo_action(undo);
+                        self.add_rebuild(RebuildFlags::all());
+                    }
+                    if ui
+                        .menu_item_config("Transform model...")
+                        .selected(self.transform_opened.is_some())
+                        .build()
+                    {
+                        self.transform_opened = match self.transform_opened {
+                            Some(_) => None,
+                            // By default set the height
+                            None => Some(TransformSize {
+                                axis: 1,
+                                size: self.real_size().y,
+                            }),
+                        }
+                    }
+                    if ui
+                        .menu_item_config("Simplify model...")
+                        .selected(self.simplify_opened.is_some())
+                        .build()
+                    {
+                        self.simplify_opened = match self.simplify_opened {
+                            Some(_) => None,
+                            None => Some(SimplifyOptions {
+                                target_faces: self.data.papercraft().model().num_faces() / 2,
+                                max_error: None,
+                            }),
+                        }
+                    }
+                    if ui
+                        .menu_item_config("Revert simplification")
+                        .enabled(self.pre_simplify.is_some())
+                        .build()
+                    {
+                        self.revert_simplify();
+                    }
+
+                    ui.separator();
+
+                    if ui
+                        .menu_item_config("Select all islands on this page")
+                        .build()
+                    {
+                        let flags = self.data.select_page_islands(to_cgv2(self.sz_paper));
+                        self.add_rebuild(flags);
+                    }
+                    let num_selected = self.data.selected_islands().len();
+                    ui.menu_config("Align").with(|| {
+                        static ALIGNS: &[(&str, IslandAlign, usize)] = &[
+                            ("Left", IslandAlign::Left, 2),
+                            ("Right", IslandAlign::Right, 2),
+                            ("Top", IslandAlign::Top, 2),
+                            ("Bottom", IslandAlign::Bottom, 2),
+                            ("Center horizontally", IslandAlign::CenterH, 2),
+                            ("Center vertically", IslandAlign::CenterV, 2),
+                            ("Distribute horizontally", IslandAlign::DistributeH, 3),
+                            ("Distribute vertically", IslandAlign::DistributeV, 3),
+                        ];
+                        for &(name, align, min_selected) in ALIGNS {
+                            if align == IslandAlign::DistributeH {
+                                ui.separator();
+                            }
+                            if ui
+                                .menu_item_config(name)
+                                .enabled(num_selected >= min_selected)
+                                .build()
+                            {
+                                let undo = self.data.align_islands(align);
+                                self.data.push_undo_action(undo);
+                                self.add_rebuild(RebuildFlags::PAPER | RebuildFlags::SELECTION);
+                            }
+                        }
+                    });
                 }
             });
             ui.menu_config("View").with(|| {
@@ -1425,6 +2082,43 @@ impl GlobalContext {
                     self.data.ui.highlight_overlaps ^= true;
                     self.add_rebuild(RebuildFlags::PAPER_REDRAW);
                 }
+                if ui
+                    .menu_item_config("Problems")
+                    .selected(self.problems.is_some())
+                    .build()
+                {
+                    self.problems = match self.problems {
+                        Some(_) => None,
+                        None => Some(self.data.find_conflicts()),
+                    }
+                }
+                let num_parts = self.data.papercraft().model().num_parts();
+                if num_parts > 0 {
+                    ui.menu_config("Parts").with(|| {
+                        if ui.menu_item_config("Show all").build() {
+                            for i_part in 0..num_parts {
+                                let flags =
+                                    self.data.set_part_visible(PartIndex::from(i_part), true);
+                                self.add_rebuild(flags);
+                            }
+                        }
+                        ui.separator();
+                        for i_part in 0..num_parts {
+                            let i_part = PartIndex::from(i_part);
+                            let name = self.data.papercraft().model().part_name(i_part);
+                            let name = if name.is_empty() { "(unnamed)" } else { name };
+                            let visible = self.data.is_part_visible(i_part);
+                            if ui
+                                .menu_item_config(format!("{name}##{}", usize::from(i_part)))
+                                .selected(visible)
+                                .build()
+                            {
+                                let flags = self.data.set_part_visible(i_part, !visible);
+                                self.add_rebuild(flags);
+                            }
+                        }
+                    });
+                }
                 if ui.menu_item_config("Reset views").build() {
                     menu_actions.reset_views = true;
                     self.add_rebuild(RebuildFlags::PAPER_REDRAW | RebuildFlags::SCENE_REDRAW);
@@ -1452,7 +2146,14 @@ impl GlobalContext {
                     self.set_mouse_mode(MouseMode::Flap);
                 }
                 if ui.is_key_down(imgui::Key::ModCtrl) && ui.is_key_pressed(imgui::Key::Z) {
-                    menu_actions.undo = true;
+                    if ui.is_key_down(imgui::Key::ModShift) {
+                        menu_actions.redo = true;
+                    } else {
+                        menu_actions.undo = true;
+                    }
+                }
+                if ui.is_key_down(imgui::Key::ModCtrl) && ui.is_key_pressed(imgui::Key::Y) {
+                    menu_actions.redo = true;
                 }
             }
             if ui.is_key_down(imgui::Key::ModCtrl) && ui.is_key_pressed(imgui::Key::Q) {
@@ -1606,8 +2307,13 @@ impl GlobalContext {
             self.data
                 .reset_views(to_cgv2(self.sz_scene), to_cgv2(self.sz_paper));
         }
-        if menu_actions.undo {
-            match self.data.undo_action() {
+        if menu_actions.undo || menu_actions.redo {
+            let res = if menu_actions.undo {
+                self.data.undo_action()
+            } else {
+                self.data.redo_action()
+            };
+            match res {
                 UndoResult::Model => {
                     self.add_rebuild(RebuildFlags::all());
                 }
@@ -1671,10 +2377,13 @@ impl GlobalContext {
             open_file_dialog = true;
         }
         const LOAD_MODEL_FILTER: &str = "\
-            All models (*.obj *.pdo *.stl) {.obj,.pdo,.stl},\
+            All models (*.obj *.pdo *.stl *.gltf *.glb *.ply *.3mf) {.obj,.pdo,.stl,.gltf,.glb,.ply,.3mf},\
             Wavefront (*.obj) {.obj},\
             Pepakura (*.pdo) {.pdo},\
             Stl (*.stl) {.stl},\
+            glTF (*.gltf *.glb) {.gltf,.glb},\
+            PLY (*.ply) {.ply},\
+            3MF (*.3mf) {.3mf},\
             All files {.*}\
             ";
         match menu_actions.import_model {
@@ -1733,32 +2442,55 @@ impl GlobalContext {
             self.file_dialog = Some((fd, "Export OBJ...", FileAction::ExportObj));
             open_file_dialog = true;
         }
-        if menu_actions.generate_printable {
-            use std::borrow::Cow::{Borrowed, Owned};
-
-            let (last_path, last_file) = if self.last_export.is_empty() {
-                (Borrowed(&self.last_path), Borrowed(""))
-            } else {
-                let path = PathBuf::from(&self.last_export);
-                let last_path = path
-                    .parent()
-                    .map(|p| p.to_string_lossy().into_owned())
-                    .unwrap_or_else(String::new);
-                let last_file = path
-                    .file_name()
-                    .map(|p| p.to_string_lossy().into_owned())
-                    .unwrap_or_else(String::new);
-                (Owned(last_path), Owned(last_file))
-            };
+        if menu_actions.export_pdo {
             let fd = imgui_filedialog::Builder::new("fd")
-                .filter("PDF document (*.pdf) {.pdf},SVG documents (*.svg) {.svg},PNG documents (*.png) {.png},All files {.*}")
-                .path(&last_path)
-                .file(&last_file)
-                .flags(imgui_filedialog::Flags::CONFIRM_OVERWRITE | imgui_filedialog::Flags::NO_DIALOG)
+                .filter("Pepakura (*.pdo) {.pdo},All files {.*}")
+                .path(&self.last_path)
+                .flags(
+                    imgui_filedialog::Flags::CONFIRM_OVERWRITE | imgui_filedialog::Flags::NO_DIALOG,
+                )
                 .open();
-            self.file_dialog = Some((fd, "Generate Printable...", FileAction::GeneratePrintable));
+            self.file_dialog = Some((fd, "Export Pepakura...", FileAction::ExportPdo));
             open_file_dialog = true;
         }
+        match menu_actions.generate_printable {
+            BoolWithConfirm::Requested => {
+                self.open_confirmation_dialog(
+                    ui,
+                    "Generate Printable",
+                    "Some pieces overlap or are out of the pages, see View/Problems.\nContinue anyway?",
+                    |a| a.generate_printable = BoolWithConfirm::Confirmed,
+                );
+            }
+            BoolWithConfirm::Confirmed => {
+                use std::borrow::Cow::{Borrowed, Owned};
+
+                let (last_path, last_file) = if self.last_export.is_empty() {
+                    (Borrowed(&self.last_path), Borrowed(""))
+                } else {
+                    let path = PathBuf::from(&self.last_export);
+                    let last_path = path
+                        .parent()
+                        .map(|p| p.to_string_lossy().into_owned())
+                        .unwrap_or_else(String::new);
+                    let last_file = path
+                        .file_name()
+                        .map(|p| p.to_string_lossy().into_owned())
+                        .unwrap_or_else(String::new);
+                    (Owned(last_path), Owned(last_file))
+                };
+                let fd = imgui_filedialog::Builder::new("fd")
+                .filter("PDF document (*.pdf) {.pdf},SVG documents (*.svg) {.svg},PNG documents (*.png) {.png},DXF drawings (*.dxf) {.dxf},All files {.*}")
+                .path(&last_path)
+                .file(&last_file)
+                .flags(imgui_filedialog::Flags::CONFIRM_OVERWRITE | imgui_filedialog::Flags::NO_DIALOG)
+                .open();
+                self.file_dialog =
+                    Some((fd, "Generate Printable...", FileAction::GeneratePrintable));
+                open_file_dialog = true;
+            }
+            BoolWithConfirm::None => {}
+        }
 
         // There are two Wait modals and two Error modals. One pair over the FileDialog, the other to be opened directly ("Save").
 
@@ -2054,6 +2786,15 @@ impl GlobalContext {
                 u.line_color = Rgba::new(0.0, 0.0, 0.0, 1.0);
             }
 
+            // Texture bleed, under everything else
+            if u.texturize != 0 {
+                gl_fixs.prg_paper_solid.draw(
+                    &u,
+                    &self.data.gl_objs().paper_vertices_bleed,
+                    glow::TRIANGLES,
+                );
+            }
+
             // Line Flaps
             if self.data.ui.show_flaps {
                 gl_fixs.prg_paper_line.draw(
@@ -2126,6 +2867,14 @@ impl GlobalContext {
                 );
             }
 
+            // Rubber band selection
+            u.line_color = Rgba::new(0.0, 0.0, 0.0, 1.0);
+            gl_fixs.prg_paper_line.draw(
+                &u,
+                &self.data.gl_objs().paper_vertices_rubber_band,
+                glow::LINES,
+            );
+
             // Draw the highlight overlap if "1 < STENCIL"
             self.gl.enable(glow::STENCIL_TEST);
             self.gl.stencil_op(glow::KEEP, glow::KEEP, glow::KEEP);
@@ -2179,6 +2928,10 @@ impl GlobalContext {
     }
     fn set_mouse_mode(&mut self, mode: MouseMode) {
         self.data.ui.mode = mode;
+        if mode != MouseMode::Flap {
+            let flags = self.data.clear_selected_flaps();
+            self.add_rebuild(flags);
+        }
         self.add_rebuild(
             RebuildFlags::SELECTION | RebuildFlags::SCENE_REDRAW | RebuildFlags::PAPER_REDRAW,
         );
@@ -2255,6 +3008,7 @@ impl GlobalContext {
                 self.data.modified = true;
             }
             FileAction::ExportObj => self.export_obj(file_name)?,
+            FileAction::ExportPdo => self.export_pdo(file_name)?,
             FileAction::GeneratePrintable => {
                 // Rebuild everything, just in case
                 //TODO: should pass show_texts as argument?
@@ -2274,9 +3028,10 @@ impl GlobalContext {
         let fs = std::fs::File::open(file_name)
             .with_context(|| format!("Error opening file {}", file_name.display()))?;
         let fs = std::io::BufReader::new(fs);
-        let papercraft = Papercraft::load(fs)
+        self.data = PapercraftContext::load(fs, &self.gl)
             .with_context(|| format!("Error loading file {}", file_name.display()))?;
-        self.data = PapercraftContext::from_papercraft(papercraft, &self.gl)?;
+        self.pre_simplify = None;
+        self.import_options.cleanup = self.data.papercraft().options().import_cleanup;
         self.data.reset_views(self.sz_scene, self.sz_paper);
         if let Some(o) = self.options_opened.as_mut() {
             *o = self.data.papercraft().options().clone();
@@ -2289,33 +3044,63 @@ impl GlobalContext {
             .with_context(|| format!("Error creating file {}", file_name.display()))?;
         let f = std::io::BufWriter::new(f);
         self.data
-            .papercraft()
             .save(f)
             .with_context(|| format!("Error saving file {}", file_name.display()))?;
         Ok(())
     }
     fn import_model(&mut self, file_name: &Path) -> anyhow::Result<bool> {
-        let (papercraft, is_native) = import_model_file(file_name)?;
+        let (papercraft, is_native, report) = import_model_file(file_name, &self.import_options)?;
+        self.import_options.cleanup = papercraft.options().import_cleanup;
         self.data = PapercraftContext::from_papercraft(papercraft, &self.gl)?;
+        self.pre_simplify = None;
         self.data.reset_views(self.sz_scene, self.sz_paper);
         if let Some(o) = self.options_opened.as_mut() {
             *o = self.data.papercraft().options().clone();
         }
         self.rebuild = RebuildFlags::all();
+        self.import_report = (!report.is_empty()).then_some(report);
         Ok(is_native)
     }
     fn update_obj(&mut self, file_name: &Path) -> anyhow::Result<()> {
-        let (mut new_papercraft, _) = import_model_file(file_name)?;
+        let (mut new_papercraft, _, report) = import_model_file(file_name, &self.import_options)?;
         new_papercraft.update_from_obj(self.data.papercraft());
+        // The old options are kept, but not how the new model was imported
+        let mut options = new_papercraft.options().clone();
+        options.import_cleanup = self.import_options.cleanup;
+        new_papercraft.set_options(options, false);
 
         // Preserve the main user visible settings
         let prev_ui = self.data.ui.clone();
         self.data = PapercraftContext::from_papercraft(new_papercraft, &self.gl)?;
+        self.pre_simplify = None;
         self.rebuild = RebuildFlags::all();
         self.data.ui = prev_ui;
         self.data.modified = true;
+        self.import_report = (!report.is_empty()).then_some(report);
         Ok(())
     }
+    fn simplify_model(&mut self, options: &SimplifyOptions) -> anyhow::Result<()> {
+        let new_papercraft = self.data.papercraft().simplified(options)?;
+
+        // Preserve the main user visible settings
+        let mut data = PapercraftContext::from_papercraft(new_papercraft, &self.gl)?;
+        data.ui = self.data.ui.clone();
+        data.modified = true;
+        // Keep the old model, with its undo history, to be able to revert this
+        self.pre_simplify = Some(std::mem::replace(&mut self.data, data));
+        self.rebuild = RebuildFlags::all();
+        Ok(())
+    }
+    fn revert_simplify(&mut self) {
+        let Some(mut data) = self.pre_simplify.take() else {
+            return;
+        };
+        data.ui = self.data.ui.clone();
+        // The simplified model may have been saved
+        data.modified = true;
+        self.data = data;
+        self.rebuild = RebuildFlags::all();
+    }
     fn export_obj(&self, file_name: &Path) -> anyhow::Result<()> {
         self.data
             .papercraft()
@@ -2324,302 +3109,91 @@ impl GlobalContext {
         Ok(())
     }
 
+    fn export_pdo(&self, file_name: &Path) -> anyhow::Result<()> {
+        self.data
+            .papercraft()
+            .export_pdo(file_name.as_ref())
+            .with_context(|| format!("Error exporting to {}", file_name.display()))?;
+        Ok(())
+    }
+
     fn generate_printable(
         &self,
         text_tex_id: Option<glow::Texture>,
         file_name: &Path,
     ) -> anyhow::Result<()> {
-        let res = match file_name
-            .extension()
-            .map(|s| s.to_string_lossy().into_owned().to_ascii_lowercase())
-            .as_deref()
-        {
-            Some("pdf") => self.generate_pdf(file_name),
-            Some("svg") => self.generate_svg(file_name),
-            Some("png") => self.generate_png(text_tex_id, file_name),
-            _ => anyhow::bail!(
-                "Don't know how to write the format of {}",
-                file_name.display()
-            ),
+        let printable = Printable::new(
+            self.data.papercraft(),
+            self.title(false),
+            self.font_text_line_scale,
+        );
+        let res = if self.software_render {
+            SoftRenderer::new(self.data.papercraft())
+                .and_then(|mut renderer| printable.generate(&mut renderer, None, file_name))
+        } else {
+            GlPageRenderer::new(self, text_tex_id)
+                .and_then(|mut renderer| printable.generate(&mut renderer, None, file_name))
         };
         res.with_context(|| format!("Error exporting to {}", file_name.display()))?;
         Ok(())
     }
-    fn generate_pdf(&self, file_name: &Path) -> anyhow::Result<()> {
-        use printpdf::{BuiltinFont, Mm, PdfDocument, TextMatrix};
-
-        let options = self.data.papercraft().options();
-        let resolution = options.resolution as f32;
-        let page_size_mm = Vector2::from(options.page_size);
-
-        let title = self.title(false);
-        let (doc, page_ref, layer_ref) =
-            PdfDocument::new(title, Mm(page_size_mm.x), Mm(page_size_mm.y), "Layer");
-        let doc = doc.with_creator(signature());
-        let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
-        let edge_id_position = options.edge_id_position;
-
-        let mut first_page = Some((page_ref, layer_ref));
-
-        self.generate_pages(None, |_page, pixbuf, texts, _| {
-            let (page_ref, layer_ref) = first_page
-                .take()
-                .unwrap_or_else(|| doc.add_page(Mm(page_size_mm.x), Mm(page_size_mm.y), "Layer"));
-            let layer = doc.get_page(page_ref).get_layer(layer_ref);
-
-            let write_texts = || {
-                if texts.is_empty() {
-                    return;
-                }
-                layer.begin_text_section();
-                for text in texts {
-                    let size = text.size * 72.0 / 25.4;
-                    // PDF fonts are just slightly larger than expected
-                    let size = size / 1.1;
-                    let x = text.pos.x;
-                    // (0,0) is in lower-left
-                    let y = page_size_mm.y - text.pos.y;
-                    let angle = -Deg::from(text.angle).0;
-                    let (width, cps) = pdf_metrics::measure_helvetica(&text.text);
-                    let width = width as f32 * text.size / 1000.0;
-                    let dx = match text.align {
-                        TextAlign::Near => 0.0,
-                        TextAlign::Center => -width / 2.0,
-                        TextAlign::Far => -width,
-                    };
-                    let x = x + dx * text.angle.cos();
-                    let y = y - dx * text.angle.sin();
-                    layer.set_font(&font, size);
-                    layer.set_text_matrix(TextMatrix::TranslateRotate(
-                        Mm(x).into_pt(),
-                        Mm(y).into_pt(),
-                        angle,
-                    ));
-                    layer.write_positioned_codepoints(cps);
-                }
-                layer.end_text_section();
-            };
-
-            if edge_id_position != EdgeIdPosition::Inside {
-                write_texts();
-            }
-
-            let img = printpdf::Image::from_dynamic_image(pixbuf);
-            // This image with the default transformation and the right resolution should cover the page exactly
-            let tr = printpdf::ImageTransform {
-                dpi: Some(resolution),
-                ..Default::default()
-            };
-            img.add_to_layer(layer.clone(), tr);
-
-            if edge_id_position == EdgeIdPosition::Inside {
-                write_texts();
-            }
-            Ok(())
-        })?;
-
-        let out = std::fs::File::create(file_name)?;
-        let mut out = std::io::BufWriter::new(out);
-        doc.save(&mut out)?;
-
-        Ok(())
-    }
-
-    fn generate_svg(&self, file_name: &Path) -> anyhow::Result<()> {
-        let options = self.data.papercraft().options();
-        let edge_id_position = options.edge_id_position;
-
-        self.generate_pages(None, |page, pixbuf, texts, lines_by_island| {
-            let name = Self::file_name_for_page(file_name, page);
-            let out = std::fs::File::create(name)?;
-            let mut out = std::io::BufWriter::new(out);
-
-            let page_size = Vector2::from(options.page_size);
-            let in_page = options.is_in_page_fn(page);
-
-            let mut png = Vec::new();
-            let mut cpng = std::io::Cursor::new(&mut png);
-            pixbuf.write_to(&mut cpng, image::ImageFormat::Png)?;
-
-            let mut all_page_cuts = Vec::new();
-
-            for (idx, (_, (lines, _))) in lines_by_island.iter().enumerate() {
-                if let Some(page_cuts) = cuts_to_page_cuts(lines.iter_cut(), &in_page) {
-                    all_page_cuts.push((idx, page_cuts));
-                };
-            }
-            writeln!(&mut out, r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>"#)?;
-            writeln!(
-                &mut out,
-                r#"<svg width="{0}mm" height="{1}mm" viewBox="0 0 {0} {1}" version="1.1" xmlns="http://www.w3.org/2000/svg" xmlns:inkscape="http://www.inkscape.org/namespaces/inkscape" xmlns:xlink="http://www.w3.org/1999/xlink">"#,
-                page_size.x, page_size.y
-            )?;
-
-            let write_layer_text = |out: &mut std::io::BufWriter<std::fs::File>| -> anyhow::Result<()> {
-                if texts.is_empty() {
-                    return Ok(());
-                }
-                // begin layer Text
-                writeln!(out, r#"<g inkscape:label="Text" inkscape:groupmode="layer" id="Text">"#)?;
-                for text in texts {
-                    let basis2: cgmath::Basis2<f32> = Rotation2::from_angle(-text.angle);
-                    let pos = basis2.rotate_vector(text.pos);
-                    writeln!(out, r#"<text x="{}" y="{}" style="{}font-size:{};font-family:sans-serif;fill:#000000" transform="rotate({})">{}</text>"#,
-                        pos.x,
-                        pos.y,
-                        match text.align {
-                            TextAlign::Near => "",
-                            TextAlign::Center => "text-anchor:middle;",
-                            TextAlign::Far => "text-anchor:end;",
-                        },
-                        text.size,
-                        Deg::from(text.angle).0,
-                        text.text,
-                    )?;
-                }
-                writeln!(out, r#"</g>"#)?;
-                // end layer Text
-                Ok(())
-            };
-
-            if edge_id_position != EdgeIdPosition::Inside {
-                write_layer_text(&mut out)?;
-            }
-
-            // begin layer Background
-            writeln!(&mut out, r#"<g inkscape:label="Background" inkscape:groupmode="layer" id="Background">"#)?;
-            write!(
-                &mut out,
-                r#"<image width="{}" height="{}" preserveAspectRatio="none" xlink:href="data:image/png;base64,"#,
-                page_size.x, page_size.y)?;
-            {
-                use base64::prelude::*;
-                let mut b64png = base64::write::EncoderWriter::new(&mut out, &BASE64_STANDARD);
-                b64png.write_all(&png)?;
-                b64png.finish()?;
-            }
-            writeln!(&mut out, r#"" id="background" x="0" y="0" style="display:inline"/>"#)?;
-
-            writeln!(&mut out, r#"</g>"#)?;
-            // end layer Background
-
-            if edge_id_position == EdgeIdPosition::Inside {
-                write_layer_text(&mut out)?;
-            }
-
-            // begin layer Cut
-            writeln!(&mut out, r#"<g inkscape:label="Cut" inkscape:groupmode="layer" id="Cut" style="display:none">"#)?;
-            for (idx, page_cut) in all_page_cuts {
-                writeln!(&mut out, r#"<path style="fill:none;stroke:#000000;stroke-width:1;stroke-linecap:butt;stroke-linejoin:miter" id="cut_{}" d=""#, idx)?;
-                write!(&mut out, r#"M "#)?;
-                let page_contour = cut_to_contour(page_cut);
-                for v in page_contour {
-                    writeln!(&mut out, r#"{},{}"#, v.x, v.y)?;
-                }
-                writeln!(&mut out, r#"z"#)?;
-                writeln!(&mut out, r#"" />"#)?;
-            }
-            writeln!(&mut out, r#"</g>"#)?;
-            // end layer Cut
-
-            // begin layer Fold
-            writeln!(&mut out, r#"<g inkscape:label="Fold" inkscape:groupmode="layer" id="Fold" style="display:none">"#)?;
-            for fold_kind in [EdgeDrawKind::Mountain, EdgeDrawKind::Valley] {
-                writeln!(&mut out, r#"<g inkscape:label="{0}" inkscape:groupmode="layer" id="{0}">"#,
-                    if fold_kind == EdgeDrawKind::Mountain { "Mountain"} else { "Valley" })?;
-                for (idx, (_, (lines, extra))) in lines_by_island.iter().enumerate() {
-                    let creases = lines.iter_crease(extra, fold_kind);
-                    // each crease can be checked for bounds individually
-                    let page_creases = creases
-                        .filter_map(|(a, b)| {
-                            let (is_in_a, a) = in_page(a.pos);
-                            let (is_in_b, b) = in_page(b.pos);
-                            (is_in_a || is_in_b).then_some((a, b))
-                        })
-                        .collect::<Vec<_>>();
-                    if !page_creases.is_empty() {
-                        writeln!(&mut out, r#"<path style="fill:none;stroke:{1};stroke-width:1;stroke-linecap:butt;stroke-linejoin:miter" id="{2}_{0}" d=""#,
-                            idx,
-                            if fold_kind == EdgeDrawKind::Mountain  { "#ff0000" } else { "#0000ff" },
-                            if fold_kind == EdgeDrawKind::Mountain  { "foldm" } else { "foldv" }
-                        )?;
-                        for (a, b) in page_creases {
-                            writeln!(&mut out, r#"M {},{} {},{}"#, a.x, a.y, b.x, b.y)?;
-                        }
-                        writeln!(&mut out, r#"" />"#)?;
-                    }
-                }
-                writeln!(&mut out, r#"</g>"#)?;
-            }
-            writeln!(&mut out, r#"</g>"#)?;
-            // end layer Fold
-
-            writeln!(&mut out, r#"</svg>"#)?;
-            Ok(())
-        })?;
-        Ok(())
+    fn save_backup_on_panic(&self) {
+        if !self.data.modified {
+            return;
+        }
+        let mut dir = std::env::temp_dir();
+        dir.push(format!("crashed-{}.craft", std::process::id()));
+        eprintln!(
+            "Papercraft panicked! Saving backup at \"{}\"",
+            dir.display()
+        );
+        if let Err(e) = self.save_as_craft(&dir) {
+            eprintln!("backup failed with {e:?}");
+        } else {
+            eprintln!("backup complete");
+        }
     }
-    fn generate_png(
-        &self,
-        text_tex_id: Option<glow::Texture>,
-        file_name: &Path,
-    ) -> anyhow::Result<()> {
-        self.generate_pages(text_tex_id, |page, pixbuf, _texts, _| {
-            let name = Self::file_name_for_page(file_name, page);
-            let f = std::fs::File::create(name)?;
-            let mut f = std::io::BufWriter::new(f);
-            pixbuf.write_to(&mut f, image::ImageFormat::Png)?;
-            Ok(())
-        })?;
-        Ok(())
+    fn pre_render(&mut self, ui: &Ui, rebuild: RebuildFlags) {
+        let text_helper = TextHelper {
+            ui,
+            font_text_line_scale: self.font_text_line_scale,
+            font_id: self.font_text,
+        };
+        self.data.pre_render(rebuild, &text_helper);
+        if rebuild.intersects(RebuildFlags::PAPER | RebuildFlags::PAGES) && self.problems.is_some()
+        {
+            self.problems = Some(self.data.find_conflicts());
+        }
     }
+}
 
-    fn file_name_for_page(file_name: &Path, page: u32) -> PathBuf {
-        if page == 0 {
-            return file_name.to_owned();
-        }
-        let ext = file_name.extension().unwrap_or_default();
-        let stem = file_name.file_stem().unwrap_or_default();
-        let stem = stem.to_string_lossy();
-        let stem = stem.strip_suffix("_1").unwrap_or(&stem);
-        let parent = file_name.parent().map(|p| p.to_owned()).unwrap_or_default();
-        let mut name = PathBuf::from(format!("{}_{}", stem, page + 1));
-        name.set_extension(ext);
-        parent.join(name)
-    }
-    fn generate_pages<F>(
-        &self,
-        text_tex_id: Option<glow::Texture>,
-        mut do_page_fn: F,
-    ) -> anyhow::Result<()>
-    where
-        F: FnMut(
-            u32,
-            &DynamicImage,
-            &[PrintableText],
-            &[(IslandKey, (PaperDrawFaceArgs, PaperDrawFaceArgsExtra))],
-        ) -> anyhow::Result<()>,
-    {
-        let options = self.data.papercraft().options();
-        let (_margin_top, margin_left, margin_right, margin_bottom) = options.margin;
-        let resolution = options.resolution as f32;
-        let page_size_mm = Vector2::from(options.page_size);
-        let page_size_inches = page_size_mm / 25.4;
-        let page_size_pixels = page_size_inches * resolution;
-        let page_size_pixels =
-            cgmath::Vector2::new(page_size_pixels.x as i32, page_size_pixels.y as i32);
+// Draws the printable pages with the same GL objects used in the paper view
+struct GlPageRenderer<'a> {
+    ctx: &'a GlobalContext,
+    text_tex_id: Option<glow::Texture>,
+    page_size_pixels: cgmath::Vector2<i32>,
+    fbo: glr::Framebuffer,
+    _rbo: glr::Renderbuffer,
+    // If using AA another FBO/RBO to blit the antialiased image before reading
+    rbo_fbo_no_aa: Option<(glr::Renderbuffer, glr::Framebuffer)>,
+}
 
-        let mut pixbuf =
-            image::RgbaImage::new(page_size_pixels.x as u32, page_size_pixels.y as u32);
+impl<'a> GlPageRenderer<'a> {
+    fn new(
+        ctx: &'a GlobalContext,
+        text_tex_id: Option<glow::Texture>,
+    ) -> anyhow::Result<GlPageRenderer<'a>> {
+        let (width, height) = printable::page_size_pixels(ctx.data.papercraft());
+        let page_size_pixels = cgmath::Vector2::new(width as i32, height as i32);
 
         unsafe {
-            let fbo = glr::Framebuffer::generate(&self.gl)?;
-            let rbo = glr::Renderbuffer::generate(&self.gl)?;
+            let fbo = glr::Framebuffer::generate(&ctx.gl)?;
+            let rbo = glr::Renderbuffer::generate(&ctx.gl)?;
 
             let draw_fb_binder = BinderDrawFramebuffer::bind(&fbo);
             let read_fb_binder = BinderReadFramebuffer::bind(&fbo);
             let rb_binder = BinderRenderbuffer::bind(&rbo);
-            self.gl.framebuffer_renderbuffer(
+            ctx.gl.framebuffer_renderbuffer(
                 draw_fb_binder.target(),
                 glow::COLOR_ATTACHMENT0,
                 glow::RENDERBUFFER,
@@ -2630,32 +3204,32 @@ impl GlobalContext {
                 // multisample buffers cannot be read directly, it has to be copied to a regular one.
                 for samples in MULTISAMPLES {
                     // check if these many samples are usable
-                    self.gl.renderbuffer_storage_multisample(
+                    ctx.gl.renderbuffer_storage_multisample(
                         rb_binder.target(),
                         *samples,
                         glow::RGBA8,
                         page_size_pixels.x,
                         page_size_pixels.y,
                     );
-                    if self.gl.check_framebuffer_status(glow::DRAW_FRAMEBUFFER)
+                    if ctx.gl.check_framebuffer_status(glow::DRAW_FRAMEBUFFER)
                         != glow::FRAMEBUFFER_COMPLETE
                     {
                         continue;
                     }
 
                     // If using AA create another FBO/RBO to blit the antialiased image before reading
-                    let rbo2 = glr::Renderbuffer::generate(&self.gl)?;
+                    let rbo2 = glr::Renderbuffer::generate(&ctx.gl)?;
                     rb_binder.rebind(&rbo2);
-                    self.gl.renderbuffer_storage(
+                    ctx.gl.renderbuffer_storage(
                         rb_binder.target(),
                         glow::RGBA8,
                         page_size_pixels.x,
                         page_size_pixels.y,
                     );
 
-                    let fbo2 = glr::Framebuffer::generate(&self.gl)?;
+                    let fbo2 = glr::Framebuffer::generate(&ctx.gl)?;
                     read_fb_binder.rebind(&fbo2);
-                    self.gl.framebuffer_renderbuffer(
+                    ctx.gl.framebuffer_renderbuffer(
                         read_fb_binder.target(),
                         glow::COLOR_ATTACHMENT0,
                         glow::RENDERBUFFER,
@@ -2665,7 +3239,7 @@ impl GlobalContext {
                     break 'check_aa Some((rbo2, fbo2));
                 }
                 println!("No multisample!");
-                self.gl.renderbuffer_storage(
+                ctx.gl.renderbuffer_storage(
                     rb_binder.target(),
                     glow::RGBA8,
                     page_size_pixels.x,
@@ -2674,10 +3248,45 @@ impl GlobalContext {
                 None
             };
             // Consume the possible error from the multisample above
-            let _ = self.gl.get_error();
+            let _ = ctx.gl.get_error();
+
+            Ok(GlPageRenderer {
+                ctx,
+                text_tex_id,
+                page_size_pixels,
+                fbo,
+                _rbo: rbo,
+                rbo_fbo_no_aa,
+            })
+        }
+    }
+}
 
-            let _vp =
-                glr::PushViewport::push(&self.gl, 0, 0, page_size_pixels.x, page_size_pixels.y);
+impl PageRenderer for GlPageRenderer<'_> {
+    fn render_page(
+        &mut self,
+        page: u32,
+        content: PageContent,
+        pixbuf: &mut image::RgbaImage,
+    ) -> anyhow::Result<()> {
+        let gl = &self.ctx.gl;
+        let gl_fixs = &self.ctx.gl_fixs;
+        let gl_objs = self.ctx.data.gl_objs();
+        let options = self.ctx.data.papercraft().options();
+        let page_size_mm = Vector2::from(options.page_size);
+        let page_size_pixels = self.page_size_pixels;
+        let text_tex_id = if content == PageContent::Full {
+            self.text_tex_id
+        } else {
+            None
+        };
+        let with_lines = content != PageContent::TextureOnly;
+
+        unsafe {
+            let draw_fb_binder = BinderDrawFramebuffer::bind(&self.fbo);
+            let read_fb_binder = BinderReadFramebuffer::bind(&self.fbo);
+
+            let _vp = glr::PushViewport::push(gl, 0, 0, page_size_pixels.x, page_size_pixels.y);
 
             // Cairo surfaces are alpha-premultiplied:
             // * The framebuffer will be premultiplied, but the input fragments are not.
@@ -2685,292 +3294,154 @@ impl GlobalContext {
             // * In the screen DST_ALPHA does not matter, because the framebuffer is not
             //   transparent, but here we have to set it to the proper value: use separate blend
             //   functions or we'll get the alpha squared.
-            self.gl.clear_color(0.0, 0.0, 0.0, 0.0);
-            self.gl.enable(glow::BLEND);
-            self.gl.blend_func_separate(
+            gl.clear_color(0.0, 0.0, 0.0, 0.0);
+            gl.enable(glow::BLEND);
+            gl.blend_func_separate(
                 glow::SRC_ALPHA,
                 glow::ONE_MINUS_SRC_ALPHA,
                 glow::ONE,
                 glow::ONE_MINUS_SRC_ALPHA,
             );
 
-            let gl_fixs = &self.gl_fixs;
-
             let mut texturize = 0;
 
-            self.gl.bind_vertex_array(Some(gl_fixs.vao.id()));
-            if let (Some(tex), true) = (&self.data.gl_objs().textures, options.texture) {
-                self.gl.active_texture(glow::TEXTURE0);
-                self.gl.bind_texture(glow::TEXTURE_2D_ARRAY, Some(tex.id()));
+            gl.bind_vertex_array(Some(gl_fixs.vao.id()));
+            if let (Some(tex), true) = (&gl_objs.textures, options.texture) {
+                gl.active_texture(glow::TEXTURE0);
+                gl.bind_texture(glow::TEXTURE_2D_ARRAY, Some(tex.id()));
                 texturize = 1;
             }
 
             let ortho = util_3d::ortho2d_zero(page_size_mm.x, -page_size_mm.y);
-
-            let page_count = options.pages;
             let flap_style = options.flap_style;
 
-            let mut texts = Vec::new();
-            let lines_by_island = self.data.lines_by_island();
-
-            for page in 0..page_count {
-                // Start render
-                self.gl.clear(glow::COLOR_BUFFER_BIT);
-                let page_pos = options.page_position(page);
-                let mt = Matrix3::from_translation(-page_pos);
-                let mut u = Uniforms2D {
-                    m: ortho * mt,
-                    tex: 0,
-                    frac_dash: 0.5,
-                    line_color: Rgba::new(0.0, 0.0, 0.0, 1.0),
-                    texturize,
-                    notex_color: Rgba::new(1.0, 1.0, 1.0, 1.0),
-                };
+            // Start render
+            gl.clear(glow::COLOR_BUFFER_BIT);
+            let page_pos = options.page_position(page);
+            let mt = Matrix3::from_translation(-page_pos);
+            let mut u = Uniforms2D {
+                m: ortho * mt,
+                tex: 0,
+                frac_dash: 0.5,
+                line_color: Rgba::new(0.0, 0.0, 0.0, 1.0),
+                texturize,
+                notex_color: Rgba::new(1.0, 1.0, 1.0, 1.0),
+            };
 
-                // Draw the texts
-                if text_tex_id.is_some() && options.edge_id_position == EdgeIdPosition::Outside {
-                    self.gl.active_texture(glow::TEXTURE0);
-                    self.gl.bind_texture(glow::TEXTURE_2D, text_tex_id);
-                    gl_fixs
-                        .prg_text
-                        .draw(&u, &self.data.gl_objs().paper_text, glow::TRIANGLES);
-                }
+            // Texture bleed, under everything else
+            if u.texturize != 0 {
+                gl_fixs
+                    .prg_paper_solid
+                    .draw(&u, &gl_objs.paper_vertices_bleed, glow::TRIANGLES);
+            }
 
-                // Line Flaps
-                if flap_style != FlapStyle::None {
-                    gl_fixs.prg_paper_line.draw(
-                        &u,
-                        &self.data.gl_objs().paper_vertices_flap_edge,
-                        glow::LINES,
-                    );
-                }
+            // Draw the texts
+            if text_tex_id.is_some()
+                && options.edge_id_position == EdgeIdPosition::Outside
+                && !options.texts_over_texture()
+            {
+                gl.active_texture(glow::TEXTURE0);
+                gl.bind_texture(glow::TEXTURE_2D, text_tex_id);
+                gl_fixs
+                    .prg_text
+                    .draw(&u, &gl_objs.paper_text, glow::TRIANGLES);
+            }
 
-                // Solid Flaps
-                if flap_style != FlapStyle::None && flap_style != FlapStyle::White {
-                    gl_fixs.prg_paper_solid.draw(
-                        &u,
-                        &self.data.gl_objs().paper_vertices_flap,
-                        glow::TRIANGLES,
-                    );
-                }
+            // Line Flaps
+            if with_lines && flap_style != FlapStyle::None {
+                gl_fixs
+                    .prg_paper_line
+                    .draw(&u, &gl_objs.paper_vertices_flap_edge, glow::LINES);
+            }
 
-                // Borders
-                gl_fixs.prg_paper_line.draw(
-                    &u,
-                    &self.data.gl_objs().paper_vertices_edge_cut,
-                    glow::LINES,
-                );
+            // Solid Flaps
+            if flap_style != FlapStyle::None && flap_style != FlapStyle::White {
+                gl_fixs
+                    .prg_paper_solid
+                    .draw(&u, &gl_objs.paper_vertices_flap, glow::TRIANGLES);
+            }
 
-                // Textured faces
-                self.gl.vertex_attrib_4_f32(
-                    gl_fixs
-                        .prg_paper_solid
-                        .attrib_by_name("color")
-                        .unwrap()
-                        .location(),
-                    0.0,
-                    0.0,
-                    0.0,
-                    0.0,
-                );
-                gl_fixs.prg_paper_solid.draw(
-                    &u,
-                    &self.data.gl_objs().paper_vertices,
-                    glow::TRIANGLES,
-                );
+            // Borders
+            if with_lines {
+                gl_fixs
+                    .prg_paper_line
+                    .draw(&u, &gl_objs.paper_vertices_edge_cut, glow::LINES);
+            }
 
-                // Shadow Flaps
-                u.texturize = 0;
-                u.notex_color = Rgba::new(0.0, 0.0, 0.0, 0.0);
-                gl_fixs.prg_paper_solid.draw(
-                    &u,
-                    &self.data.gl_objs().paper_vertices_shadow_flap,
-                    glow::TRIANGLES,
-                );
-                u.notex_color = Rgba::new(1.0, 1.0, 1.0, 1.0);
+            // Textured faces
+            gl.vertex_attrib_4_f32(
+                gl_fixs
+                    .prg_paper_solid
+                    .attrib_by_name("color")
+                    .unwrap()
+                    .location(),
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+            );
+            gl_fixs
+                .prg_paper_solid
+                .draw(&u, &gl_objs.paper_vertices, glow::TRIANGLES);
 
-                // Creases
-                gl_fixs.prg_paper_line.draw(
-                    &u,
-                    &self.data.gl_objs().paper_vertices_edge_crease,
-                    glow::LINES,
-                );
+            // Shadow Flaps
+            u.texturize = 0;
+            u.notex_color = Rgba::new(0.0, 0.0, 0.0, 0.0);
+            gl_fixs
+                .prg_paper_solid
+                .draw(&u, &gl_objs.paper_vertices_shadow_flap, glow::TRIANGLES);
+            u.notex_color = Rgba::new(1.0, 1.0, 1.0, 1.0);
 
-                // Draw the texts
-                if text_tex_id.is_some() && options.edge_id_position == EdgeIdPosition::Inside {
-                    self.gl.active_texture(glow::TEXTURE0);
-                    self.gl.bind_texture(glow::TEXTURE_2D, text_tex_id);
-                    gl_fixs
-                        .prg_text
-                        .draw(&u, &self.data.gl_objs().paper_text, glow::TRIANGLES);
-                }
-                // End render
-
-                if let Some((_, fbo_no_aa)) = &rbo_fbo_no_aa {
-                    read_fb_binder.rebind(&fbo);
-                    draw_fb_binder.rebind(fbo_no_aa);
-                    self.gl.blit_framebuffer(
-                        0,
-                        0,
-                        page_size_pixels.x,
-                        page_size_pixels.y,
-                        0,
-                        0,
-                        page_size_pixels.x,
-                        page_size_pixels.y,
-                        glow::COLOR_BUFFER_BIT,
-                        glow::NEAREST,
-                    );
-                    read_fb_binder.rebind(fbo_no_aa);
-                    draw_fb_binder.rebind(&fbo);
-                }
+            // Creases
+            if with_lines {
+                gl_fixs
+                    .prg_paper_line
+                    .draw(&u, &gl_objs.paper_vertices_edge_crease, glow::LINES);
+            }
 
-                self.gl.read_buffer(glow::COLOR_ATTACHMENT0);
+            // Draw the texts
+            if text_tex_id.is_some() && options.texts_over_texture() {
+                gl.active_texture(glow::TEXTURE0);
+                gl.bind_texture(glow::TEXTURE_2D, text_tex_id);
+                gl_fixs
+                    .prg_text
+                    .draw(&u, &gl_objs.paper_text, glow::TRIANGLES);
+            }
+            // End render
 
-                self.gl.read_pixels(
+            if let Some((_, fbo_no_aa)) = &self.rbo_fbo_no_aa {
+                read_fb_binder.rebind(&self.fbo);
+                draw_fb_binder.rebind(fbo_no_aa);
+                gl.blit_framebuffer(
                     0,
                     0,
                     page_size_pixels.x,
                     page_size_pixels.y,
-                    glow::RGBA,
-                    glow::UNSIGNED_BYTE,
-                    glow::PixelPackData::Slice(&mut pixbuf),
+                    0,
+                    0,
+                    page_size_pixels.x,
+                    page_size_pixels.y,
+                    glow::COLOR_BUFFER_BIT,
+                    glow::NEAREST,
                 );
-
-                let edge_id_font_size = options.edge_id_font_size * 25.4 / 72.0; // pt to mm
-                let edge_id_position = options.edge_id_position;
-
-                texts.clear();
-                if options.show_self_promotion {
-                    let x = margin_left;
-                    let y = (page_size_mm.y - margin_bottom + FONT_SIZE)
-                        .min(page_size_mm.y - FONT_SIZE);
-                    let text = String::from(signature());
-                    texts.push(PrintableText {
-                        size: FONT_SIZE,
-                        pos: Vector2::new(x, y),
-                        angle: Rad(0.0),
-                        align: TextAlign::Near,
-                        text,
-                    });
-                }
-                if options.show_page_number {
-                    let x = page_size_mm.x - margin_right;
-                    let y = (page_size_mm.y - margin_bottom + FONT_SIZE)
-                        .min(page_size_mm.y - FONT_SIZE);
-                    let text = format!("Page {}/{}", page + 1, page_count);
-                    texts.push(PrintableText {
-                        size: FONT_SIZE,
-                        pos: Vector2::new(x, y),
-                        angle: Rad(0.0),
-                        align: TextAlign::Far,
-                        text,
-                    });
-                }
-                if edge_id_position != EdgeIdPosition::None {
-                    let in_page = options.is_in_page_fn(page);
-                    for (i_island, (lines, extra)) in &lines_by_island {
-                        let Some(page_cuts) = cuts_to_page_cuts(lines.iter_cut(), &in_page) else {
-                            continue;
-                        };
-                        // Edge ids
-                        for cut_idx in extra.cut_indices() {
-                            let i_island_b =
-                                self.data.papercraft().island_by_face(cut_idx.i_face_b);
-                            let ii = self
-                                .data
-                                .papercraft()
-                                .island_by_key(i_island_b)
-                                .map(|island_b| island_b.name())
-                                .unwrap_or("?");
-                            let text = format!("{}:{}", ii, cut_idx.id);
-                            let pos =
-                                in_page(cut_idx.pos(self.font_text_line_scale * edge_id_font_size))
-                                    .1;
-                            texts.push(PrintableText {
-                                size: edge_id_font_size,
-                                pos,
-                                angle: cut_idx.angle,
-                                align: TextAlign::Center,
-                                text,
-                            });
-                        }
-                        // Island ids
-                        let pos = match edge_id_position {
-                            // On top
-                            EdgeIdPosition::None | EdgeIdPosition::Outside => {
-                                let top = page_cuts
-                                    .iter()
-                                    .min_by(|a, b| a.0.y.total_cmp(&b.0.y))
-                                    .unwrap()
-                                    .0;
-                                top - Vector2::new(0.0, edge_id_font_size)
-                            }
-                            // In the middle
-                            EdgeIdPosition::Inside => {
-                                let island =
-                                    self.data.papercraft().island_by_key(*i_island).unwrap();
-                                let (flat_face, total_area) =
-                                    self.data.papercraft().get_biggest_flat_face(island);
-                                // Compute the center of mass of the flat-face, that will be the
-                                // weighted mean of the centers of masses of each single face.
-                                let center: Vector2 = flat_face
-                                    .iter()
-                                    .map(|(i_face, area)| {
-                                        let vv: Vector2 =
-                                            lines.vertices_for_face(*i_face).into_iter().sum();
-                                        vv * *area
-                                    })
-                                    .sum();
-                                // Don't forget to divide the center of each triangle by 3!
-                                let center = center / total_area / 3.0;
-                                let center = in_page(center).1;
-                                center + Vector2::new(0.0, edge_id_font_size)
-                            }
-                        };
-                        if let Some(island) = self.data.papercraft().island_by_key(*i_island) {
-                            texts.push(PrintableText {
-                                size: 2.0 * edge_id_font_size,
-                                pos,
-                                angle: Rad(0.0),
-                                align: TextAlign::Center,
-                                text: String::from(island.name()),
-                            });
-                        }
-                    }
-                }
-                let img = DynamicImage::from(pixbuf);
-                do_page_fn(page, &img, &texts, &lines_by_island)?;
-                // restore the
-                pixbuf = img.into();
+                read_fb_binder.rebind(fbo_no_aa);
+                draw_fb_binder.rebind(&self.fbo);
             }
+
+            gl.read_buffer(glow::COLOR_ATTACHMENT0);
+
+            gl.read_pixels(
+                0,
+                0,
+                page_size_pixels.x,
+                page_size_pixels.y,
+                glow::RGBA,
+                glow::UNSIGNED_BYTE,
+                glow::PixelPackData::Slice(pixbuf),
+            );
         }
         Ok(())
     }
-    fn save_backup_on_panic(&self) {
-        if !self.data.modified {
-            return;
-        }
-        let mut dir = std::env::temp_dir();
-        dir.push(format!("crashed-{}.craft", std::process::id()));
-        eprintln!(
-            "Papercraft panicked! Saving backup at \"{}\"",
-            dir.display()
-        );
-        if let Err(e) = self.save_as_craft(&dir) {
-            eprintln!("backup failed with {e:?}");
-        } else {
-            eprintln!("backup complete");
-        }
-    }
-    fn pre_render(&mut self, ui: &Ui, rebuild: RebuildFlags) {
-        let text_helper = TextHelper {
-            ui,
-            font_text_line_scale: self.font_text_line_scale,
-            font_id: self.font_text,
-        };
-        self.data.pre_render(rebuild, &text_helper);
-    }
 }
 
 #[derive(Debug)]
@@ -3084,6 +3555,95 @@ fn load_image_from_memory(data: &[u8], premultiply: bool) -> Result<image::RgbaI
     Ok(image)
 }
 
+// The state of the "Transform model" window: a dimension of the model and its size in mm
+struct TransformSize {
+    axis: usize,
+    size: f32,
+}
+
+// A rotation of 90 degrees around one of the axes, built by hand so that it is exact
+fn rotation_90(axis: usize) -> Matrix3 {
+    let (b, c) = ((axis + 1) % 3, (axis + 2) % 3);
+    let mut mx = Matrix3::zero();
+    mx[axis][axis] = 1.0;
+    mx[b][c] = 1.0;
+    mx[c][b] = -1.0;
+    mx
+}
+
+fn mirror(axis: usize) -> Matrix3 {
+    let mut mx = Matrix3::identity();
+    mx[axis][axis] = -1.0;
+    mx
+}
+
+fn import_report_summary(report: &ImportReport) -> Vec<String> {
+    let counts = [
+        (report.welded_vertices, "vertices welded"),
+        (report.degenerate_faces, "degenerate faces removed"),
+        (report.duplicated_faces, "duplicated faces removed"),
+        (
+            report.dropped_faces,
+            "faces dropped because they reuse an edge",
+        ),
+        (
+            report.flipped_faces.len(),
+            "faces flipped to match their neighbors",
+        ),
+        (
+            report.non_manifold_edges.len(),
+            "edges with more than two faces",
+        ),
+        (
+            report.inverted_edges.len(),
+            "edges between faces with opposite winding",
+        ),
+    ];
+    counts
+        .into_iter()
+        .filter(|(n, _)| *n > 0)
+        .map(|(n, text)| format!("{n} {text}"))
+        .collect()
+}
+
+static FLAP_SHAPES: &[FlapShape] = &[
+    FlapShape::Trapezoid,
+    FlapShape::Triangle,
+    FlapShape::Rounded,
+    FlapShape::Notched,
+    FlapShape::ZigZag,
+    FlapShape::TabAndSlot,
+];
+
+fn fmt_flap_shape(s: FlapShape) -> &'static str {
+    match s {
+        FlapShape::Trapezoid => "Trapezoid",
+        FlapShape::Triangle => "Triangle",
+        FlapShape::Rounded => "Rounded",
+        FlapShape::Notched => "Notched",
+        FlapShape::ZigZag => "Zig-zag",
+        FlapShape::TabAndSlot => "Tab & slot",
+    }
+}
+
+fn conflict_description(papercraft: &Papercraft, conflict: &Conflict) -> String {
+    let part_name = |island: &str, part| match part {
+        IslandPart::Face(i_face) => format!("{island}, face {}", usize::from(i_face)),
+        IslandPart::Flap(_, i_edge) => match papercraft.edge_id(i_edge) {
+            Some(id) => format!("{island}, flap {id}"),
+            None => format!("{island}, flap of edge {}", usize::from(i_edge)),
+        },
+    };
+    let a = part_name(&conflict.names.0, conflict.part);
+    match conflict.other {
+        Some((_, other)) => format!(
+            "Piece {a} overlaps piece {}",
+            part_name(&conflict.names.1, other)
+        ),
+        None => format!("Piece {a} is out of the pages"),
+    }
+}
+
 fn advance_cursor(ui: &Ui, x: f32, y: f32) {
     let f = ui.get_font_size();
     advance_cursor_pixels(ui, f * x, f * y);
@@ -3121,55 +3681,6 @@ fn center_url(ui: &Ui, s: &str, id: &str, cmd: Option<&str>, w: f32) {
     });
 }
 
-pub fn cut_to_contour(mut cuts: Vec<(Vector2, Vector2)>) -> Vec<Vector2> {
-    // Order the vertices in a closed loop
-    let mut res = Vec::with_capacity(cuts.len());
-    while let Some(mut p) = cuts.pop() {
-        res.push(p.0);
-        while let Some((next, _)) = cuts
-            .iter()
-            .enumerate()
-            .map(|(idx, (v0, _))| (idx, v0.distance2(p.1)))
-            .min_by(|(_, a), (_, b)| f32::total_cmp(a, b))
-        {
-            p = cuts.swap_remove(next);
-            res.push(p.0);
-        }
-        // the last point should connect to the first
-    }
-    res
-}
-
-pub fn cuts_to_page_cuts<'c>(
-    cuts: impl Iterator<Item = (&'c MVertex2DLine, &'c MVertex2DLine)>,
-    in_page: impl Fn(Vector2) -> (bool, Vector2),
-) -> Option<Vec<(Vector2, Vector2)>> {
-    let mut touching = false;
-    let page_cut = cuts
-        .map(|(v0, v1)| {
-            let (is_in_0, v0) = in_page(v0.pos);
-            let (is_in_1, v1) = in_page(v1.pos);
-            touching |= is_in_0 | is_in_1;
-            (v0, v1)
-        })
-        .collect::<Vec<_>>();
-    touching.then_some(page_cut)
-}
-
-enum TextAlign {
-    Near,
-    Center,
-    Far,
-}
-
-struct PrintableText {
-    size: f32,
-    pos: Vector2,
-    angle: Rad<f32>,
-    align: TextAlign,
-    text: String,
-}
-
 #[cfg(target_os = "linux")]
 #[inline(never)]
 unsafe fn install_crash_backup(
@@ -3225,8 +3736,7 @@ impl imgui::UiBuilder for GlobalContext {
         let edge_id_font_size =
             (options.edge_id_font_size * options.resolution as f32 / 72.0).min(350.0);
         self.font_text = atlas.add_font(imgui::FontInfo::new(&*KARLA_TTF, edge_id_font_size));
-        // This is eye-balled, depending on the particular font
-        self.font_text_line_scale = 0.80;
+        self.font_text_line_scale = FONT_TEXT_LINE_SCALE;
 
         self.logo_rect =
             atlas.add_custom_rect_regular([LOGO_IMG.width(), LOGO_IMG.height()], |_, img| {
@@ -10,10 +10,15 @@ use slotmap::{new_key_type, SlotMap};
 
 use super::*;
 mod file;
+mod overlap;
+mod pack;
+mod unfold;
 mod update;
 
+pub use overlap::{Conflict, IslandPart};
+
 // Which side of a cut will the flap be drawn, compare with face_sign
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub enum FlapSide {
     False,
     True,
@@ -72,6 +77,35 @@ pub enum FlapStyle {
     None,
 }
 
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FlapShape {
+    #[default]
+    Trapezoid,
+    Triangle,
+    Rounded,
+    Notched,
+    ZigZag,
+    // A tab with a narrow neck, that goes into a slit printed in the other face
+    TabAndSlot,
+}
+
+// Per-edge changes to the flap geometry, None means to use the global options
+#[derive(Default, Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FlapOverride {
+    #[serde(default)]
+    pub width: Option<f32>,
+    #[serde(default)]
+    pub angle: Option<f32>, //degrees
+    #[serde(default)]
+    pub shape: Option<FlapShape>,
+}
+
+impl FlapOverride {
+    pub fn is_empty(&self) -> bool {
+        *self == FlapOverride::default()
+    }
+}
+
 #[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
 pub enum FoldStyle {
     #[default]
@@ -83,6 +117,16 @@ pub enum FoldStyle {
     None,
 }
 
+// Marks printed at both sides of a cut edge, to line them up when gluing
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AlignmentMarks {
+    #[default]
+    None,
+    Midpoint,
+    // Two ticks, at 1/4 and 1/2 of the edge counted from the same end, so a flipped edge shows
+    Fractions,
+}
+
 #[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
 pub enum EdgeIdPosition {
     None,
@@ -95,13 +139,18 @@ new_key_type! {
     pub struct IslandKey;
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct JoinResult {
     pub i_edge: EdgeIndex,
+    // IslandKeys are not stable, so this one is not saved with the undo history
+    #[serde(skip)]
     pub i_island: IslandKey,
     pub prev_root: FaceIndex,
     pub prev_rot: Rad<f32>,
     pub prev_loc: Vector2,
+    // A joined edge has no flap, so its override is removed, but an undo restores it
+    #[serde(default)]
+    pub prev_flap_override: FlapOverride,
 }
 
 fn my_true() -> bool {
@@ -113,6 +162,9 @@ fn default_fold_line_width() -> f32 {
 fn default_edge_id_font_size() -> f32 {
     8.0
 }
+fn default_pack_spacing() -> f32 {
+    1.0
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PaperOptions {
@@ -126,10 +178,14 @@ pub struct PaperOptions {
     pub texture: bool,
     #[serde(default = "my_true")]
     pub tex_filter: bool,
+    #[serde(default)]
+    pub bleed: f32, //mm
     #[serde(default, rename = "tab_style")]
     pub flap_style: FlapStyle,
     #[serde(default)]
     pub fold_style: FoldStyle,
+    #[serde(default)]
+    pub flap_shape: FlapShape,
     #[serde(rename = "tab_width")]
     pub flap_width: f32,
     #[serde(rename = "tab_angle")]
@@ -141,6 +197,10 @@ pub struct PaperOptions {
     pub fold_line_width: f32, //only for folds in & out
     #[serde(default)]
     pub hidden_line_angle: f32, //degrees
+    #[serde(default)]
+    pub paper_thickness: f32, //mm
+    #[serde(default)]
+    pub thick_board: bool,
     #[serde(default = "my_true")]
     pub show_self_promotion: bool,
     #[serde(default = "my_true")]
@@ -149,6 +209,26 @@ pub struct PaperOptions {
     pub edge_id_font_size: f32,
     #[serde(default)]
     pub edge_id_position: EdgeIdPosition,
+    #[serde(default)]
+    pub alignment_marks: AlignmentMarks,
+    #[serde(default)]
+    pub vector_pdf: bool,
+    // A single DXF file with all the pages, instead of one per page
+    #[serde(default)]
+    pub dxf_whole_canvas: bool,
+    #[serde(default)]
+    pub print_and_cut: bool,
+    #[serde(default)]
+    pub score_creases: bool, //only with print_and_cut
+    #[serde(default = "default_pack_spacing")]
+    pub pack_spacing: f32, //mm
+    #[serde(default)]
+    pub save_undo_history: bool,
+    #[serde(default)]
+    pub island_name_with_part: bool,
+    // The model was cleaned up when imported, so it is done again when it is updated
+    #[serde(default)]
+    pub import_cleanup: bool,
 }
 
 impl Default for PaperOptions {
@@ -162,18 +242,31 @@ impl Default for PaperOptions {
             margin: (10.0, 10.0, 10.0, 10.0),
             texture: true,
             tex_filter: true,
+            bleed: 0.0,
             flap_style: FlapStyle::default(),
             fold_style: FoldStyle::default(),
+            flap_shape: FlapShape::default(),
             flap_width: 5.0,
             flap_angle: 45.0,
             fold_line_len: 4.0,
             shadow_flap_alpha: 0.0,
             fold_line_width: default_fold_line_width(),
             hidden_line_angle: 0.0,
+            paper_thickness: 0.0,
+            thick_board: false,
             show_self_promotion: true,
             show_page_number: true,
             edge_id_font_size: default_edge_id_font_size(),
             edge_id_position: EdgeIdPosition::default(),
+            alignment_marks: AlignmentMarks::default(),
+            vector_pdf: false,
+            dxf_whole_canvas: false,
+            print_and_cut: false,
+            score_creases: false,
+            pack_spacing: default_pack_spacing(),
+            save_undo_history: false,
+            island_name_with_part: false,
+            import_cleanup: false,
         }
     }
 }
@@ -185,9 +278,22 @@ pub struct PageOffset {
     pub offset: Vector2,
 }
 
+// Pieces that overlap less than this, in mm, are just touching
+const OVERLAP_EPSILON: f32 = 1e-3;
+// Folds sharper than this are inset as if they were this angle, or the inset would be huge
+const MAX_INSET_ANGLE: f32 = 170.0 * std::f32::consts::PI / 180.0;
 const PAGE_SEP: f32 = 10.0; // Currently not configurable
                             //
 impl PaperOptions {
+    // Inside texts are drawn over the texture, the bevel angles without edge-ids go inside.
+    // Outside texts too if the texture bleeds under them.
+    pub fn texts_over_texture(&self) -> bool {
+        match self.edge_id_position {
+            EdgeIdPosition::None => self.thick_board,
+            EdgeIdPosition::Outside => self.texture && self.bleed > 0.0,
+            EdgeIdPosition::Inside => true,
+        }
+    }
     pub fn page_position(&self, page: u32) -> Vector2 {
         let page_cols = self.page_cols;
         let row = page / page_cols;
@@ -223,6 +329,18 @@ impl PaperOptions {
         let zero_pos = self.row_col_position(po.row, po.col);
         zero_pos + po.offset
     }
+    // The page that contains this position, if any
+    pub fn page_of(&self, pos: Vector2) -> Option<u32> {
+        if !self.is_inside_canvas(pos) {
+            return None;
+        }
+        let PageOffset { row, col, .. } = self.global_to_page(pos);
+        if col >= self.page_cols as i32 {
+            return None;
+        }
+        let page = row as u32 * self.page_cols + col as u32;
+        (page < self.pages).then_some(page)
+    }
     pub fn is_inside_canvas(&self, pos: Vector2) -> bool {
         let page_cols = self.page_cols;
         let page_rows = (self.pages + self.page_cols - 1) / self.page_cols;
@@ -249,6 +367,8 @@ pub struct Papercraft {
     #[serde(default)] //TODO: default not actually needed
     options: PaperOptions,
     edges: Vec<EdgeStatus>, //parallel to EdgeIndex
+    #[serde(default)]
+    flap_overrides: FxHashMap<EdgeIndex, FlapOverride>,
     #[serde(with = "super::ser::slot_map")]
     islands: SlotMap<IslandKey, Island>,
 
@@ -280,6 +400,126 @@ pub struct FlapGeom {
     pub tan_1: f32,
     pub width: f32,
     pub triangular: bool,
+    pub shape: FlapShape,
+}
+
+// Number of segments of each rounded corner
+const FLAP_ROUND_STEPS: usize = 6;
+
+impl FlapGeom {
+    // The trapezoid of the flap of the edge from pos0 to pos1, all the flap shapes fit inside it.
+    // Its length is 4, or 3 if it is triangular.
+    pub fn hull(&self, pos0: Vector2, pos1: Vector2) -> Vec<Vector2> {
+        let v = pos1 - pos0;
+        let vn = v * (self.width / v.magnitude());
+        let n = Vector2::new(-vn.y, vn.x);
+        let p1 = pos0 + n + vn * self.tan_1;
+        if self.triangular {
+            vec![pos0, p1, pos1]
+        } else {
+            vec![pos0, p1, pos1 + n - vn * self.tan_0, pos1]
+        }
+    }
+    // The outline of the flap of the edge from pos0 to pos1, the same that is drawn and cut.
+    // It starts in pos0 and ends in pos1.
+    pub fn outline(&self, pos0: Vector2, pos1: Vector2) -> Vec<Vector2> {
+        let hull = self.hull(pos0, pos1);
+        let w = self.width;
+        if w <= 0.0 {
+            return hull;
+        }
+        let v = pos1 - pos0;
+        let n = Vector2::new(-v.y, v.x) / v.magnitude();
+        // The top of the flap is a single point if it is triangular
+        let (top_0, top_1) = (hull[1], hull[hull.len() - 2]);
+        let top_len = top_0.distance(top_1);
+
+        let mut res = vec![pos0];
+        match self.shape {
+            FlapShape::Trapezoid | FlapShape::Triangle => return hull,
+            FlapShape::Notched | FlapShape::ZigZag if self.triangular => return hull,
+            FlapShape::Rounded => {
+                // Each corner of the top is replaced by a quadratic Bézier curve
+                let r = w / 2.0;
+                let corners = if self.triangular {
+                    vec![(pos0, top_0, pos1)]
+                } else {
+                    vec![(pos0, top_0, top_1), (top_0, top_1, pos1)]
+                };
+                for (a, c, b) in corners {
+                    let p0 = c + (a - c) * (r / a.distance(c)).min(0.5);
+                    let p1 = c + (b - c) * (r / b.distance(c)).min(0.5);
+                    for i in 0..=FLAP_ROUND_STEPS {
+                        let t = i as f32 / FLAP_ROUND_STEPS as f32;
+                        res.push(p0 * (1.0 - t) * (1.0 - t) + c * 2.0 * t * (1.0 - t) + p1 * t * t);
+                    }
+                }
+            }
+            FlapShape::Notched => {
+                // V-shaped notches in the top, so that it bends along curved seams
+                let count = ((top_len / (1.5 * w)) as usize).min(32);
+                let step = top_len / (count + 1) as f32;
+                let half = (w / 4.0).min(step / 4.0);
+                let dir = (top_1 - top_0) / top_len;
+                res.push(top_0);
+                for i in 1..=count {
+                    let c = top_0 + dir * (step * i as f32);
+                    res.extend([c - dir * half, c - n * (0.75 * w), c + dir * half]);
+                }
+                res.push(top_1);
+            }
+            FlapShape::ZigZag => {
+                let teeth = (top_len / w).round().clamp(1.0, 64.0) as usize;
+                for i in 0..=2 * teeth {
+                    let p = top_0 + (top_1 - top_0) * (i as f32 / (2 * teeth) as f32);
+                    res.push(if i % 2 == 0 { p } else { p - n * (w / 2.0) });
+                }
+            }
+            FlapShape::TabAndSlot => {
+                let ([neck_0, neck_1], [barb_0, barb_1]) = self.tab_neck(pos0, pos1);
+                res.extend([neck_0, barb_0, top_0]);
+                if !self.triangular {
+                    res.push(top_1);
+                }
+                res.extend([barb_1, neck_1]);
+            }
+        }
+        res.push(pos1);
+        // Very short flaps may repeat some points
+        res.dedup_by(|a, b| a.distance2(*b) < 1e-8);
+        res
+    }
+    // The slit of a tab-and-slot flap, that is printed in the face at the other side of the edge.
+    // `pos0` and `pos1` are the edge as seen from the face with the flap, but in the paper
+    // coordinates of the other face.
+    pub fn slot(&self, pos0: Vector2, pos1: Vector2) -> Option<(Vector2, Vector2)> {
+        if self.shape != FlapShape::TabAndSlot || self.width <= 0.0 {
+            return None;
+        }
+        let ([neck_0, neck_1], _) = self.tab_neck(pos0, pos1);
+        Some((neck_0, neck_1))
+    }
+    // The narrowest part of the tab, as wide as the slit, and the barbs just above it
+    fn tab_neck(&self, pos0: Vector2, pos1: Vector2) -> ([Vector2; 2], [Vector2; 2]) {
+        let hull = self.hull(pos0, pos1);
+        let (top_0, top_1) = (hull[1], hull[hull.len() - 2]);
+        let w = self.width;
+        let u = (pos1 - pos0).normalize();
+        // The points of the sides of the trapezoid at height `h`
+        let side = |h: f32| {
+            [
+                pos0 + (top_0 - pos0) * (h / w),
+                pos1 + (top_1 - pos1) * (h / w),
+            ]
+        };
+        let depth = 0.4 * w;
+        let [s0, s1] = side(depth);
+        let inset = (0.2 * w).min(s0.distance(s1) / 4.0);
+        let neck = [s0 + u * inset, s1 - u * inset];
+        // The barbs are just above the neck, so that they are wider than it unless the flap
+        // angle is very small
+        (neck, side(depth + inset / 4.0))
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -307,7 +547,7 @@ struct Memoization {
     flat_face_flap_dimensions:
         RefCell<FxHashMap<IslandKey, FxHashMap<(FaceIndex, EdgeIndex), FlapGeom>>>,
 
-    // This depends on the islands, but not on the options
+    // This depends on the islands, and on the options because of the paper thickness
     island_perimeters: RefCell<FxHashMap<IslandKey, Vec<FlapEdgeData>>>,
 }
 
@@ -315,6 +555,7 @@ impl Memoization {
     fn invalidate_options(&self) {
         self.face_to_face_edge_matrix.borrow_mut().clear();
         self.flat_face_flap_dimensions.borrow_mut().clear();
+        self.island_perimeters.borrow_mut().clear();
     }
     fn invalidate_islands(&self, islands: &[IslandKey]) {
         self.island_by_face.borrow_mut().clear();
@@ -334,6 +575,7 @@ impl Papercraft {
             model: Model::empty(),
             options: PaperOptions::default(),
             edges: Vec::new(),
+            flap_overrides: FxHashMap::default(),
             islands: SlotMap::with_key(),
             memo: Memoization::default(),
             edge_ids: Vec::new(),
@@ -346,6 +588,14 @@ impl Papercraft {
     pub fn options(&self) -> &PaperOptions {
         &self.options
     }
+    // How far the texture goes beyond the cuts, if it is printed at all
+    pub fn texture_bleed(&self) -> f32 {
+        if self.options.texture && self.model.has_textures() {
+            self.options.bleed.max(0.0)
+        } else {
+            0.0
+        }
+    }
     // Returns the old options
     pub fn set_options(
         &mut self,
@@ -387,70 +637,18 @@ impl Papercraft {
 
         options
     }
+    // Rotates or mirrors the model in place, the islands and the edges are kept.
+    pub fn transform_model(&mut self, mx: &Matrix3) {
+        self.model.transform(mx);
+        // The flat faces of a mirrored model are mirrored too, so nothing is valid
+        self.memo = Memoization::default();
+    }
     pub fn islands(&self) -> impl Iterator<Item = (IslandKey, &Island)> + '_ {
         self.islands.iter()
     }
     pub fn num_islands(&self) -> usize {
         self.islands.len()
     }
-    pub fn island_bounding_box_angle(
-        &self,
-        island: &Island,
-        angle: Rad<f32>,
-    ) -> (Vector2, Vector2) {
-        let mx = island.matrix() * Matrix3::from(Matrix2::from_angle(angle));
-        let mut vx = Vec::new();
-        traverse_faces_ex(
-            &self.model,
-            island.root_face(),
-            mx,
-            NormalTraverseFace(self),
-            |_, face, mx| {
-                let vs = face.index_vertices().map(|v| {
-                    let normal = self.model.face_plane(face);
-                    mx.transform_point(Point2::from_vec(
-                        normal.project(&self.model[v].pos(), self.options.scale),
-                    ))
-                    .to_vec()
-                });
-                vx.extend(vs);
-                ControlFlow::Continue(())
-            },
-        );
-
-        let (a, b) = util_3d::bounding_box_2d(vx);
-        let m = self.options.flap_width;
-        let mm = Vector2::new(m, m);
-        (a - mm, b + mm)
-    }
-    pub fn island_best_bounding_box(&self, island: &Island) -> (Rad<f32>, (Vector2, Vector2)) {
-        const TRIES: i32 = 60;
-
-        fn bbox_weight(bb: (Vector2, Vector2)) -> f32 {
-            let d = bb.1 - bb.0;
-            d.y
-        }
-
-        let delta_a = Rad::full_turn() / TRIES as f32;
-
-        let mut best_angle = Rad::zero();
-        let mut best_bb = self.island_bounding_box_angle(island, best_angle);
-        let mut best_width = bbox_weight(best_bb);
-
-        let mut angle2 = delta_a;
-        for _ in 1..TRIES {
-            let bb2 = self.island_bounding_box_angle(island, angle2);
-            let width2 = bbox_weight(bb2);
-
-            if width2 < best_width {
-                best_width = width2;
-                best_angle = angle2;
-                best_bb = bb2;
-            }
-            angle2 += delta_a;
-        }
-        (best_angle, best_bb)
-    }
     pub fn island_by_face(&self, i_face: FaceIndex) -> IslandKey {
         // Try to use a memoized value
         let mut memo = self.memo.island_by_face.borrow_mut();
@@ -478,6 +676,12 @@ impl Papercraft {
         self.islands.get_mut(key)
     }
     pub fn rebuild_island_names(&mut self) {
+        for (i_island, name) in self.island_names() {
+            self.islands[i_island].name = name;
+        }
+    }
+    // The names that `rebuild_island_names` would give to the islands right now
+    pub fn island_names(&self) -> Vec<(IslandKey, String)> {
         // To get somewhat predictable names try to sort the islands before naming them.
         // For now, sort them by number of faces.
         let mut islands: Vec<_> = self
@@ -501,11 +705,28 @@ impl Papercraft {
             name.push(b'A');
         }
 
-        let mut island_name = Vec::new();
+        // With parts each one has its own sequence of names, prefixed with the name of the part
+        let with_part = self.options.island_name_with_part && self.model.num_parts() > 0;
+        let mut island_names = vec![Vec::new(); self.model.num_parts().max(1)];
+        let mut res = Vec::with_capacity(islands.len());
         for (i_island, _) in &islands {
-            next_name(&mut island_name);
-            self.islands[*i_island].name = String::from_utf8(island_name.clone()).unwrap();
+            let island = &self.islands[*i_island];
+            let i_part = if with_part {
+                self.model[island.root_face()].part()
+            } else {
+                PartIndex::from(0)
+            };
+            let island_name = &mut island_names[usize::from(i_part)];
+            next_name(island_name);
+            let name = std::str::from_utf8(island_name).unwrap();
+            let name = if with_part {
+                format!("{}-{name}", self.model.part_name(i_part))
+            } else {
+                name.to_owned()
+            };
+            res.push((*i_island, name));
         }
+        res
     }
 
     pub fn edge_status(&self, edge: EdgeIndex) -> EdgeStatus {
@@ -533,6 +754,50 @@ impl Papercraft {
         }
     }
 
+    pub fn flap_override(&self, i_edge: EdgeIndex) -> FlapOverride {
+        self.flap_overrides
+            .get(&i_edge)
+            .copied()
+            .unwrap_or_default()
+    }
+    // Returns the old value
+    pub fn set_flap_override(
+        &mut self,
+        i_edge: EdgeIndex,
+        flap_override: FlapOverride,
+    ) -> FlapOverride {
+        let prev = if flap_override.is_empty() {
+            self.flap_overrides.remove(&i_edge)
+        } else {
+            self.flap_overrides.insert(i_edge, flap_override)
+        };
+        // The flap may be in any of both sides
+        let (i_face_a, i_face_b) = self.model[i_edge].faces();
+        let islands: Vec<_> = [Some(i_face_a), i_face_b]
+            .into_iter()
+            .flatten()
+            .map(|i_face| self.island_by_face(i_face))
+            .collect();
+        self.memo.invalidate_islands(&islands);
+        prev.unwrap_or_default()
+    }
+    pub fn flap_width(&self, i_edge: EdgeIndex) -> f32 {
+        self.flap_override(i_edge)
+            .width
+            .unwrap_or(self.options.flap_width)
+    }
+    pub fn flap_angle(&self, i_edge: EdgeIndex) -> Deg<f32> {
+        Deg(self
+            .flap_override(i_edge)
+            .angle
+            .unwrap_or(self.options.flap_angle))
+    }
+    pub fn flap_shape(&self, i_edge: EdgeIndex) -> FlapShape {
+        self.flap_override(i_edge)
+            .shape
+            .unwrap_or(self.options.flap_shape)
+    }
+
     pub fn edge_cut(&mut self, i_edge: EdgeIndex, offset: Option<f32>) {
         match self.edges[usize::from(i_edge)] {
             EdgeStatus::Joined => {}
@@ -631,6 +896,7 @@ impl Papercraft {
         // Join both islands
         let i_island_a = self.island_by_face(i_face_a);
         self.memo.invalidate_islands(&[i_island_a, i_island_b]);
+        let prev_flap_override = self.flap_overrides.remove(&i_edge).unwrap_or_default();
         let mut island_b = self.islands.remove(i_island_b).unwrap();
 
         // Keep position of a or b?
@@ -645,6 +911,7 @@ impl Papercraft {
                 prev_root: island_b.root_face(),
                 prev_rot: island_b.rotation(),
                 prev_loc: island_b.location(),
+                prev_flap_override,
             },
         );
         self.edges[usize::from(i_edge)] = EdgeStatus::Joined;
@@ -698,6 +965,19 @@ impl Papercraft {
         });
         area
     }
+    // The bounding box of the island as drawn in the paper, flaps included
+    pub fn island_bounding_box(&self, i_island: IslandKey) -> (Vector2, Vector2) {
+        let mut vs = Vec::new();
+        if let Some(island) = self.island_by_key(i_island) {
+            self.traverse_faces(island, |i_face, face, mx| {
+                for part in self.face_part_polygons(i_island, i_face, face, mx) {
+                    vs.extend(part.vs);
+                }
+                ControlFlow::Continue(())
+            });
+        }
+        util_3d::bounding_box_2d(vs)
+    }
     pub fn get_flat_faces(&self, i_face: FaceIndex) -> FxHashSet<FaceIndex> {
         let mut res = FxHashSet::default();
         traverse_faces_ex(
@@ -712,6 +992,29 @@ impl Papercraft {
         );
         res
     }
+    // The vertices of the contour of a flat face, in order, without the hidden edges inside.
+    // None if the contour is not a single loop, such as a flat face with a hole.
+    pub fn flat_face_contour(&self, flat_face: &FxHashSet<FaceIndex>) -> Option<Vec<VertexIndex>> {
+        let mut flat_contour: Vec<_> = flat_face
+            .iter()
+            .flat_map(|&f| self.model[f].vertices_with_edges())
+            .filter_map(|(i_v0, i_v1, e)| {
+                if self.edge_status(e) == EdgeStatus::Hidden {
+                    None
+                } else {
+                    Some((i_v0, i_v1))
+                }
+            })
+            .collect();
+        let mut contour = Vec::with_capacity(flat_contour.len());
+        let mut next = flat_contour.len().checked_sub(1);
+        while let Some(pos) = next {
+            let (i_v0, i_v1) = flat_contour.remove(pos);
+            contour.push(i_v0);
+            next = flat_contour.iter().position(|(i_x0, _)| i_v1 == *i_x0);
+        }
+        flat_contour.is_empty().then_some(contour)
+    }
     fn get_flat_faces_with_matrix(&self, i_face: FaceIndex) -> FxHashMap<FaceIndex, Matrix3> {
         let mut res = FxHashMap::default();
         traverse_faces_ex(
@@ -761,6 +1064,137 @@ impl Papercraft {
         let mabt1 = Matrix3::from_translation(a0);
         mabt1 * mabr * mabt0
     }
+    // How much the faces are moved inwards at a fold, because of the paper thickness.
+    // Mountain folds make the faces smaller, valley folds make them bigger.
+    pub fn fold_inset(&self, i_edge: EdgeIndex) -> f32 {
+        let thickness = self.options.paper_thickness;
+        if thickness <= 0.0 || self.edge_status(i_edge) != EdgeStatus::Joined {
+            return 0.0;
+        }
+        let angle = self.model[i_edge].angle().0;
+        let angle = angle.clamp(-MAX_INSET_ANGLE, MAX_INSET_ANGLE);
+        thickness / 2.0 * (angle / 2.0).tan()
+    }
+    // Like face_to_face_edge_matrix(), but if the edge is a fold it moves face_b so that both
+    // faces, inset by fold_inset(), meet at the fold.
+    pub fn fold_matrix(&self, edge: &Edge, face_a: &Face, face_b: &Face) -> Matrix3 {
+        let medge = self.face_to_face_edge_matrix(edge, face_a, face_b);
+        let i_edge = self.model.edge_index(edge);
+        let inset = self.fold_inset(i_edge);
+        if inset == 0.0 {
+            return medge;
+        }
+        let scale = self.options.scale;
+        let plane_a = self.model.face_plane(face_a);
+        let (v0, v1) = self.model.edge_pos(edge);
+        let a0 = plane_a.project(&v0, scale);
+        let a1 = plane_a.project(&v1, scale);
+        let a2 = plane_a.project(&self.model[face_a.opposite_edge(i_edge)].pos(), scale);
+        // n points out of face_a
+        let v = a1 - a0;
+        let mut n = Vector2::new(-v.y, v.x).normalize();
+        if n.dot(a2 - a0) > 0.0 {
+            n = -n;
+        }
+        Matrix3::from_translation(-n * (2.0 * inset)) * medge
+    }
+    // The vertices of the face, projected into its plane, with each fold moved inwards by
+    // fold_inset(). Without paper thickness it is just the projection.
+    pub fn face_vertices_inset(&self, face: &Face) -> [Vector2; 3] {
+        let scale = self.options.scale;
+        let plane = self.model.face_plane(face);
+        let ps = face
+            .index_vertices()
+            .map(|i_v| plane.project(&self.model[i_v].pos(), scale));
+        if self.options.paper_thickness <= 0.0 {
+            return ps;
+        }
+        let i_face = self.model.face_index(face);
+        std::array::from_fn(|i| {
+            let i_v = face.index_vertices()[i];
+            // The two sides of the flat-face that touch this vertex, moved inwards.
+            // The flat-face is coplanar so every side is projected with this same plane.
+            let sides = self.flat_face_sides_at(i_face, i_v).map(|sides| {
+                sides.map(|(i_face_s, i_edge)| {
+                    let edge = &self.model[i_edge];
+                    let (v0, v1) = self.model.edge_pos(edge);
+                    let p0 = plane.project(&v0, scale);
+                    let p1 = plane.project(&v1, scale);
+                    let i_opposite = self.model[i_face_s].opposite_edge(i_edge);
+                    let p2 = plane.project(&self.model[i_opposite].pos(), scale);
+                    let v = p1 - p0;
+                    let mut n = Vector2::new(-v.y, v.x).normalize();
+                    if n.dot(p2 - p0) < 0.0 {
+                        n = -n;
+                    }
+                    (n, self.fold_inset(i_edge))
+                })
+            });
+            let Some([(n0, d0), (n1, d1)]) = sides else {
+                return ps[i];
+            };
+            if d0 == 0.0 && d1 == 0.0 {
+                return ps[i];
+            }
+            // The vertex goes to the crossing of both moved sides, unless they are parallel
+            let m = Matrix2::new(n0.x, n1.x, n0.y, n1.y);
+            if m.determinant().abs() < 1e-3 {
+                return ps[i] + n0 * ((d0 + d1) / 2.0);
+            }
+            match m.invert() {
+                Some(m_inv) => ps[i] + m_inv * Vector2::new(d0, d1),
+                None => ps[i],
+            }
+        })
+    }
+    // Goes around the vertex i_v of a face, crossing the hidden edges, looking for the sides of
+    // the flat-face that touch it. Returns pairs of (face, edge).
+    // If the vertex is inside of the flat-face it returns None.
+    fn flat_face_sides_at(
+        &self,
+        i_face: FaceIndex,
+        i_v: VertexIndex,
+    ) -> Option<[(FaceIndex, EdgeIndex); 2]> {
+        let mut sides = self.model[i_face]
+            .vertices_with_edges()
+            .filter(|&(v0, v1, _)| v0 == i_v || v1 == i_v)
+            .map(|(_, _, i_edge)| {
+                let (mut i_face_s, mut i_edge_s) = (i_face, i_edge);
+                // Limit the walk, just in case the model is not manifold
+                for _ in 0..64 {
+                    if self.edge_status(i_edge_s) != EdgeStatus::Hidden {
+                        return Some((i_face_s, i_edge_s));
+                    }
+                    i_face_s = match self.model[i_edge_s].faces() {
+                        (fa, Some(fb)) if fa == i_face_s => fb,
+                        (fa, Some(_)) => fa,
+                        _ => return None,
+                    };
+                    if i_face_s == i_face {
+                        return None;
+                    }
+                    i_edge_s = self.model[i_face_s]
+                        .vertices_with_edges()
+                        .find(|&(v0, v1, e)| (v0 == i_v || v1 == i_v) && e != i_edge_s)?
+                        .2;
+                }
+                None
+            });
+        Some([sides.next()??, sides.next()??])
+    }
+    // Cuts every fold, so that each flat face is a panel by itself
+    pub fn split_into_panels(&mut self) -> Vec<EdgeIndex> {
+        let folds: Vec<_> = self
+            .model
+            .edges()
+            .map(|(i_edge, _)| i_edge)
+            .filter(|&i_edge| self.edge_status(i_edge) == EdgeStatus::Joined)
+            .collect();
+        for &i_edge in &folds {
+            self.edge_cut(i_edge, None);
+        }
+        folds
+    }
     // Returns the max. angles of the flap sides, actually their cotangent, and the max. width.
     // Ideally it should return all the flap metrics
     pub fn flat_face_flap_dimensions(
@@ -804,7 +1238,8 @@ impl Papercraft {
     ) -> FlapGeom {
         // Compute the flat-face_b contour
         let scale = self.options.scale;
-        let flap_angle = Rad::from(Deg(self.options.flap_angle));
+        let flap_angle = Rad::from(self.flap_angle(i_edge));
+        let flap_width = self.flap_width(i_edge);
         let mut a0 = flap_angle;
         let mut a1 = flap_angle;
 
@@ -915,7 +1350,7 @@ impl Papercraft {
         // what happens
 
         let compute_width = |a0: Rad<f32>, a1: Rad<f32>| -> f32 {
-            let mut minimum_width = self.options.flap_width;
+            let mut minimum_width = flap_width;
             let (flap_sin_0, flap_cos_0) = a0.sin_cos();
             let normal_0 = Vector2::new(
                 n.x * flap_sin_0 - n.y * flap_cos_0,
@@ -1057,18 +1492,25 @@ impl Papercraft {
             // Invalid flap
             FlapGeom::default()
         } else {
-            let tan_0 = a0.cot();
-            let tan_1 = a1.cot();
+            let mut tan_0 = a0.cot();
+            let mut tan_1 = a1.cot();
             let base2 = base_len - (tan_0 + tan_1) * width;
             let triangular = base2 <= 0.0;
             if triangular {
                 width = base_len / (tan_0 + tan_1);
+            } else if self.flap_shape(i_edge) == FlapShape::Triangle {
+                // Make the sides steeper so that they meet at the same width, that is always
+                // inside the trapezium so it will not collide with anything
+                let k = base_len / ((tan_0 + tan_1) * width);
+                tan_0 *= k;
+                tan_1 *= k;
             }
             FlapGeom {
                 tan_0,
                 tan_1,
                 width,
-                triangular,
+                triangular: triangular || self.flap_shape(i_edge) == FlapShape::Triangle,
+                shape: self.flap_shape(i_edge),
             }
         }
     }
@@ -1107,8 +1549,10 @@ impl Papercraft {
                 // Rims cannot be joined
                 _ => continue,
             };
-            // Only cuts can be joined
-            if !matches!(self.edge_status(i_edge), EdgeStatus::Cut(_)) {
+            // Only cuts can be joined, and the strip does not go into another part
+            if !matches!(self.edge_status(i_edge), EdgeStatus::Cut(_))
+                || self.model.edge_between_parts(i_edge)
+            {
                 continue;
             }
 
@@ -1167,66 +1611,6 @@ impl Papercraft {
         renames
     }
 
-    pub fn pack_islands(&mut self) -> u32 {
-        let mut row_height = 0.0f32;
-        let mut pos_x = 0.0;
-        let mut pos_y = 0.0;
-        let mut num_in_row = 0;
-
-        let mut page = 0;
-        let page_margin = Vector2::new(self.options.margin.1, self.options.margin.0);
-        let page_size = Vector2::new(
-            self.options.page_size.0 - self.options.margin.1 - self.options.margin.2,
-            self.options.page_size.1 - self.options.margin.0 - self.options.margin.3,
-        );
-        let mut zero = self.options().page_position(page) + page_margin;
-
-        // The island position cannot be updated while iterating
-        let mut positions = slotmap::SecondaryMap::<IslandKey, (Rad<f32>, Vector2)>::new();
-
-        let mut ordered_islands: Vec<_> = self
-            .islands
-            .iter()
-            .map(|(i_island, island)| {
-                let (angle, bbox) = self.island_best_bounding_box(island);
-                (i_island, angle, bbox)
-            })
-            .collect();
-        ordered_islands.sort_by_key(|(_, _, bbox)| {
-            let w = bbox.1.x - bbox.0.x;
-            let h = bbox.1.y - bbox.0.y;
-            -(w * h) as i64
-        });
-
-        for (i_island, angle, bbox) in ordered_islands {
-            let mut next_pos_x = pos_x + bbox.1.x - bbox.0.x;
-            if next_pos_x > page_size.x && num_in_row > 0 {
-                next_pos_x -= pos_x;
-                pos_x = 0.0;
-                pos_y += row_height;
-                row_height = 0.0;
-                num_in_row = 0;
-                if pos_y > page_size.y {
-                    pos_y = 0.0;
-                    page += 1;
-                    zero = self.options().page_position(page) + page_margin;
-                }
-            }
-            let pos = Vector2::new(pos_x - bbox.0.x, pos_y - bbox.0.y);
-            pos_x = next_pos_x;
-            row_height = row_height.max(bbox.1.y - bbox.0.y);
-            num_in_row += 1;
-
-            positions.insert(i_island, (angle, zero + pos));
-        }
-        for (i_island, (angle, pos)) in positions {
-            let island = self.island_by_key_mut(i_island).unwrap();
-            island.loc += pos;
-            island.rot += angle;
-            island.recompute_matrix();
-        }
-        page + 1
-    }
     // Returns the ((face, area), total_area)
     pub fn get_biggest_flat_face(&self, island: &Island) -> (Vec<(FaceIndex, f32)>, f32) {
         let mut biggest_face = None;
@@ -1269,17 +1653,13 @@ impl Papercraft {
 
     fn island_perimeter_internal(&self, island_key: IslandKey) -> Vec<FlapEdgeData> {
         let island = self.island_by_key(island_key).unwrap();
-        let scale = self.options.scale;
         let mut perimeter = Vec::new();
 
         self.traverse_faces(island, |i_face, face, mx| {
-            let plane = self.model.face_plane(face);
-            let tr = |v: VertexIndex| {
-                let v = &self.model[v];
-                let v = plane.project(&v.pos(), scale);
-                mx.transform_point(Point2::from_vec(v)).to_vec()
-            };
-            for (i_v0, i_v1, i_e) in face.vertices_with_edges() {
+            let ps = self
+                .face_vertices_inset(face)
+                .map(|p| mx.transform_point(Point2::from_vec(p)).to_vec());
+            for (i, (i_v0, i_v1, i_e)) in face.vertices_with_edges().enumerate() {
                 let e = self.edge_status(i_e);
                 if !matches!(e, EdgeStatus::Cut(_)) {
                     // we want the cut-contour, without flaps
@@ -1290,8 +1670,8 @@ impl Papercraft {
                     i_edge: i_e,
                     i_v0,
                     i_v1,
-                    p0: tr(i_v0),
-                    p1: tr(i_v1),
+                    p0: ps[i],
+                    p1: ps[(i + 1) % 3],
                 };
                 perimeter.push(edata);
             }
@@ -1423,7 +1803,7 @@ impl TraverseFacePolicy for NormalTraverseFace<'_> {
         i_next_face: FaceIndex,
     ) -> Self::State {
         let next_face = &self.0.model[i_next_face];
-        let medge = self.0.face_to_face_edge_matrix(edge, face, next_face);
+        let medge = self.0.fold_matrix(edge, face, next_face);
         st * medge
     }
 }
@@ -1612,6 +1992,41 @@ impl<'de> Deserialize<'de> for FlapStyle {
     }
 }
 
+impl Serialize for FlapShape {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let is = match self {
+            FlapShape::Trapezoid => 0,
+            FlapShape::Triangle => 1,
+            FlapShape::Rounded => 2,
+            FlapShape::Notched => 3,
+            FlapShape::ZigZag => 4,
+            FlapShape::TabAndSlot => 5,
+        };
+        serializer.serialize_i32(is)
+    }
+}
+impl<'de> Deserialize<'de> for FlapShape {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let d = u32::deserialize(deserializer)?;
+        let res = match d {
+            0 => FlapShape::Trapezoid,
+            1 => FlapShape::Triangle,
+            2 => FlapShape::Rounded,
+            3 => FlapShape::Notched,
+            4 => FlapShape::ZigZag,
+            5 => FlapShape::TabAndSlot,
+            _ => return Err(serde::de::Error::missing_field("invalid flap shape value")),
+        };
+        Ok(res)
+    }
+}
+
 impl Serialize for FoldStyle {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -1647,6 +2062,39 @@ impl<'de> Deserialize<'de> for FoldStyle {
     }
 }
 
+impl Serialize for AlignmentMarks {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let is = match self {
+            AlignmentMarks::None => 0,
+            AlignmentMarks::Midpoint => 1,
+            AlignmentMarks::Fractions => 2,
+        };
+        serializer.serialize_i32(is)
+    }
+}
+impl<'de> Deserialize<'de> for AlignmentMarks {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let d = u32::deserialize(deserializer)?;
+        let res = match d {
+            0 => AlignmentMarks::None,
+            1 => AlignmentMarks::Midpoint,
+            2 => AlignmentMarks::Fractions,
+            _ => {
+                return Err(serde::de::Error::missing_field(
+                    "invalid alignment_marks value",
+                ))
+            }
+        };
+        Ok(res)
+    }
+}
+
 impl Serialize for EdgeIdPosition {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -5,16 +5,27 @@ use std::{
 
 use super::*;
 use anyhow::Result;
-use model::import::Importer;
+use model::import::{ImportReport, Importer};
+use serde::de::DeserializeOwned;
 
 impl Papercraft {
-    pub fn save<W: Write + Seek>(&self, w: W) -> Result<()> {
+    // The undo history is opaque here, it is just stored next to the model
+    pub fn save<W: Write + Seek, H: Serialize>(
+        &self,
+        w: W,
+        undo_history: Option<&H>,
+    ) -> Result<()> {
         let mut zip = zip::ZipWriter::new(w);
         let options = zip::write::FileOptions::default();
 
         zip.start_file("model.json", options)?;
         serde_json::to_writer(&mut zip, self)?;
 
+        if let Some(undo_history) = undo_history {
+            zip.start_file("undo.json", options)?;
+            serde_json::to_writer(&mut zip, undo_history)?;
+        }
+
         for tex in self.model.textures() {
             if let Some(pixbuf) = tex.pixbuf() {
                 let file_name = tex.file_name();
@@ -32,6 +43,28 @@ impl Papercraft {
 
     pub fn load<R: Read + Seek>(r: R) -> Result<Papercraft> {
         let mut zip = zip::ZipArchive::new(r)?;
+        Self::load_zip(&mut zip)
+    }
+    // Like `load` but also returns the undo history, if there is one.
+    // A broken history is not worth failing the whole file, it is just ignored.
+    pub fn load_with_undo_history<R: Read + Seek, H: DeserializeOwned>(
+        r: R,
+    ) -> Result<(Papercraft, Option<H>)> {
+        let mut zip = zip::ZipArchive::new(r)?;
+        let papercraft = Self::load_zip(&mut zip)?;
+        let undo_history = match zip.by_name("undo.json") {
+            Ok(zundo) => match serde_json::from_reader(zundo) {
+                Ok(h) => Some(h),
+                Err(e) => {
+                    log::warn!("Error loading the undo history: {e}");
+                    None
+                }
+            },
+            Err(_) => None,
+        };
+        Ok((papercraft, undo_history))
+    }
+    fn load_zip<R: Read + Seek>(zip: &mut zip::ZipArchive<R>) -> Result<Papercraft> {
         let mut zmodel = zip.by_name("model.json")?;
         let mut papercraft: Papercraft = serde_json::from_reader(&mut zmodel)?;
         drop(zmodel);
@@ -86,8 +119,8 @@ impl Papercraft {
         self.edge_ids = edge_ids;
     }
 
-    pub fn import<I: Importer>(mut importer: I) -> Papercraft {
-        let (model, face_map, edge_map) = Model::from_importer(&mut importer);
+    pub fn import<I: Importer>(mut importer: I, report: &mut ImportReport) -> Papercraft {
+        let (model, face_map, edge_map) = Model::from_importer(&mut importer, report);
 
         let edges: Vec<_> = edge_map
             .iter()
@@ -111,10 +144,15 @@ impl Papercraft {
                             _ => EdgeStatus::Cut(FlapSide::Hidden),
                         }
                     }
-                    // Normal edge
-                    _ => importer
-                        .compute_edge_status(*edge_id)
-                        .unwrap_or(EdgeStatus::Cut(FlapSide::False)),
+                    // Normal edge, but never joined between two parts
+                    _ => match importer.compute_edge_status(*edge_id) {
+                        Some(EdgeStatus::Joined | EdgeStatus::Hidden)
+                            if model.edge_between_parts(i_edge) =>
+                        {
+                            EdgeStatus::Cut(FlapSide::False)
+                        }
+                        status => status.unwrap_or(EdgeStatus::Cut(FlapSide::False)),
+                    },
                 }
             })
             .collect();
@@ -162,14 +200,13 @@ impl Papercraft {
             model,
             options,
             edges,
+            flap_overrides: FxHashMap::default(),
             islands,
             memo: Memoization::default(),
             edge_ids: Vec::new(),
         };
         if need_fix_options {
-            let (v_min, v_max) = crate::util_3d::bounding_box_3d(
-                papercraft.model().vertices().map(|(_, v)| v.pos()),
-            );
+            let (v_min, v_max) = papercraft.model().bounding_box();
             let size = (v_max.x - v_min.x)
                 .max(v_max.y - v_min.y)
                 .max(v_max.z - v_min.z);
@@ -189,13 +226,23 @@ impl Papercraft {
             papercraft.options.scale = scale;
         }
         if need_packing {
-            let num_pages = papercraft.pack_islands();
+            let num_pages = if papercraft.model.num_faces() > pack::PACK_ON_IMPORT_MAX_FACES {
+                papercraft.pack_islands_in_rows()
+            } else {
+                papercraft.pack_islands(false)
+            };
             papercraft.options.pages = num_pages;
         }
         papercraft.recompute_edge_ids();
         papercraft
     }
 
+    pub fn export_pdo(&self, file_name: &Path) -> Result<()> {
+        let f = std::fs::File::create(file_name)?;
+        let f = std::io::BufWriter::new(f);
+        model::import::pepakura::export_pdo(self, f)
+    }
+
     pub fn export_waveobj(&self, file_name: &Path) -> Result<()> {
         use std::collections::hash_map::Entry;
         use std::io::prelude::*;
@@ -342,7 +389,7 @@ impl Papercraft {
         }
         writeln!(f, "s 0")?;
 
-        let by_mat = if has_textures {
+        let mut by_mat = if has_textures {
             let mut by_mat = vec![Vec::new(); self.model.num_textures()];
             for (i_face, face) in self.model.faces() {
                 by_mat[usize::from(face.material())].push(i_face);
@@ -351,6 +398,13 @@ impl Papercraft {
         } else {
             vec![(0..self.model.num_faces()).map(FaceIndex::from).collect()]
         };
+        // Keep the faces of each part together, so that each one is a single group
+        let has_parts = self.model.num_parts() > 0;
+        if has_parts {
+            for faces in &mut by_mat {
+                faces.sort_by_key(|&i_face| usize::from(self.model[i_face].part()));
+            }
+        }
 
         // We iterate over the triangles, but export the flat-face, we have to skip duplicated
         // triangles. If one flat-face uses two different materials, that will not be properly
@@ -363,40 +417,40 @@ impl Papercraft {
             if has_textures {
                 writeln!(f, "usemtl Material.{i_mat:03}")?;
             }
+            let mut last_part = None;
             for &i_face in face_by_mat {
                 if done_faces[usize::from(i_face)] {
                     continue;
                 }
+                let part = self.model[i_face].part();
+                if has_parts && last_part != Some(part) {
+                    writeln!(f, "g {}", self.model.part_name(part))?;
+                    last_part = Some(part);
+                }
                 //In model, faces are all triangles, group them by flatness
                 let flat_face = self.get_flat_faces(i_face);
-                let mut flat_contour: Vec<_> = flat_face
-                    .iter()
-                    .map(|&f| {
-                        done_faces[usize::from(f)] = true;
-                        f
-                    })
-                    .flat_map(|f| self.model()[f].vertices_with_edges())
-                    .filter_map(|(i_v0, i_v1, e)| {
-                        if self.edge_status(e) == EdgeStatus::Hidden {
-                            None
-                        } else {
-                            Some((i_v0, i_v1))
-                        }
-                    })
-                    .collect();
-                write!(f, "f")?;
-                let mut next = Some(flat_contour.len() - 1);
-                while let Some(pos) = next {
-                    let vertex = flat_contour.remove(pos);
-                    let (i_v0, i_v1) = vertex;
-                    let v0 = vertex_map[usize::from(i_v0)];
-                    let vx = &self.model()[i_v0];
-                    let t = index_vt[&index_vector2(&vx.uv())];
-                    let n = index_vn[&index_vector3(&vx.normal())];
-                    write!(f, " {v0}/{t}/{n}")?;
-                    next = flat_contour.iter().position(|(i_x0, _)| i_v1 == *i_x0);
+                for &f in &flat_face {
+                    done_faces[usize::from(f)] = true;
+                }
+                // If the contour is not a single polygon, write the triangles instead
+                let polygons = match self.flat_face_contour(&flat_face) {
+                    Some(contour) => vec![contour],
+                    None => flat_face
+                        .iter()
+                        .map(|&f| self.model[f].index_vertices().to_vec())
+                        .collect(),
+                };
+                for polygon in polygons {
+                    write!(f, "f")?;
+                    for i_v0 in polygon {
+                        let v0 = vertex_map[usize::from(i_v0)];
+                        let vx = &self.model()[i_v0];
+                        let t = index_vt[&index_vector2(&vx.uv())];
+                        let n = index_vn[&index_vector3(&vx.normal())];
+                        write!(f, " {v0}/{t}/{n}")?;
+                    }
+                    writeln!(f)?;
                 }
-                writeln!(f)?;
             }
         }
         drop(f);
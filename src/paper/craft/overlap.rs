@@ -0,0 +1,163 @@
+use super::*;
+
+// A piece of an island, as it is drawn in the paper
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IslandPart {
+    Face(FaceIndex),
+    // The face that has the flap, and the edge of the flap
+    Flap(FaceIndex, EdgeIndex),
+}
+
+impl IslandPart {
+    pub fn face(self) -> FaceIndex {
+        match self {
+            IslandPart::Face(i_face) | IslandPart::Flap(i_face, _) => i_face,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    pub i_island: IslandKey,
+    pub part: IslandPart,
+    // The other part that overlaps this one, or None if this one is out of its page
+    pub other: Option<(IslandKey, IslandPart)>,
+    // The names of the islands, as they would be printed, the second one only if `other` is set
+    pub names: (String, String),
+    // The area of the conflict, in paper coordinates
+    pub bbox: (Vector2, Vector2),
+}
+
+pub(super) struct PartPolygon {
+    i_island: IslandKey,
+    part: IslandPart,
+    pub(super) vs: Vec<Vector2>,
+    bbox: (Vector2, Vector2),
+}
+
+impl Papercraft {
+    // Looks for overlapping pieces and for pieces that are not fully inside a page.
+    // Unlike the "Highlight overlaps" option, this is done with the real geometry, so it does
+    // not depend on the zoom, nor on the GL context.
+    // The island names are computed here, the ones stored in the islands may be outdated.
+    pub fn find_conflicts(&self) -> Vec<Conflict> {
+        let names: FxHashMap<_, _> = self.island_names().into_iter().collect();
+        let name = |i_island| names.get(&i_island).cloned().unwrap_or_default();
+        let mut polygons = Vec::new();
+        for (i_island, island) in self.islands() {
+            self.traverse_faces(island, |i_face, face, mx| {
+                polygons.extend(self.face_part_polygons(i_island, i_face, face, mx));
+                ControlFlow::Continue(())
+            });
+        }
+
+        let mut conflicts = Vec::new();
+
+        // Pieces out of the pages, only one for each island, or it will be too noisy
+        let mut out_islands = FxHashSet::default();
+        for p in &polygons {
+            if out_islands.contains(&p.i_island) || self.polygon_in_page(&p.vs) {
+                continue;
+            }
+            out_islands.insert(p.i_island);
+            conflicts.push(Conflict {
+                i_island: p.i_island,
+                part: p.part,
+                other: None,
+                names: (name(p.i_island), String::new()),
+                bbox: p.bbox,
+            });
+        }
+
+        // The texture bleed of a piece must not be printed over another island
+        let bleed = self.texture_bleed();
+
+        // Sweep along the X axis, so that only the nearby pieces are compared
+        polygons.sort_by(|a, b| a.bbox.0.x.total_cmp(&b.bbox.0.x));
+        for (i, a) in polygons.iter().enumerate() {
+            for b in &polygons[i + 1..] {
+                if b.bbox.0.x > a.bbox.1.x + bleed {
+                    break;
+                }
+                let gap = if a.i_island == b.i_island { 0.0 } else { bleed };
+                let bbox_a_grown = (
+                    a.bbox.0 - Vector2::new(gap, gap),
+                    a.bbox.1 + Vector2::new(gap, gap),
+                );
+                if !util_3d::bounding_boxes_overlap(bbox_a_grown, b.bbox)
+                    || !util_3d::convex_polygons_overlap(&a.vs, &b.vs, OVERLAP_EPSILON - gap)
+                {
+                    continue;
+                }
+                let (bbox_a, bbox_b) = (a.bbox, b.bbox);
+                let bbox = util_3d::bounding_box_2d([bbox_a.0, bbox_a.1, bbox_b.0, bbox_b.1]);
+                conflicts.push(Conflict {
+                    i_island: a.i_island,
+                    part: a.part,
+                    other: Some((b.i_island, b.part)),
+                    names: (name(a.i_island), name(b.i_island)),
+                    bbox,
+                });
+            }
+        }
+        conflicts
+    }
+
+    // The face itself and its visible flaps, if any
+    pub(super) fn face_part_polygons(
+        &self,
+        i_island: IslandKey,
+        i_face: FaceIndex,
+        face: &Face,
+        mx: &Matrix3,
+    ) -> Vec<PartPolygon> {
+        let ps = self
+            .face_vertices_inset(face)
+            .map(|p| mx.transform_point(Point2::from_vec(p)).to_vec());
+        let mut res = Vec::new();
+        let mut push = |part, vs: Vec<Vector2>| {
+            let bbox = util_3d::bounding_box_2d(vs.iter().copied());
+            res.push(PartPolygon {
+                i_island,
+                part,
+                vs,
+                bbox,
+            });
+        };
+        push(IslandPart::Face(i_face), ps.to_vec());
+
+        if self.options.flap_style == FlapStyle::None {
+            return res;
+        }
+        for (i, i_edge) in face.index_edges().into_iter().enumerate() {
+            let EdgeStatus::Cut(c) = self.edge_status(i_edge) else {
+                continue;
+            };
+            let edge = &self.model[i_edge];
+            if !c.flap_visible(edge.face_sign(i_face)) {
+                continue;
+            }
+            let i_face_b = match edge.faces() {
+                (fa, Some(fb)) if i_face == fb => Some(fa),
+                (_, fb) => fb,
+            };
+            let flap_geom = self.flat_face_flap_dimensions(i_face, i_face_b, i_edge);
+            push(
+                IslandPart::Flap(i_face, i_edge),
+                flap_geom.hull(ps[i], ps[(i + 1) % 3]),
+            );
+        }
+        res
+    }
+
+    fn polygon_in_page(&self, vs: &[Vector2]) -> bool {
+        let Some(&v0) = vs.first() else {
+            return true;
+        };
+        let Some(page) = self.options.page_of(v0) else {
+            return false;
+        };
+        let in_page = self.options.is_in_page_fn(page);
+        vs.iter().all(|&v| in_page(v).0)
+    }
+}
@@ -0,0 +1,469 @@
+use super::*;
+
+// Size of the cells used to nest the islands, in mm
+const PACK_CELL: f32 = 0.5;
+// Number of rotations tried for each island
+const PACK_ROTATIONS: i32 = 16;
+// Models with more faces than this are not nested when imported, that may take a while, the
+// islands are just put in rows and the user can pack them later
+pub const PACK_ON_IMPORT_MAX_FACES: usize = 5000;
+
+// A bitmap of occupied cells, one bit per cell, one `u64` for each 64 cells in a row
+struct CellMap {
+    width: usize,
+    height: usize,
+    words: usize,
+    bits: Vec<u64>,
+}
+
+impl CellMap {
+    fn new(width: usize, height: usize) -> CellMap {
+        let words = width.div_ceil(64);
+        CellMap {
+            width,
+            height,
+            words,
+            bits: vec![0; words * height],
+        }
+    }
+    fn row(&self, y: usize) -> &[u64] {
+        &self.bits[y * self.words..(y + 1) * self.words]
+    }
+    // Sets the cells from x0 to x1, both included
+    fn set_span(&mut self, y: usize, x0: usize, x1: usize) {
+        let row = &mut self.bits[y * self.words..(y + 1) * self.words];
+        for x in x0..=x1 {
+            row[x / 64] |= 1 << (x % 64);
+        }
+    }
+    // The first free cell of row `y` from `x`, if any
+    fn next_free(&self, y: usize, x: usize) -> Option<usize> {
+        let row = self.row(y);
+        let (mut i, mut mask) = (x / 64, !0u64 << (x % 64));
+        while i < self.words {
+            let free = !row[i] & mask;
+            if free != 0 {
+                let c = i * 64 + free.trailing_zeros() as usize;
+                return (c < self.width).then_some(c);
+            }
+            i += 1;
+            mask = !0;
+        }
+        None
+    }
+    // The first and last occupied cells of row `y`, if any
+    fn used_range(&self, y: usize) -> Option<(usize, usize)> {
+        let row = self.row(y);
+        let i0 = row.iter().position(|&bits| bits != 0)?;
+        let i1 = row.iter().rposition(|&bits| bits != 0)?;
+        Some((
+            i0 * 64 + row[i0].trailing_zeros() as usize,
+            i1 * 64 + 63 - row[i1].leading_zeros() as usize,
+        ))
+    }
+    // Checks if `other`, placed at (x, y), touches any occupied cell
+    fn collides(&self, other: &CellMap, x: usize, y: usize) -> bool {
+        (0..other.height).any(|r| {
+            let row = self.row(y + r);
+            shifted_row(other.row(r), x, self.words).any(|(i, bits)| row[i] & bits != 0)
+        })
+    }
+    // Marks the cells of `other`, placed at (x, y), as occupied
+    fn merge(&mut self, other: &CellMap, x: usize, y: usize) {
+        for r in 0..other.height.min(self.height.saturating_sub(y)) {
+            let row = &mut self.bits[(y + r) * self.words..(y + r + 1) * self.words];
+            for (i, bits) in shifted_row(other.row(r), x, row.len()) {
+                row[i] |= bits;
+            }
+        }
+    }
+}
+
+// The words of a row of bits shifted `x` bits, with their new index.
+// The words that fall out of `words` are skipped.
+fn shifted_row(row: &[u64], x: usize, words: usize) -> impl Iterator<Item = (usize, u64)> + '_ {
+    let (w0, shift) = (x / 64, x % 64);
+    row.iter()
+        .enumerate()
+        .filter(|(_, &bits)| bits != 0)
+        .flat_map(move |(i, &bits)| {
+            let lo = (w0 + i, bits << shift);
+            let hi = (shift != 0).then(|| (w0 + i + 1, bits >> (64 - shift)));
+            std::iter::once(lo).chain(hi)
+        })
+        .filter(move |&(i, _)| i < words)
+}
+
+// The horizontal extent of a convex polygon between two horizontal lines
+fn convex_x_extent(vs: &[Vector2], y0: f32, y1: f32) -> Option<(f32, f32)> {
+    let mut res: Option<(f32, f32)> = None;
+    let mut add = |x: f32| {
+        res = Some(match res {
+            None => (x, x),
+            Some((a, b)) => (a.min(x), b.max(x)),
+        });
+    };
+    for (i, &p) in vs.iter().enumerate() {
+        let q = vs[(i + 1) % vs.len()];
+        let d = q - p;
+        if d.y == 0.0 {
+            if (y0..=y1).contains(&p.y) {
+                add(p.x);
+                add(q.x);
+            }
+            continue;
+        }
+        let ta = (y0 - p.y) / d.y;
+        let tb = (y1 - p.y) / d.y;
+        let t0 = ta.min(tb).max(0.0);
+        let t1 = ta.max(tb).min(1.0);
+        if t0 <= t1 {
+            add(p.x + t0 * d.x);
+            add(p.x + t1 * d.x);
+        }
+    }
+    res
+}
+
+// The cells covered by a set of convex polygons, grown by `margin`.
+// Returns the bitmap and the position of its (0, 0) cell.
+fn polygons_cell_map(polygons: &[Vec<Vector2>], margin: f32) -> (CellMap, Vector2) {
+    let (a, b) = util_3d::bounding_box_2d(polygons.iter().flatten().copied());
+    let origin = a - Vector2::new(margin, margin);
+    let size = b - a + 2.0 * Vector2::new(margin, margin);
+    let width = (size.x / PACK_CELL) as usize + 1;
+    let height = (size.y / PACK_CELL) as usize + 1;
+    let mut map = CellMap::new(width, height);
+    for vs in polygons {
+        let (pa, pb) = util_3d::bounding_box_2d(vs.iter().copied());
+        let r0 = ((pa.y - margin - origin.y) / PACK_CELL) as usize;
+        let r1 = (((pb.y + margin - origin.y) / PACK_CELL) as usize).min(height - 1);
+        for r in r0..=r1 {
+            let y0 = origin.y + r as f32 * PACK_CELL - margin;
+            let Some((x0, x1)) = convex_x_extent(vs, y0, y0 + PACK_CELL + 2.0 * margin) else {
+                continue;
+            };
+            let c0 = ((x0 - margin - origin.x) / PACK_CELL).max(0.0) as usize;
+            let c1 = (((x1 + margin - origin.x) / PACK_CELL) as usize).min(width - 1);
+            map.set_span(r, c0, c1);
+        }
+    }
+    (map, origin)
+}
+
+// An island rotated by some angle
+struct PackRotation {
+    angle: Rad<f32>,
+    map: CellMap,
+    origin: Vector2,
+}
+
+impl Papercraft {
+    // The space between the pieces, the texture bleed must fit in it, too
+    fn pack_spacing(&self) -> f32 {
+        self.options.pack_spacing.max(0.0) + self.texture_bleed()
+    }
+
+    // Nests the islands in the pages, using the real outline of the pieces and their flaps, so
+    // that the smaller pieces fill the holes left by the bigger ones.
+    // If `keep_pages` each island is first tried in the page where it is now.
+    // Returns the number of pages used.
+    pub fn pack_islands(&mut self, keep_pages: bool) -> u32 {
+        let spacing = self.pack_spacing();
+        let (margin_top, margin_left, margin_right, margin_bottom) = self.options.margin;
+        let page_area = Vector2::new(
+            self.options.page_size.0 - margin_left - margin_right,
+            self.options.page_size.1 - margin_top - margin_bottom,
+        );
+        // Each island is grown by half the spacing, so the page grows by the same amount, too
+        let page_zero =
+            Vector2::new(margin_left, margin_top) - Vector2::new(spacing, spacing) / 2.0;
+        let page_width = ((page_area.x + spacing) / PACK_CELL).max(0.0) as usize;
+        let page_height = ((page_area.y + spacing) / PACK_CELL).max(0.0) as usize;
+
+        let mut islands: Vec<_> = self
+            .islands
+            .iter()
+            .map(|(i_island, island)| {
+                let mut polygons = Vec::new();
+                self.traverse_faces(island, |i_face, face, mx| {
+                    polygons.extend(
+                        self.face_part_polygons(i_island, i_face, face, mx)
+                            .into_iter()
+                            .map(|p| p.vs),
+                    );
+                    ControlFlow::Continue(())
+                });
+                let (a, b) = util_3d::bounding_box_2d(polygons.iter().flatten().copied());
+                let center = (a + b) / 2.0;
+                let prev_page = keep_pages.then(|| self.options.page_of(center)).flatten();
+                (
+                    i_island,
+                    self.island_area(island),
+                    center,
+                    prev_page,
+                    polygons,
+                )
+            })
+            .collect();
+        // Bigger first, the small ones will fill the gaps
+        islands.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        let mut pages: Vec<CellMap> = Vec::new();
+        let mut used_pages = 0;
+        let mut moves = Vec::new();
+        for (i_island, _, center, prev_page, polygons) in islands {
+            let rotations: Vec<_> = (0..PACK_ROTATIONS)
+                .map(|i| {
+                    let angle = Rad::full_turn() * i as f32 / PACK_ROTATIONS as f32;
+                    let rot = Matrix2::from_angle(angle);
+                    let rotated: Vec<Vec<Vector2>> = polygons
+                        .iter()
+                        .map(|vs| vs.iter().map(|&p| rot * (p - center)).collect())
+                        .collect();
+                    let (map, origin) = polygons_cell_map(&rotated, spacing / 2.0);
+                    PackRotation { angle, map, origin }
+                })
+                .collect();
+
+            let num_pages = pages.len();
+            let page_order = prev_page
+                .into_iter()
+                .chain((0..num_pages as u32).filter(|&p| Some(p) != prev_page))
+                .chain(std::iter::once(
+                    prev_page.map_or(0, |p| p + 1).max(num_pages as u32),
+                ));
+            let mut found = None;
+            for page in page_order {
+                let page = page as usize;
+                while pages.len() <= page {
+                    pages.push(CellMap::new(page_width, page_height));
+                }
+                if let Some((i_rot, x, y)) = find_place(&pages[page], &rotations) {
+                    found = Some((page, i_rot, x, y));
+                    break;
+                }
+            }
+            // It does not fit even in an empty page, the last one tried, so it will not be
+            // printable, but put it there anyway
+            let (page, i_rot, x, y) = found.unwrap_or((pages.len() - 1, 0, 0, 0));
+            let rotation = &rotations[i_rot];
+            pages[page].merge(&rotation.map, x, y);
+            used_pages = used_pages.max(page + 1);
+
+            let zero = self.options.page_position(page as u32) + page_zero;
+            let new_center = zero + Vector2::new(x as f32, y as f32) * PACK_CELL - rotation.origin;
+            moves.push((i_island, center, rotation.angle, new_center));
+        }
+        for (i_island, center, angle, new_center) in moves {
+            let island = self.island_by_key_mut(i_island).unwrap();
+            island.rotate(angle, center);
+            island.translate(new_center - center);
+        }
+        used_pages.max(1) as u32
+    }
+
+    // Puts the islands in rows, by their bounding boxes, without rotating them. It is much
+    // faster than `pack_islands`, but it wastes a lot of paper.
+    // Returns the number of pages used.
+    pub fn pack_islands_in_rows(&mut self) -> u32 {
+        let spacing = self.pack_spacing();
+        let (margin_top, margin_left, margin_right, margin_bottom) = self.options.margin;
+        let page_area = Vector2::new(
+            self.options.page_size.0 - margin_left - margin_right,
+            self.options.page_size.1 - margin_top - margin_bottom,
+        );
+        let mut islands: Vec<_> = self
+            .islands
+            .keys()
+            .map(|i_island| (i_island, self.island_bounding_box(i_island)))
+            .collect();
+        // Taller first, so that the rows are more even
+        islands.sort_by(|(_, a), (_, b)| (b.1.y - b.0.y).total_cmp(&(a.1.y - a.0.y)));
+
+        let mut page = 0;
+        let mut pos = Vector2::zero();
+        let mut row_height = 0.0f32;
+        let mut moves = Vec::with_capacity(islands.len());
+        for (i_island, (a, b)) in islands {
+            let size = b - a;
+            if pos.x > 0.0 && pos.x + size.x > page_area.x {
+                pos = Vector2::new(0.0, pos.y + row_height + spacing);
+                row_height = 0.0;
+            }
+            if pos.y > 0.0 && pos.y + size.y > page_area.y {
+                pos = Vector2::zero();
+                row_height = 0.0;
+                page += 1;
+            }
+            let zero = self.options.page_position(page) + Vector2::new(margin_left, margin_top);
+            moves.push((i_island, zero + pos - a));
+            pos.x += size.x + spacing;
+            row_height = row_height.max(size.y);
+        }
+        for (i_island, delta) in moves {
+            self.island_by_key_mut(i_island).unwrap().translate(delta);
+        }
+        page + 1
+    }
+}
+
+// Looks for the first free place in the page, from top to bottom, for any of the rotations.
+// Returns the index of the rotation and the position.
+fn find_place(page: &CellMap, rotations: &[PackRotation]) -> Option<(usize, usize, usize)> {
+    let mut best: Option<(usize, usize, usize)> = None;
+    // The best is the one with the lowest bottom side, then the leftmost
+    let bottom = |(i_rot, x, y): (usize, usize, usize)| (y + rotations[i_rot].map.height, x);
+    for (i_rot, rotation) in rotations.iter().enumerate() {
+        let map = &rotation.map;
+        if map.width > page.width || map.height > page.height {
+            continue;
+        }
+        let ends: Vec<_> = (0..map.height)
+            .filter_map(|r| Some((r, map.used_range(r)?)))
+            .collect();
+        let max_x = page.width - map.width;
+        'y: for y in 0..=page.height - map.height {
+            if let Some(b) = best {
+                if y + map.height > bottom(b).0 {
+                    break;
+                }
+            }
+            let mut x = 0;
+            'x: while x <= max_x {
+                // The cells at both ends of each row must be free, so jump over the occupied
+                // cells without checking the whole island, most of the page is discarded this way
+                for &(r, (left, right)) in &ends {
+                    for end in [left, right] {
+                        match page.next_free(y + r, x + end) {
+                            Some(c) if c == x + end => {}
+                            Some(c) => {
+                                x = c - end;
+                                continue 'x;
+                            }
+                            None => continue 'y,
+                        }
+                    }
+                }
+                if !page.collides(map, x, y) {
+                    let candidate = (i_rot, x, y);
+                    best = match best {
+                        Some(b) if bottom(b) <= bottom(candidate) => Some(b),
+                        _ => Some(candidate),
+                    };
+                    break 'y;
+                }
+                x += 1;
+            }
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cube(scale: f32) -> Papercraft {
+        let mut papercraft = crate::paper::import::tests::cube(1.0);
+        let mut options = papercraft.options().clone();
+        options.scale = scale;
+        papercraft.set_options(options, false);
+        papercraft
+    }
+
+    // A page with some random cells already taken
+    fn random_page(width: usize, height: usize, seed: u32) -> CellMap {
+        let mut page = CellMap::new(width, height);
+        let mut rnd = seed;
+        for y in 0..height {
+            for x in 0..width {
+                rnd = rnd.wrapping_mul(1664525).wrapping_add(1013904223);
+                if rnd >> 28 < 5 {
+                    page.set_span(y, x, x);
+                }
+            }
+        }
+        page
+    }
+
+    #[test]
+    fn find_place_is_the_first_free_place() {
+        // An L shape, so that the row ends are not enough to find a place
+        let mut map = CellMap::new(3, 3);
+        map.set_span(0, 0, 0);
+        map.set_span(1, 0, 0);
+        map.set_span(2, 0, 2);
+        let rotations = [PackRotation {
+            angle: Rad(0.0),
+            map,
+            origin: Vector2::zero(),
+        }];
+        for seed in 0..20 {
+            let page = random_page(70, 40, seed);
+            let map = &rotations[0].map;
+            let naive = (0..=page.height - map.height).find_map(|y| {
+                (0..=page.width - map.width)
+                    .find(|&x| !page.collides(map, x, y))
+                    .map(|x| (0, x, y))
+            });
+            assert_eq!(find_place(&page, &rotations), naive);
+        }
+    }
+
+    #[test]
+    fn packed_islands_do_not_overlap() {
+        for pack_in_rows in [false, true] {
+            let mut papercraft = cube(50.0);
+            assert_eq!(papercraft.islands().count(), 6);
+            let pages = if pack_in_rows {
+                papercraft.pack_islands_in_rows()
+            } else {
+                papercraft.pack_islands(false)
+            };
+            let mut options = papercraft.options().clone();
+            options.pages = pages;
+            papercraft.set_options(options, false);
+            assert!(papercraft.find_conflicts().is_empty());
+        }
+    }
+
+    #[test]
+    fn bleed_keeps_the_islands_apart() {
+        let mut papercraft = crate::paper::import::tests::textured_cube(1.0);
+        let mut options = papercraft.options().clone();
+        assert!(options.texture);
+        options.scale = 50.0;
+        options.pack_spacing = 0.0;
+        papercraft.set_options(options.clone(), false);
+        options.pages = papercraft.pack_islands(false);
+
+        // Packed without the bleed, some pieces are too near
+        options.bleed = 3.0;
+        papercraft.set_options(options.clone(), false);
+        assert!(!papercraft.find_conflicts().is_empty());
+
+        options.pages = papercraft.pack_islands(false);
+        papercraft.set_options(options, false);
+        assert!(papercraft.find_conflicts().is_empty());
+    }
+
+    #[test]
+    fn no_bleed_without_texture() {
+        let mut papercraft = cube(50.0);
+        let mut options = papercraft.options().clone();
+        options.texture = true;
+        options.bleed = 3.0;
+        papercraft.set_options(options, false);
+        // The model has no texture, so there is nothing to bleed
+        assert_eq!(papercraft.texture_bleed(), 0.0);
+        assert_eq!(papercraft.pack_spacing(), papercraft.options().pack_spacing);
+    }
+
+    #[test]
+    fn pieces_fill_the_pages() {
+        // Six 50mm squares, with their flaps, fit in an A4 page
+        let mut papercraft = cube(50.0);
+        assert_eq!(papercraft.pack_islands(false), 1);
+    }
+}
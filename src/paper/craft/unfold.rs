@@ -0,0 +1,176 @@
+use super::*;
+
+// Number of rotations tried to check if an island fits in the page
+const FIT_TRIES: i32 = 36;
+
+impl Papercraft {
+    // Joins as many edges as possible, as long as the islands do not overlap themselves and they
+    // still fit in the printable area of a page. Edges between two parts are left cut.
+    // The flattest edges are tried first, so that the folds are mostly the sharp ones.
+    // Returns the joins done, in order, with the island that disappears in each one.
+    pub fn auto_unfold(&mut self) -> Vec<(IslandKey, JoinResult)> {
+        let mut edges: Vec<_> = self
+            .model
+            .edges()
+            .filter(|(i_edge, edge)| {
+                matches!(self.edge_status(*i_edge), EdgeStatus::Cut(_))
+                    && edge.faces().1.is_some()
+                    && !self.model.edge_between_parts(*i_edge)
+            })
+            .map(|(i_edge, edge)| (i_edge, edge.angle().0.abs()))
+            .collect();
+        // Stable sort, so the result is always the same
+        edges.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+        let (margin_top, margin_left, margin_right, margin_bottom) = self.options.margin;
+        let page_area = Vector2::new(
+            self.options.page_size.0 - margin_left - margin_right,
+            self.options.page_size.1 - margin_top - margin_bottom,
+        );
+
+        let mut joins = Vec::new();
+        for (i_edge, _) in edges {
+            let edge_status = self.edge_status(i_edge);
+            let (i_face_a, Some(i_face_b)) = self.model[i_edge].faces() else {
+                continue;
+            };
+            let i_island_a = self.island_by_face(i_face_a);
+            let i_island_b = self.island_by_face(i_face_b);
+            if i_island_a == i_island_b {
+                continue;
+            }
+            // Each island is already fine, only the faces of one against the other are checked
+            let mut faces_b = FxHashSet::default();
+            self.traverse_faces_no_matrix(&self.islands[i_island_b], |i_face| {
+                faces_b.insert(i_face);
+                ControlFlow::Continue(())
+            });
+
+            let renames = self.edge_join(i_edge, None);
+            let Some((&i_removed, &join_result)) = renames.iter().next() else {
+                continue;
+            };
+            if self.unfold_island_is_valid(join_result.i_island, &faces_b, page_area) {
+                joins.push((i_removed, join_result));
+            } else {
+                // Revert the join, just like an undo, but keep the flap
+                self.edge_cut(i_edge, None);
+                self.edges[usize::from(i_edge)] = edge_status;
+                self.set_flap_override(i_edge, join_result.prev_flap_override);
+                let i_prev_island = self.island_by_face(join_result.prev_root);
+                self.islands[i_prev_island].reset_transformation(
+                    join_result.prev_root,
+                    join_result.prev_rot,
+                    join_result.prev_loc,
+                );
+            }
+        }
+        joins
+    }
+
+    fn unfold_island_is_valid(
+        &self,
+        i_island: IslandKey,
+        faces_b: &FxHashSet<FaceIndex>,
+        page_area: Vector2,
+    ) -> bool {
+        let mut tris_a = Vec::new();
+        let mut tris_b = Vec::new();
+        let mut flap_width = 0.0_f32;
+        self.traverse_faces(&self.islands[i_island], |i_face, face, mx| {
+            let tri = self
+                .face_vertices_inset(face)
+                .map(|p| mx.transform_point(Point2::from_vec(p)).to_vec());
+            if faces_b.contains(&i_face) {
+                tris_b.push(tri);
+            } else {
+                tris_a.push(tri);
+            }
+            for i_edge in face.index_edges() {
+                if matches!(self.edge_status(i_edge), EdgeStatus::Cut(_)) {
+                    flap_width = flap_width.max(self.flap_width(i_edge));
+                }
+            }
+            ControlFlow::Continue(())
+        });
+
+        // The flaps are not computed yet, so leave some room for the widest one
+        let flaps = Vector2::new(2.0, 2.0) * flap_width;
+        let all_points = || tris_a.iter().chain(&tris_b).flatten().copied();
+        let fits = (0..FIT_TRIES).any(|i| {
+            let rot = Matrix2::from_angle(Rad::turn_div_2() * i as f32 / FIT_TRIES as f32);
+            let (a, b) = util_3d::bounding_box_2d(all_points().map(|p| rot * p));
+            let size = b - a + flaps;
+            size.x <= page_area.x && size.y <= page_area.y
+        });
+        if !fits {
+            return false;
+        }
+
+        // Only the triangles of A near the triangles of B are checked, using a coarse grid over B
+        // so that a big island does not compare every pair of faces
+        let bbox_b = util_3d::bounding_box_2d(tris_b.iter().flatten().copied());
+        let grid = TriangleGrid::new(&tris_b, bbox_b);
+        !tris_a.iter().any(|ta| {
+            let bbox_a = util_3d::bounding_box_2d(ta.iter().copied());
+            if !util_3d::bounding_boxes_overlap(bbox_a, bbox_b) {
+                return false;
+            }
+            grid.candidates(bbox_a)
+                .any(|i_tb| util_3d::convex_polygons_overlap(ta, &tris_b[i_tb], OVERLAP_EPSILON))
+        })
+    }
+}
+
+// Buckets the triangles of an island by their bounding box, in cells of about the size of a face
+struct TriangleGrid {
+    origin: Vector2,
+    cell: f32,
+    cols: usize,
+    rows: usize,
+    cells: Vec<Vec<usize>>,
+}
+
+impl TriangleGrid {
+    fn new(tris: &[[Vector2; 3]], bbox: (Vector2, Vector2)) -> TriangleGrid {
+        let size = bbox.1 - bbox.0;
+        let n = (tris.len() as f32).sqrt().ceil().clamp(1.0, 256.0);
+        let cell = (size.x.max(size.y) / n).max(f32::EPSILON);
+        let cols = (size.x / cell) as usize + 1;
+        let rows = (size.y / cell) as usize + 1;
+        let mut grid = TriangleGrid {
+            origin: bbox.0,
+            cell,
+            cols,
+            rows,
+            cells: vec![Vec::new(); cols * rows],
+        };
+        for (i_tri, tri) in tris.iter().enumerate() {
+            let (x0, y0, x1, y1) = grid.range(util_3d::bounding_box_2d(tri.iter().copied()));
+            for y in y0..=y1 {
+                for x in x0..=x1 {
+                    grid.cells[y * cols + x].push(i_tri);
+                }
+            }
+        }
+        grid
+    }
+    fn range(&self, bbox: (Vector2, Vector2)) -> (usize, usize, usize, usize) {
+        let to_cell = |v: f32, max: usize| (((v / self.cell).max(0.0)) as usize).min(max - 1);
+        let a = bbox.0 - self.origin;
+        let b = bbox.1 - self.origin;
+        (
+            to_cell(a.x, self.cols),
+            to_cell(a.y, self.rows),
+            to_cell(b.x, self.cols),
+            to_cell(b.y, self.rows),
+        )
+    }
+    // May return the same triangle more than once, that is fine for an overlap check
+    fn candidates(&self, bbox: (Vector2, Vector2)) -> impl Iterator<Item = usize> + '_ {
+        let (x0, y0, x1, y1) = self.range(bbox);
+        (y0..=y1).flat_map(move |y| {
+            (x0..=x1).flat_map(move |x| self.cells[y * self.cols + x].iter().copied())
+        })
+    }
+}
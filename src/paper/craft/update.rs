@@ -1,4 +1,5 @@
 use super::*;
+use model::import::{ImportReport, PapercraftImporter, SimplifyImporter, SimplifyOptions};
 
 fn compute_edge_map(new: &Papercraft, old: &Papercraft) -> FxHashMap<EdgeIndex, (EdgeIndex, bool)> {
     let mut map = FxHashMap::default();
@@ -63,6 +64,24 @@ fn compute_island_map(
 }
 
 impl Papercraft {
+    // Builds a new papercraft with fewer faces, the cuts and joins are kept where the edges
+    // are still there
+    pub fn simplified(&self, options: &SimplifyOptions) -> anyhow::Result<Papercraft> {
+        // As when importing, a bug here should not take the whole document with it
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let importer = SimplifyImporter::new(PapercraftImporter::new(self), options);
+            let mut new = Papercraft::import(importer, &mut ImportReport::default());
+            new.update_from_obj(self);
+            new
+        }));
+        match res {
+            Ok(new) => Ok(new),
+            Err(err) => match err.downcast_ref::<&str>() {
+                Some(msg) => anyhow::bail!("Panic simplifying the model!\n{msg}"),
+                None => anyhow::bail!("Panic simplifying the model!"),
+            },
+        }
+    }
     pub fn update_from_obj(&mut self, old_obj: &Papercraft) {
         self.options = old_obj.options.clone();
         // Options are changed, discard memo
@@ -93,12 +112,12 @@ impl Papercraft {
             let o_status = old_obj.edge_status(o);
             let i_status = self.edge_status(i);
             if i_status != EdgeStatus::Hidden && o_status != EdgeStatus::Hidden {
-                edge_status_map.insert(i_edge, (o_status, o_cross));
+                edge_status_map.insert(i_edge, (o_status, o_cross, old_obj.flap_override(o)));
             }
         }
 
         //Apply the old status to the new model
-        for (i_edge, (status, crossed)) in edge_status_map {
+        for (i_edge, (status, crossed, flap_override)) in edge_status_map {
             match status {
                 EdgeStatus::Hidden => { /* should not happen */ }
                 EdgeStatus::Joined => {
@@ -107,6 +126,9 @@ impl Papercraft {
                 EdgeStatus::Cut(c) => {
                     // This  should not be needed, because when a model is imported all edges are cut by default, but just in case
                     self.edge_cut(i_edge, None);
+                    if !flap_override.is_empty() {
+                        self.flap_overrides.insert(i_edge, flap_override);
+                    }
                     if let EdgeStatus::Cut(new_c) = self.edge_status(i_edge) {
                         match c {
                             FlapSide::Hidden => {
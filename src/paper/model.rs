@@ -1,18 +1,18 @@
 use anyhow::{Context, Result};
-use cgmath::{Angle, InnerSpace, Rad};
+use cgmath::{Angle, InnerSpace, Rad, SquareMatrix};
 use image::DynamicImage;
 use serde::{Deserialize, Serialize};
 use std::cell::Cell;
 use std::marker::PhantomData;
 
-use crate::paper::import::Importer;
-use crate::util_3d::{self, Vector2, Vector3};
+use crate::paper::import::{ImportReport, Importer};
+use crate::util_3d::{self, Matrix3, Vector2, Vector3};
 
 use super::{EdgeStatus, Island, PaperOptions};
 
 pub mod import;
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Texture {
     file_name: String,
     #[serde(skip)]
@@ -34,6 +34,8 @@ pub struct Model {
     vertices: Vec<Vertex>,
     edges: Vec<Edge>,
     faces: Vec<Face>,
+    // Names of the parts of the model, such as OBJ objects, empty if it is all one piece
+    parts: Vec<String>,
 }
 
 use maybe_owned::MaybeOwned;
@@ -48,6 +50,12 @@ struct ModelSer<'s> {
     edges: MaybeOwned<'s, Vec<Edge>>,
     #[serde(rename = "fs")]
     faces: MaybeOwned<'s, Vec<Face>>,
+    #[serde(default, skip_serializing_if = "is_empty_parts")]
+    parts: MaybeOwned<'s, Vec<String>>,
+}
+
+fn is_empty_parts(parts: &MaybeOwned<'_, Vec<String>>) -> bool {
+    parts.is_empty()
 }
 
 // Hack to pass a serialization context to the Edges, it will be removed, eventually
@@ -86,6 +94,7 @@ impl Serialize for Model {
             vertices: Borrowed(&self.vertices),
             edges: Borrowed(&self.edges),
             faces: Borrowed(&self.faces),
+            parts: Borrowed(&self.parts),
         };
         se.serialize(ser)
     }
@@ -101,6 +110,7 @@ impl<'de> Deserialize<'de> for Model {
             vertices: Owned(vertices),
             edges: Owned(edges),
             faces: Owned(faces),
+            parts: Owned(parts),
         } = ModelSer::deserialize(des)?
         else {
             unreachable!()
@@ -110,6 +120,7 @@ impl<'de> Deserialize<'de> for Model {
             vertices,
             edges,
             faces,
+            parts,
         };
         model.post_create();
         Ok(model)
@@ -149,6 +160,11 @@ index_type!(pub MaterialIndex: u32);
 index_type!(pub VertexIndex: u32);
 index_type!(pub EdgeIndex: u32);
 index_type!(pub FaceIndex: u32);
+index_type!(pub PartIndex: u32);
+
+fn is_first_part(i_part: &PartIndex) -> bool {
+    i_part.0 == 0
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Face {
@@ -158,6 +174,8 @@ pub struct Face {
     vertices: [VertexIndex; 3],
     #[serde(rename = "es")]
     edges: [EdgeIndex; 3],
+    #[serde(rename = "pt", default, skip_serializing_if = "is_first_part")]
+    part: PartIndex,
 }
 
 // Beware! The vertices that form the edge in `f0` and those in `f1` may be different, because of
@@ -219,11 +237,13 @@ impl Model {
             vertices: Vec::new(),
             edges: Vec::new(),
             faces: Vec::new(),
+            parts: Vec::new(),
         }
     }
 
     pub fn from_importer<I: Importer>(
         obj: &mut I,
+        report: &mut ImportReport,
     ) -> (Model, Vec<FaceSource>, Vec<(I::VertexId, I::VertexId)>) {
         let (has_normals, mut vertices) = obj.build_vertices();
 
@@ -233,9 +253,18 @@ impl Model {
         let mut edges: Vec<Edge> = Vec::with_capacity(num_faces * 3 / 2);
         let mut edge_map: Vec<(I::VertexId, I::VertexId)> = Vec::with_capacity(num_faces * 3 / 2);
 
+        let parts = obj.part_names();
         let mut face_source_id = 0;
+        // To report each non-manifold edge only once
+        let mut non_manifold_edges = fxhash::FxHashSet::default();
         'face_loop: for (face_verts, face_mat) in obj.faces() {
             let face_verts = face_verts.as_ref();
+            let face_part = if parts.is_empty() {
+                PartIndex(0)
+            } else {
+                obj.face_part(face_source_id as usize)
+            };
+            let face_flipped = obj.face_flipped(face_source_id as usize);
             face_source_id += 1;
             let to_tess: Vec<_> = face_verts
                 .iter()
@@ -272,14 +301,17 @@ impl Model {
                         .position(|(p0, p1)| (p0, p1) == (&v0, &v1) || (p0, p1) == (&v1, &v0));
 
                     if let Some(i_edge) = i_edge_candidate {
+                        let i_edge_report = EdgeIndex::from(i_edge);
                         if edges[i_edge].f1.is_some() {
                             // Maximum 2 faces per edge, additional faces will clone the edge and be disconnected
-                            println!("Warning: three-faced edge #{i_edge}");
+                            if non_manifold_edges.insert(i_edge_report) {
+                                report.non_manifold_edges.push(i_edge_report);
+                            }
                             i_edge_candidate = None;
                         } else if edge_map[i_edge] != (v1, v0) {
                             // The found edge should be inverted: (v1,v0), unless you are doing a Moebius strip or something weird.
                             // This is mostly harmless, though.
-                            println!("Warning: inverted edge #{i_edge}: {v0:?}-{v1:?}");
+                            report.inverted_edges.push(i_edge_report);
                         }
                     }
 
@@ -303,6 +335,7 @@ impl Model {
                     | [_, EdgeCreation::Existing(a), EdgeCreation::Existing(b)]
                         if a == b =>
                     {
+                        report.dropped_faces += 1;
                         continue 'face_loop;
                     }
                     _ => {}
@@ -336,11 +369,15 @@ impl Model {
                     vertices[usize::from(face_vertices[2])].normal += normal;
                 }
 
+                if face_flipped {
+                    report.flipped_faces.push(i_face);
+                }
                 face_map.push(FaceSource(face_source_id));
                 faces.push(Face {
                     material: face_mat,
                     vertices: face_vertices,
                     edges,
+                    part: face_part,
                 });
             }
         }
@@ -351,6 +388,7 @@ impl Model {
             vertices,
             edges,
             faces,
+            parts,
         };
         model.post_create();
         (model, face_map, edge_map)
@@ -431,6 +469,27 @@ impl Model {
     pub fn is_empty(&self) -> bool {
         self.vertices.is_empty()
     }
+    pub fn num_parts(&self) -> usize {
+        self.parts.len()
+    }
+    pub fn parts(&self) -> impl Iterator<Item = (PartIndex, &str)> + '_ {
+        self.parts
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (PartIndex::from(i), p.as_str()))
+    }
+    pub fn part_name(&self, i_part: PartIndex) -> &str {
+        self.parts
+            .get(usize::from(i_part))
+            .map_or("", |p| p.as_str())
+    }
+    // Edges between two different parts are never joined automatically
+    pub fn edge_between_parts(&self, i_edge: EdgeIndex) -> bool {
+        match self[i_edge].faces() {
+            (fa, Some(fb)) => self[fa].part != self[fb].part,
+            _ => false,
+        }
+    }
     pub fn textures(&self) -> impl Iterator<Item = &Texture> + '_ {
         self.textures.iter()
     }
@@ -447,6 +506,67 @@ impl Model {
         }
         Ok(())
     }
+    // VertexIndex -> the same id for all the vertices of both sides of the edges, that is the
+    // same point of the mesh, even if they have different UVs
+    pub fn joined_vertex_ids(&self) -> Vec<u32> {
+        fn find_root(ids: &mut [u32], mut i: u32) -> u32 {
+            while ids[i as usize] != i {
+                let next = ids[i as usize];
+                ids[i as usize] = ids[next as usize];
+                i = next;
+            }
+            i
+        }
+
+        let mut ids: Vec<u32> = (0..self.vertices.len() as u32).collect();
+        for (i_edge, edge) in self.edges() {
+            let (fa, Some(fb)) = edge.faces() else {
+                continue;
+            };
+            let (Some((a0, a1)), Some((b0, b1))) = (
+                self[fa].vertices_of_edge(i_edge),
+                self[fb].vertices_of_edge(i_edge),
+            ) else {
+                continue;
+            };
+            // Usually the edge goes in the other direction in the other face
+            let pairs = if self[a0].pos == self[b0].pos && self[a0].pos != self[b1].pos {
+                [(a0, b0), (a1, b1)]
+            } else {
+                [(a0, b1), (a1, b0)]
+            };
+            for (x, y) in pairs {
+                let rx = find_root(&mut ids, x.0);
+                let ry = find_root(&mut ids, y.0);
+                ids[rx.max(ry) as usize] = rx.min(ry);
+            }
+        }
+        for i in 0..ids.len() {
+            ids[i] = find_root(&mut ids, i as u32);
+        }
+        ids
+    }
+    pub fn bounding_box(&self) -> (Vector3, Vector3) {
+        util_3d::bounding_box_3d(self.vertices.iter().map(|v| v.pos))
+    }
+    // Applies the transformation around the center of the model. It should be orthogonal, such
+    // as a rotation or a mirror, so that the normals can be transformed with the same matrix.
+    pub fn transform(&mut self, mx: &Matrix3) {
+        let (v_min, v_max) = self.bounding_box();
+        let center = (v_min + v_max) / 2.0;
+        for v in &mut self.vertices {
+            v.pos = center + mx * (v.pos - center);
+            v.normal = mx * v.normal;
+        }
+        // A mirror turns the faces inside out, reverse them to keep them pointing outwards
+        if mx.determinant() < 0.0 {
+            for face in &mut self.faces {
+                face.vertices.swap(1, 2);
+                face.edges.swap(0, 2);
+            }
+        }
+        self.post_create();
+    }
     pub fn face_plane(&self, face: &Face) -> util_3d::Plane {
         util_3d::Plane::from_tri([
             self[face.vertices[0]].pos(),
@@ -499,6 +619,9 @@ impl Face {
     pub fn material(&self) -> MaterialIndex {
         self.material
     }
+    pub fn part(&self) -> PartIndex {
+        self.part
+    }
     pub fn vertices_with_edges(
         &self,
     ) -> impl Iterator<Item = (VertexIndex, VertexIndex, EdgeIndex)> + '_ {
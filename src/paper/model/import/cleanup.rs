@@ -0,0 +1,451 @@
+use std::cell::Cell;
+
+use super::*;
+use cgmath::{InnerSpace, MetricSpace, Zero};
+use fxhash::{FxHashMap, FxHashSet};
+
+struct CleanFace {
+    verts: Vec<VertexIndex>,
+    mat: MaterialIndex,
+    // Index of the face in the inner importer
+    source: usize,
+    flipped: bool,
+}
+
+// Wraps any importer and fixes the most common problems of the mesh before building the model:
+// unwelded vertices, degenerate or duplicated faces and inconsistent winding.
+pub struct CleanupImporter<I: Importer> {
+    inner: I,
+    has_normals: bool,
+    vertices: Cell<Vec<Vertex>>,
+    // VertexIndex -> welded vertex id
+    weld_ids: Vec<u32>,
+    // Welded vertex id -> one of its vertices, to ask the inner importer about its edges
+    weld_reps: Vec<VertexIndex>,
+    faces: Vec<CleanFace>,
+}
+
+impl<I: Importer> CleanupImporter<I> {
+    pub fn new(inner: I, weld_tolerance: f32, report: &mut ImportReport) -> Self {
+        let (has_normals, mut vertices) = inner.build_vertices();
+
+        let (v_min, v_max) = crate::util_3d::bounding_box_3d(vertices.iter().map(|v| v.pos));
+        let tolerance = weld_tolerance * v_min.distance(v_max);
+
+        // Weld the vertices, looking for a close one in the neighbor cells of a grid
+        let cell_size = if tolerance > 0.0 { tolerance } else { 1.0 };
+        let cell = |p: Vector3| (p / cell_size).map(|x| x.floor() as i64);
+        let mut grid: FxHashMap<[i64; 3], Vec<u32>> = FxHashMap::default();
+        let mut weld_ids = Vec::with_capacity(vertices.len());
+        let mut weld_reps: Vec<VertexIndex> = Vec::new();
+        // The different ids of the inner importer in each welded vertex, to count them
+        let mut weld_sources: Vec<Vec<I::VertexId>> = Vec::new();
+        for i_v in 0..vertices.len() {
+            let pos = vertices[i_v].pos;
+            let c = cell(pos);
+            let mut found = None;
+            'search: for dx in -1..=1 {
+                for dy in -1..=1 {
+                    for dz in -1..=1 {
+                        let Some(ids) = grid.get(&[c.x + dx, c.y + dy, c.z + dz]) else {
+                            continue;
+                        };
+                        let near = ids.iter().copied().find(|&id| {
+                            let rep = &vertices[usize::from(weld_reps[id as usize])];
+                            rep.pos.distance(pos) <= tolerance
+                        });
+                        if near.is_some() {
+                            found = near;
+                            break 'search;
+                        }
+                    }
+                }
+            }
+            let source = inner.vertex_map(VertexIndex::from(i_v));
+            let id = match found {
+                Some(id) => {
+                    // Snap it to the same position, so that the faces fit together
+                    vertices[i_v].pos = vertices[usize::from(weld_reps[id as usize])].pos;
+                    let sources = &mut weld_sources[id as usize];
+                    if !sources.contains(&source) {
+                        sources.push(source);
+                    }
+                    id
+                }
+                None => {
+                    let id = weld_reps.len() as u32;
+                    weld_reps.push(VertexIndex::from(i_v));
+                    weld_sources.push(vec![source]);
+                    grid.entry([c.x, c.y, c.z]).or_default().push(id);
+                    id
+                }
+            };
+            weld_ids.push(id);
+        }
+        report.welded_vertices = weld_sources.iter().map(|s| s.len() - 1).sum();
+
+        let mut faces = Vec::with_capacity(inner.face_count());
+        let mut face_keys = FxHashSet::default();
+        for (source, (face_verts, mat)) in inner.faces().enumerate() {
+            // Consecutive vertices welded together are just one
+            let mut verts: Vec<VertexIndex> = Vec::with_capacity(face_verts.as_ref().len());
+            for &i_v in face_verts.as_ref() {
+                let id = weld_ids[usize::from(i_v)];
+                if !matches!(verts.last(), Some(&prev) if weld_ids[usize::from(prev)] == id) {
+                    verts.push(i_v);
+                }
+            }
+            while verts.len() > 1
+                && weld_ids[usize::from(verts[0])] == weld_ids[usize::from(*verts.last().unwrap())]
+            {
+                verts.pop();
+            }
+            if verts.len() < 3 || is_degenerate(&vertices, &verts, tolerance) {
+                report.degenerate_faces += 1;
+                continue;
+            }
+            // The same vertices, in any order, is the same face
+            let mut key: Vec<u32> = verts.iter().map(|v| weld_ids[usize::from(*v)]).collect();
+            key.sort_unstable();
+            if !face_keys.insert(key) {
+                report.duplicated_faces += 1;
+                continue;
+            }
+            faces.push(CleanFace {
+                verts,
+                mat,
+                source,
+                flipped: false,
+            });
+        }
+
+        fix_winding(&mut faces, &weld_ids);
+        if has_normals {
+            flip_normals(&mut faces, &mut vertices, &mut weld_ids);
+        }
+
+        CleanupImporter {
+            inner,
+            has_normals,
+            vertices: Cell::new(vertices),
+            weld_ids,
+            weld_reps,
+            faces,
+        }
+    }
+}
+
+// A face is degenerate if all its vertices are in a line, within the tolerance
+fn is_degenerate(vertices: &[Vertex], verts: &[VertexIndex], tolerance: f32) -> bool {
+    let origin = vertices[usize::from(verts[0])].pos;
+    let pos = |i: usize| vertices[usize::from(verts[i % verts.len()])].pos - origin;
+    // Newell's method, the length of the normal is twice the area
+    let mut normal = Vector3::zero();
+    let mut max_len = 0.0_f32;
+    for i in 0..verts.len() {
+        let (a, b) = (pos(i), pos(i + 1));
+        normal += a.cross(b);
+        max_len = max_len.max(a.distance(b));
+    }
+    // twice the area divided by the base is the height
+    max_len == 0.0 || normal.magnitude() / max_len <= tolerance
+}
+
+// Makes the winding of the faces the same as their neighbors. In each connected component, the
+// faces that are in the minority are the ones reversed.
+fn fix_winding(faces: &mut [CleanFace], weld_ids: &[u32]) {
+    let face_edges = |face: &CleanFace| {
+        let ids: Vec<u32> = face
+            .verts
+            .iter()
+            .map(|v| weld_ids[usize::from(*v)])
+            .collect();
+        (0..ids.len())
+            .map(|i| (ids[i], ids[(i + 1) % ids.len()]))
+            .collect::<Vec<_>>()
+    };
+    // (min, max) -> [(face, the face goes from min to max)]
+    let mut edge_faces: FxHashMap<(u32, u32), Vec<(usize, bool)>> = FxHashMap::default();
+    for (i_face, face) in faces.iter().enumerate() {
+        for (a, b) in face_edges(face) {
+            edge_faces
+                .entry((a.min(b), a.max(b)))
+                .or_default()
+                .push((i_face, a < b));
+        }
+    }
+
+    let mut visited = vec![false; faces.len()];
+    let mut flip = vec![false; faces.len()];
+    for i_seed in 0..faces.len() {
+        if visited[i_seed] {
+            continue;
+        }
+        visited[i_seed] = true;
+        let mut component = vec![i_seed];
+        let mut pending = vec![i_seed];
+        while let Some(i_face) = pending.pop() {
+            for (a, b) in face_edges(&faces[i_face]) {
+                let fwd = (a < b) != flip[i_face];
+                // Edges with more than two faces are non-manifold, these do not tell anything
+                let [f0, f1] = edge_faces[&(a.min(b), a.max(b))][..] else {
+                    continue;
+                };
+                let (i_other, other_fwd) = if f0.0 == i_face { f1 } else { f0 };
+                if i_other == i_face || visited[i_other] {
+                    continue;
+                }
+                // The neighbor must go through the edge in the other direction
+                flip[i_other] = other_fwd == fwd;
+                visited[i_other] = true;
+                component.push(i_other);
+                pending.push(i_other);
+            }
+        }
+        let num_flipped = component.iter().filter(|&&i| flip[i]).count();
+        if 2 * num_flipped > component.len() {
+            for i in component {
+                flip[i] = !flip[i];
+            }
+        }
+    }
+
+    for (face, flip) in faces.iter_mut().zip(flip) {
+        if flip {
+            face.verts.reverse();
+            face.flipped = true;
+        }
+    }
+}
+
+// The normals of the reversed faces must be reversed, too. A vertex used only by reversed faces
+// is just changed, but if it is shared with other faces it is duplicated.
+fn flip_normals(faces: &mut [CleanFace], vertices: &mut Vec<Vertex>, weld_ids: &mut Vec<u32>) {
+    let mut unflipped_uses = vec![false; vertices.len()];
+    for face in faces.iter().filter(|f| !f.flipped) {
+        for &i_v in &face.verts {
+            unflipped_uses[usize::from(i_v)] = true;
+        }
+    }
+    // Old vertex -> vertex with the normal reversed
+    let mut flipped: FxHashMap<VertexIndex, VertexIndex> = FxHashMap::default();
+    for face in faces.iter_mut().filter(|f| f.flipped) {
+        for i_v in &mut face.verts {
+            *i_v = *flipped.entry(*i_v).or_insert_with(|| {
+                let old = usize::from(*i_v);
+                if unflipped_uses[old] {
+                    let v = &vertices[old];
+                    vertices.push(Vertex {
+                        pos: v.pos,
+                        normal: -v.normal,
+                        uv: v.uv,
+                    });
+                    weld_ids.push(weld_ids[old]);
+                    VertexIndex::from(vertices.len() - 1)
+                } else {
+                    vertices[old].normal = -vertices[old].normal;
+                    *i_v
+                }
+            });
+        }
+    }
+}
+
+impl<I: Importer> Importer for CleanupImporter<I> {
+    type VertexId = u32;
+
+    fn vertex_map(&self, i_v: VertexIndex) -> Self::VertexId {
+        self.weld_ids[usize::from(i_v)]
+    }
+    fn build_vertices(&self) -> (bool, Vec<Vertex>) {
+        (self.has_normals, self.vertices.take())
+    }
+    fn face_count(&self) -> usize {
+        self.faces.len()
+    }
+    fn faces(&self) -> impl Iterator<Item = (impl AsRef<[VertexIndex]>, MaterialIndex)> {
+        self.faces.iter().map(|f| (f.verts.as_slice(), f.mat))
+    }
+    fn build_textures(&self) -> Vec<Texture> {
+        self.inner.build_textures()
+    }
+    fn compute_edge_status(&self, edge_id: (u32, u32)) -> Option<EdgeStatus> {
+        let inner_id = |id: u32| self.inner.vertex_map(self.weld_reps[id as usize]);
+        self.inner
+            .compute_edge_status((inner_id(edge_id.0), inner_id(edge_id.1)))
+    }
+    fn relocate_islands<'a>(
+        &self,
+        model: &Model,
+        islands: impl Iterator<Item = &'a mut Island>,
+    ) -> bool {
+        // The inner importer may expect its own faces, so only if none is missing
+        self.faces.len() == self.inner.face_count() && self.inner.relocate_islands(model, islands)
+    }
+    fn build_options(&self) -> Option<PaperOptions> {
+        self.inner.build_options()
+    }
+    fn part_names(&self) -> Vec<String> {
+        self.inner.part_names()
+    }
+    fn face_part(&self, i_face: usize) -> PartIndex {
+        self.inner.face_part(self.faces[i_face].source)
+    }
+    fn face_flipped(&self, i_face: usize) -> bool {
+        self.faces[i_face].flipped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A minimal importer, each vertex is its own id
+    struct Mesh {
+        vertices: Vec<(Vector3, Vector3)>,
+        faces: Vec<Vec<VertexIndex>>,
+    }
+
+    impl Importer for Mesh {
+        type VertexId = usize;
+
+        fn vertex_map(&self, i_v: VertexIndex) -> usize {
+            usize::from(i_v)
+        }
+        fn build_vertices(&self) -> (bool, Vec<Vertex>) {
+            let vs = self
+                .vertices
+                .iter()
+                .map(|&(pos, normal)| Vertex {
+                    pos,
+                    normal,
+                    uv: Vector2::zero(),
+                })
+                .collect();
+            (true, vs)
+        }
+        fn face_count(&self) -> usize {
+            self.faces.len()
+        }
+        fn faces(&self) -> impl Iterator<Item = (impl AsRef<[VertexIndex]>, MaterialIndex)> {
+            self.faces.iter().map(|f| (f, MaterialIndex::from(0)))
+        }
+        fn build_textures(&self) -> Vec<Texture> {
+            vec![Texture::default()]
+        }
+    }
+
+    fn mesh(ps: &[[f32; 3]], faces: &[&[usize]]) -> Mesh {
+        let up = Vector3::new(0.0, 0.0, 1.0);
+        Mesh {
+            vertices: ps.iter().map(|&p| (Vector3::from(p), up)).collect(),
+            faces: faces
+                .iter()
+                .map(|f| f.iter().map(|&i| VertexIndex::from(i)).collect())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn weld_and_remove_bad_faces() {
+        // Two triangles of a square, each one with its own vertices, one of them a bit off
+        let m = mesh(
+            &[
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [0.0, 0.0, 0.0],
+                [1.0, 1.000001, 0.0],
+                [0.0, 1.0, 0.0],
+                [0.5, 0.5, 0.0],
+            ],
+            &[
+                &[0, 1, 2],
+                &[3, 4, 5],
+                // duplicated, in another order
+                &[4, 5, 0],
+                // in a line
+                &[0, 6, 2],
+                // two vertices welded into one
+                &[0, 3, 5],
+            ],
+        );
+        let mut report = ImportReport::default();
+        let clean = CleanupImporter::new(m, 1e-5, &mut report);
+        assert_eq!(report.welded_vertices, 2);
+        assert_eq!(report.duplicated_faces, 1);
+        assert_eq!(report.degenerate_faces, 2);
+        assert_eq!(clean.face_count(), 2);
+        assert_eq!(
+            clean.vertex_map(VertexIndex::from(0)),
+            clean.vertex_map(VertexIndex::from(3))
+        );
+        assert_eq!(
+            clean.vertex_map(VertexIndex::from(2)),
+            clean.vertex_map(VertexIndex::from(4))
+        );
+        // The welded vertex is moved, so the faces fit together
+        let (_, vs) = clean.build_vertices();
+        assert_eq!(vs[4].pos, vs[2].pos);
+    }
+
+    #[test]
+    fn winding_and_normals() {
+        // A strip of triangles, the second one reversed, with its normals matching its winding
+        let mut m = mesh(
+            &[
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [2.0, 1.0, 0.0],
+                [2.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0],
+            ],
+            &[&[0, 1, 2], &[6, 7, 3], &[1, 4, 3], &[1, 5, 4]],
+        );
+        m.vertices[6].1 = -m.vertices[6].1;
+        m.vertices[7].1 = -m.vertices[7].1;
+        let original: Vec<Vec<Vector3>> = m
+            .faces
+            .iter()
+            .map(|f| f.iter().map(|&i| m.vertices[usize::from(i)].1).collect())
+            .collect();
+
+        let mut report = ImportReport::default();
+        let clean = CleanupImporter::new(m, 1e-5, &mut report);
+        assert_eq!(report.welded_vertices, 2);
+        let flipped: Vec<_> = (0..clean.face_count())
+            .map(|i| clean.face_flipped(i))
+            .collect();
+        assert_eq!(flipped, [false, true, false, false]);
+
+        let (_, vs) = clean.build_vertices();
+        let faces: Vec<Vec<VertexIndex>> =
+            clean.faces().map(|(f, _)| f.as_ref().to_vec()).collect();
+        // Vertex 3 is shared with an unflipped face, so it is duplicated, the others are changed
+        assert_eq!(vs.len(), 8 + 1);
+        assert_eq!(faces[1].len(), 3);
+        for (i_face, face) in faces.iter().enumerate() {
+            let mut normals: Vec<Vector3> =
+                face.iter().map(|&i| vs[usize::from(i)].normal).collect();
+            if flipped[i_face] {
+                normals.reverse();
+                for n in &mut normals {
+                    *n = -*n;
+                }
+            }
+            assert_eq!(normals, original[i_face]);
+        }
+        // The reversed face now agrees with its neighbors and with its own normals
+        let pos = |i: VertexIndex| vs[usize::from(i)].pos;
+        let [a, b, c] = [0, 1, 2].map(|i| pos(faces[1][i]));
+        assert!((b - a).cross(c - a).z > 0.0);
+        assert!(vs[usize::from(faces[1][1])].normal.z > 0.0);
+        assert_eq!(
+            clean.vertex_map(faces[1][0]),
+            clean.vertex_map(VertexIndex::from(3))
+        );
+    }
+}
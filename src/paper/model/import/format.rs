@@ -0,0 +1,203 @@
+use super::*;
+use std::io::{Seek, SeekFrom};
+
+// How many bytes are read to guess the format of a file
+const SNIFF_SIZE: usize = 4096;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ModelFormat {
+    Craft,
+    Pepakura,
+    Stl,
+    Gltf,
+    Ply,
+    ThreeMf,
+    WaveObj,
+}
+
+impl ModelFormat {
+    pub fn from_extension(ext: &str) -> Option<ModelFormat> {
+        let f = match ext.to_ascii_lowercase().as_str() {
+            "craft" => ModelFormat::Craft,
+            "pdo" => ModelFormat::Pepakura,
+            "stl" => ModelFormat::Stl,
+            "gltf" | "glb" => ModelFormat::Gltf,
+            "ply" => ModelFormat::Ply,
+            "3mf" => ModelFormat::ThreeMf,
+            "obj" => ModelFormat::WaveObj,
+            _ => return None,
+        };
+        Some(f)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ModelFormat::Craft => "Papercraft",
+            ModelFormat::Pepakura => "Pepakura",
+            ModelFormat::Stl => "STL",
+            ModelFormat::Gltf => "glTF",
+            ModelFormat::Ply => "PLY",
+            ModelFormat::ThreeMf => "3MF",
+            ModelFormat::WaveObj => "Wavefront",
+        }
+    }
+
+    // Guesses the format from the contents of the file, the reader is rewound after that.
+    // Returns None if it does not look like anything known.
+    pub fn sniff<R: Read + Seek>(r: &mut R) -> Result<Option<ModelFormat>> {
+        let len = r.seek(SeekFrom::End(0))?;
+        r.rewind()?;
+        let mut hdr = Vec::with_capacity(SNIFF_SIZE);
+        r.by_ref().take(SNIFF_SIZE as u64).read_to_end(&mut hdr)?;
+        r.rewind()?;
+
+        let format = if hdr.starts_with(b"PK\x03\x04") {
+            // Both are zip files, look inside
+            let zip = zip::ZipArchive::new(&mut *r);
+            let res = zip.ok().and_then(|mut zip| {
+                if zip.by_name("model.json").is_ok() {
+                    Some(ModelFormat::Craft)
+                } else if zip.file_names().any(|n| n.ends_with(".model")) {
+                    Some(ModelFormat::ThreeMf)
+                } else {
+                    None
+                }
+            });
+            r.rewind()?;
+            res
+        } else if hdr.starts_with(b"version 3\n") {
+            Some(ModelFormat::Pepakura)
+        } else if hdr.starts_with(b"glTF") {
+            Some(ModelFormat::Gltf)
+        } else if hdr.starts_with(b"ply\n") || hdr.starts_with(b"ply\r\n") {
+            Some(ModelFormat::Ply)
+        } else if is_binary_stl(&hdr, len) || is_text_stl(&hdr) {
+            Some(ModelFormat::Stl)
+        } else if is_gltf_json(&hdr) {
+            Some(ModelFormat::Gltf)
+        } else if is_wavefront(&hdr) {
+            Some(ModelFormat::WaveObj)
+        } else {
+            None
+        };
+        Ok(format)
+    }
+}
+
+// Binary STL have a header of 80 bytes, that may be anything, even "solid", and then the
+// number of triangles, 50 bytes each.
+fn is_binary_stl(hdr: &[u8], len: u64) -> bool {
+    let Some(n) = hdr.get(80..84) else {
+        return false;
+    };
+    let n = u32::from_le_bytes(n.try_into().unwrap());
+    n > 0 && len == 84 + 50 * u64::from(n)
+}
+
+fn is_text_stl(hdr: &[u8]) -> bool {
+    let Some(rest) = hdr.strip_prefix(b"solid") else {
+        return false;
+    };
+    // The name is followed by the first facet, if any
+    let text = String::from_utf8_lossy(rest);
+    text.split_ascii_whitespace()
+        .any(|w| w == "facet" || w == "endsolid")
+}
+
+fn is_gltf_json(hdr: &[u8]) -> bool {
+    let text = String::from_utf8_lossy(hdr);
+    text.trim_start().starts_with('{') && text.contains("\"asset\"")
+}
+
+// The first line that is not a comment must be a known keyword
+fn is_wavefront(hdr: &[u8]) -> bool {
+    const KEYWORDS: &[&str] = &[
+        "v", "vt", "vn", "vp", "f", "l", "o", "g", "s", "mtllib", "usemtl",
+    ];
+    let text = String::from_utf8_lossy(hdr);
+    text.lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty() && !l.starts_with('#'))
+        .and_then(|l| l.split_ascii_whitespace().next())
+        .is_some_and(|w| KEYWORDS.contains(&w))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+
+    fn sniff(data: &[u8]) -> Option<ModelFormat> {
+        let mut r = Cursor::new(data);
+        let res = ModelFormat::sniff(&mut r).unwrap();
+        // It is ready to be read again
+        assert_eq!(r.position(), 0);
+        res
+    }
+
+    fn zip_with(name: &str) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file(name, zip::write::FileOptions::default())
+            .unwrap();
+        zip.write_all(b"{}").unwrap();
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn text_formats() {
+        assert_eq!(sniff(b"ply\nformat ascii 1.0\n"), Some(ModelFormat::Ply));
+        assert_eq!(
+            sniff(b"ply\r\nformat ascii 1.0\r\n"),
+            Some(ModelFormat::Ply)
+        );
+        assert_eq!(sniff(b"version 3\n"), Some(ModelFormat::Pepakura));
+        assert_eq!(
+            sniff(b"solid cube\n  facet normal 0 0 1\n"),
+            Some(ModelFormat::Stl)
+        );
+        assert_eq!(
+            sniff(b" {\n \"asset\": { \"version\": \"2.0\" } }"),
+            Some(ModelFormat::Gltf)
+        );
+        assert_eq!(
+            sniff(b"# comment\n\nmtllib a.mtl\nv 0 0 0\n"),
+            Some(ModelFormat::WaveObj)
+        );
+        assert_eq!(sniff(b"hello world\n"), None);
+        assert_eq!(sniff(b""), None);
+    }
+
+    #[test]
+    fn binary_formats() {
+        assert_eq!(sniff(b"glTF\x02\0\0\0"), Some(ModelFormat::Gltf));
+
+        // A binary STL may start with "solid", only the size tells them apart
+        let mut stl = b"solid but binary".to_vec();
+        stl.resize(80, 0);
+        stl.extend(2u32.to_le_bytes());
+        stl.resize(84 + 2 * 50, 0);
+        assert_eq!(sniff(&stl), Some(ModelFormat::Stl));
+        stl.push(0);
+        assert_eq!(sniff(&stl), None);
+    }
+
+    #[test]
+    fn zip_formats() {
+        assert_eq!(sniff(&zip_with("model.json")), Some(ModelFormat::Craft));
+        assert_eq!(
+            sniff(&zip_with("3D/3dmodel.model")),
+            Some(ModelFormat::ThreeMf)
+        );
+        assert_eq!(sniff(&zip_with("something.txt")), None);
+    }
+
+    #[test]
+    fn extensions() {
+        assert_eq!(ModelFormat::from_extension("GLB"), Some(ModelFormat::Gltf));
+        assert_eq!(
+            ModelFormat::from_extension("3mf"),
+            Some(ModelFormat::ThreeMf)
+        );
+        assert_eq!(ModelFormat::from_extension("mtl"), None);
+    }
+}
@@ -0,0 +1,523 @@
+use crate::paper::import::*;
+use crate::util_3d::{Matrix4, Quaternion};
+use base64::prelude::*;
+use cgmath::One;
+use fxhash::FxHashMap;
+use image::DynamicImage;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+// Only the parts of a glTF 2.0 document that are needed to import the model are here
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Document {
+    scene: Option<usize>,
+    #[serde(default)]
+    scenes: Vec<Scene>,
+    #[serde(default)]
+    nodes: Vec<Node>,
+    #[serde(default)]
+    meshes: Vec<Mesh>,
+    #[serde(default)]
+    accessors: Vec<Accessor>,
+    #[serde(default)]
+    buffer_views: Vec<BufferView>,
+    #[serde(default)]
+    buffers: Vec<Buffer>,
+    #[serde(default)]
+    materials: Vec<Material>,
+    #[serde(default)]
+    textures: Vec<TextureDef>,
+    #[serde(default)]
+    images: Vec<Image>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Scene {
+    #[serde(default)]
+    nodes: Vec<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Node {
+    mesh: Option<usize>,
+    #[serde(default)]
+    children: Vec<usize>,
+    matrix: Option<[f32; 16]>,
+    translation: Option<[f32; 3]>,
+    rotation: Option<[f32; 4]>, // x, y, z, w
+    scale: Option<[f32; 3]>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Mesh {
+    primitives: Vec<Primitive>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Primitive {
+    pub attributes: FxHashMap<String, usize>,
+    pub indices: Option<usize>,
+    pub material: Option<usize>,
+    #[serde(default = "default_mode")]
+    pub mode: u32,
+}
+
+// Triangles
+fn default_mode() -> u32 {
+    4
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Accessor {
+    buffer_view: Option<usize>,
+    #[serde(default)]
+    byte_offset: usize,
+    component_type: u32,
+    #[serde(default)]
+    normalized: bool,
+    count: usize,
+    #[serde(rename = "type")]
+    kind: String,
+    sparse: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BufferView {
+    buffer: usize,
+    #[serde(default)]
+    byte_offset: usize,
+    byte_length: usize,
+    byte_stride: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Buffer {
+    uri: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Material {
+    pub name: Option<String>,
+    #[serde(default)]
+    pub pbr_metallic_roughness: PbrMetallicRoughness,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PbrMetallicRoughness {
+    #[serde(default = "default_base_color")]
+    pub base_color_factor: [f32; 4],
+    pub base_color_texture: Option<TextureInfo>,
+}
+
+fn default_base_color() -> [f32; 4] {
+    [1.0, 1.0, 1.0, 1.0]
+}
+
+impl Default for PbrMetallicRoughness {
+    fn default() -> Self {
+        PbrMetallicRoughness {
+            base_color_factor: default_base_color(),
+            base_color_texture: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TextureInfo {
+    pub index: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct TextureDef {
+    source: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Image {
+    uri: Option<String>,
+    buffer_view: Option<usize>,
+    mime_type: Option<String>,
+    name: Option<String>,
+}
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const GLB_CHUNK_JSON: u32 = 0x4E4F534A;
+const GLB_CHUNK_BIN: u32 = 0x004E4942;
+
+pub struct Gltf {
+    doc: Document,
+    buffers: Vec<Vec<u8>>,
+    // Relative URIs are relative to this directory
+    base_dir: PathBuf,
+}
+
+impl Gltf {
+    // Reads both the JSON .gltf and the binary .glb formats
+    pub fn from_reader<R: Read>(mut r: R, file_name: &Path) -> Result<Gltf> {
+        let mut data = Vec::new();
+        r.read_to_end(&mut data)?;
+
+        let (json, mut bin) = if data.starts_with(GLB_MAGIC) {
+            split_glb(&data)?
+        } else {
+            (&data[..], None)
+        };
+        let doc: Document = serde_json::from_slice(json)?;
+        let base_dir = file_name
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+
+        let mut buffers = Vec::with_capacity(doc.buffers.len());
+        for (i_buffer, buffer) in doc.buffers.iter().enumerate() {
+            let data = match &buffer.uri {
+                Some(uri) => read_uri(uri, &base_dir)
+                    .with_context(|| format!("Error reading buffer #{i_buffer}"))?,
+                // A buffer without URI is the binary chunk of the GLB
+                None => match bin.take() {
+                    Some(bin) => bin.to_vec(),
+                    None => bail!("Buffer #{i_buffer} has no data"),
+                },
+            };
+            buffers.push(data);
+        }
+        Ok(Gltf {
+            doc,
+            buffers,
+            base_dir,
+        })
+    }
+
+    // All the meshes in the scene, with their global transformation
+    pub fn mesh_instances(&self) -> Vec<(usize, Matrix4)> {
+        let doc = &self.doc;
+        let roots = match doc
+            .scene
+            .or(if doc.scenes.is_empty() { None } else { Some(0) })
+        {
+            Some(i_scene) => doc
+                .scenes
+                .get(i_scene)
+                .map(|s| s.nodes.clone())
+                .unwrap_or_default(),
+            // Without scenes, any node that is not a child is a root
+            None => {
+                let mut is_child = vec![false; doc.nodes.len()];
+                for &c in doc.nodes.iter().flat_map(|n| &n.children) {
+                    if let Some(x) = is_child.get_mut(c) {
+                        *x = true;
+                    }
+                }
+                (0..doc.nodes.len()).filter(|&i| !is_child[i]).collect()
+            }
+        };
+
+        let mut res = Vec::new();
+        let mut stack: Vec<_> = roots
+            .into_iter()
+            .map(|i_node| (i_node, Matrix4::one(), 0))
+            .collect();
+        while let Some((i_node, parent, depth)) = stack.pop() {
+            // Avoid infinite loops with broken files
+            let Some(node) = doc.nodes.get(i_node) else {
+                continue;
+            };
+            if depth > doc.nodes.len() {
+                continue;
+            }
+            let mx = parent * node.transformation();
+            if let Some(i_mesh) = node.mesh {
+                res.push((i_mesh, mx));
+            }
+            stack.extend(node.children.iter().map(|&c| (c, mx, depth + 1)));
+        }
+        // Some files have meshes but no nodes, just take them all
+        if res.is_empty() && doc.nodes.is_empty() {
+            res = (0..doc.meshes.len())
+                .map(|i_mesh| (i_mesh, Matrix4::one()))
+                .collect();
+        }
+        res
+    }
+
+    pub fn primitives(&self, i_mesh: usize) -> Result<&[Primitive]> {
+        let mesh = self
+            .doc
+            .meshes
+            .get(i_mesh)
+            .ok_or_else(|| anyhow!("Invalid mesh #{i_mesh}"))?;
+        Ok(&mesh.primitives)
+    }
+
+    pub fn materials(&self) -> &[Material] {
+        &self.doc.materials
+    }
+
+    // Reads an accessor of floats, with `components` values per element.
+    // Integer values, such as normalized UVs, are converted.
+    pub fn read_floats(&self, i_accessor: usize, components: usize) -> Result<Vec<f32>> {
+        self.read_accessor(i_accessor, components, |bytes, acc| {
+            read_component(bytes, acc.component_type, acc.normalized)
+        })
+    }
+
+    pub fn read_indices(&self, i_accessor: usize) -> Result<Vec<u32>> {
+        self.read_accessor(i_accessor, 1, |bytes, acc| {
+            let i = match acc.component_type {
+                5121 => bytes[0] as u32,
+                5123 => u16::from_le_bytes([bytes[0], bytes[1]]) as u32,
+                5125 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+                t => bail!("Invalid index type {t}"),
+            };
+            Ok(i)
+        })
+    }
+
+    fn read_accessor<T: Default + Clone>(
+        &self,
+        i_accessor: usize,
+        components: usize,
+        read: impl Fn(&[u8], &Accessor) -> Result<T>,
+    ) -> Result<Vec<T>> {
+        let acc = self
+            .doc
+            .accessors
+            .get(i_accessor)
+            .ok_or_else(|| anyhow!("Invalid accessor #{i_accessor}"))?;
+        let acc_components = match acc.kind.as_str() {
+            "SCALAR" => 1,
+            "VEC2" => 2,
+            "VEC3" => 3,
+            "VEC4" => 4,
+            _ => 0,
+        };
+        if acc_components != components {
+            bail!(
+                "Accessor #{i_accessor} is {}, expected {components} components",
+                acc.kind
+            );
+        }
+        if acc.sparse.is_some() {
+            bail!("Sparse accessors are not supported");
+        }
+        // An accessor without buffer view is all zeros
+        let Some(i_view) = acc.buffer_view else {
+            return Ok(vec![T::default(); acc.count * components]);
+        };
+        let view = self
+            .doc
+            .buffer_views
+            .get(i_view)
+            .ok_or_else(|| anyhow!("Invalid buffer view #{i_view}"))?;
+        let buffer = self
+            .buffers
+            .get(view.buffer)
+            .ok_or_else(|| anyhow!("Invalid buffer #{}", view.buffer))?;
+        let view_end = view.byte_offset + view.byte_length;
+        if view_end > buffer.len() {
+            bail!("Buffer view #{i_view} out of bounds");
+        }
+        let view_data = &buffer[view.byte_offset..view_end];
+
+        let component_size = component_size(acc.component_type)?;
+        let elem_size = components * component_size;
+        let stride = view.byte_stride.unwrap_or(elem_size);
+        if acc.count > 0 && acc.byte_offset + (acc.count - 1) * stride + elem_size > view_data.len()
+        {
+            bail!("Accessor #{i_accessor} out of bounds");
+        }
+
+        let mut res = Vec::with_capacity(acc.count * components);
+        for i in 0..acc.count {
+            let elem = acc.byte_offset + i * stride;
+            for c in 0..components {
+                let offs = elem + c * component_size;
+                res.push(read(&view_data[offs..offs + component_size], acc)?);
+            }
+        }
+        Ok(res)
+    }
+
+    // Returns the name and the decoded image of a texture
+    pub fn texture_image(&self, i_texture: usize) -> Result<(String, DynamicImage)> {
+        let i_image = self
+            .doc
+            .textures
+            .get(i_texture)
+            .and_then(|t| t.source)
+            .ok_or_else(|| anyhow!("Invalid texture #{i_texture}"))?;
+        let image = self
+            .doc
+            .images
+            .get(i_image)
+            .ok_or_else(|| anyhow!("Invalid image #{i_image}"))?;
+
+        let ext = match image.mime_type.as_deref() {
+            Some("image/jpeg") => "jpg",
+            _ => "png",
+        };
+        let (name, data) = match (&image.uri, image.buffer_view) {
+            (Some(uri), _) => {
+                let data = read_uri(uri, &self.base_dir)?;
+                let name = if uri.starts_with("data:") {
+                    None
+                } else {
+                    Path::new(&percent_decode(uri))
+                        .file_name()
+                        .map(|f| f.to_string_lossy().into_owned())
+                };
+                (name, data)
+            }
+            (None, Some(i_view)) => {
+                let view = self
+                    .doc
+                    .buffer_views
+                    .get(i_view)
+                    .ok_or_else(|| anyhow!("Invalid buffer view #{i_view}"))?;
+                let data = self
+                    .buffers
+                    .get(view.buffer)
+                    .and_then(|b| b.get(view.byte_offset..view.byte_offset + view.byte_length))
+                    .ok_or_else(|| anyhow!("Buffer view #{i_view} out of bounds"))?;
+                (None, data.to_vec())
+            }
+            (None, None) => bail!("Image #{i_image} has no data"),
+        };
+        let name = name.unwrap_or_else(|| match &image.name {
+            Some(n) => format!("{n}.{ext}"),
+            None => format!("image{i_image}.{ext}"),
+        });
+        let img = image::io::Reader::new(std::io::Cursor::new(&data))
+            .with_guessed_format()?
+            .decode()?;
+        Ok((name, img))
+    }
+}
+
+impl Node {
+    fn transformation(&self) -> Matrix4 {
+        if let Some(m) = self.matrix {
+            // Column-major, the same as cgmath
+            let mut cols = [[0.0; 4]; 4];
+            for (i, col) in cols.iter_mut().enumerate() {
+                col.copy_from_slice(&m[4 * i..4 * i + 4]);
+            }
+            return Matrix4::from(cols);
+        }
+        let mut mx = Matrix4::one();
+        if let Some([x, y, z]) = self.translation {
+            mx = mx * Matrix4::from_translation(Vector3::new(x, y, z));
+        }
+        if let Some([x, y, z, w]) = self.rotation {
+            mx = mx * Matrix4::from(Quaternion::new(w, x, y, z));
+        }
+        if let Some([x, y, z]) = self.scale {
+            mx = mx * Matrix4::from_nonuniform_scale(x, y, z);
+        }
+        mx
+    }
+}
+
+// Returns the JSON chunk and maybe the BIN chunk
+fn split_glb(data: &[u8]) -> Result<(&[u8], Option<&[u8]>)> {
+    let rdr = &mut &data[4..];
+    let version = read_u32(rdr)?;
+    if version != 2 {
+        bail!("Unsupported GLB version {version}");
+    }
+    let length = read_u32(rdr)? as usize;
+    let data = data
+        .get(..length)
+        .ok_or_else(|| anyhow!("Truncated GLB file"))?;
+
+    let mut json = None;
+    let mut bin = None;
+    let mut offs = 12;
+    while offs + 8 <= data.len() {
+        let rdr = &mut &data[offs..];
+        let chunk_len = read_u32(rdr)? as usize;
+        let chunk_type = read_u32(rdr)?;
+        let chunk = data
+            .get(offs + 8..offs + 8 + chunk_len)
+            .ok_or_else(|| anyhow!("Truncated GLB chunk"))?;
+        match chunk_type {
+            GLB_CHUNK_JSON if json.is_none() => json = Some(chunk),
+            GLB_CHUNK_BIN if bin.is_none() => bin = Some(chunk),
+            // unknown chunks must be ignored
+            _ => {}
+        }
+        offs += 8 + chunk_len;
+    }
+    let json = json.ok_or_else(|| anyhow!("GLB file without JSON chunk"))?;
+    Ok((json, bin))
+}
+
+// Reads an embedded "data:" URI or an external file
+fn read_uri(uri: &str, base_dir: &Path) -> Result<Vec<u8>> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let (_, b64) = data
+            .split_once(";base64,")
+            .ok_or_else(|| anyhow!("Only base64 data URIs are supported"))?;
+        return Ok(BASE64_STANDARD.decode(b64)?);
+    }
+    let path = base_dir.join(percent_decode(uri));
+    let data = std::fs::read(&path).with_context(|| format!("Error reading {}", path.display()))?;
+    Ok(data)
+}
+
+// URIs in glTF may have escaped characters, such as "%20"
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut res = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(x)) => {
+                res.push(x);
+                i += 3;
+            }
+            (b, _) => {
+                res.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&res).into_owned()
+}
+
+fn component_size(component_type: u32) -> Result<usize> {
+    let size = match component_type {
+        5120 | 5121 => 1, // byte, unsigned byte
+        5122 | 5123 => 2, // short, unsigned short
+        5125 | 5126 => 4, // unsigned int, float
+        t => bail!("Invalid component type {t}"),
+    };
+    Ok(size)
+}
+
+fn read_component(bytes: &[u8], component_type: u32, normalized: bool) -> Result<f32> {
+    let x = match (component_type, normalized) {
+        (5120, false) => bytes[0] as i8 as f32,
+        (5120, true) => (bytes[0] as i8 as f32 / 127.0).max(-1.0),
+        (5121, false) => bytes[0] as f32,
+        (5121, true) => bytes[0] as f32 / 255.0,
+        (5122, false) => i16::from_le_bytes([bytes[0], bytes[1]]) as f32,
+        (5122, true) => (i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32767.0).max(-1.0),
+        (5123, false) => u16::from_le_bytes([bytes[0], bytes[1]]) as f32,
+        (5123, true) => u16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 65535.0,
+        (5125, _) => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32,
+        (5126, _) => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        (t, _) => bail!("Invalid component type {t}"),
+    };
+    Ok(x)
+}
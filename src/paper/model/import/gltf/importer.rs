@@ -0,0 +1,352 @@
+use std::cell::Cell;
+
+use super::super::*;
+use super::data;
+use cgmath::{EuclideanSpace, InnerSpace, Matrix, Point3, SquareMatrix, Transform, Zero};
+use fxhash::FxHashSet;
+use image::{DynamicImage, RgbImage};
+
+pub struct GltfImporter {
+    vertices: Vec<Vertex>,
+    // VertexIndex -> position. glTF splits the vertices in the UV seams, but for us they
+    // are the same vertex, or the model would be cut there.
+    vertex_ids: Vec<[u32; 3]>,
+    faces: Vec<([VertexIndex; 3], MaterialIndex)>,
+    has_normals: bool,
+    // Built in advance because reading them may fail, consumed by build_textures()
+    textures: Cell<Vec<Texture>>,
+}
+
+impl GltfImporter {
+    pub fn new<R: BufRead>(f: R, file_name: &Path) -> Result<Self> {
+        let gltf = data::Gltf::from_reader(f, file_name)?;
+
+        let num_materials = gltf.materials().len();
+        // Primitives without material use an additional default one
+        let default_material = MaterialIndex::from(num_materials);
+
+        let mut vertices = Vec::new();
+        let mut vertex_ids = Vec::new();
+        let mut faces = Vec::new();
+        let mut has_normals = true;
+
+        for (i_mesh, mx) in gltf.mesh_instances() {
+            let mx_normal = mx.invert().unwrap_or(mx).transpose();
+            // A mirrored node inverts the winding of the triangles
+            let mirrored = mx.determinant() < 0.0;
+
+            for prim in gltf.primitives(i_mesh)? {
+                let tris = match prim.mode {
+                    4..=6 => prim.mode,
+                    // Points and lines are not useful here
+                    _ => continue,
+                };
+                let Some(&i_pos) = prim.attributes.get("POSITION") else {
+                    continue;
+                };
+                let err_mesh = || format!("Error reading mesh #{i_mesh}");
+                let pos = gltf.read_floats(i_pos, 3).with_context(err_mesh)?;
+                let num_vs = pos.len() / 3;
+                let normals = match prim.attributes.get("NORMAL") {
+                    Some(&i_normal) => Some(gltf.read_floats(i_normal, 3).with_context(err_mesh)?),
+                    None => None,
+                };
+                let uvs = match prim.attributes.get("TEXCOORD_0") {
+                    Some(&i_uv) => Some(gltf.read_floats(i_uv, 2).with_context(err_mesh)?),
+                    None => None,
+                };
+                if normals.as_ref().is_some_and(|n| n.len() != pos.len())
+                    || uvs.as_ref().is_some_and(|t| t.len() / 2 != num_vs)
+                {
+                    bail!("Inconsistent vertex attributes in mesh #{i_mesh}");
+                }
+                if normals.is_none() {
+                    has_normals = false;
+                }
+
+                let base = vertices.len();
+                for i in 0..num_vs {
+                    let p = Vector3::new(pos[3 * i], pos[3 * i + 1], pos[3 * i + 2]);
+                    let p = mx.transform_point(Point3::from_vec(p)).to_vec();
+                    let normal = match &normals {
+                        Some(n) => {
+                            let n = Vector3::new(n[3 * i], n[3 * i + 1], n[3 * i + 2]);
+                            let n = mx_normal.transform_vector(n);
+                            if n.magnitude2() > 0.0 {
+                                n.normalize()
+                            } else {
+                                n
+                            }
+                        }
+                        None => Vector3::zero(),
+                    };
+                    // glTF UVs have the origin at the top-left, as we do
+                    let uv = match &uvs {
+                        Some(t) => Vector2::new(t[2 * i], t[2 * i + 1]),
+                        None => Vector2::zero(),
+                    };
+                    vertices.push(Vertex { pos: p, normal, uv });
+                    vertex_ids.push([p.x, p.y, p.z].map(f32::to_bits));
+                }
+
+                let indices = match prim.indices {
+                    Some(i_indices) => gltf.read_indices(i_indices).with_context(err_mesh)?,
+                    None => (0..num_vs as u32).collect(),
+                };
+                if indices.iter().any(|&i| i as usize >= num_vs) {
+                    bail!("Invalid vertex index in mesh #{i_mesh}");
+                }
+                let mat = match prim.material {
+                    Some(m) if m < num_materials => MaterialIndex::from(m),
+                    _ => default_material,
+                };
+                let tris: Vec<[u32; 3]> = match tris {
+                    // TRIANGLES
+                    4 => indices
+                        .chunks_exact(3)
+                        .map(|t| [t[0], t[1], t[2]])
+                        .collect(),
+                    // TRIANGLE_STRIP, every other triangle is reversed
+                    5 => indices
+                        .windows(3)
+                        .enumerate()
+                        .map(|(i, t)| {
+                            if i % 2 == 0 {
+                                [t[0], t[1], t[2]]
+                            } else {
+                                [t[1], t[0], t[2]]
+                            }
+                        })
+                        .collect(),
+                    // TRIANGLE_FAN
+                    _ => indices
+                        .windows(2)
+                        .skip(1)
+                        .map(|t| [indices[0], t[0], t[1]])
+                        .collect(),
+                };
+                faces.extend(tris.into_iter().map(|t| {
+                    let t = if mirrored { [t[0], t[2], t[1]] } else { t };
+                    (t.map(|i| VertexIndex::from(base + i as usize)), mat)
+                }));
+            }
+        }
+        if faces.is_empty() {
+            bail!("No triangles found in the model");
+        }
+
+        // The images first, so that the flat colors can get names different from all of them
+        let mut images = Vec::with_capacity(num_materials);
+        for (i_mat, mat) in gltf.materials().iter().enumerate() {
+            let image = match &mat.pbr_metallic_roughness.base_color_texture {
+                Some(info) => Some(gltf.texture_image(info.index).with_context(|| {
+                    let name = match &mat.name {
+                        Some(n) if !n.is_empty() => n.clone(),
+                        _ => format!("material{i_mat}"),
+                    };
+                    format!("Error reading the texture of material {name}")
+                })?),
+                None => None,
+            };
+            images.push(image);
+        }
+        let mut used_names: FxHashSet<String> = images
+            .iter()
+            .flatten()
+            .map(|(file_name, _)| file_name.clone())
+            .collect();
+
+        let mut textures = Vec::with_capacity(num_materials + 1);
+        for (mat, image) in gltf.materials().iter().zip(images) {
+            let tex = match image {
+                Some((file_name, img)) => Texture {
+                    file_name,
+                    pixbuf: Some(img),
+                },
+                None => {
+                    // A flat color is just a one-pixel texture
+                    let [r, g, b, _] = mat
+                        .pbr_metallic_roughness
+                        .base_color_factor
+                        .map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+                    let img = RgbImage::from_pixel(1, 1, image::Rgb([r, g, b]));
+                    Texture {
+                        file_name: generated_texture_name("color", &mut used_names),
+                        pixbuf: Some(DynamicImage::ImageRgb8(img)),
+                    }
+                }
+            };
+            textures.push(tex);
+        }
+        textures.push(Texture::default());
+
+        Ok(GltfImporter {
+            vertices,
+            vertex_ids,
+            faces,
+            has_normals,
+            textures: Cell::new(textures),
+        })
+    }
+}
+
+impl Importer for GltfImporter {
+    type VertexId = [u32; 3];
+
+    fn vertex_map(&self, i_v: VertexIndex) -> Self::VertexId {
+        self.vertex_ids[usize::from(i_v)]
+    }
+    fn build_vertices(&self) -> (bool, Vec<Vertex>) {
+        let vs = self
+            .vertices
+            .iter()
+            .map(|v| Vertex {
+                pos: v.pos,
+                normal: v.normal,
+                uv: v.uv,
+            })
+            .collect();
+        (self.has_normals, vs)
+    }
+    fn face_count(&self) -> usize {
+        self.faces.len()
+    }
+    fn faces(&self) -> impl Iterator<Item = (impl AsRef<[VertexIndex]>, MaterialIndex)> {
+        self.faces.iter().copied()
+    }
+    fn build_textures(&self) -> Vec<Texture> {
+        self.textures.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::prelude::*;
+
+    const SQUARE: [u16; 6] = [0, 1, 2, 0, 2, 3];
+
+    // A unit square in the XY plane, as 4 positions and the u16 indices
+    fn square_bin(indices: &[u16]) -> Vec<u8> {
+        let mut bin = Vec::new();
+        for p in [
+            [0.0f32, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [0.0, 1.0, 0.0],
+        ] {
+            for c in p {
+                bin.extend(c.to_le_bytes());
+            }
+        }
+        for i in indices {
+            bin.extend(i.to_le_bytes());
+        }
+        bin
+    }
+
+    fn square_json(buffer: &str, node: &str, mode: u32, count: usize) -> String {
+        let (index_len, buffer_len) = (2 * count, 48 + 2 * count);
+        format!(
+            r#"{{
+                "asset": {{ "version": "2.0" }},
+                "scene": 0,
+                "scenes": [ {{ "nodes": [0] }} ],
+                "nodes": [ {{ "mesh": 0, {node} }} ],
+                "meshes": [ {{ "primitives": [ {{
+                    "attributes": {{ "POSITION": 0 }}, "indices": 1, "material": 0, "mode": {mode}
+                }} ] }} ],
+                "materials": [ {{
+                    "name": "red", "pbrMetallicRoughness": {{ "baseColorFactor": [1, 0, 0, 1] }}
+                }} ],
+                "accessors": [
+                    {{ "bufferView": 0, "componentType": 5126, "count": 4, "type": "VEC3" }},
+                    {{ "bufferView": 1, "componentType": 5123, "count": {count}, "type": "SCALAR" }}
+                ],
+                "bufferViews": [
+                    {{ "buffer": 0, "byteOffset": 0, "byteLength": 48 }},
+                    {{ "buffer": 0, "byteOffset": 48, "byteLength": {index_len} }}
+                ],
+                "buffers": [ {{ {buffer} "byteLength": {buffer_len} }} ]
+            }}"#
+        )
+    }
+
+    fn glb(json: &str, bin: &[u8]) -> Vec<u8> {
+        fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8], pad: u8) {
+            let len = data.len().div_ceil(4) * 4;
+            out.extend((len as u32).to_le_bytes());
+            out.extend(kind);
+            out.extend(data);
+            out.resize(out.len() + len - data.len(), pad);
+        }
+        let mut chunks = Vec::new();
+        chunk(&mut chunks, b"JSON", json.as_bytes(), b' ');
+        chunk(&mut chunks, b"BIN\0", bin, 0);
+        let mut res = Vec::new();
+        res.extend(b"glTF");
+        res.extend(2u32.to_le_bytes());
+        res.extend((12 + chunks.len() as u32).to_le_bytes());
+        res.extend(chunks);
+        res
+    }
+
+    fn positions(imp: &GltfImporter, face: usize) -> [Vector3; 3] {
+        imp.faces[face].0.map(|i| imp.vertices[usize::from(i)].pos)
+    }
+
+    #[test]
+    fn glb_with_translation() {
+        let json = square_json("", r#""translation": [10, 0, 0]"#, 4, 6);
+        let data = glb(&json, &square_bin(&SQUARE));
+        let imp = GltfImporter::new(&data[..], Path::new("square.glb")).unwrap();
+        assert_eq!(imp.face_count(), 2);
+        assert!(!imp.has_normals);
+        assert!(imp
+            .vertices
+            .iter()
+            .all(|v| v.pos.x >= 10.0 && v.pos.x <= 11.0));
+        assert_eq!(positions(&imp, 1)[2], Vector3::new(10.0, 1.0, 0.0));
+
+        // The flat color becomes a one-pixel texture, plus the default material
+        let textures = imp.build_textures();
+        assert_eq!(textures.len(), 2);
+        assert_eq!(textures[0].file_name, "_papercraft_color0.png");
+        let img = textures[0].pixbuf.as_ref().unwrap().to_rgb8();
+        assert_eq!(img.get_pixel(0, 0).0, [255, 0, 0]);
+    }
+
+    #[test]
+    fn gltf_data_uri_mirrored() {
+        let uri = format!(
+            r#""uri": "data:application/octet-stream;base64,{}","#,
+            BASE64_STANDARD.encode(square_bin(&SQUARE))
+        );
+        let json = square_json(&uri, r#""scale": [-1, 1, 1]"#, 4, 6);
+        let imp = GltfImporter::new(json.as_bytes(), Path::new("square.gltf")).unwrap();
+        assert_eq!(imp.face_count(), 2);
+        // The mirror flips the winding back, so the normal still points to +Z
+        let [a, b, c] = positions(&imp, 0);
+        assert!((b - a).cross(c - a).z > 0.0);
+    }
+
+    #[test]
+    fn triangle_strip() {
+        let json = square_json("", r#""translation": [0, 0, 0]"#, 5, 4);
+        let data = glb(&json, &square_bin(&[0, 1, 3, 2]));
+        let imp = GltfImporter::new(&data[..], Path::new("strip.glb")).unwrap();
+        // Every other triangle of the strip is reversed, so all of them face +Z
+        assert_eq!(imp.face_count(), 2);
+        for face in 0..2 {
+            let [a, b, c] = positions(&imp, face);
+            assert!((b - a).cross(c - a).z > 0.0);
+        }
+    }
+
+    #[test]
+    fn bad_index() {
+        let bin = square_bin(&[0, 1, 2, 0, 2, 7]);
+        let data = glb(&square_json("", r#""translation": [0, 0, 0]"#, 4, 6), &bin);
+        assert!(GltfImporter::new(&data[..], Path::new("bad.glb")).is_err());
+    }
+}
@@ -1,14 +1,29 @@
 use anyhow::{anyhow, bail, Context, Result};
-use std::io::{BufRead, Read};
-use std::panic::catch_unwind;
+use std::io::{BufRead, Read, Seek};
+use std::panic::{catch_unwind, AssertUnwindSafe};
 use std::path::Path;
 
-use super::{EdgeStatus, Island, MaterialIndex, Model, PaperOptions, Texture, Vertex, VertexIndex};
+use super::{
+    EdgeIndex, EdgeStatus, FaceIndex, Island, MaterialIndex, Model, PaperOptions, PartIndex,
+    Texture, Vertex, VertexIndex,
+};
 use crate::paper::{FlapSide, PageOffset, Papercraft};
 use crate::util_3d::{Vector2, Vector3};
 
+pub use cleanup::CleanupImporter;
+pub use format::ModelFormat;
+pub use papercraft::PapercraftImporter;
+pub use simplify::{SimplifyImporter, SimplifyOptions};
+
+mod cleanup;
+mod format;
+pub mod gltf;
+mod papercraft;
 pub mod pepakura;
+pub mod ply;
+mod simplify;
 pub mod stl;
+pub mod threemf;
 pub mod waveobj;
 
 fn read_u8(rdr: &mut impl Read) -> Result<u8> {
@@ -67,6 +82,18 @@ fn read_vector3_f32(rdr: &mut impl Read) -> Result<Vector3> {
     Ok(Vector3::new(x, y, z))
 }
 
+// Generated textures, such as the flat colors, have names that are unlikely in a real file, and
+// they are checked anyway, because the textures are saved by name
+const GENERATED_TEXTURE_PREFIX: &str = "_papercraft_";
+
+// A name for a generated texture, different from all the names in `used`, and then added to it
+fn generated_texture_name(kind: &str, used: &mut fxhash::FxHashSet<String>) -> String {
+    (0..)
+        .map(|i| format!("{GENERATED_TEXTURE_PREFIX}{kind}{i}.png"))
+        .find(|name| used.insert(name.clone()))
+        .unwrap()
+}
+
 pub trait Importer: Sized {
     type VertexId: Copy + Eq + std::fmt::Debug;
 
@@ -99,12 +126,93 @@ pub trait Importer: Sized {
     fn build_options(&self) -> Option<PaperOptions> {
         None
     }
+    // Names of the parts of the model, if it has more than one
+    fn part_names(&self) -> Vec<String> {
+        Vec::new()
+    }
+    // The part of a face, by its position in faces(), only called if there are part_names()
+    fn face_part(&self, _i_face: usize) -> PartIndex {
+        PartIndex::from(0)
+    }
+    // If the winding of a face has been reversed, it is only used for the report
+    fn face_flipped(&self, _i_face: usize) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ImportOptions {
+    // Weld the vertices and fix the faces before building the model
+    pub cleanup: bool,
+    // Maximum distance between welded vertices, relative to the size of the model
+    pub weld_tolerance: f32,
+    // Reduce the number of faces, after the cleanup
+    pub simplify: Option<SimplifyOptions>,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions {
+            cleanup: false,
+            weld_tolerance: 1e-5,
+            simplify: None,
+        }
+    }
+}
+
+// The problems found in the mesh while importing it, and what was done about them
+#[derive(Debug, Default, Clone)]
+pub struct ImportReport {
+    // Vertices merged with another one at the same position
+    pub welded_vertices: usize,
+    // Faces removed because they have no area
+    pub degenerate_faces: usize,
+    // Faces removed because another one has the same vertices
+    pub duplicated_faces: usize,
+    // Faces that could not be added because they use the same edge twice
+    pub dropped_faces: usize,
+    // Faces with their winding reversed to match their neighbors
+    pub flipped_faces: Vec<FaceIndex>,
+    // Edges with more than two faces, the extra faces are not connected to it
+    pub non_manifold_edges: Vec<EdgeIndex>,
+    // Edges whose two faces have opposite winding
+    pub inverted_edges: Vec<EdgeIndex>,
 }
 
-// Returns (model, is_native_format)
-pub fn import_model_file(file_name: &Path) -> Result<(Papercraft, bool)> {
+impl ImportReport {
+    pub fn is_empty(&self) -> bool {
+        self.welded_vertices == 0
+            && self.degenerate_faces == 0
+            && self.duplicated_faces == 0
+            && self.dropped_faces == 0
+            && self.flipped_faces.is_empty()
+            && self.non_manifold_edges.is_empty()
+            && self.inverted_edges.is_empty()
+    }
+}
+
+// Returns (model, is_native_format, report)
+pub fn import_model_file(
+    file_name: &Path,
+    options: &ImportOptions,
+) -> Result<(Papercraft, bool, ImportReport)> {
+    let f = std::fs::File::open(file_name)
+        .with_context(|| format!("Error opening file {}", file_name.display()))?;
+    import_model_reader(f, file_name, options)
+}
+
+// Imports a model from any reader, such as stdin or a memory buffer.
+// `file_name` is used to find the related files, such as materials or textures, and its
+// extension is a hint of the format, but the contents are checked, too.
+pub fn import_model_reader<R: Read + Seek>(
+    r: R,
+    file_name: &Path,
+    options: &ImportOptions,
+) -> Result<(Papercraft, bool, ImportReport)> {
     // Models have a lot of indices and unwraps, a corrupted file could easily panic
-    match catch_unwind(|| import_model_file_priv(file_name)) {
+    match catch_unwind(AssertUnwindSafe(|| {
+        import_model_reader_priv(r, file_name, options)
+    })) {
         Ok(res) => res,
         Err(err) => {
             if let Some(msg) = err.downcast_ref::<&str>() {
@@ -119,48 +227,181 @@ pub fn import_model_file(file_name: &Path) -> Result<(Papercraft, bool)> {
         }
     }
 }
-pub fn import_model_file_priv(file_name: &Path) -> Result<(Papercraft, bool)> {
-    let ext = match file_name.extension() {
-        None => String::new(),
-        Some(ext) => {
-            let mut ext = ext.to_string_lossy().into_owned();
-            ext.make_ascii_lowercase();
-            ext
+
+fn import_model_reader_priv<R: Read + Seek>(
+    r: R,
+    file_name: &Path,
+    options: &ImportOptions,
+) -> Result<(Papercraft, bool, ImportReport)> {
+    let ext = file_name
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    let mut f = std::io::BufReader::new(r);
+
+    let sniffed = ModelFormat::sniff(&mut f)
+        .with_context(|| format!("Error reading file {}", file_name.display()))?;
+    let format = match (ModelFormat::from_extension(&ext), sniffed) {
+        (Some(by_ext), Some(by_content)) => {
+            // The contents are more reliable than the name
+            if by_ext != by_content {
+                log::warn!(
+                    "File {} looks like {}, not {}",
+                    file_name.display(),
+                    by_content.name(),
+                    by_ext.name()
+                );
+            }
+            by_content
+        }
+        (None, Some(by_content)) => by_content,
+        (Some(by_ext), None) => by_ext,
+        (None, None) => {
+            if ext == "mtl" {
+                anyhow::bail!(
+                    "MTL are material files for OBJ models. Try opening the OBJ file instead."
+                );
+            }
+            // unknown files are tried as obj, that was the default previously
+            ModelFormat::WaveObj
         }
     };
 
-    let f = std::fs::File::open(file_name)
-        .with_context(|| format!("Error opening file {}", file_name.display()))?;
-    let f = std::io::BufReader::new(f);
-    let mut is_native = false;
-
-    let papercraft = match ext.as_str() {
-        "craft" => {
-            is_native = true;
-            Papercraft::load(f)
-                .with_context(|| format!("Error reading Papercraft file {}", file_name.display()))?
-        }
-        "pdo" => {
-            let importer = pepakura::PepakuraImporter::new(f)
-                .with_context(|| format!("Error reading Pepakura file {}", file_name.display()))?;
-            Papercraft::import(importer)
+    let err_read = || {
+        format!(
+            "Error reading {} file {}",
+            format.name(),
+            file_name.display()
+        )
+    };
+    let mut report = ImportReport::default();
+    let papercraft = match format {
+        ModelFormat::Craft => Papercraft::load(f).with_context(err_read)?,
+        ModelFormat::Pepakura => import_with(
+            pepakura::PepakuraImporter::new(f).with_context(err_read)?,
+            options,
+            &mut report,
+        ),
+        ModelFormat::Stl => import_with(
+            stl::StlImporter::new(f).with_context(err_read)?,
+            options,
+            &mut report,
+        ),
+        ModelFormat::Gltf => import_with(
+            gltf::GltfImporter::new(f, file_name).with_context(err_read)?,
+            options,
+            &mut report,
+        ),
+        ModelFormat::Ply => import_with(
+            ply::PlyImporter::new(f, file_name).with_context(err_read)?,
+            options,
+            &mut report,
+        ),
+        ModelFormat::ThreeMf => import_with(
+            threemf::ThreeMfImporter::new(f).with_context(err_read)?,
+            options,
+            &mut report,
+        ),
+        ModelFormat::WaveObj => import_with(
+            waveobj::WaveObjImporter::new(f, file_name).with_context(err_read)?,
+            options,
+            &mut report,
+        ),
+    };
+    Ok((papercraft, format == ModelFormat::Craft, report))
+}
+
+fn import_with<I: Importer>(
+    importer: I,
+    options: &ImportOptions,
+    report: &mut ImportReport,
+) -> Papercraft {
+    let mut papercraft = if options.cleanup {
+        let importer = CleanupImporter::new(importer, options.weld_tolerance, report);
+        import_simplified(importer, options, report)
+    } else {
+        import_simplified(importer, options, report)
+    };
+    let mut paper_options = papercraft.options().clone();
+    paper_options.import_cleanup = options.cleanup;
+    papercraft.set_options(paper_options, false);
+    papercraft
+}
+
+fn import_simplified<I: Importer>(
+    importer: I,
+    options: &ImportOptions,
+    report: &mut ImportReport,
+) -> Papercraft {
+    match &options.simplify {
+        Some(simplify) => Papercraft::import(SimplifyImporter::new(importer, simplify), report),
+        None => Papercraft::import(importer, report),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    const CUBE_CORNERS: [[u8; 3]; 8] = [
+        [0, 0, 0],
+        [1, 0, 0],
+        [1, 1, 0],
+        [0, 1, 0],
+        [0, 0, 1],
+        [1, 0, 1],
+        [1, 1, 1],
+        [0, 1, 1],
+    ];
+    const CUBE_FACES: [[u8; 4]; 6] = [
+        [0, 3, 2, 1],
+        [4, 5, 6, 7],
+        [0, 1, 5, 4],
+        [1, 2, 6, 5],
+        [2, 3, 7, 6],
+        [3, 0, 4, 7],
+    ];
+
+    fn import(data: String, file_name: &str) -> Papercraft {
+        let (papercraft, _, _) = import_model_reader(
+            std::io::Cursor::new(data),
+            Path::new(file_name),
+            &ImportOptions::default(),
+        )
+        .unwrap();
+        papercraft
+    }
+
+    // A cube with sides of `size`, imported from an OBJ with the default options
+    pub fn cube(size: f32) -> Papercraft {
+        let mut obj = String::new();
+        for c in CUBE_CORNERS {
+            let [x, y, z] = c.map(|x| f32::from(x) * size);
+            obj += &format!("v {x} {y} {z}\n");
         }
-        "stl" => {
-            let importer = stl::StlImporter::new(f)
-                .with_context(|| format!("Error reading STL file {}", file_name.display()))?;
-            Papercraft::import(importer)
+        for f in CUBE_FACES {
+            let [a, b, c, d] = f.map(|i| i + 1);
+            obj += &format!("f {a} {b} {c} {d}\n");
         }
-        "mtl" => {
-            anyhow::bail!(
-                "MTL are material files for OBJ models. Try opening the OBJ file instead."
-            );
+        import(obj, "cube.obj")
+    }
+
+    // The same cube, but from a PLY with vertex colors, so it has a texture
+    pub fn textured_cube(size: f32) -> Papercraft {
+        let mut ply = String::from(
+            "ply\nformat ascii 1.0\nelement vertex 8\n\
+             property float x\nproperty float y\nproperty float z\n\
+             property uchar red\nproperty uchar green\nproperty uchar blue\n\
+             element face 6\nproperty list uchar int vertex_indices\nend_header\n",
+        );
+        for c in CUBE_CORNERS {
+            let [x, y, z] = c.map(|x| f32::from(x) * size);
+            let [r, g, b] = c.map(|x| x * 255);
+            ply += &format!("{x} {y} {z} {r} {g} {b}\n");
         }
-        // unknown extensions are tried as obj, that was the default previously
-        "obj" | _ => {
-            let importer = waveobj::WaveObjImporter::new(f, file_name)
-                .with_context(|| format!("Error reading Wavefront file {}", file_name.display()))?;
-            Papercraft::import(importer)
+        for [a, b, c, d] in CUBE_FACES {
+            ply += &format!("4 {a} {b} {c} {d}\n");
         }
-    };
-    Ok((papercraft, is_native))
+        import(ply, "cube.ply")
+    }
 }
@@ -0,0 +1,103 @@
+use super::*;
+use fxhash::FxHashSet;
+
+// Imports the model of an existing papercraft, to build a new one from it, such as when
+// simplifying it. The flat faces are imported as polygons.
+pub struct PapercraftImporter<'a> {
+    papercraft: &'a Papercraft,
+    // VertexIndex -> the same id for all the vertices connected by an edge
+    vertex_ids: Vec<u32>,
+    faces: Vec<(Vec<VertexIndex>, MaterialIndex, PartIndex)>,
+    // Edges inside the flat faces that are imported as separate triangles
+    hidden_edges: FxHashSet<(u32, u32)>,
+}
+
+impl<'a> PapercraftImporter<'a> {
+    pub fn new(papercraft: &'a Papercraft) -> Self {
+        let model = papercraft.model();
+        let vertex_ids = model.joined_vertex_ids();
+
+        let mut done_faces = vec![false; model.num_faces()];
+        let mut faces = Vec::new();
+        let mut hidden_edges = FxHashSet::default();
+        for (i_face, face) in model.faces() {
+            if done_faces[usize::from(i_face)] {
+                continue;
+            }
+            let flat_face = papercraft.get_flat_faces(i_face);
+            for &f in &flat_face {
+                done_faces[usize::from(f)] = true;
+            }
+            match papercraft.flat_face_contour(&flat_face) {
+                Some(contour) => faces.push((contour, face.material(), face.part())),
+                // A flat face with holes can't be a polygon, use its triangles and keep the
+                // edges between them hidden
+                None => {
+                    for &f in &flat_face {
+                        let face = &model[f];
+                        faces.push((face.index_vertices().to_vec(), face.material(), face.part()));
+                        for (i_v0, i_v1, i_edge) in face.vertices_with_edges() {
+                            if papercraft.edge_status(i_edge) == EdgeStatus::Hidden {
+                                let (a, b) =
+                                    (vertex_ids[usize::from(i_v0)], vertex_ids[usize::from(i_v1)]);
+                                hidden_edges.insert((a.min(b), a.max(b)));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        PapercraftImporter {
+            papercraft,
+            vertex_ids,
+            faces,
+            hidden_edges,
+        }
+    }
+}
+
+impl Importer for PapercraftImporter<'_> {
+    type VertexId = u32;
+
+    fn vertex_map(&self, i_v: VertexIndex) -> Self::VertexId {
+        self.vertex_ids[usize::from(i_v)]
+    }
+    fn build_vertices(&self) -> (bool, Vec<Vertex>) {
+        let vs = self
+            .papercraft
+            .model()
+            .vertices()
+            .map(|(_, v)| Vertex {
+                pos: v.pos,
+                normal: v.normal,
+                uv: v.uv,
+            })
+            .collect();
+        (true, vs)
+    }
+    fn face_count(&self) -> usize {
+        self.faces.len()
+    }
+    fn faces(&self) -> impl Iterator<Item = (impl AsRef<[VertexIndex]>, MaterialIndex)> {
+        self.faces.iter().map(|(vs, mat, _)| (vs.as_slice(), *mat))
+    }
+    fn build_textures(&self) -> Vec<Texture> {
+        self.papercraft.model().textures().cloned().collect()
+    }
+    fn compute_edge_status(&self, (a, b): (u32, u32)) -> Option<EdgeStatus> {
+        self.hidden_edges
+            .contains(&(a.min(b), a.max(b)))
+            .then_some(EdgeStatus::Hidden)
+    }
+    fn part_names(&self) -> Vec<String> {
+        self.papercraft
+            .model()
+            .parts()
+            .map(|(_, name)| name.to_owned())
+            .collect()
+    }
+    fn face_part(&self, i_face: usize) -> PartIndex {
+        self.faces[i_face].2
+    }
+}
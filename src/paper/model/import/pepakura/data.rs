@@ -7,11 +7,12 @@
 
 use anyhow::anyhow;
 use std::cell::Cell;
+use std::io::Write;
 
 use super::super::*;
 type Vector2 = cgmath::Vector2<f32>;
 type Vector3 = cgmath::Vector3<f32>;
-use cgmath::Rad;
+use cgmath::{InnerSpace, Rad};
 
 #[derive(Debug)]
 pub struct Pdo {
@@ -22,6 +23,19 @@ pub struct Pdo {
 }
 
 impl Pdo {
+    pub fn new(
+        objs: Vec<Object>,
+        mats: Vec<Material>,
+        unfold: Option<Unfold>,
+        settings: Settings,
+    ) -> Pdo {
+        Pdo {
+            objs,
+            mats,
+            unfold,
+            settings,
+        }
+    }
     pub fn from_reader<R: Read>(mut rdr: R) -> Result<Pdo> {
         let mut reader = Reader {
             rdr: &mut rdr,
@@ -43,6 +57,12 @@ impl Pdo {
     pub fn settings(&self) -> &Settings {
         &self.settings
     }
+    pub fn write<W: Write>(&self, mut w: W) -> Result<()> {
+        let mut writer = Writer { w: &mut w };
+        writer.write_pdo(self)?;
+        w.flush()?;
+        Ok(())
+    }
 }
 
 struct Reader<'r, R> {
@@ -506,3 +526,245 @@ impl<'r, R: Read> Reader<'r, R> {
         })
     }
 }
+
+// The version that is written. It is the latest one that the reader understands, the same
+// Pepakura 4 writes.
+const WRITE_VERSION: u32 = 6;
+
+struct Writer<'w, W> {
+    w: &'w mut W,
+}
+
+fn write_u8(w: &mut impl Write, x: u8) -> Result<()> {
+    w.write_all(&[x])?;
+    Ok(())
+}
+fn write_bool(w: &mut impl Write, x: bool) -> Result<()> {
+    write_u8(w, u8::from(x))
+}
+fn write_u16(w: &mut impl Write, x: u16) -> Result<()> {
+    w.write_all(&x.to_le_bytes())?;
+    Ok(())
+}
+fn write_u32(w: &mut impl Write, x: u32) -> Result<()> {
+    w.write_all(&x.to_le_bytes())?;
+    Ok(())
+}
+fn write_f32(w: &mut impl Write, x: f32) -> Result<()> {
+    w.write_all(&x.to_le_bytes())?;
+    Ok(())
+}
+fn write_f64(w: &mut impl Write, x: f64) -> Result<()> {
+    w.write_all(&x.to_le_bytes())?;
+    Ok(())
+}
+fn write_vector2_f64(w: &mut impl Write, v: Vector2) -> Result<()> {
+    write_f64(w, f64::from(v.x))?;
+    write_f64(w, f64::from(v.y))?;
+    Ok(())
+}
+fn write_vector3_f64(w: &mut impl Write, v: Vector3) -> Result<()> {
+    write_f64(w, f64::from(v.x))?;
+    write_f64(w, f64::from(v.y))?;
+    write_f64(w, f64::from(v.z))?;
+    Ok(())
+}
+
+impl<W: Write> Writer<'_, W> {
+    fn write_bounding_box(&mut self, bb: &BoundingBox) -> Result<()> {
+        write_vector2_f64(self.w, bb.v0)?;
+        write_vector2_f64(self.w, bb.v1)?;
+        Ok(())
+    }
+    // Strings are written without shift, and with the NUL that the reader discards
+    fn write_string(&mut self, s: &str) -> Result<()> {
+        write_u32(self.w, s.len() as u32 + 1)?;
+        self.w.write_all(s.as_bytes())?;
+        write_u8(self.w, 0)?;
+        Ok(())
+    }
+    fn write_texture(&mut self, tex: &Texture) -> Result<()> {
+        let data = tex.data.take();
+        let mut z = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        let zdata = z.write_all(&data).and_then(|_| z.finish());
+        tex.data.set(data);
+        let zdata = zdata?;
+        write_u32(self.w, tex.width)?;
+        write_u32(self.w, tex.height)?;
+        write_u32(self.w, zdata.len() as u32)?;
+        self.w.write_all(&zdata)?;
+        Ok(())
+    }
+    fn write_face(&mut self, obj: &Object, face: &Face) -> Result<()> {
+        write_u32(self.w, face.mat_index)?;
+        write_u32(self.w, face.part_index)?;
+        write_vector3_f64(self.w, face.normal)?;
+        // The distance of the plane to the origin, it is not read
+        let coord = face
+            .verts
+            .first()
+            .map(|v| face.normal.dot(obj.vertices[v.i_v as usize].v))
+            .unwrap_or_default();
+        write_f64(self.w, f64::from(coord))?;
+        write_u32(self.w, face.verts.len() as u32)?;
+        for v in &face.verts {
+            write_u32(self.w, v.i_v)?;
+            write_vector2_f64(self.w, v.pos2d)?;
+            write_vector2_f64(self.w, v.uv)?;
+            write_bool(self.w, v.flap.is_some())?;
+            let (width, a1, a2) = v
+                .flap
+                .as_ref()
+                .map(|f| (f.width, f.angle1.0, f.angle2.0))
+                .unwrap_or_default();
+            write_f64(self.w, f64::from(width))?;
+            write_f64(self.w, f64::from(a1))?;
+            write_f64(self.w, f64::from(a2))?;
+            // fold info, unused
+            self.w.write_all(&[0; 24])?;
+        }
+        Ok(())
+    }
+    fn write_object(&mut self, obj: &Object) -> Result<()> {
+        self.write_string(&obj.name)?;
+        write_bool(self.w, obj.visible)?;
+        write_u32(self.w, obj.vertices.len() as u32)?;
+        for v in &obj.vertices {
+            write_vector3_f64(self.w, v.v)?;
+        }
+        write_u32(self.w, obj.faces.len() as u32)?;
+        for face in &obj.faces {
+            self.write_face(obj, face)?;
+        }
+        write_u32(self.w, obj.edges.len() as u32)?;
+        for edge in &obj.edges {
+            write_u32(self.w, edge.i_f1)?;
+            write_u32(self.w, edge.i_f2.unwrap_or(u32::MAX))?;
+            write_u32(self.w, edge.i_v1)?;
+            write_u32(self.w, edge.i_v2)?;
+            write_u16(self.w, u16::from(edge.connected))?;
+            // Not read, it looks like it is set for the edges with a single face
+            write_u32(self.w, u32::from(edge.i_f2.is_none()))?;
+        }
+        Ok(())
+    }
+    fn write_material(&mut self, mat: &Material) -> Result<()> {
+        self.write_string(&mat.name)?;
+        // 3D colors: ambient, diffuse, specular and emission, then the 2D color
+        #[rustfmt::skip]
+        const COLORS: [f32; 20] = [
+            0.2, 0.2, 0.2, 1.0,
+            1.0, 1.0, 1.0, 1.0,
+            0.0, 0.0, 0.0, 1.0,
+            0.0, 0.0, 0.0, 1.0,
+            1.0, 1.0, 1.0, 1.0,
+        ];
+        for c in COLORS {
+            write_f32(self.w, c)?;
+        }
+        write_bool(self.w, mat.texture.is_some())?;
+        if let Some(tex) = &mat.texture {
+            self.write_texture(tex)?;
+        }
+        Ok(())
+    }
+    fn write_part(&mut self, part: &Part) -> Result<()> {
+        write_u32(self.w, part.i_obj)?;
+        self.write_bounding_box(&part.bb)?;
+        self.write_string(&part.name)?;
+        write_u32(self.w, part.lines.len() as u32)?;
+        for line in &part.lines {
+            write_bool(self.w, line.hidden)?;
+            write_u32(self.w, line.type_)?;
+            write_u8(self.w, 0)?;
+            write_u32(self.w, line.first.0)?;
+            write_u32(self.w, line.first.1)?;
+            write_bool(self.w, line.second.is_some())?;
+            if let Some((i_f, i_v)) = line.second {
+                write_u32(self.w, i_f)?;
+                write_u32(self.w, i_v)?;
+            }
+        }
+        Ok(())
+    }
+    fn write_pdo(&mut self, pdo: &Pdo) -> Result<()> {
+        self.w.write_all(b"version 3\n")?;
+        write_u32(self.w, WRITE_VERSION)?;
+        write_u32(self.w, 0)?; // mbcs
+        write_u32(self.w, 0)?;
+        self.write_string("Papercraft")?; // designer
+        write_u32(self.w, 0)?; // shift
+        self.write_string("en")?; // locale
+        self.write_string("1252")?; // codepage
+        write_u32(self.w, 0)?; // texlock
+        write_bool(self.w, false)?; // show startup notes
+        write_bool(self.w, false)?; // password flag
+        self.write_string("")?; // key
+        write_u32(self.w, 0)?; // v6 lock
+
+        let assembled_height = pdo.unfold.as_ref().map_or(0.0, |unfold| {
+            let (v_min, v_max) = crate::util_3d::bounding_box_3d(
+                pdo.objs.iter().flat_map(|o| o.vertices.iter().map(|v| v.v)),
+            );
+            (v_max.y - v_min.y) * unfold.scale
+        });
+        write_f64(self.w, f64::from(assembled_height))?;
+        write_vector3_f64(self.w, Vector3::new(0.0, 0.0, 0.0))?; // origin
+
+        write_u32(self.w, pdo.objs.len() as u32)?;
+        for obj in &pdo.objs {
+            self.write_object(obj)?;
+        }
+        write_u32(self.w, pdo.mats.len() as u32)?;
+        for mat in &pdo.mats {
+            self.write_material(mat)?;
+        }
+
+        write_bool(self.w, pdo.unfold.is_some())?;
+        if let Some(unfold) = &pdo.unfold {
+            write_f64(self.w, f64::from(unfold.scale))?;
+            write_bool(self.w, unfold.padding)?;
+            self.write_bounding_box(&unfold.bb)?;
+            write_u32(self.w, unfold.parts.len() as u32)?;
+            for part in &unfold.parts {
+                self.write_part(part)?;
+            }
+            write_u32(self.w, 0)?; // texts
+            write_u32(self.w, 0)?; // images
+            write_u32(self.w, 0)?; // images2
+            if !unfold.parts.is_empty() {
+                write_u32(self.w, 0)?;
+            }
+        }
+
+        // settings
+        let settings = &pdo.settings;
+        write_bool(self.w, true)?; // show flaps
+        write_bool(self.w, true)?; // show edge id
+        write_bool(self.w, false)?; // edge id position
+        write_bool(self.w, false)?; // face material
+        write_bool(self.w, settings.fold_line_hide_angle.is_some())?;
+        write_u32(self.w, settings.fold_line_hide_angle.unwrap_or(180))?;
+        write_bool(self.w, false)?; // draw white dot
+        for _ in 0..4 {
+            write_u32(self.w, 0)?; // mountain style
+        }
+        // Always a custom page size, in portrait
+        write_u32(self.w, 11)?;
+        write_f64(self.w, f64::from(settings.page_size.x))?;
+        write_f64(self.w, f64::from(settings.page_size.y))?;
+        write_u32(self.w, 0)?;
+        write_u32(self.w, settings.margin_side)?;
+        write_u32(self.w, settings.margin_top)?;
+        for _ in 0..12 {
+            write_f64(self.w, 0.0)?; // fold pattern
+        }
+        write_bool(self.w, false)?; // outline padding
+        write_f64(self.w, 1.0)?; // scale factor
+        self.write_string("")?; // author
+        self.write_string("")?; // comment
+
+        write_u32(self.w, 9999)?;
+        Ok(())
+    }
+}
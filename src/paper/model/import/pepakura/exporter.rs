@@ -0,0 +1,374 @@
+use super::super::*;
+use super::data;
+use cgmath::{Deg, EuclideanSpace, Point2, Rad, Transform};
+use fxhash::{FxHashMap, FxHashSet};
+use std::cell::Cell;
+use std::io::Write;
+use std::ops::ControlFlow;
+
+// Writes the papercraft as a Pepakura file. The model is a single object, with the flat faces as
+// polygons, and each island is an unfolded part.
+pub fn export_pdo<W: Write>(papercraft: &Papercraft, w: W) -> Result<()> {
+    let model = papercraft.model();
+    let options = papercraft.options();
+
+    // The PDO vertices are unique for each position, not for each UV
+    let joined_ids = model.joined_vertex_ids();
+    let mut pdo_ids = FxHashMap::default();
+    let mut vertices = Vec::new();
+    let vertex_ids: Vec<u32> = joined_ids
+        .iter()
+        .map(|&id| {
+            *pdo_ids.entry(id).or_insert_with(|| {
+                vertices.push(data::Vertex {
+                    v: model[VertexIndex::from(id as usize)].pos(),
+                });
+                vertices.len() as u32 - 1
+            })
+        })
+        .collect();
+    let pdo_id = |i_v: VertexIndex| vertex_ids[usize::from(i_v)];
+
+    // Pepakura lays out the pages one after the other, without the margins
+    let margin = Vector2::new(options.margin.1, options.margin.0);
+    let area_size = Vector2::from(options.page_size) - 2.0 * margin;
+    let to_unfold = |pos: Vector2| {
+        let po = options.global_to_page(pos);
+        Vector2::new(
+            po.col as f32 * area_size.x + po.offset.x - margin.x,
+            po.row as f32 * area_size.y + po.offset.y - margin.y,
+        )
+    };
+
+    let scale = options.scale;
+    let mut faces = Vec::new();
+    let mut parts = Vec::new();
+    // FaceIndex -> index of the PDO face
+    let mut pdo_faces = vec![0; model.num_faces()];
+    for (i_part, (_, island)) in papercraft.islands().enumerate() {
+        // The position of each vertex of the faces, in the unfolded space
+        let mut face_pos = FxHashMap::default();
+        let mut island_faces = Vec::new();
+        let _ = papercraft.traverse_faces(island, |i_face, face, mx| {
+            let plane = model.face_plane(face);
+            let pos = face.index_vertices().map(|i_v| {
+                let p = plane.project(&model[i_v].pos(), scale);
+                to_unfold(mx.transform_point(Point2::from_vec(p)).to_vec())
+            });
+            face_pos.insert(i_face, pos);
+            island_faces.push(i_face);
+            ControlFlow::Continue(())
+        });
+
+        let mut done = FxHashSet::default();
+        let first_face = faces.len();
+        for i_face in island_faces {
+            if done.contains(&i_face) {
+                continue;
+            }
+            let flat_face = papercraft.get_flat_faces(i_face);
+            done.extend(flat_face.iter().copied());
+            // If the contour is not a single polygon, each triangle is a PDO face
+            let polygons = match papercraft.flat_face_contour(&flat_face) {
+                Some(contour) => vec![(contour, flat_face.iter().copied().collect())],
+                None => flat_face
+                    .iter()
+                    .map(|&f| (model[f].index_vertices().to_vec(), vec![f]))
+                    .collect(),
+            };
+            for (contour, polygon_faces) in polygons {
+                for &f in &polygon_faces {
+                    pdo_faces[usize::from(f)] = faces.len() as u32;
+                }
+                let verts = contour
+                    .into_iter()
+                    .map(|i_v| {
+                        let pos2d = polygon_faces
+                            .iter()
+                            .find_map(|f| {
+                                let i =
+                                    model[*f].index_vertices().iter().position(|&v| v == i_v)?;
+                                Some(face_pos[f][i])
+                            })
+                            .unwrap_or(Vector2::new(0.0, 0.0));
+                        data::VertInFace {
+                            i_v: pdo_id(i_v),
+                            pos2d,
+                            uv: model[i_v].uv(),
+                            flap: None,
+                        }
+                    })
+                    .collect();
+                let face = &model[i_face];
+                faces.push(data::Face {
+                    mat_index: usize::from(face.material()) as u32,
+                    part_index: i_part as u32,
+                    normal: model.face_plane(face).normal(),
+                    verts,
+                });
+            }
+        }
+
+        // The 2D coordinates are relative to the bounding box of the part
+        let (v0, v1) = crate::util_3d::bounding_box_2d(
+            faces[first_face..]
+                .iter()
+                .flat_map(|f| f.verts.iter().map(|v| v.pos2d)),
+        );
+        for face in &mut faces[first_face..] {
+            for v in &mut face.verts {
+                v.pos2d -= v0;
+            }
+        }
+        parts.push(data::Part {
+            i_obj: 0,
+            bb: data::BoundingBox { v0, v1 },
+            name: island.name().to_owned(),
+            lines: Vec::new(),
+        });
+    }
+
+    // Where an edge starts in a PDO face, going from `a` to `b`
+    let vertex_in_face = |faces: &[data::Face], i_face: FaceIndex, i_edge: EdgeIndex| {
+        let i_pdo_face = pdo_faces[usize::from(i_face)];
+        let (a, b) = model[i_face].vertices_of_edge(i_edge).unwrap();
+        let (a, b) = (pdo_id(a), pdo_id(b));
+        let verts = &faces[i_pdo_face as usize].verts;
+        let Some(i_v) =
+            (0..verts.len()).find(|&i| verts[i].i_v == a && verts[(i + 1) % verts.len()].i_v == b)
+        else {
+            bail!(
+                "Edge {} is not in the contour of its face",
+                usize::from(i_edge)
+            );
+        };
+        Ok((i_pdo_face, i_v as u32))
+    };
+
+    let mut edges = Vec::new();
+    for (i_edge, edge) in model.edges() {
+        let status = papercraft.edge_status(i_edge);
+        let (fa, fb) = edge.faces();
+        // A hidden edge is inside a PDO face, unless its flat face was split in triangles
+        let split =
+            matches!(fb, Some(fb) if pdo_faces[usize::from(fa)] != pdo_faces[usize::from(fb)]);
+        if status == EdgeStatus::Hidden && !split {
+            continue;
+        }
+        let (a, b) = model[fa].vertices_of_edge(i_edge).unwrap();
+        edges.push(data::Edge {
+            i_f1: pdo_faces[usize::from(fa)],
+            i_f2: fb.map(|fb| pdo_faces[usize::from(fb)]),
+            i_v1: pdo_id(a),
+            i_v2: pdo_id(b),
+            connected: matches!(status, EdgeStatus::Joined | EdgeStatus::Hidden),
+        });
+
+        let first = vertex_in_face(&faces, fa, i_edge)?;
+        let second = fb
+            .map(|fb| vertex_in_face(&faces, fb, i_edge))
+            .transpose()?;
+        let part_of = |(i_pdo_face, _): (u32, u32)| faces[i_pdo_face as usize].part_index as usize;
+        match status {
+            EdgeStatus::Joined | EdgeStatus::Hidden => {
+                let angle = edge.angle();
+                parts[part_of(first)].lines.push(data::Line {
+                    hidden: status == EdgeStatus::Hidden
+                        || Rad(angle.0.abs()) < Rad::from(Deg(options.hidden_line_angle)),
+                    type_: if angle.0.is_sign_negative() { 2 } else { 1 },
+                    first,
+                    second,
+                });
+            }
+            EdgeStatus::Cut(flap_side) => {
+                // A cut is drawn in each part
+                for side in [Some(first), second].into_iter().flatten() {
+                    parts[part_of(side)].lines.push(data::Line {
+                        hidden: false,
+                        type_: 0,
+                        first: side,
+                        second: None,
+                    });
+                }
+                let flap_face = match flap_side {
+                    FlapSide::False => Some(first),
+                    FlapSide::True => second,
+                    FlapSide::Hidden => None,
+                };
+                if let Some((i_pdo_face, i_v)) = flap_face {
+                    let flap_angle = Rad::from(papercraft.flap_angle(i_edge));
+                    faces[i_pdo_face as usize].verts[i_v as usize].flap = Some(data::Flap {
+                        width: papercraft.flap_width(i_edge),
+                        angle1: flap_angle,
+                        angle2: flap_angle,
+                    });
+                }
+            }
+        }
+    }
+
+    let mats = model
+        .textures()
+        .map(|tex| {
+            let name = Path::new(tex.file_name())
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            // PDO textures have no alpha, so blend them over the white paper
+            let texture = tex.pixbuf().map(|img| {
+                let img = img.to_rgba8();
+                let data = img
+                    .pixels()
+                    .flat_map(|p| {
+                        let [r, g, b, a] = p.0;
+                        let a = u32::from(a);
+                        [r, g, b].map(|c| ((u32::from(c) * a + 255 * (255 - a)) / 255) as u8)
+                    })
+                    .collect();
+                data::Texture {
+                    width: img.width(),
+                    height: img.height(),
+                    data: Cell::new(data),
+                }
+            });
+            data::Material { name, texture }
+        })
+        .collect();
+
+    let (v0, v1) = crate::util_3d::bounding_box_2d(parts.iter().flat_map(|p| [p.bb.v0, p.bb.v1]));
+    let unfold = data::Unfold {
+        scale,
+        padding: false,
+        bb: data::BoundingBox { v0, v1 },
+        parts,
+    };
+    let settings = data::Settings {
+        margin_side: options.margin.1.round() as u32,
+        margin_top: options.margin.0.round() as u32,
+        page_size: Vector2::from(options.page_size),
+        fold_line_hide_angle: Some((180.0 - options.hidden_line_angle).round() as u32),
+    };
+    let obj = data::Object {
+        name: String::from("Papercraft"),
+        visible: true,
+        vertices,
+        faces,
+        edges,
+    };
+    let pdo = data::Pdo::new(vec![obj], mats, Some(unfold), settings);
+    pdo.write(w)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paper::import::tests::cube;
+    use crate::paper::EdgeToggleFlapAction;
+    use cgmath::MetricSpace;
+    use std::io::Cursor;
+
+    type Key = [i32; 3];
+
+    fn key(v: Vector3) -> Key {
+        [v.x, v.y, v.z].map(|x| (x * 100.0).round() as i32)
+    }
+    // The triangles of a flat face may be different, so this is the center of the flat face
+    fn face_key(papercraft: &Papercraft, i_face: FaceIndex) -> Key {
+        let model = papercraft.model();
+        let vs: FxHashSet<Key> = papercraft
+            .get_flat_faces(i_face)
+            .into_iter()
+            .flat_map(|f| model[f].index_vertices())
+            .map(|i_v| key(model[i_v].pos()))
+            .collect();
+        let sum = vs
+            .iter()
+            .fold([0; 3], |s, v| [0, 1, 2].map(|i| s[i] + v[i]));
+        sum.map(|x| x / vs.len() as i32)
+    }
+
+    // For each edge, by its vertices: its status, and the face with the flap, if any.
+    // The hidden edges depend on the tessellation, so they are not compared.
+    fn edge_states(papercraft: &Papercraft) -> FxHashMap<(Key, Key), (EdgeStatus, Option<Key>)> {
+        let model = papercraft.model();
+        model
+            .edges()
+            .filter(|&(i_edge, _)| papercraft.edge_status(i_edge) != EdgeStatus::Hidden)
+            .map(|(i_edge, edge)| {
+                let (a, b) = model.edge_pos(edge);
+                let (a, b) = (key(a), key(b));
+                let status = papercraft.edge_status(i_edge);
+                let flap_face = match status {
+                    EdgeStatus::Cut(side) => {
+                        let (fa, fb) = edge.faces();
+                        [Some(fa), fb]
+                            .into_iter()
+                            .flatten()
+                            .find(|&f| side.flap_visible(edge.face_sign(f)))
+                            .map(|f| face_key(papercraft, f))
+                    }
+                    _ => None,
+                };
+                // The flap side depends on the direction of the edge, so that is not compared
+                let status = match status {
+                    EdgeStatus::Cut(FlapSide::True) => EdgeStatus::Cut(FlapSide::False),
+                    s => s,
+                };
+                ((a.min(b), a.max(b)), (status, flap_face))
+            })
+            .collect()
+    }
+
+    // The bounding box of the island of each face
+    fn island_boxes(papercraft: &Papercraft) -> FxHashMap<Key, (Vector2, Vector2)> {
+        let model = papercraft.model();
+        model
+            .faces()
+            .map(|(i_face, _)| {
+                let i_island = papercraft.island_by_face(i_face);
+                (
+                    face_key(papercraft, i_face),
+                    papercraft.island_bounding_box(i_island),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut papercraft = cube(50.0);
+
+        // Join some edges, and move some flaps to the other side
+        let cuts: Vec<EdgeIndex> = papercraft
+            .model()
+            .edges()
+            .map(|(i_edge, _)| i_edge)
+            .filter(|&i_edge| matches!(papercraft.edge_status(i_edge), EdgeStatus::Cut(_)))
+            .collect();
+        for &i_edge in &cuts[..3] {
+            papercraft.edge_join(i_edge, None);
+        }
+        for &i_edge in &cuts[3..6] {
+            papercraft.edge_toggle_flap(i_edge, EdgeToggleFlapAction::Toggle);
+        }
+        papercraft.pack_islands(false);
+
+        let mut pdo = Vec::new();
+        export_pdo(&papercraft, &mut pdo).unwrap();
+        let (new, _, _) = import_model_reader(
+            Cursor::new(pdo),
+            Path::new("cube.pdo"),
+            &ImportOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(new.num_islands(), papercraft.num_islands());
+        assert_eq!(edge_states(&new), edge_states(&papercraft));
+
+        let (old_boxes, new_boxes) = (island_boxes(&papercraft), island_boxes(&new));
+        for (face, (a0, a1)) in old_boxes {
+            let (b0, b1) = new_boxes[&face];
+            assert!(a0.distance(b0) < 0.01 && a1.distance(b1) < 0.01, "{face:?}");
+        }
+    }
+}
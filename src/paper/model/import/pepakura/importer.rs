@@ -135,19 +135,26 @@ impl Importer for PepakuraImporter {
             .iter()
             .find(|&e| vv == (e.i_v1, e.i_v2) || vv == (e.i_v2, e.i_v1))?;
         if edge.connected {
-            Some(EdgeStatus::Joined)
-        } else {
-            let v_f = obj.faces[edge.i_f1 as usize]
-                .verts
-                .iter()
-                .find(|v_f| v_f.i_v == edge.i_v1)
-                .unwrap();
-            if v_f.flap.is_some() {
-                Some(EdgeStatus::Cut(FlapSide::True))
-            } else {
-                None
-            }
+            return Some(EdgeStatus::Joined);
         }
+        // The flap is in the vertex of the face where the edge starts, in any of both faces.
+        // It is on our side if that face goes in the same direction as our edge.
+        let flap_dir = [edge.i_f1].into_iter().chain(edge.i_f2).find_map(|i_f| {
+            let verts = &obj.faces[i_f as usize].verts;
+            (0..verts.len()).find_map(|i| {
+                let (a, b) = (verts[i].i_v, verts[(i + 1) % verts.len()].i_v);
+                let is_edge = (a, b) == vv || (b, a) == vv;
+                (is_edge && verts[i].flap.is_some()).then_some((a, b))
+            })
+        });
+        // Without a flap it is a default cut
+        flap_dir.map(|dir| {
+            EdgeStatus::Cut(if dir == vv {
+                FlapSide::False
+            } else {
+                FlapSide::True
+            })
+        })
     }
     fn relocate_islands<'a>(
         &self,
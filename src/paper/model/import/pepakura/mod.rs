@@ -1,4 +1,6 @@
 mod data;
+mod exporter;
 mod importer;
 
+pub use exporter::export_pdo;
 pub use importer::PepakuraImporter;
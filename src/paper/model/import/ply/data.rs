@@ -0,0 +1,363 @@
+use crate::paper::import::*;
+
+// The counts in the file are not trusted for the allocations, a broken file could ask for a lot
+const MAX_PREALLOC: usize = 0x10000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Format {
+    Ascii,
+    BinaryLe,
+    BinaryBe,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    fn parse(s: &str) -> Result<ScalarType> {
+        let t = match s {
+            "char" | "int8" => ScalarType::I8,
+            "uchar" | "uint8" => ScalarType::U8,
+            "short" | "int16" => ScalarType::I16,
+            "ushort" | "uint16" => ScalarType::U16,
+            "int" | "int32" => ScalarType::I32,
+            "uint" | "uint32" => ScalarType::U32,
+            "float" | "float32" => ScalarType::F32,
+            "double" | "float64" => ScalarType::F64,
+            _ => bail!("invalid PLY type {s}"),
+        };
+        Ok(t)
+    }
+    fn is_float(self) -> bool {
+        matches!(self, ScalarType::F32 | ScalarType::F64)
+    }
+}
+
+#[derive(Debug)]
+enum PropertyType {
+    Scalar(ScalarType),
+    // (count type, item type)
+    List(ScalarType, ScalarType),
+}
+
+#[derive(Debug)]
+struct Property {
+    name: String,
+    ty: PropertyType,
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    props: Vec<Property>,
+}
+
+#[derive(Debug)]
+pub struct PlyVertex {
+    pub pos: Vector3,
+    pub normal: Option<Vector3>,
+    pub uv: Option<Vector2>,
+    // 0.0 - 1.0
+    pub color: Option<Vector3>,
+}
+
+#[derive(Debug)]
+pub struct Ply {
+    vertices: Vec<PlyVertex>,
+    faces: Vec<Vec<u32>>,
+    texture_file: Option<String>,
+}
+
+impl Ply {
+    pub fn from_reader<R: BufRead>(mut f: R) -> Result<Ply> {
+        let mut line = String::new();
+        f.read_line(&mut line)?;
+        if line.trim_end() != "ply" {
+            bail!("not a PLY file");
+        }
+
+        let mut format = None;
+        let mut elements: Vec<Element> = Vec::new();
+        let mut texture_file = None;
+        loop {
+            line.clear();
+            if f.read_line(&mut line)? == 0 {
+                bail!("unexpected end of PLY header");
+            }
+            let mut words = line.split_ascii_whitespace();
+            match words.next() {
+                Some("end_header") => break,
+                Some("format") => {
+                    format = Some(match words.next() {
+                        Some("ascii") => Format::Ascii,
+                        Some("binary_little_endian") => Format::BinaryLe,
+                        Some("binary_big_endian") => Format::BinaryBe,
+                        x => bail!("invalid PLY format {x:?}"),
+                    });
+                }
+                // This is what MeshLab writes for textured models
+                Some("comment") if words.next() == Some("TextureFile") => {
+                    texture_file = Some(words.collect::<Vec<_>>().join(" "));
+                }
+                Some("element") => {
+                    let (Some(name), Some(count)) = (words.next(), words.next()) else {
+                        bail!("invalid PLY element");
+                    };
+                    elements.push(Element {
+                        name: name.to_owned(),
+                        count: count.parse()?,
+                        props: Vec::new(),
+                    });
+                }
+                Some("property") => {
+                    let Some(elem) = elements.last_mut() else {
+                        bail!("PLY property without element");
+                    };
+                    let ty = match words.next() {
+                        Some("list") => {
+                            let (Some(tc), Some(ti)) = (words.next(), words.next()) else {
+                                bail!("invalid PLY list property");
+                            };
+                            PropertyType::List(ScalarType::parse(tc)?, ScalarType::parse(ti)?)
+                        }
+                        Some(t) => PropertyType::Scalar(ScalarType::parse(t)?),
+                        None => bail!("invalid PLY property"),
+                    };
+                    let Some(name) = words.next() else {
+                        bail!("invalid PLY property");
+                    };
+                    elem.props.push(Property {
+                        name: name.to_owned(),
+                        ty,
+                    });
+                }
+                // comments, obj_info and unknown lines are ignored
+                _ => {}
+            }
+        }
+        let format = format.ok_or_else(|| anyhow!("PLY format missing"))?;
+
+        let mut rdr = ValueReader {
+            f,
+            format,
+            line: String::new(),
+            pos: 0,
+        };
+        let mut vertices = Vec::new();
+        let mut faces = Vec::new();
+        for elem in &elements {
+            match elem.name.as_str() {
+                "vertex" => vertices = read_vertices(&mut rdr, elem)?,
+                "face" => faces = read_faces(&mut rdr, elem)?,
+                _ => {
+                    for _ in 0..elem.count {
+                        rdr.start_row()?;
+                        for prop in &elem.props {
+                            rdr.read_property(&prop.ty)?;
+                        }
+                    }
+                }
+            }
+        }
+        let num_vertices = vertices.len() as u32;
+        if faces.iter().flatten().any(|&i| i >= num_vertices) {
+            bail!("invalid vertex index in PLY face");
+        }
+        Ok(Ply {
+            vertices,
+            faces,
+            texture_file,
+        })
+    }
+    pub fn vertices(&self) -> &[PlyVertex] {
+        &self.vertices
+    }
+    pub fn faces(&self) -> &[Vec<u32>] {
+        &self.faces
+    }
+    pub fn texture_file(&self) -> Option<&str> {
+        self.texture_file.as_deref()
+    }
+}
+
+fn read_vertices<R: BufRead>(rdr: &mut ValueReader<R>, elem: &Element) -> Result<Vec<PlyVertex>> {
+    let prop_index = |names: &[&str]| elem.props.iter().position(|p| names.contains(&&*p.name));
+    let idx_pos = [prop_index(&["x"]), prop_index(&["y"]), prop_index(&["z"])];
+    let idx_normal = [
+        prop_index(&["nx"]),
+        prop_index(&["ny"]),
+        prop_index(&["nz"]),
+    ];
+    let idx_uv = [
+        prop_index(&["s", "u", "texture_u", "texture_s"]),
+        prop_index(&["t", "v", "texture_v", "texture_t"]),
+    ];
+    let idx_color = [
+        prop_index(&["red", "diffuse_red"]),
+        prop_index(&["green", "diffuse_green"]),
+        prop_index(&["blue", "diffuse_blue"]),
+    ];
+    let [Some(ix), Some(iy), Some(iz)] = idx_pos else {
+        bail!("PLY vertex without position");
+    };
+    // Colors are usually bytes, but they may be floats
+    let color_scale = match idx_color[0].map(|i| &elem.props[i].ty) {
+        Some(PropertyType::Scalar(t)) if t.is_float() => 1.0,
+        _ => 1.0 / 255.0,
+    };
+
+    let mut values = vec![0.0; elem.props.len()];
+    let mut vertices = Vec::with_capacity(elem.count.min(MAX_PREALLOC));
+    for _ in 0..elem.count {
+        rdr.start_row()?;
+        for (prop, value) in elem.props.iter().zip(&mut values) {
+            // lists in vertices are not used
+            *value = rdr.read_property(&prop.ty)?.unwrap_or(0.0);
+        }
+        let get = |i: usize| values[i] as f32;
+        let normal = match idx_normal {
+            [Some(x), Some(y), Some(z)] => Some(Vector3::new(get(x), get(y), get(z))),
+            _ => None,
+        };
+        let uv = match idx_uv {
+            [Some(s), Some(t)] => Some(Vector2::new(get(s), get(t))),
+            _ => None,
+        };
+        let color = match idx_color {
+            [Some(r), Some(g), Some(b)] => Some(Vector3::new(get(r), get(g), get(b)) * color_scale),
+            _ => None,
+        };
+        vertices.push(PlyVertex {
+            pos: Vector3::new(get(ix), get(iy), get(iz)),
+            normal,
+            uv,
+            color,
+        });
+    }
+    Ok(vertices)
+}
+
+fn read_faces<R: BufRead>(rdr: &mut ValueReader<R>, elem: &Element) -> Result<Vec<Vec<u32>>> {
+    let idx_list = elem
+        .props
+        .iter()
+        .position(|p| {
+            matches!(p.ty, PropertyType::List(..))
+                && (p.name == "vertex_indices" || p.name == "vertex_index")
+        })
+        .ok_or_else(|| anyhow!("PLY face without vertex indices"))?;
+
+    let mut faces = Vec::with_capacity(elem.count.min(MAX_PREALLOC));
+    for _ in 0..elem.count {
+        rdr.start_row()?;
+        for (i_prop, prop) in elem.props.iter().enumerate() {
+            if i_prop == idx_list {
+                let PropertyType::List(tc, ti) = prop.ty else {
+                    unreachable!()
+                };
+                let n = rdr.read_scalar(tc)? as usize;
+                let mut face = Vec::with_capacity(n.min(MAX_PREALLOC));
+                for _ in 0..n {
+                    let i = rdr.read_scalar(ti)?;
+                    // `as u32` would turn a negative index into 0, that is a valid one
+                    if i < 0.0 {
+                        bail!("negative vertex index in PLY face");
+                    }
+                    face.push(i as u32);
+                }
+                faces.push(face);
+            } else {
+                rdr.read_property(&prop.ty)?;
+            }
+        }
+    }
+    Ok(faces)
+}
+
+struct ValueReader<R> {
+    f: R,
+    format: Format,
+    // Only for ASCII, the current row and the position of the next value
+    line: String,
+    pos: usize,
+}
+
+impl<R: BufRead> ValueReader<R> {
+    // In ASCII each element is a line
+    fn start_row(&mut self) -> Result<()> {
+        if self.format == Format::Ascii {
+            loop {
+                self.line.clear();
+                if self.f.read_line(&mut self.line)? == 0 {
+                    bail!("unexpected end of PLY file");
+                }
+                if !self.line.trim().is_empty() {
+                    break;
+                }
+            }
+            self.pos = 0;
+        }
+        Ok(())
+    }
+    // Returns the value of a scalar property, lists are skipped
+    fn read_property(&mut self, ty: &PropertyType) -> Result<Option<f64>> {
+        match *ty {
+            PropertyType::Scalar(t) => Ok(Some(self.read_scalar(t)?)),
+            PropertyType::List(tc, ti) => {
+                let n = self.read_scalar(tc)? as usize;
+                for _ in 0..n {
+                    self.read_scalar(ti)?;
+                }
+                Ok(None)
+            }
+        }
+    }
+    fn read_scalar(&mut self, ty: ScalarType) -> Result<f64> {
+        if self.format == Format::Ascii {
+            let rest = &self.line[self.pos..];
+            let start = rest.len() - rest.trim_start().len();
+            let rest = &rest[start..];
+            let len = rest
+                .find(|c: char| c.is_ascii_whitespace())
+                .unwrap_or(rest.len());
+            if len == 0 {
+                bail!("missing value in PLY row");
+            }
+            self.pos += start + len;
+            return Ok(rest[..len].parse()?);
+        }
+
+        macro_rules! read_bin {
+            ($t:ty) => {{
+                let mut x = [0; std::mem::size_of::<$t>()];
+                self.f.read_exact(&mut x)?;
+                if self.format == Format::BinaryLe {
+                    <$t>::from_le_bytes(x) as f64
+                } else {
+                    <$t>::from_be_bytes(x) as f64
+                }
+            }};
+        }
+        let x = match ty {
+            ScalarType::I8 => read_bin!(i8),
+            ScalarType::U8 => read_bin!(u8),
+            ScalarType::I16 => read_bin!(i16),
+            ScalarType::U16 => read_bin!(u16),
+            ScalarType::I32 => read_bin!(i32),
+            ScalarType::U32 => read_bin!(u32),
+            ScalarType::F32 => read_bin!(f32),
+            ScalarType::F64 => read_bin!(f64),
+        };
+        Ok(x)
+    }
+}
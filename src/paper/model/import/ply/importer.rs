@@ -0,0 +1,343 @@
+use std::cell::Cell;
+
+use super::super::*;
+use super::data;
+use crate::util_3d;
+use cgmath::{InnerSpace, Zero};
+use image::{DynamicImage, Rgb, RgbImage};
+
+// Limits of the baked vertex color texture
+const ATLAS_MAX_SIZE: u32 = 4096;
+const ATLAS_MIN_CELL: u32 = 4;
+const ATLAS_MAX_CELL: u32 = 16;
+// Pixels around each face in the atlas, so that the texture filter does not mix the colors of
+// the neighbor faces
+const ATLAS_GUTTER: u32 = 1;
+
+pub struct PlyImporter {
+    // Vertices as they will be used, may be split from the PLY vertices.
+    // Consumed by build_vertices()
+    vertices: Cell<Vec<Vertex>>,
+    // The original PLY vertex of each one of `vertices`
+    vertex_ids: Vec<u32>,
+    faces: Vec<Vec<VertexIndex>>,
+    has_normals: bool,
+    texture: Cell<Option<Texture>>,
+}
+
+impl PlyImporter {
+    pub fn new<R: BufRead>(f: R, file_name: &Path) -> Result<PlyImporter> {
+        let ply = data::Ply::from_reader(f)?;
+        let has_normals = ply.vertices().iter().all(|v| v.normal.is_some());
+        let has_uv = ply.vertices().iter().all(|v| v.uv.is_some());
+        let has_color = ply.vertices().iter().all(|v| v.color.is_some());
+
+        let texture = match ply.texture_file() {
+            Some(name) if has_uv => {
+                let path = file_name.with_file_name(name);
+                let err_map = || format!("Error reading texture file {}", path.display());
+                let img = image::io::Reader::open(&path)
+                    .with_context(err_map)?
+                    .with_guessed_format()
+                    .with_context(err_map)?
+                    .decode()
+                    .with_context(err_map)?;
+                let name = path
+                    .file_name()
+                    .and_then(|f| f.to_str())
+                    .ok_or_else(|| anyhow!("Invalid texture name"))?;
+                Some(Texture {
+                    file_name: name.to_owned(),
+                    pixbuf: Some(img),
+                })
+            }
+            _ => None,
+        };
+
+        let importer = if texture.is_none() && has_color && !ply.vertices().is_empty() {
+            // Only vertex colors, the paper needs a real texture
+            let (vertices, vertex_ids, faces, img) = bake_vertex_colors(&ply)?;
+            let stem = file_name
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            PlyImporter {
+                vertices: Cell::new(vertices),
+                vertex_ids,
+                faces,
+                has_normals,
+                texture: Cell::new(Some(Texture {
+                    file_name: format!("{stem}_colors.png"),
+                    pixbuf: Some(img),
+                })),
+            }
+        } else {
+            let vertices = ply
+                .vertices()
+                .iter()
+                .map(|v| Vertex {
+                    pos: v.pos,
+                    normal: v.normal.unwrap_or_else(Vector3::zero),
+                    // PLY has the origin of the texture at the bottom, as OBJ.
+                    // Without a texture the UVs are still kept, the user may add one later.
+                    uv: match v.uv {
+                        Some(uv) => Vector2::new(uv.x, 1.0 - uv.y),
+                        None => Vector2::zero(),
+                    },
+                })
+                .collect();
+            let faces = ply
+                .faces()
+                .iter()
+                .map(|f| f.iter().map(|&i| VertexIndex(i)).collect())
+                .collect();
+            PlyImporter {
+                vertices: Cell::new(vertices),
+                vertex_ids: (0..ply.vertices().len() as u32).collect(),
+                faces,
+                has_normals,
+                texture: Cell::new(texture),
+            }
+        };
+        Ok(importer)
+    }
+}
+
+// The split vertices, the PLY vertex of each one, the faces and the texture
+type BakedColors = (Vec<Vertex>, Vec<u32>, Vec<Vec<VertexIndex>>, DynamicImage);
+
+// Bakes the vertex colors into a texture. Each face gets a small cell of the texture, with the
+// face drawn in it with its real shape, and the colors interpolated from its vertices.
+// The vertices are split per face, because each one has a different UV.
+fn bake_vertex_colors(ply: &data::Ply) -> Result<BakedColors> {
+    let num_faces = ply.faces().len().max(1);
+    let cols = (num_faces as f64).sqrt().ceil() as u32;
+    if cols.saturating_mul(ATLAS_MIN_CELL) > ATLAS_MAX_SIZE {
+        bail!("The model has too many faces ({num_faces}) to bake its vertex colors");
+    }
+    // rows <= cols, so the atlas is never bigger than ATLAS_MAX_SIZE
+    let rows = (num_faces as u32).div_ceil(cols);
+    let cell = (ATLAS_MAX_SIZE / cols).clamp(ATLAS_MIN_CELL, ATLAS_MAX_CELL);
+    // The face is drawn in the inner part of the cell, from the center of the first pixel to the
+    // center of the last one
+    let inner = (cell - 2 * ATLAS_GUTTER - 1) as f32;
+    let atlas_size = Vector2::new((cols * cell) as f32, (rows * cell) as f32);
+    let mut img = RgbImage::new(cols * cell, rows * cell);
+
+    let mut vertices = Vec::new();
+    let mut vertex_ids = Vec::new();
+    let mut faces = Vec::with_capacity(ply.faces().len());
+    for (i_face, face) in ply.faces().iter().enumerate() {
+        let ply_vs: Vec<&data::PlyVertex> =
+            face.iter().map(|&i| &ply.vertices()[i as usize]).collect();
+        let ps: Vec<Vector3> = ply_vs.iter().map(|v| v.pos).collect();
+        let colors: Vec<Vector3> = ply_vs
+            .iter()
+            .map(|v| v.color.unwrap_or_else(Vector3::zero))
+            .collect();
+        let (tris, plane) = util_3d::tessellate(&ps);
+        let ps2: Vec<Vector2> = ps.iter().map(|p| plane.project(p, 1.0)).collect();
+        let (a, b) = util_3d::bounding_box_2d(ps2.iter().copied());
+        // Keep the aspect ratio of the face
+        let side = (b - a).x.max((b - a).y);
+        let side = if side > 0.0 { side } else { 1.0 };
+
+        let i_face = i_face as u32;
+        let (cx, cy) = ((i_face % cols) * cell, (i_face / cols) * cell);
+        for ty in 0..cell {
+            for tx in 0..cell {
+                // The gutter pixels are outside of the face, they get the nearest color
+                let t = Vector2::new(tx as f32, ty as f32)
+                    - Vector2::new(1.0, 1.0) * ATLAS_GUTTER as f32;
+                let p = a + t * side / inner;
+                let c = interpolate_color(p, &tris, &ps2, &colors) * 255.0;
+                let c = [c.x, c.y, c.z].map(|x| x.round().clamp(0.0, 255.0) as u8);
+                img.put_pixel(cx + tx, cy + ty, Rgb(c));
+            }
+        }
+
+        let cell_origin = Vector2::new(cx as f32, cy as f32)
+            + Vector2::new(1.0, 1.0) * (ATLAS_GUTTER as f32 + 0.5);
+        let mut face_vs = Vec::with_capacity(face.len());
+        for ((&i_v, v), p) in face.iter().zip(&ply_vs).zip(&ps2) {
+            let uv = cell_origin + (p - a) / side * inner;
+            face_vs.push(VertexIndex(vertices.len() as u32));
+            vertices.push(Vertex {
+                pos: v.pos,
+                normal: v.normal.unwrap_or_else(Vector3::zero),
+                uv: Vector2::new(uv.x / atlas_size.x, uv.y / atlas_size.y),
+            });
+            vertex_ids.push(i_v);
+        }
+        faces.push(face_vs);
+    }
+    Ok((vertices, vertex_ids, faces, DynamicImage::ImageRgb8(img)))
+}
+
+// The color at `p` interpolated from the triangle that contains it, or the nearest one
+fn interpolate_color(
+    p: Vector2,
+    tris: &[[usize; 3]],
+    ps: &[Vector2],
+    colors: &[Vector3],
+) -> Vector3 {
+    let mut best: Option<(f32, [usize; 3], [f32; 3])> = None;
+    for &tri in tris {
+        let [p0, p1, p2] = tri.map(|i| ps[i]);
+        let d = (p1 - p0).perp_dot(p2 - p0);
+        if d.abs() < f32::EPSILON {
+            continue;
+        }
+        let l1 = (p - p0).perp_dot(p2 - p0) / d;
+        let l2 = (p1 - p0).perp_dot(p - p0) / d;
+        let ls = [1.0 - l1 - l2, l1, l2];
+        // The most inside triangle is the one with the greatest minimum weight
+        let score = ls[0].min(ls[1]).min(ls[2]);
+        if !matches!(best, Some((s, _, _)) if s >= score) {
+            best = Some((score, tri, ls));
+        }
+    }
+    let Some((_, tri, ls)) = best else {
+        // A degenerate face, just use the average
+        return colors.iter().sum::<Vector3>() / colors.len().max(1) as f32;
+    };
+    let ls = ls.map(|l| l.max(0.0));
+    let total: f32 = ls.iter().sum();
+    (0..3)
+        .map(|i| colors[tri[i]] * ls[i] / total)
+        .sum::<Vector3>()
+}
+
+impl Importer for PlyImporter {
+    type VertexId = u32;
+
+    fn build_vertices(&self) -> (bool, Vec<Vertex>) {
+        (self.has_normals, self.vertices.take())
+    }
+
+    fn vertex_map(&self, i_v: VertexIndex) -> Self::VertexId {
+        self.vertex_ids[usize::from(i_v)]
+    }
+
+    fn face_count(&self) -> usize {
+        self.faces.len()
+    }
+
+    fn faces(&self) -> impl Iterator<Item = (impl AsRef<[VertexIndex]>, MaterialIndex)> {
+        self.faces.iter().map(|f| (f, MaterialIndex(0)))
+    }
+
+    fn build_textures(&self) -> Vec<Texture> {
+        vec![self.texture.take().unwrap_or_default()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn import(text: &str) -> Result<PlyImporter> {
+        PlyImporter::new(text.as_bytes(), Path::new("test.ply"))
+    }
+
+    const HEADER: &str = "ply\nformat ascii 1.0\n";
+
+    #[test]
+    fn ascii_with_uvs_and_no_texture() {
+        let ply = format!(
+            "{HEADER}element vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+             property float s\nproperty float t\nelement face 1\n\
+             property list uchar int vertex_indices\nend_header\n\
+             0 0 0 0 0\n1 0 0 1 0\n0 1 0 0 1\n3 0 1 2\n"
+        );
+        let imp = import(&ply).unwrap();
+        assert_eq!(imp.face_count(), 1);
+        let (has_normals, vs) = imp.build_vertices();
+        assert!(!has_normals);
+        // The V is flipped, as in OBJ
+        let uvs: Vec<_> = vs.iter().map(|v| v.uv).collect();
+        assert_eq!(
+            uvs,
+            [
+                Vector2::new(0.0, 1.0),
+                Vector2::new(1.0, 1.0),
+                Vector2::new(0.0, 0.0)
+            ]
+        );
+        assert!(imp.build_textures()[0].pixbuf.is_none());
+    }
+
+    #[test]
+    fn binary_big_endian() {
+        let mut data = b"ply\nformat binary_big_endian 1.0\nelement vertex 3\n\
+            property float x\nproperty float y\nproperty float z\nelement face 1\n\
+            property list uchar ushort vertex_indices\nend_header\n"
+            .to_vec();
+        for p in [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 2.0, 0.0]] {
+            for c in p {
+                data.extend(c.to_be_bytes());
+            }
+        }
+        data.push(3);
+        for i in [0u16, 1, 2] {
+            data.extend(i.to_be_bytes());
+        }
+        let imp = PlyImporter::new(&data[..], Path::new("test.ply")).unwrap();
+        let (_, vs) = imp.build_vertices();
+        assert_eq!(vs[2].pos, Vector3::new(0.0, 2.0, 0.0));
+        assert_eq!(
+            imp.faces[0],
+            [VertexIndex(0), VertexIndex(1), VertexIndex(2)]
+        );
+    }
+
+    #[test]
+    fn vertex_colors_are_baked() {
+        let ply = format!(
+            "{HEADER}element vertex 4\nproperty float x\nproperty float y\nproperty float z\n\
+             property uchar red\nproperty uchar green\nproperty uchar blue\nelement face 2\n\
+             property list uchar int vertex_indices\nend_header\n\
+             0 0 0 255 0 0\n1 0 0 255 0 0\n1 1 0 255 0 0\n5 5 5 0 0 255\n\
+             3 0 1 2\n3 1 3 2\n"
+        );
+        let imp = import(&ply).unwrap();
+        // The vertices are split per face
+        assert_eq!(imp.vertex_ids, [0, 1, 2, 1, 3, 2]);
+        let img = imp.build_textures()[0].pixbuf.as_ref().unwrap().to_rgb8();
+        assert!(img.width() <= ATLAS_MAX_SIZE && img.height() <= ATLAS_MAX_SIZE);
+
+        let (_, vs) = imp.build_vertices();
+        let pixel = |uv: Vector2| {
+            let (x, y) = (uv.x * img.width() as f32, uv.y * img.height() as f32);
+            img.get_pixel(x as u32, y as u32).0
+        };
+        // The first face is all red, its gutter too, even if the next cell is blue
+        for v in &vs[..3] {
+            assert_eq!(pixel(v.uv), [255, 0, 0]);
+        }
+        assert_eq!(img.get_pixel(0, 0).0, [255, 0, 0]);
+        // The pixel of the blue vertex is not exactly on it, so it is not all blue
+        let [r, _, b] = pixel(vs[4].uv);
+        assert!(b > 200 && r < 60);
+    }
+
+    #[test]
+    fn negative_index() {
+        let ply = format!(
+            "{HEADER}element vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+             element face 1\nproperty list uchar int vertex_indices\nend_header\n\
+             0 0 0\n1 0 0\n0 1 0\n3 0 1 -1\n"
+        );
+        assert!(import(&ply).is_err());
+    }
+
+    #[test]
+    fn huge_count() {
+        // It fails because there is no data, not because it runs out of memory
+        let ply = format!(
+            "{HEADER}element vertex 100000000000\nproperty float x\nproperty float y\n\
+             property float z\nend_header\n0 0 0\n"
+        );
+        assert!(import(&ply).is_err());
+    }
+}
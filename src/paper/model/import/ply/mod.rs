@@ -0,0 +1,4 @@
+mod data;
+mod importer;
+
+pub use importer::PlyImporter;
@@ -0,0 +1,593 @@
+use std::cell::Cell;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+use super::*;
+use cgmath::{InnerSpace, MetricSpace};
+use fxhash::{FxHashMap, FxHashSet};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SimplifyOptions {
+    // Stop when the model has this many faces, or less
+    pub target_faces: usize,
+    // Stop before moving the surface more than this, relative to the size of the model
+    pub max_error: Option<f32>,
+}
+
+// A symmetric 4x4 matrix, the sum of the squared distances to a set of planes.
+// They are not weighted by area, so the value is at least the squared distance to any of them.
+#[derive(Debug, Copy, Clone, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn from_plane(n: Vector3, d: f32) -> Quadric {
+        let [a, b, c, d] = [n.x, n.y, n.z, d].map(f64::from);
+        Quadric([
+            a * a,
+            a * b,
+            a * c,
+            a * d,
+            b * b,
+            b * c,
+            b * d,
+            c * c,
+            c * d,
+            d * d,
+        ])
+    }
+    fn add(&mut self, other: &Quadric) {
+        for (x, y) in self.0.iter_mut().zip(other.0) {
+            *x += y;
+        }
+    }
+    fn eval(&self, p: Vector3) -> f64 {
+        let [x, y, z] = [p.x, p.y, p.z].map(f64::from);
+        let q = &self.0;
+        q[0] * x * x
+            + 2.0 * q[1] * x * y
+            + 2.0 * q[2] * x * z
+            + 2.0 * q[3] * x
+            + q[4] * y * y
+            + 2.0 * q[5] * y * z
+            + 2.0 * q[6] * y
+            + q[7] * z * z
+            + 2.0 * q[8] * z
+            + q[9]
+    }
+}
+
+struct Tri {
+    v: [VertexIndex; 3],
+    mat: MaterialIndex,
+    // Index of the face in the inner importer
+    source: usize,
+    alive: bool,
+}
+
+// Moving `from` onto `to`, with the stamps of both when it was computed
+struct Collapse {
+    cost: f64,
+    from: u32,
+    to: u32,
+    stamps: (u32, u32),
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for Collapse {}
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.cost.total_cmp(&other.cost)
+    }
+}
+
+// Reduces the number of faces of the model of an importer, with quadric edge collapses.
+// A vertex is only removed if all its faces have the same material, part and texture
+// coordinates and it is not in a border, so the UVs and the material boundaries are kept.
+pub struct SimplifyImporter<I: Importer> {
+    inner: I,
+    has_normals: bool,
+    vertices: Cell<Vec<Vertex>>,
+    // VertexIndex -> position id, that is the VertexId of the model
+    pos_ids: Vec<u32>,
+    // Position id -> one of its original vertices, to ask the inner importer
+    pos_reps: Vec<VertexIndex>,
+    // (vertices, material, index of the face in the inner importer)
+    faces: Vec<([VertexIndex; 3], MaterialIndex, usize)>,
+    // Edges between two triangles of the same original face
+    hidden_edges: FxHashSet<(u32, u32)>,
+}
+
+fn edge_key(a: u32, b: u32) -> (u32, u32) {
+    (a.min(b), a.max(b))
+}
+
+impl<I: Importer> SimplifyImporter<I> {
+    pub fn new(inner: I, options: &SimplifyOptions) -> Self {
+        let (has_normals, vertices) = inner.build_vertices();
+
+        // Vertices at the same position are the same vertex only if the inner importer says so
+        let mut by_pos: FxHashMap<[u32; 3], Vec<u32>> = FxHashMap::default();
+        let mut pos_ids = Vec::with_capacity(vertices.len());
+        let mut pos_reps: Vec<VertexIndex> = Vec::new();
+        for (i_v, v) in vertices.iter().enumerate() {
+            let i_v = VertexIndex::from(i_v);
+            let source = inner.vertex_map(i_v);
+            let same = by_pos
+                .entry([v.pos.x, v.pos.y, v.pos.z].map(f32::to_bits))
+                .or_default();
+            let id = match same
+                .iter()
+                .find(|&&id| inner.vertex_map(pos_reps[id as usize]) == source)
+            {
+                Some(&id) => id,
+                None => {
+                    let id = pos_reps.len() as u32;
+                    pos_reps.push(i_v);
+                    same.push(id);
+                    id
+                }
+            };
+            pos_ids.push(id);
+        }
+        let pos = |i_v: VertexIndex| vertices[usize::from(i_v)].pos;
+        let pos_of = |id: u32| pos(pos_reps[id as usize]);
+
+        let mut tris = Vec::with_capacity(inner.face_count());
+        for (source, (verts, mat)) in inner.faces().enumerate() {
+            let verts = verts.as_ref();
+            let to_tess: Vec<_> = verts.iter().map(|&v| pos(v)).collect();
+            let (tess, _) = crate::util_3d::tessellate(&to_tess);
+            for t in tess {
+                tris.push(Tri {
+                    v: t.map(|i| verts[i]),
+                    mat,
+                    source,
+                    alive: true,
+                });
+            }
+        }
+        let num_ids = pos_reps.len();
+        let id_of = |i_v: VertexIndex| pos_ids[usize::from(i_v)];
+
+        // Position id -> triangles that use it
+        let mut id_tris: Vec<Vec<usize>> = vec![Vec::new(); num_ids];
+        let mut edge_count: FxHashMap<(u32, u32), u32> = FxHashMap::default();
+        for (i_tri, tri) in tris.iter().enumerate() {
+            for i in 0..3 {
+                id_tris[id_of(tri.v[i]) as usize].push(i_tri);
+                *edge_count
+                    .entry(edge_key(id_of(tri.v[i]), id_of(tri.v[(i + 1) % 3])))
+                    .or_default() += 1;
+            }
+        }
+
+        // Vertices that must not be moved
+        let has_parts = !inner.part_names().is_empty();
+        let part = |tri: &Tri| {
+            if !has_parts {
+                PartIndex::from(0)
+            } else {
+                inner.face_part(tri.source)
+            }
+        };
+        let mut locked = vec![false; num_ids];
+        for (&(a, b), &n) in &edge_count {
+            if n != 2 {
+                locked[a as usize] = true;
+                locked[b as usize] = true;
+            }
+        }
+        for (id, id_tris) in id_tris.iter().enumerate() {
+            let Some(&first) = id_tris.first() else {
+                continue;
+            };
+            let corner = |tri: &Tri| {
+                let i_v = *tri.v.iter().find(|&&v| id_of(v) == id as u32).unwrap();
+                vertices[usize::from(i_v)].uv
+            };
+            let t0 = &tris[first];
+            let (uv0, mat0, part0) = (corner(t0), t0.mat, part(t0));
+            if id_tris.iter().any(|&t| {
+                let tri = &tris[t];
+                corner(tri) != uv0 || tri.mat != mat0 || part(tri) != part0
+            }) {
+                locked[id] = true;
+            }
+        }
+
+        // The cost of a collapse is a sum of squared distances, so this is a bound of the
+        // distance to each original plane
+        let (v_min, v_max) = crate::util_3d::bounding_box_3d(vertices.iter().map(|v| v.pos));
+        let max_cost = options
+            .max_error
+            .map(|e| f64::from(e * v_min.distance(v_max)).powi(2))
+            .unwrap_or(f64::INFINITY);
+
+        let mut quadrics = vec![Quadric::default(); num_ids];
+        for tri in &tris {
+            let [p0, p1, p2] = tri.v.map(pos);
+            let cross = (p1 - p0).cross(p2 - p0);
+            if cross.magnitude2() == 0.0 {
+                continue;
+            }
+            let n = cross.normalize();
+            let q = Quadric::from_plane(n, -n.dot(p0));
+            for v in tri.v {
+                quadrics[id_of(v) as usize].add(&q);
+            }
+        }
+
+        let mut stamps = vec![0u32; num_ids];
+        let mut heap = BinaryHeap::new();
+        let candidate = |from: u32, to: u32, quadrics: &[Quadric], stamps: &[u32]| {
+            let mut q = quadrics[from as usize];
+            q.add(&quadrics[to as usize]);
+            Reverse(Collapse {
+                cost: q.eval(pos_of(to)),
+                from,
+                to,
+                stamps: (stamps[from as usize], stamps[to as usize]),
+            })
+        };
+        for &(a, b) in edge_count.keys() {
+            if !locked[a as usize] {
+                heap.push(candidate(a, b, &quadrics, &stamps));
+            }
+            if !locked[b as usize] {
+                heap.push(candidate(b, a, &quadrics, &stamps));
+            }
+        }
+
+        let neighbors = |id: u32, tris: &[Tri], id_tris: &[Vec<usize>]| {
+            let mut res = FxHashSet::default();
+            for &t in &id_tris[id as usize] {
+                res.extend(tris[t].v.map(id_of).into_iter().filter(|&x| x != id));
+            }
+            res
+        };
+
+        let mut num_tris = tris.len();
+        while num_tris > options.target_faces {
+            let Some(Reverse(c)) = heap.pop() else {
+                break;
+            };
+            if c.stamps != (stamps[c.from as usize], stamps[c.to as usize]) {
+                continue;
+            }
+            if c.cost > max_cost {
+                break;
+            }
+            let (from, to) = (c.from, c.to);
+
+            // The triangles of the edge are removed, the others are moved
+            let (edge_tris, moved_tris): (Vec<usize>, Vec<usize>) = id_tris[from as usize]
+                .iter()
+                .copied()
+                .partition(|&t| tris[t].v.iter().any(|&v| id_of(v) == to));
+            if edge_tris.len() != 2 {
+                continue;
+            }
+            // Link condition: the only common neighbors are those of the edge triangles,
+            // or the mesh would be non-manifold
+            let n_from = neighbors(from, &tris, &id_tris);
+            let n_to = neighbors(to, &tris, &id_tris);
+            if n_from.intersection(&n_to).count() != 2 {
+                continue;
+            }
+            // The moved triangles must not flip or become degenerate
+            let p_to = pos_of(to);
+            let flips = moved_tris.iter().any(|&t| {
+                let [p0, p1, p2] = tris[t].v.map(pos);
+                let old_n = (p1 - p0).cross(p2 - p0);
+                let [q0, q1, q2] = tris[t]
+                    .v
+                    .map(|v| if id_of(v) == from { p_to } else { pos(v) });
+                let new_n = (q1 - q0).cross(q2 - q0);
+                new_n.magnitude2() == 0.0 || old_n.normalize().dot(new_n.normalize()) < 0.2
+            });
+            if flips {
+                continue;
+            }
+
+            // `from` is not in a seam, so its faces take the same vertex of `to` as the edge
+            let tri_e = &tris[edge_tris[0]];
+            let v_to = *tri_e.v.iter().find(|&&v| id_of(v) == to).unwrap();
+            for &t in &edge_tris {
+                tris[t].alive = false;
+                for v in tris[t].v {
+                    let id = id_of(v);
+                    if id != from {
+                        id_tris[id as usize].retain(|&x| x != t);
+                    }
+                }
+            }
+            num_tris -= edge_tris.len();
+            for &t in &moved_tris {
+                for v in &mut tris[t].v {
+                    if id_of(*v) == from {
+                        *v = v_to;
+                    }
+                }
+                id_tris[to as usize].push(t);
+            }
+            id_tris[from as usize].clear();
+            let q_from = quadrics[from as usize];
+            quadrics[to as usize].add(&q_from);
+
+            // The costs of the edges of `to` have changed, and `from` is gone
+            stamps[from as usize] += 1;
+            stamps[to as usize] += 1;
+            for n in neighbors(to, &tris, &id_tris) {
+                if !locked[n as usize] {
+                    heap.push(candidate(n, to, &quadrics, &stamps));
+                }
+                if !locked[to as usize] {
+                    heap.push(candidate(to, n, &quadrics, &stamps));
+                }
+            }
+        }
+
+        // Keep only the vertices in use
+        let mut new_index = vec![None; vertices.len()];
+        let mut num_used = 0;
+        for tri in tris.iter().filter(|t| t.alive) {
+            for v in tri.v {
+                new_index[usize::from(v)].get_or_insert_with(|| {
+                    num_used += 1;
+                    VertexIndex::from(num_used - 1)
+                });
+            }
+        }
+        let mut new_pos_ids = vec![0; num_used];
+        let mut new_vertices: Vec<_> = vertices.into_iter().map(Some).collect();
+        let mut vertices = Vec::with_capacity(num_used);
+        let mut order: Vec<_> = new_index
+            .iter()
+            .enumerate()
+            .filter_map(|(old, new)| new.map(|new| (new, old)))
+            .collect();
+        order.sort_by_key(|&(new, _)| usize::from(new));
+        for (new, old) in order {
+            new_pos_ids[usize::from(new)] = pos_ids[old];
+            vertices.push(new_vertices[old].take().unwrap());
+        }
+
+        let faces: Vec<_> = tris
+            .iter()
+            .filter(|t| t.alive)
+            .map(|t| {
+                let v = t.v.map(|v| new_index[usize::from(v)].unwrap());
+                (v, t.mat, t.source)
+            })
+            .collect();
+
+        // Triangles of the same original face keep their edges hidden
+        let mut edge_sources: FxHashMap<(u32, u32), Vec<usize>> = FxHashMap::default();
+        for (v, _, source) in &faces {
+            for i in 0..3 {
+                let a = new_pos_ids[usize::from(v[i])];
+                let b = new_pos_ids[usize::from(v[(i + 1) % 3])];
+                edge_sources
+                    .entry(edge_key(a, b))
+                    .or_default()
+                    .push(*source);
+            }
+        }
+        let hidden_edges = edge_sources
+            .into_iter()
+            .filter(|(_, s)| matches!(s[..], [a, b] if a == b))
+            .map(|(e, _)| e)
+            .collect();
+
+        SimplifyImporter {
+            inner,
+            has_normals,
+            vertices: Cell::new(vertices),
+            pos_ids: new_pos_ids,
+            pos_reps,
+            faces,
+            hidden_edges,
+        }
+    }
+}
+
+impl<I: Importer> Importer for SimplifyImporter<I> {
+    type VertexId = u32;
+
+    fn vertex_map(&self, i_v: VertexIndex) -> Self::VertexId {
+        self.pos_ids[usize::from(i_v)]
+    }
+    fn build_vertices(&self) -> (bool, Vec<Vertex>) {
+        (self.has_normals, self.vertices.take())
+    }
+    fn face_count(&self) -> usize {
+        self.faces.len()
+    }
+    fn faces(&self) -> impl Iterator<Item = (impl AsRef<[VertexIndex]>, MaterialIndex)> {
+        self.faces.iter().map(|(v, mat, _)| (*v, *mat))
+    }
+    fn build_textures(&self) -> Vec<Texture> {
+        self.inner.build_textures()
+    }
+    fn compute_edge_status(&self, edge_id: (u32, u32)) -> Option<EdgeStatus> {
+        if self.hidden_edges.contains(&edge_key(edge_id.0, edge_id.1)) {
+            return Some(EdgeStatus::Hidden);
+        }
+        let inner_id = |id: u32| self.inner.vertex_map(self.pos_reps[id as usize]);
+        self.inner
+            .compute_edge_status((inner_id(edge_id.0), inner_id(edge_id.1)))
+    }
+    fn build_options(&self) -> Option<PaperOptions> {
+        self.inner.build_options()
+    }
+    fn part_names(&self) -> Vec<String> {
+        self.inner.part_names()
+    }
+    fn face_part(&self, i_face: usize) -> PartIndex {
+        self.inner.face_part(self.faces[i_face].2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Zero;
+
+    // A square grid of n x n cells, as triangles, with a height
+    struct Grid {
+        n: usize,
+        height: fn(f32, f32) -> f32,
+        // A cell that is left out, to make a hole
+        hole: Option<(usize, usize)>,
+        // All the edges are hidden, so it is a single flat face
+        hidden: bool,
+    }
+
+    impl Grid {
+        fn index(&self, i: usize, j: usize) -> VertexIndex {
+            VertexIndex::from(j * (self.n + 1) + i)
+        }
+        fn triangles(&self) -> Vec<[VertexIndex; 3]> {
+            let mut res = Vec::new();
+            for j in 0..self.n {
+                for i in 0..self.n {
+                    if self.hole == Some((i, j)) {
+                        continue;
+                    }
+                    let [a, b, c, d] = [
+                        self.index(i, j),
+                        self.index(i + 1, j),
+                        self.index(i + 1, j + 1),
+                        self.index(i, j + 1),
+                    ];
+                    res.push([a, b, c]);
+                    res.push([a, c, d]);
+                }
+            }
+            res
+        }
+    }
+
+    impl Importer for Grid {
+        type VertexId = usize;
+
+        fn vertex_map(&self, i_v: VertexIndex) -> usize {
+            usize::from(i_v)
+        }
+        fn build_vertices(&self) -> (bool, Vec<Vertex>) {
+            let mut vs = Vec::new();
+            for j in 0..=self.n {
+                for i in 0..=self.n {
+                    let (x, y) = (i as f32, j as f32);
+                    vs.push(Vertex {
+                        pos: Vector3::new(x, y, (self.height)(x, y)),
+                        normal: Vector3::new(0.0, 0.0, 1.0),
+                        uv: Vector2::zero(),
+                    });
+                }
+            }
+            (true, vs)
+        }
+        fn face_count(&self) -> usize {
+            self.triangles().len()
+        }
+        fn faces(&self) -> impl Iterator<Item = (impl AsRef<[VertexIndex]>, MaterialIndex)> {
+            self.triangles()
+                .into_iter()
+                .map(|t| (t, MaterialIndex::from(0)))
+        }
+        fn build_textures(&self) -> Vec<Texture> {
+            vec![Texture::default()]
+        }
+        fn compute_edge_status(&self, _edge_id: (usize, usize)) -> Option<EdgeStatus> {
+            self.hidden.then_some(EdgeStatus::Hidden)
+        }
+    }
+
+    #[test]
+    fn error_is_bounded() {
+        let grid = Grid {
+            n: 16,
+            height: |x, y| (x * 0.4).sin() * (y * 0.3).cos(),
+            hole: None,
+            hidden: false,
+        };
+        let (_, original) = grid.build_vertices();
+        let num_faces = grid.face_count();
+        let (v_min, v_max) = crate::util_3d::bounding_box_3d(original.iter().map(|v| v.pos));
+        let max_error = 0.005;
+        let bound = max_error * v_min.distance(v_max);
+
+        let simple = SimplifyImporter::new(
+            grid,
+            &SimplifyOptions {
+                target_faces: 0,
+                max_error: Some(max_error),
+            },
+        );
+        let (_, vs) = simple.build_vertices();
+        let tris: Vec<[Vector3; 3]> = simple
+            .faces()
+            .map(|(f, _)| {
+                let f = f.as_ref();
+                [0, 1, 2].map(|i| vs[usize::from(f[i])].pos)
+            })
+            .collect();
+        assert!(tris.len() < num_faces * 3 / 4);
+
+        // It is still a height map, so compare the height of the surface at each original vertex
+        for v in &original {
+            let p = v.pos;
+            let z = tris
+                .iter()
+                .find_map(|&[a, b, c]| {
+                    let det = (b.x - a.x) * (c.y - a.y) - (c.x - a.x) * (b.y - a.y);
+                    let u = ((p.x - a.x) * (c.y - a.y) - (c.x - a.x) * (p.y - a.y)) / det;
+                    let w = ((b.x - a.x) * (p.y - a.y) - (p.x - a.x) * (b.y - a.y)) / det;
+                    let inside = [u, w, 1.0 - u - w].iter().all(|&t| t >= -1e-4);
+                    inside.then_some(a.z + u * (b.z - a.z) + w * (c.z - a.z))
+                })
+                .unwrap();
+            assert!((z - p.z).abs() <= bound, "{p:?} {z} > {bound}");
+        }
+    }
+
+    #[test]
+    fn flat_face_with_hole() {
+        let grid = Grid {
+            n: 3,
+            height: |_, _| 0.0,
+            hole: Some((1, 1)),
+            hidden: true,
+        };
+        let papercraft = Papercraft::import(grid, &mut ImportReport::default());
+        let hidden = |p: &Papercraft| {
+            p.model()
+                .edges()
+                .filter(|&(i_edge, _)| p.edge_status(i_edge) == EdgeStatus::Hidden)
+                .count()
+        };
+        let flat_face = papercraft.get_flat_faces(FaceIndex::from(0));
+        assert_eq!(flat_face.len(), 16);
+        assert!(papercraft.flat_face_contour(&flat_face).is_none());
+
+        // Nothing to simplify, but the hole must not be filled
+        let new = papercraft
+            .simplified(&SimplifyOptions {
+                target_faces: usize::MAX,
+                max_error: None,
+            })
+            .unwrap();
+        assert_eq!(new.model().num_faces(), 16);
+        assert_eq!(hidden(&new), hidden(&papercraft));
+    }
+}
@@ -1,6 +1,7 @@
 use crate::paper::import::*;
 use anyhow::bail;
 use cgmath::Zero;
+use std::io::{Seek, SeekFrom};
 
 #[derive(Debug)]
 pub struct Stl {
@@ -14,13 +15,26 @@ pub struct Triangle {
 }
 
 impl Stl {
-    pub fn new<R: BufRead>(mut f: R) -> Result<Stl> {
-        let mut hdr = [0; 80];
+    pub fn new<R: BufRead + Seek>(mut f: R) -> Result<Stl> {
+        // Some binary files start with "solid", too, so check the size of the file
+        let len = f.seek(SeekFrom::End(0))?;
+        f.rewind()?;
+        let mut hdr = [0; 84];
+        if len >= 84 {
+            f.read_exact(&mut hdr)?;
+            let n_tris = u32::from_le_bytes(hdr[80..84].try_into().unwrap());
+            if len == 84 + 50 * u64::from(n_tris) {
+                f.rewind()?;
+                f.read_exact(&mut hdr[..80])?;
+                return Self::new_binary(f);
+            }
+            f.rewind()?;
+        }
         f.read_exact(&mut hdr[..5])?;
         if &hdr[..5] == b"solid" {
             Self::new_text(f)
         } else {
-            f.read_exact(&mut hdr[5..])?;
+            f.read_exact(&mut hdr[5..80])?;
             Self::new_binary(f)
         }
     }
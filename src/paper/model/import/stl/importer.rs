@@ -1,13 +1,14 @@
 use super::super::*;
 use super::data;
 use cgmath::Zero;
+use std::io::Seek;
 
 pub struct StlImporter {
     stl: data::Stl,
 }
 
 impl StlImporter {
-    pub fn new<R: BufRead>(f: R) -> Result<StlImporter> {
+    pub fn new<R: BufRead + Seek>(f: R) -> Result<StlImporter> {
         let stl = data::Stl::new(f)?;
 
         Ok(StlImporter { stl })
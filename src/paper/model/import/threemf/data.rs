@@ -0,0 +1,378 @@
+use super::xml::{Element, XmlEvent, XmlReader};
+use crate::paper::import::*;
+use crate::util_3d::Matrix4;
+use cgmath::One;
+use fxhash::FxHashMap;
+use image::DynamicImage;
+use std::io::Seek;
+
+// Components can reference other objects, that may reference others...
+const MAX_COMPONENT_DEPTH: u32 = 32;
+
+#[derive(Debug)]
+pub struct Triangle {
+    pub v: [u32; 3],
+    pub pid: Option<u32>,
+    pub p: [Option<u32>; 3],
+}
+
+#[derive(Debug, Default)]
+pub struct Mesh {
+    pub vertices: Vec<Vector3>,
+    pub triangles: Vec<Triangle>,
+}
+
+#[derive(Debug)]
+struct Component {
+    // The model file of the object, if it is not the same as the component
+    path: Option<String>,
+    object_id: u32,
+    mx: Matrix4,
+}
+
+#[derive(Debug)]
+pub struct Object {
+    pub pid: Option<u32>,
+    pub pindex: Option<u32>,
+    mesh: Option<Mesh>,
+    components: Vec<Component>,
+    // Support objects are only for printing, they are not part of the model
+    is_support: bool,
+}
+
+#[derive(Debug)]
+enum PropertyGroup {
+    // basematerials and colorgroup
+    Colors(Vec<[u8; 4]>),
+    // texture2dgroup: (texture id, coordinates)
+    TexCoords(u32, Vec<Vector2>),
+}
+
+// The resolved value of a triangle property
+#[derive(Debug)]
+pub enum Property<'a> {
+    Color([u8; 4]),
+    // (path of the texture image, UV)
+    TexCoord(&'a str, Vector2),
+}
+
+// The contents of a .model file. Resource ids are local to each file.
+#[derive(Debug, Default)]
+pub struct ModelPart {
+    objects: FxHashMap<u32, Object>,
+    groups: FxHashMap<u32, PropertyGroup>,
+    // texture2d id -> path of the image inside the package
+    textures: FxHashMap<u32, String>,
+    build: Vec<(u32, Matrix4)>,
+}
+
+impl ModelPart {
+    pub fn property(&self, pid: u32, index: u32) -> Option<Property<'_>> {
+        match self.groups.get(&pid)? {
+            PropertyGroup::Colors(colors) => {
+                colors.get(index as usize).map(|&c| Property::Color(c))
+            }
+            PropertyGroup::TexCoords(texid, uvs) => {
+                let path = self.textures.get(texid)?;
+                uvs.get(index as usize)
+                    .map(|&uv| Property::TexCoord(path, uv))
+            }
+        }
+    }
+}
+
+pub struct MeshInstance<'a> {
+    pub part: &'a ModelPart,
+    pub object: &'a Object,
+    pub mesh: &'a Mesh,
+    pub mx: Matrix4,
+}
+
+pub struct ThreeMf {
+    root: String,
+    parts: FxHashMap<String, ModelPart>,
+    images: FxHashMap<String, DynamicImage>,
+}
+
+// Paths inside the package are absolute, but zip entries are not
+fn zip_path(path: &str) -> String {
+    path.trim_start_matches('/').to_owned()
+}
+
+fn read_entry<R: Read + Seek>(zip: &mut zip::ZipArchive<R>, path: &str) -> Result<String> {
+    let mut entry = zip
+        .by_name(path)
+        .with_context(|| format!("{path} not found in the 3MF package"))?;
+    let mut s = String::new();
+    entry
+        .read_to_string(&mut s)
+        .with_context(|| format!("Error reading {path}"))?;
+    Ok(s)
+}
+
+impl ThreeMf {
+    pub fn from_reader<R: Read + Seek>(r: R) -> Result<ThreeMf> {
+        let mut zip = zip::ZipArchive::new(r)?;
+        let root = find_root_model(&mut zip)?;
+
+        let mut parts = FxHashMap::default();
+        let mut images = FxHashMap::default();
+        let mut pending = vec![root.clone()];
+        while let Some(path) = pending.pop() {
+            if parts.contains_key(&path) {
+                continue;
+            }
+            let xml = read_entry(&mut zip, &path)?;
+            let part = parse_model(&xml).with_context(|| format!("Error reading {path}"))?;
+            for obj in part.objects.values() {
+                pending.extend(obj.components.iter().filter_map(|c| c.path.clone()));
+            }
+            for tex_path in part.textures.values() {
+                if images.contains_key(tex_path) {
+                    continue;
+                }
+                let err_tex = || format!("Error reading texture file {tex_path}");
+                let mut data = Vec::new();
+                zip.by_name(tex_path)
+                    .with_context(err_tex)?
+                    .read_to_end(&mut data)
+                    .with_context(err_tex)?;
+                let img = image::io::Reader::new(std::io::Cursor::new(&data))
+                    .with_guessed_format()
+                    .with_context(err_tex)?
+                    .decode()
+                    .with_context(err_tex)?;
+                images.insert(tex_path.clone(), img);
+            }
+            parts.insert(path, part);
+        }
+        Ok(ThreeMf {
+            root,
+            parts,
+            images,
+        })
+    }
+
+    // All the meshes in the build, with their full transformation
+    pub fn mesh_instances(&self) -> Result<Vec<MeshInstance<'_>>> {
+        let root = &self.parts[&self.root];
+        let mut res = Vec::new();
+        for &(object_id, mx) in &root.build {
+            self.add_instances(&self.root, object_id, mx, 0, &mut res)?;
+        }
+        Ok(res)
+    }
+
+    fn add_instances<'s>(
+        &'s self,
+        path: &str,
+        object_id: u32,
+        mx: Matrix4,
+        depth: u32,
+        res: &mut Vec<MeshInstance<'s>>,
+    ) -> Result<()> {
+        if depth > MAX_COMPONENT_DEPTH {
+            bail!("Too many nested components");
+        }
+        let part = &self.parts[path];
+        let object = part
+            .objects
+            .get(&object_id)
+            .ok_or_else(|| anyhow!("Invalid object id {object_id} in {path}"))?;
+        if object.is_support {
+            return Ok(());
+        }
+        if let Some(mesh) = &object.mesh {
+            res.push(MeshInstance {
+                part,
+                object,
+                mesh,
+                mx,
+            });
+        }
+        for c in &object.components {
+            let c_path = c.path.as_deref().unwrap_or(path);
+            self.add_instances(c_path, c.object_id, mx * c.mx, depth + 1, res)?;
+        }
+        Ok(())
+    }
+
+    pub fn take_image(&mut self, path: &str) -> Option<DynamicImage> {
+        self.images.remove(path)
+    }
+}
+
+// The relationships of the package tell where the model is, but it is usually in the same place
+fn find_root_model<R: Read + Seek>(zip: &mut zip::ZipArchive<R>) -> Result<String> {
+    const DEFAULT_MODEL: &str = "3D/3dmodel.model";
+    let Ok(rels) = read_entry(zip, "_rels/.rels") else {
+        return Ok(DEFAULT_MODEL.to_owned());
+    };
+    let mut xml = XmlReader::new(&rels);
+    while let Some(ev) = xml.next_event()? {
+        if let XmlEvent::Start(e) = ev {
+            if e.name == "Relationship" && e.attr("Type").is_some_and(|t| t.ends_with("/3dmodel")) {
+                if let Some(target) = e.attr("Target") {
+                    return Ok(zip_path(target));
+                }
+            }
+        }
+    }
+    Ok(DEFAULT_MODEL.to_owned())
+}
+
+// "m00 m01 m02 m10 m11 m12 m20 m21 m22 m30 m31 m32", with the points as row vectors
+fn parse_transform(e: &Element) -> Result<Matrix4> {
+    let Some(s) = e.attr("transform") else {
+        return Ok(Matrix4::one());
+    };
+    let m = s
+        .split_ascii_whitespace()
+        .map(|x| x.parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()
+        .ok()
+        .filter(|m| m.len() == 12)
+        .ok_or_else(|| anyhow!("invalid transform '{s}'"))?;
+    #[rustfmt::skip]
+    let mx = Matrix4::new(
+        m[0], m[1], m[2], 0.0,
+        m[3], m[4], m[5], 0.0,
+        m[6], m[7], m[8], 0.0,
+        m[9], m[10], m[11], 1.0,
+    );
+    Ok(mx)
+}
+
+// "#RRGGBB" or "#RRGGBBAA"
+fn parse_color(s: &str) -> Result<[u8; 4]> {
+    let hex = s
+        .trim()
+        .strip_prefix('#')
+        .filter(|h| h.len() == 6 || h.len() == 8)
+        .ok_or_else(|| anyhow!("invalid color '{s}'"))?;
+    let mut c = [255; 4];
+    for (i, x) in c.iter_mut().enumerate().take(hex.len() / 2) {
+        *x = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16)
+            .map_err(|_| anyhow!("invalid color '{s}'"))?;
+    }
+    Ok(c)
+}
+
+fn parse_model(xml: &str) -> Result<ModelPart> {
+    let mut part = ModelPart::default();
+    let mut xml = XmlReader::new(xml);
+    let mut object: Option<(u32, Object)> = None;
+    let mut group: Option<(u32, PropertyGroup)> = None;
+    // The slices extension has vertices, too, only the ones in the mesh are used
+    let mut in_mesh = false;
+
+    while let Some(ev) = xml.next_event()? {
+        match ev {
+            XmlEvent::Start(e) => match e.name {
+                "basematerials" | "colorgroup" => {
+                    group = Some((e.attr_req("id")?, PropertyGroup::Colors(Vec::new())));
+                }
+                "texture2dgroup" => {
+                    group = Some((
+                        e.attr_req("id")?,
+                        PropertyGroup::TexCoords(e.attr_req("texid")?, Vec::new()),
+                    ));
+                }
+                "base" | "color" => {
+                    if let Some((_, PropertyGroup::Colors(colors))) = &mut group {
+                        let attr = if e.name == "base" {
+                            "displaycolor"
+                        } else {
+                            "color"
+                        };
+                        colors.push(parse_color(e.attr(attr).unwrap_or("#FFFFFF"))?);
+                    }
+                }
+                "tex2coord" => {
+                    if let Some((_, PropertyGroup::TexCoords(_, uvs))) = &mut group {
+                        uvs.push(Vector2::new(e.attr_req("u")?, e.attr_req("v")?));
+                    }
+                }
+                "texture2d" => {
+                    let path: String = e.attr_req("path")?;
+                    part.textures.insert(e.attr_req("id")?, zip_path(&path));
+                }
+                "object" => {
+                    object = Some((
+                        e.attr_req("id")?,
+                        Object {
+                            pid: e.attr_parse("pid")?,
+                            pindex: e.attr_parse("pindex")?,
+                            mesh: None,
+                            components: Vec::new(),
+                            is_support: e.attr("type") == Some("support"),
+                        },
+                    ));
+                }
+                "mesh" => {
+                    if let Some((_, obj)) = &mut object {
+                        obj.mesh = Some(Mesh::default());
+                        in_mesh = true;
+                    }
+                }
+                "vertex" if in_mesh => {
+                    if let Some(mesh) = object.as_mut().and_then(|(_, o)| o.mesh.as_mut()) {
+                        mesh.vertices.push(Vector3::new(
+                            e.attr_req("x")?,
+                            e.attr_req("y")?,
+                            e.attr_req("z")?,
+                        ));
+                    }
+                }
+                "triangle" if in_mesh => {
+                    if let Some(mesh) = object.as_mut().and_then(|(_, o)| o.mesh.as_mut()) {
+                        mesh.triangles.push(Triangle {
+                            v: [e.attr_req("v1")?, e.attr_req("v2")?, e.attr_req("v3")?],
+                            pid: e.attr_parse("pid")?,
+                            p: [
+                                e.attr_parse("p1")?,
+                                e.attr_parse("p2")?,
+                                e.attr_parse("p3")?,
+                            ],
+                        });
+                    }
+                }
+                "component" => {
+                    if let Some((_, obj)) = &mut object {
+                        obj.components.push(Component {
+                            path: e.attr("path").map(zip_path),
+                            object_id: e.attr_req("objectid")?,
+                            mx: parse_transform(&e)?,
+                        });
+                    }
+                }
+                "item" => {
+                    part.build
+                        .push((e.attr_req("objectid")?, parse_transform(&e)?));
+                }
+                _ => {}
+            },
+            XmlEvent::End(name) => match name {
+                "basematerials" | "colorgroup" | "texture2dgroup" => {
+                    if let Some((id, g)) = group.take() {
+                        part.groups.insert(id, g);
+                    }
+                }
+                "mesh" => in_mesh = false,
+                "object" => {
+                    if let Some((id, obj)) = object.take() {
+                        if let Some(mesh) = &obj.mesh {
+                            let num_vs = mesh.vertices.len() as u32;
+                            if mesh.triangles.iter().flat_map(|t| t.v).any(|i| i >= num_vs) {
+                                bail!("Invalid vertex index in object {id}");
+                            }
+                        }
+                        part.objects.insert(id, obj);
+                    }
+                }
+                _ => {}
+            },
+        }
+    }
+    Ok(part)
+}
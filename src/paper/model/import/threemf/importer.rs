@@ -0,0 +1,274 @@
+use std::cell::Cell;
+use std::io::Seek;
+
+use super::super::*;
+use super::data::{self, Property};
+use cgmath::{EuclideanSpace, Point3, SquareMatrix, Transform, Zero};
+use fxhash::{FxHashMap, FxHashSet};
+use image::{DynamicImage, RgbaImage};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum MaterialKey {
+    Color([u8; 4]),
+    // The path of the image
+    Texture(String),
+}
+
+pub struct ThreeMfImporter {
+    // One vertex per triangle corner, because each one may have a different UV
+    vertices: Cell<Vec<Vertex>>,
+    // The 3MF vertex of each one of `vertices`, counting all the instances
+    vertex_ids: Vec<u32>,
+    faces: Vec<([VertexIndex; 3], MaterialIndex)>,
+    // Built in advance because reading them may fail, consumed by build_textures()
+    textures: Cell<Vec<Texture>>,
+}
+
+impl ThreeMfImporter {
+    pub fn new<R: Read + Seek>(f: R) -> Result<Self> {
+        let mut three_mf = data::ThreeMf::from_reader(f)?;
+
+        let mut material_map: FxHashMap<MaterialKey, MaterialIndex> = FxHashMap::default();
+        let mut materials = Vec::new();
+        let mut vertices = Vec::new();
+        let mut vertex_ids = Vec::new();
+        let mut faces = Vec::new();
+        let mut next_id = 0;
+
+        for inst in three_mf.mesh_instances()? {
+            let mirrored = inst.mx.determinant() < 0.0;
+            let base_id = next_id;
+            next_id += inst.mesh.vertices.len() as u32;
+
+            for tri in &inst.mesh.triangles {
+                // The properties of the triangle default to those of the object
+                let (pid, p0) = match tri.pid {
+                    Some(pid) => (Some(pid), tri.p[0]),
+                    None => (inst.object.pid, tri.p[0].or(inst.object.pindex)),
+                };
+                let props: [Option<Property>; 3] = std::array::from_fn(|i| {
+                    let index = if i == 0 { p0 } else { tri.p[i].or(p0) }?;
+                    inst.part.property(pid?, index)
+                });
+                // Per-vertex colors are not supported, the first corner decides the color
+                let key = match &props[0] {
+                    Some(Property::Color(c)) => Some(MaterialKey::Color(*c)),
+                    Some(Property::TexCoord(path, _)) => {
+                        Some(MaterialKey::Texture(path.to_string()))
+                    }
+                    None => None,
+                };
+                let mat = key.map(|key| {
+                    *material_map.entry(key.clone()).or_insert_with(|| {
+                        materials.push(key);
+                        MaterialIndex::from(materials.len() - 1)
+                    })
+                });
+
+                let i_v0 = vertices.len();
+                for (&i_v, prop) in tri.v.iter().zip(&props) {
+                    let p = inst.mesh.vertices[i_v as usize];
+                    let p = inst.mx.transform_point(Point3::from_vec(p)).to_vec();
+                    // 3MF texture coordinates have the origin at the bottom-left
+                    let uv = match prop {
+                        Some(Property::TexCoord(_, uv)) => Vector2::new(uv.x, 1.0 - uv.y),
+                        _ => Vector2::zero(),
+                    };
+                    vertices.push(Vertex {
+                        // swizzle the coordinates to make the model look at the front, as in STL
+                        pos: Vector3::new(p.x, p.z, -p.y),
+                        normal: Vector3::zero(),
+                        uv,
+                    });
+                    vertex_ids.push(base_id + i_v);
+                }
+                let t = [i_v0, i_v0 + 1, i_v0 + 2].map(VertexIndex::from);
+                let t = if mirrored { [t[0], t[2], t[1]] } else { t };
+                faces.push((t, mat));
+            }
+        }
+        if faces.is_empty() {
+            bail!("No triangles found in the model");
+        }
+
+        let image_name = |path: &str| path.rsplit('/').next().unwrap_or(path).to_owned();
+        let mut used_names: FxHashSet<String> = materials
+            .iter()
+            .filter_map(|key| match key {
+                MaterialKey::Texture(path) => Some(image_name(path)),
+                MaterialKey::Color(_) => None,
+            })
+            .collect();
+        let mut textures = Vec::with_capacity(materials.len() + 1);
+        for key in materials {
+            let tex = match key {
+                MaterialKey::Color(c) => {
+                    // A flat color is just a one-pixel texture
+                    let img = RgbaImage::from_pixel(1, 1, image::Rgba(c));
+                    Texture {
+                        file_name: generated_texture_name("color", &mut used_names),
+                        pixbuf: Some(DynamicImage::ImageRgba8(img)),
+                    }
+                }
+                MaterialKey::Texture(path) => Texture {
+                    file_name: image_name(&path),
+                    pixbuf: three_mf.take_image(&path),
+                },
+            };
+            textures.push(tex);
+        }
+        // Triangles without properties use an additional default material
+        let default_material = MaterialIndex::from(textures.len());
+        let faces = faces
+            .into_iter()
+            .map(|(t, mat)| (t, mat.unwrap_or(default_material)))
+            .collect();
+        textures.push(Texture::default());
+
+        Ok(ThreeMfImporter {
+            vertices: Cell::new(vertices),
+            vertex_ids,
+            faces,
+            textures: Cell::new(textures),
+        })
+    }
+}
+
+impl Importer for ThreeMfImporter {
+    type VertexId = u32;
+
+    fn vertex_map(&self, i_v: VertexIndex) -> Self::VertexId {
+        self.vertex_ids[usize::from(i_v)]
+    }
+    fn build_vertices(&self) -> (bool, Vec<Vertex>) {
+        (false, self.vertices.take())
+    }
+    fn face_count(&self) -> usize {
+        self.faces.len()
+    }
+    fn faces(&self) -> impl Iterator<Item = (impl AsRef<[VertexIndex]>, MaterialIndex)> {
+        self.faces.iter().copied()
+    }
+    fn build_textures(&self) -> Vec<Texture> {
+        self.textures.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+
+    fn package(files: &[(&str, &str)]) -> Cursor<Vec<u8>> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in files {
+            zip.start_file(*name, zip::write::FileOptions::default())
+                .unwrap();
+            zip.write_all(data.as_bytes()).unwrap();
+        }
+        let mut res = zip.finish().unwrap();
+        res.set_position(0);
+        res
+    }
+
+    // A triangle with a red and a blue material, used twice, the second one mirrored in X and
+    // moved through a component
+    const MODEL: &str = r##"<?xml version="1.0" encoding="UTF-8"?>
+<model unit="millimeter" xmlns="http://schemas.microsoft.com/3dmanufacturing/core/2015/02">
+  <resources>
+    <basematerials id="1">
+      <base name="red" displaycolor="#FF0000" />
+      <base name="blue" displaycolor="#0000FFFF" />
+    </basematerials>
+    <object id="2" type="model" pid="1" pindex="0">
+      <mesh>
+        <vertices>
+          <vertex x="0" y="0" z="0" />
+          <vertex x="1" y="0" z="0" />
+          <vertex x="0" y="1" z="0" />
+          <vertex x="0" y="0" z="1" />
+        </vertices>
+        <triangles>
+          <triangle v1="0" v2="1" v3="2" />
+          <triangle v1="0" v2="1" v3="3" pid="1" p1="1" />
+        </triangles>
+      </mesh>
+    </object>
+    <object id="3" type="model">
+      <components>
+        <component objectid="2" transform="-1 0 0 0 1 0 0 0 1 10 0 0" />
+      </components>
+    </object>
+    <object id="4" type="support">
+      <components>
+        <component objectid="2" />
+      </components>
+    </object>
+  </resources>
+  <build>
+    <item objectid="2" />
+    <item objectid="3" />
+    <item objectid="4" />
+  </build>
+</model>"##;
+
+    const RELS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+  <Relationship Target="/3D/other.model" Id="rel0"
+    Type="http://schemas.microsoft.com/3dmanufacturing/2013/01/3dmodel" />
+</Relationships>"#;
+
+    #[test]
+    fn build_items_and_components() {
+        let imp = ThreeMfImporter::new(package(&[("3D/3dmodel.model", MODEL)])).unwrap();
+        // The support object is skipped
+        assert_eq!(imp.face_count(), 4);
+        // Each instance has its own vertices
+        assert_eq!(imp.vertex_ids[..3], [0, 1, 2]);
+        assert_eq!(imp.vertex_ids[6..9], [4, 5, 6]);
+
+        let vs = imp.vertices.take();
+        let pos = |face: usize| imp.faces[face].0.map(|i| vs[usize::from(i)].pos);
+        // Y up: (x, y, z) is (x, z, -y)
+        assert_eq!(pos(0)[2], Vector3::new(0.0, 0.0, -1.0));
+        // The mirrored instance is moved, and its winding is reversed to keep the normal
+        let normal = |[a, b, c]: [Vector3; 3]| (b - a).cross(c - a);
+        let [a, _, _] = pos(2);
+        assert_eq!(a, Vector3::new(10.0, 0.0, 0.0));
+        let (n0, n2) = (normal(pos(0)), normal(pos(2)));
+        assert_eq!(n2, Vector3::new(-n0.x, n0.y, n0.z));
+
+        // Red and blue, and the default one
+        let mats: Vec<_> = imp.faces.iter().map(|(_, m)| usize::from(*m)).collect();
+        assert_eq!(mats, [0, 1, 0, 1]);
+        let textures = imp.build_textures();
+        assert_eq!(textures.len(), 3);
+        let color = |i: usize| {
+            textures[i]
+                .pixbuf
+                .as_ref()
+                .unwrap()
+                .to_rgba8()
+                .get_pixel(0, 0)
+                .0
+        };
+        assert_eq!(color(0), [255, 0, 0, 255]);
+        assert_eq!(color(1), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn root_model_from_rels() {
+        let f = package(&[("_rels/.rels", RELS), ("3D/other.model", MODEL)]);
+        assert!(ThreeMfImporter::new(f).is_ok());
+        // Without the relationships the default name is used
+        let f = package(&[("3D/other.model", MODEL)]);
+        assert!(ThreeMfImporter::new(f).is_err());
+    }
+
+    #[test]
+    fn bad_vertex_index() {
+        let model = MODEL.replace(r#"v3="3""#, r#"v3="4""#);
+        let f = package(&[("3D/3dmodel.model", &model)]);
+        assert!(ThreeMfImporter::new(f).is_err());
+    }
+}
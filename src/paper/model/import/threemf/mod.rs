@@ -0,0 +1,5 @@
+mod data;
+mod importer;
+mod xml;
+
+pub use importer::ThreeMfImporter;
@@ -0,0 +1,184 @@
+// A very small XML reader, just enough for 3MF: only elements and attributes are returned,
+// text, comments, processing instructions and DTDs are skipped.
+use crate::paper::import::*;
+use std::borrow::Cow;
+
+#[derive(Debug)]
+pub enum XmlEvent<'a> {
+    // Self-closing elements are returned as a Start immediately followed by an End
+    Start(Element<'a>),
+    End(&'a str),
+}
+
+#[derive(Debug)]
+pub struct Element<'a> {
+    // The local name, the namespace prefix is removed
+    pub name: &'a str,
+    attrs: Vec<(&'a str, Cow<'a, str>)>,
+}
+
+impl Element<'_> {
+    // Attributes are also looked up by their local name
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, v)| v.as_ref())
+    }
+    pub fn attr_parse<T: std::str::FromStr>(&self, name: &str) -> Result<Option<T>> {
+        match self.attr(name) {
+            None => Ok(None),
+            Some(v) => match v.trim().parse() {
+                Ok(x) => Ok(Some(x)),
+                Err(_) => bail!(
+                    "invalid value '{v}' for attribute {name} in <{}>",
+                    self.name
+                ),
+            },
+        }
+    }
+    pub fn attr_req<T: std::str::FromStr>(&self, name: &str) -> Result<T> {
+        self.attr_parse(name)?
+            .ok_or_else(|| anyhow!("missing attribute {name} in <{}>", self.name))
+    }
+}
+
+pub struct XmlReader<'a> {
+    s: &'a str,
+    pos: usize,
+    // The name of the last self-closing element
+    pending_end: Option<&'a str>,
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+fn unescape(s: &str) -> Result<Cow<'_, str>> {
+    if !s.contains('&') {
+        return Ok(Cow::Borrowed(s));
+    }
+    let mut res = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(amp) = rest.find('&') {
+        res.push_str(&rest[..amp]);
+        rest = &rest[amp + 1..];
+        let semi = rest
+            .find(';')
+            .ok_or_else(|| anyhow!("unterminated XML entity"))?;
+        let c = match &rest[..semi] {
+            "amp" => '&',
+            "lt" => '<',
+            "gt" => '>',
+            "quot" => '"',
+            "apos" => '\'',
+            e => {
+                let code = if let Some(hex) = e.strip_prefix("#x") {
+                    u32::from_str_radix(hex, 16).ok()
+                } else if let Some(dec) = e.strip_prefix('#') {
+                    dec.parse().ok()
+                } else {
+                    None
+                };
+                code.and_then(char::from_u32)
+                    .ok_or_else(|| anyhow!("invalid XML entity &{e};"))?
+            }
+        };
+        res.push(c);
+        rest = &rest[semi + 1..];
+    }
+    res.push_str(rest);
+    Ok(Cow::Owned(res))
+}
+
+impl<'a> XmlReader<'a> {
+    pub fn new(s: &'a str) -> XmlReader<'a> {
+        XmlReader {
+            s: s.strip_prefix('\u{feff}').unwrap_or(s),
+            pos: 0,
+            pending_end: None,
+        }
+    }
+
+    // Skips everything up to and including `end`
+    fn skip_past(&mut self, end: &str) -> Result<()> {
+        let n = self.s[self.pos..]
+            .find(end)
+            .ok_or_else(|| anyhow!("unexpected end of XML"))?;
+        self.pos += n + end.len();
+        Ok(())
+    }
+
+    pub fn next_event(&mut self) -> Result<Option<XmlEvent<'a>>> {
+        if let Some(name) = self.pending_end.take() {
+            return Ok(Some(XmlEvent::End(name)));
+        }
+        loop {
+            // Text content is not needed
+            let Some(lt) = self.s[self.pos..].find('<') else {
+                return Ok(None);
+            };
+            self.pos += lt;
+            let rest = &self.s[self.pos..];
+            if rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if rest.starts_with("<![CDATA[") {
+                self.skip_past("]]>")?;
+            } else if rest.starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if rest.starts_with("<!") {
+                self.skip_past(">")?;
+            } else if let Some(rest) = rest.strip_prefix("</") {
+                let gt = rest
+                    .find('>')
+                    .ok_or_else(|| anyhow!("unexpected end of XML"))?;
+                self.pos += 2 + gt + 1;
+                return Ok(Some(XmlEvent::End(local_name(rest[..gt].trim()))));
+            } else {
+                return self.read_start().map(Some);
+            }
+        }
+    }
+
+    fn read_start(&mut self) -> Result<XmlEvent<'a>> {
+        let s = self.s;
+        let mut pos = self.pos + 1;
+        let is_name_end = |c: char| c.is_ascii_whitespace() || c == '/' || c == '>';
+        let name_len = s[pos..].find(is_name_end).unwrap_or(s.len() - pos);
+        let name = local_name(&s[pos..pos + name_len]);
+        pos += name_len;
+
+        let mut attrs = Vec::new();
+        loop {
+            pos += s[pos..].len() - s[pos..].trim_start().len();
+            let rest = &s[pos..];
+            if rest.starts_with("/>") {
+                self.pos = pos + 2;
+                self.pending_end = Some(name);
+                break;
+            }
+            if rest.starts_with('>') {
+                self.pos = pos + 1;
+                break;
+            }
+            let eq = rest
+                .find('=')
+                .ok_or_else(|| anyhow!("invalid attribute in <{name}>"))?;
+            let attr_name = local_name(rest[..eq].trim());
+            pos += eq + 1;
+            pos += s[pos..].len() - s[pos..].trim_start().len();
+            let quote = s[pos..]
+                .chars()
+                .next()
+                .filter(|&c| c == '"' || c == '\'')
+                .ok_or_else(|| anyhow!("invalid attribute {attr_name} in <{name}>"))?;
+            pos += 1;
+            let end = s[pos..]
+                .find(quote)
+                .ok_or_else(|| anyhow!("unexpected end of XML"))?;
+            attrs.push((attr_name, unescape(&s[pos..pos + end])?));
+            pos += end + 1;
+        }
+        Ok(XmlEvent::Start(Element { name, attrs }))
+    }
+}
@@ -14,12 +14,15 @@ pub struct FaceVertex {
 #[derive(Clone, Debug)]
 pub struct Face {
     material: usize,
+    part: usize,
     verts: Vec<FaceVertex>,
 }
 
 #[derive(Clone, Debug)]
 pub struct Model {
     materials: Vec<String>,
+    // Objects and groups, only those with faces
+    parts: Vec<String>,
     vs: Vec<[f32; 3]>,
     ns: Vec<[f32; 3]>,
     ts: Vec<[f32; 2]>,
@@ -33,8 +36,13 @@ impl Model {
 
         let mut material_lib = None;
         let mut current_material: usize = 0;
+        // (object, group), the part is created when the first face is found
+        let mut current_object = String::new();
+        let mut current_group = String::new();
+        let mut current_part: Option<usize> = None;
         let mut data = Model {
             materials: Vec::new(),
+            parts: Vec::new(),
             vs: Vec::new(),
             ns: Vec::new(),
             ts: Vec::new(),
@@ -51,9 +59,16 @@ impl Model {
             let mut words = line.split_whitespace();
             let first = words.next().ok_or_else(syn_error)?;
             match first {
+                // The numbering of vertices is global to the file, not to the object, so the
+                // objects and groups only tell the part of the following faces.
                 "o" => {
-                    // We combine all the objects into one.
-                    // Fortunately the numbering of vertices and faces is global to the file not to the object, so nothing to do here.
+                    current_object = words.collect::<Vec<_>>().join(" ");
+                    current_group.clear();
+                    current_part = None;
+                }
+                "g" => {
+                    current_group = words.collect::<Vec<_>>().join(" ");
+                    current_part = None;
                 }
                 "v" => {
                     let x: f32 = words.next().ok_or_else(syn_error)?.parse()?;
@@ -101,8 +116,24 @@ impl Model {
                         };
                         verts.push(v);
                     }
+                    let part = *current_part.get_or_insert_with(|| {
+                        let name = match (current_object.as_str(), current_group.as_str()) {
+                            (o, "") => o.to_owned(),
+                            ("", g) => g.to_owned(),
+                            (o, g) => format!("{o}/{g}"),
+                        };
+                        // The same object or group may appear several times
+                        data.parts
+                            .iter()
+                            .position(|p| *p == name)
+                            .unwrap_or_else(|| {
+                                data.parts.push(name);
+                                data.parts.len() - 1
+                            })
+                    });
                     data.faces.push(Face {
                         material: current_material,
+                        part,
                         verts,
                     })
                 }
@@ -134,6 +165,9 @@ impl Model {
     pub fn faces(&self) -> &[Face] {
         &self.faces
     }
+    pub fn parts(&self) -> &[String] {
+        &self.parts
+    }
     pub fn vertex_by_index(&self, idx: u32) -> &[f32; 3] {
         &self.vs[idx as usize]
     }
@@ -149,6 +183,9 @@ impl Face {
     pub fn material(&self) -> usize {
         self.material
     }
+    pub fn part(&self) -> usize {
+        self.part
+    }
     pub fn vertices(&self) -> &[FaceVertex] {
         &self.verts
     }
@@ -203,6 +240,44 @@ pub fn solve_find_matlib_file(mtl: &Path, obj: &Path) -> Option<PathBuf> {
 pub struct Material {
     name: String,
     map: Option<String>,
+    // Kd and d, as RGBA
+    color: Option<[u8; 4]>,
+}
+
+// Texture maps can have options before the file name, such as `-s 1 1 1`, then the file name
+// is the rest of the line, that may contain spaces.
+fn parse_map_path(args: &str) -> Option<String> {
+    let mut rest = args.trim();
+    while let Some(opt) = rest.strip_prefix('-') {
+        let (name, tail) = opt.split_once(char::is_whitespace).unwrap_or((opt, ""));
+        let mut tail = tail.trim_start();
+        // How many arguments each option takes, (min, max)
+        let (min, max) = match name {
+            "o" | "s" | "t" => (1, 3),
+            "mm" => (2, 2),
+            "blendu" | "blendv" | "bm" | "boost" | "cc" | "clamp" | "imfchan" | "texres" => (1, 1),
+            // Unknown option, it is probably part of the file name
+            _ => break,
+        };
+        for i in 0..max {
+            let (arg, next) = tail.split_once(char::is_whitespace).unwrap_or((tail, ""));
+            // Optional arguments are always numbers
+            if arg.is_empty() || (i >= min && arg.parse::<f32>().is_err()) {
+                break;
+            }
+            tail = next.trim_start();
+        }
+        rest = tail;
+    }
+    if rest.is_empty() {
+        return None;
+    }
+    // MTL files written in Windows may use backslashes
+    Some(rest.replace('\\', "/"))
+}
+
+fn parse_color_component(x: Option<&str>) -> Option<f32> {
+    x?.parse::<f32>().ok().map(|x| x.clamp(0.0, 1.0))
 }
 
 impl Material {
@@ -215,6 +290,10 @@ impl Material {
         struct MaterialData {
             name: Option<String>,
             map: Option<String>,
+            // map_Ka or map_Ke, if there is no map_Kd
+            map_fallback: Option<String>,
+            diffuse: Option<[f32; 3]>,
+            opacity: Option<f32>,
         }
 
         impl MaterialData {
@@ -224,10 +303,16 @@ impl Material {
                     return None;
                 }
 
+                let alpha = self.opacity.unwrap_or(1.0);
+                let color = self
+                    .diffuse
+                    .map(|kd| [kd[0], kd[1], kd[2], alpha].map(|x| (x * 255.0).round() as u8));
                 let m = Material {
                     name: self.name.take().unwrap(),
-                    map: self.map.take(),
+                    map: self.map.take().or(self.map_fallback.take()),
+                    color,
                 };
+                *self = MaterialData::default();
 
                 Some(m)
             }
@@ -241,8 +326,8 @@ impl Material {
             if line.is_empty() || line.starts_with('#') {
                 continue;
             }
-            let mut words = line.split_whitespace();
-            let first = words.next().ok_or_else(syn_error)?;
+            let (first, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let mut words = args.split_whitespace();
             match first {
                 "newmtl" => {
                     mats.extend(data.build());
@@ -251,8 +336,24 @@ impl Material {
                     data.name = Some(String::from(name));
                 }
                 "map_Kd" => {
-                    let map = words.next().ok_or_else(syn_error)?;
-                    data.map = Some(String::from(map));
+                    let map = parse_map_path(args).ok_or_else(syn_error)?;
+                    data.map = Some(map);
+                }
+                // map_Ka is preferred, it is usually the same image as map_Kd
+                "map_Ka" | "map_Ke" if data.map_fallback.is_none() || first == "map_Ka" => {
+                    data.map_fallback = Some(parse_map_path(args).ok_or_else(syn_error)?);
+                }
+                "Kd" => {
+                    // Only RGB colors, "Kd spectral" and "Kd xyz" are ignored
+                    let r = parse_color_component(words.next());
+                    if let Some(r) = r {
+                        let g = parse_color_component(words.next()).unwrap_or(r);
+                        let b = parse_color_component(words.next()).unwrap_or(r);
+                        data.diffuse = Some([r, g, b]);
+                    }
+                }
+                "d" => {
+                    data.opacity = parse_color_component(words.last());
                 }
                 _p => {
                     // Unknown attribute
@@ -266,6 +367,9 @@ impl Material {
     pub fn map(&self) -> Option<&str> {
         self.map.as_deref()
     }
+    pub fn color(&self) -> Option<[u8; 4]> {
+        self.color
+    }
     pub fn name(&self) -> &str {
         &self.name
     }
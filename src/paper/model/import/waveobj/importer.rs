@@ -4,11 +4,12 @@ use super::super::*;
 use super::data;
 use cgmath::Zero;
 use fxhash::{FxHashMap, FxHashSet};
-use image::DynamicImage;
+use image::{DynamicImage, RgbaImage};
 
 pub struct WaveObjImporter {
     obj: data::Model,
-    texture_map: FxHashMap<String, Cell<(String, DynamicImage)>>,
+    // Material name -> texture, either an image or a flat color
+    texture_map: FxHashMap<String, Cell<Texture>>,
     // VertexIndex -> FaceVertex
     all_vertices: Vec<data::FaceVertex>,
 }
@@ -24,6 +25,8 @@ impl WaveObjImporter {
             None => None,
         };
         let mut texture_map = FxHashMap::default();
+        // The flat colors are named after all the real textures are known
+        let mut flat_colors = Vec::new();
 
         if let Some(matlib) = matlib {
             // Textures are read from the .mtl file
@@ -45,8 +48,11 @@ impl WaveObjImporter {
                             .file_name()
                             .and_then(|f| f.to_str())
                             .ok_or_else(|| anyhow!("Invalid texture name"))?;
-                        texture_map
-                            .insert(lib.name().to_owned(), Cell::new((map_name.to_owned(), img)));
+                        let tex = Texture {
+                            file_name: map_name.to_owned(),
+                            pixbuf: Some(img),
+                        };
+                        texture_map.insert(lib.name().to_owned(), Cell::new(tex));
                     } else {
                         return Err(anyhow!(
                             "{} texture from {} matlib not found",
@@ -54,9 +60,24 @@ impl WaveObjImporter {
                             matlib.display()
                         ));
                     }
+                } else if let Some(color) = lib.color() {
+                    flat_colors.push((lib.name().to_owned(), color));
                 }
             }
         }
+        let mut used_names: FxHashSet<String> = texture_map
+            .values_mut()
+            .map(|tex| tex.get_mut().file_name.clone())
+            .collect();
+        for (name, color) in flat_colors {
+            // A flat color is just a one-pixel texture
+            let img = RgbaImage::from_pixel(1, 1, image::Rgba(color));
+            let tex = Texture {
+                file_name: generated_texture_name("color", &mut used_names),
+                pixbuf: Some(DynamicImage::ImageRgba8(img)),
+            };
+            texture_map.insert(name, Cell::new(tex));
+        }
 
         // Remove duplicated vertices by adding them into a set
         let all_vertices: FxHashSet<data::FaceVertex> = obj
@@ -131,17 +152,10 @@ impl Importer for WaveObjImporter {
             .obj
             .materials()
             .map(|s| {
-                let tex = &self.texture_map.get(s);
-                match tex {
-                    Some(tex) => {
-                        let (file_name, pixbuf) = tex.take();
-                        Texture {
-                            file_name,
-                            pixbuf: Some(pixbuf),
-                        }
-                    }
-                    None => Texture::default(),
-                }
+                self.texture_map
+                    .get(s)
+                    .map(|tex| tex.take())
+                    .unwrap_or_default()
             })
             .collect();
         if textures.is_empty() {
@@ -149,4 +163,44 @@ impl Importer for WaveObjImporter {
         }
         textures
     }
+    fn part_names(&self) -> Vec<String> {
+        // A single part is the same as no parts at all
+        match self.obj.parts() {
+            [] | [_] => Vec::new(),
+            parts => parts.to_vec(),
+        }
+    }
+    fn face_part(&self, i_face: usize) -> PartIndex {
+        PartIndex::from(self.obj.faces()[i_face].part())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_colors_do_not_replace_textures() {
+        let dir = std::env::temp_dir().join(format!("papercraft-obj-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // A real texture with the name that the first flat color would get
+        let tex_name = format!("{GENERATED_TEXTURE_PREFIX}color0.png");
+        RgbaImage::from_pixel(1, 1, image::Rgba([0, 255, 0, 255]))
+            .save(dir.join(&tex_name))
+            .unwrap();
+        std::fs::write(
+            dir.join("m.mtl"),
+            format!("newmtl red\nKd 1 0 0\nnewmtl tex\nmap_Kd {tex_name}\n"),
+        )
+        .unwrap();
+        let obj =
+            "mtllib m.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl red\nf 1 2 3\nusemtl tex\nf 1 3 2\n";
+        let imp = WaveObjImporter::new(obj.as_bytes(), &dir.join("m.obj"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let textures = imp.unwrap().build_textures();
+        let names: FxHashSet<_> = textures.iter().map(|t| t.file_name.as_str()).collect();
+        assert!(names.contains(tex_name.as_str()));
+        assert_eq!(names.len(), textures.len());
+    }
 }
@@ -0,0 +1,1447 @@
+/* Generation of the printable files: PDF, SVG, PNG and DXF.
+ * This module does not depend on the GUI, the rasterization of the pages is done by a `PageRenderer`
+ * so it can be used from the command line without a window.
+ */
+use anyhow::{anyhow, Result};
+use cgmath::{prelude::*, Deg, Rad};
+use fxhash::FxHashSet;
+use image::DynamicImage;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::paper::{EdgeIdPosition, IslandKey, PaperOptions, Papercraft};
+use crate::pdf_metrics;
+use crate::ui::{
+    signature, EdgeDrawKind, PaperDrawFaceArgs, PaperDrawFaceArgsExtra, PapercraftContext,
+};
+use crate::util_3d::Vector2;
+use crate::util_gl::MVertex2DLine;
+use crate::FONT_SIZE;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum PrintableFormat {
+    Pdf,
+    Svg,
+    Png,
+    Dxf,
+}
+
+impl PrintableFormat {
+    pub fn from_file_name(file_name: &Path) -> Option<PrintableFormat> {
+        let ext = file_name
+            .extension()
+            .map(|s| s.to_string_lossy().into_owned().to_ascii_lowercase());
+        let format = match ext.as_deref()? {
+            "pdf" => PrintableFormat::Pdf,
+            "svg" => PrintableFormat::Svg,
+            "png" => PrintableFormat::Png,
+            "dxf" => PrintableFormat::Dxf,
+            _ => return None,
+        };
+        Some(format)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PageContent {
+    // Everything, including the texts
+    Full,
+    // Everything but the texts, they will be written by the caller
+    NoTexts,
+    // Only the faces and flaps, the lines and texts will be written by the caller
+    TextureOnly,
+}
+
+pub trait PageRenderer {
+    // Draws the given page into `pixbuf`, that is already sized to the page and resolution.
+    fn render_page(
+        &mut self,
+        page: u32,
+        content: PageContent,
+        pixbuf: &mut image::RgbaImage,
+    ) -> Result<()>;
+}
+
+#[derive(Copy, Clone)]
+pub enum TextAlign {
+    Near,
+    Center,
+    Far,
+}
+
+#[derive(Clone)]
+pub struct PrintableText {
+    pub size: f32,
+    pub pos: Vector2,
+    pub angle: Rad<f32>,
+    pub align: TextAlign,
+    pub text: String,
+}
+
+pub struct Printable<'a> {
+    papercraft: &'a Papercraft,
+    title: String,
+    font_text_line_scale: f32,
+    pages: Vec<u32>,
+}
+
+impl<'a> Printable<'a> {
+    pub fn new(papercraft: &'a Papercraft, title: String, font_text_line_scale: f32) -> Self {
+        let pages = (0..papercraft.options().pages).collect();
+        Printable {
+            papercraft,
+            title,
+            font_text_line_scale,
+            pages,
+        }
+    }
+    // Restricts the output to these pages, 0-based
+    pub fn set_pages(&mut self, pages: Vec<u32>) {
+        self.pages = pages;
+    }
+
+    pub fn generate(
+        &self,
+        renderer: &mut dyn PageRenderer,
+        format: Option<PrintableFormat>,
+        file_name: &Path,
+    ) -> Result<()> {
+        let format = format
+            .or_else(|| PrintableFormat::from_file_name(file_name))
+            .ok_or_else(|| {
+                anyhow!(
+                    "Don't know how to write the format of {}",
+                    file_name.display()
+                )
+            })?;
+        let options = self.papercraft.options();
+        if options.print_and_cut && registration_marks(options).is_none() {
+            anyhow::bail!(
+                "The margins are too small for the registration marks, they need at least {} mm",
+                REG_MARK_MIN_SIZE
+            );
+        }
+        match format {
+            PrintableFormat::Pdf => self.generate_pdf(renderer, file_name)?,
+            PrintableFormat::Svg => self.generate_svg(renderer, file_name)?,
+            PrintableFormat::Png => self.generate_png(renderer, file_name)?,
+            PrintableFormat::Dxf => self.generate_dxf(renderer, file_name)?,
+        }
+        if options.print_and_cut {
+            let mut plt_name = file_name.to_owned();
+            plt_name.set_extension("plt");
+            self.generate_hpgl(&plt_name)?;
+        }
+        Ok(())
+    }
+
+    // The registration marks of the current options, empty if not in print & cut mode
+    fn reg_marks(&self) -> Vec<(Vector2, Vector2)> {
+        let options = self.papercraft.options();
+        if !options.print_and_cut {
+            return Vec::new();
+        }
+        registration_marks(options).unwrap_or_default()
+    }
+
+    fn generate_pdf(&self, renderer: &mut dyn PageRenderer, file_name: &Path) -> Result<()> {
+        use printpdf::{BuiltinFont, Mm, PdfDocument, TextMatrix};
+
+        let options = self.papercraft.options();
+        let resolution = options.resolution as f32;
+        let page_size_mm = Vector2::from(options.page_size);
+
+        let (doc, page_ref, layer_ref) =
+            PdfDocument::new(&self.title, Mm(page_size_mm.x), Mm(page_size_mm.y), "Layer");
+        let doc = doc.with_creator(signature());
+        let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+        let texts_over_texture = options.texts_over_texture();
+
+        let mut first_page = Some((page_ref, layer_ref));
+
+        // In vector mode only the texture is rasterized, if there is one
+        let content = if !options.vector_pdf {
+            Some(PageContent::NoTexts)
+        } else if options.texture && self.papercraft.model().has_textures() {
+            Some(PageContent::TextureOnly)
+        } else {
+            None
+        };
+
+        self.generate_pages(renderer, content, |page, pixbuf, texts, lines_by_island| {
+            let (page_ref, layer_ref) = first_page
+                .take()
+                .unwrap_or_else(|| doc.add_page(Mm(page_size_mm.x), Mm(page_size_mm.y), "Layer"));
+            let layer = doc.get_page(page_ref).get_layer(layer_ref);
+
+            let write_texts = || {
+                if texts.is_empty() {
+                    return;
+                }
+                layer.begin_text_section();
+                for text in texts {
+                    let size = text.size * 72.0 / 25.4;
+                    // PDF fonts are just slightly larger than expected
+                    let size = size / 1.1;
+                    let x = text.pos.x;
+                    // (0,0) is in lower-left
+                    let y = page_size_mm.y - text.pos.y;
+                    let angle = -Deg::from(text.angle).0;
+                    let (width, cps) = pdf_metrics::measure_helvetica(&text.text);
+                    let width = width as f32 * text.size / 1000.0;
+                    let dx = match text.align {
+                        TextAlign::Near => 0.0,
+                        TextAlign::Center => -width / 2.0,
+                        TextAlign::Far => -width,
+                    };
+                    let x = x + dx * text.angle.cos();
+                    let y = y - dx * text.angle.sin();
+                    layer.set_font(&font, size);
+                    layer.set_text_matrix(TextMatrix::TranslateRotate(
+                        Mm(x).into_pt(),
+                        Mm(y).into_pt(),
+                        angle,
+                    ));
+                    layer.write_positioned_codepoints(cps);
+                }
+                layer.end_text_section();
+            };
+
+            if !texts_over_texture {
+                write_texts();
+            }
+
+            let add_image = || {
+                let img = printpdf::Image::from_dynamic_image(pixbuf);
+                // This image with the default transformation and the right resolution should cover the page exactly
+                let tr = printpdf::ImageTransform {
+                    dpi: Some(resolution),
+                    ..Default::default()
+                };
+                img.add_to_layer(layer.clone(), tr);
+            };
+
+            match content {
+                Some(PageContent::TextureOnly) => {
+                    self.pdf_vector_page(&layer, page, lines_by_island, Some(add_image))
+                }
+                None => self.pdf_vector_page(&layer, page, lines_by_island, None::<fn()>),
+                _ => add_image(),
+            }
+
+            if texts_over_texture {
+                write_texts();
+            }
+            Ok(())
+        })?;
+
+        let out = std::fs::File::create(file_name)?;
+        let mut out = std::io::BufWriter::new(out);
+        doc.save(&mut out)?;
+
+        Ok(())
+    }
+
+    // Writes the page as PDF paths. If there is a texture, `add_image` draws it, and it is clipped
+    // to the islands, else the faces are filled with plain white.
+    fn pdf_vector_page(
+        &self,
+        layer: &printpdf::PdfLayerReference,
+        page: u32,
+        lines_by_island: &[(IslandKey, (PaperDrawFaceArgs, PaperDrawFaceArgsExtra))],
+        add_image: Option<impl FnOnce()>,
+    ) {
+        use printpdf::path::{PaintMode, WindingOrder};
+        use printpdf::{Color, Greyscale, Line, LineCapStyle, Mm, Point, Polygon};
+
+        let options = self.papercraft.options();
+        let page_size_mm = Vector2::from(options.page_size);
+        let in_page = options.is_in_page_fn(page);
+        // (0,0) is in lower-left
+        let to_pdf = |p: Vector2| Point::new(Mm(p.x), Mm(page_size_mm.y - p.y));
+        let grey = |g: f32| Color::Greyscale(Greyscale::new(g, None));
+
+        for (a, b) in self.reg_marks() {
+            layer.set_fill_color(grey(0.0));
+            layer.add_polygon(Polygon {
+                rings: vec![[a, Vector2::new(b.x, a.y), b, Vector2::new(a.x, b.y)]
+                    .map(|p| (to_pdf(p), false))
+                    .to_vec()],
+                mode: PaintMode::Fill,
+                winding_order: WindingOrder::NonZero,
+            });
+        }
+
+        let mut contours = Vec::new();
+        let mut islands = Vec::new();
+        for (_, (lines, extra)) in lines_by_island {
+            if let Some(page_cuts) = cuts_to_page_cuts(lines.iter_cut(), &in_page) {
+                contours.push(cut_to_contour(page_cuts));
+                islands.push((lines, extra));
+            }
+        }
+        if contours.is_empty() {
+            return;
+        }
+        // Each ring is closed explicitly, because stroking only closes the last one
+        let ring = |c: &[Vector2]| {
+            c.iter()
+                .chain(c.first())
+                .map(|p| (to_pdf(*p), false))
+                .collect()
+        };
+        let rings = || contours.iter().map(|c| ring(c)).collect();
+
+        match add_image {
+            Some(add_image) => {
+                // The image goes beyond the cuts as much as the bleed
+                layer.save_graphics_state();
+                layer.add_polygon(Polygon {
+                    rings: contours
+                        .iter()
+                        .map(|c| ring(&offset_contour(c, self.papercraft.texture_bleed())))
+                        .collect(),
+                    mode: PaintMode::Clip,
+                    winding_order: WindingOrder::NonZero,
+                });
+                add_image();
+                layer.restore_graphics_state();
+            }
+            None => {
+                layer.set_fill_color(grey(1.0));
+                layer.add_polygon(Polygon {
+                    rings: rings(),
+                    mode: PaintMode::Fill,
+                    winding_order: WindingOrder::NonZero,
+                });
+                // Shadow flaps are black with some alpha, over white that is just a grey
+                if options.shadow_flap_alpha > 0.0 {
+                    layer.set_fill_color(grey(1.0 - options.shadow_flap_alpha));
+                    for (lines, _) in &islands {
+                        for tri in lines.vertices_shadow_flap.chunks_exact(3) {
+                            layer.add_polygon(Polygon {
+                                rings: vec![tri
+                                    .iter()
+                                    .map(|v| (to_pdf(in_page(v.pos).1), false))
+                                    .collect()],
+                                mode: PaintMode::Fill,
+                                winding_order: WindingOrder::NonZero,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        let line_width = Mm(options.fold_line_width).into_pt().0;
+        layer.set_outline_color(grey(0.0));
+        layer.set_outline_thickness(line_width);
+        layer.set_line_cap_style(LineCapStyle::Butt);
+
+        // Cuts, including the flap outlines
+        layer.add_polygon(Polygon {
+            rings: rings(),
+            mode: PaintMode::Stroke,
+            winding_order: WindingOrder::NonZero,
+        });
+
+        // Creases, valleys are dashed just like in the raster image, the slits and the
+        // alignment marks are solid
+        for (lines, extra) in &islands {
+            for fold_kind in [
+                EdgeDrawKind::Mountain,
+                EdgeDrawKind::Valley,
+                EdgeDrawKind::Slot,
+                EdgeDrawKind::Tick,
+            ] {
+                for (a, b) in lines.iter_crease(extra, fold_kind) {
+                    let (is_in_a, pa) = in_page(a.pos);
+                    let (is_in_b, pb) = in_page(b.pos);
+                    if !is_in_a && !is_in_b {
+                        continue;
+                    }
+                    for (t0, t1) in dash_segments(a.line_dash, b.line_dash) {
+                        let line = Line {
+                            points: vec![
+                                (to_pdf(pa + (pb - pa) * t0), false),
+                                (to_pdf(pa + (pb - pa) * t1), false),
+                            ],
+                            is_closed: false,
+                        };
+                        layer.add_line(line);
+                    }
+                }
+            }
+        }
+    }
+
+    fn generate_svg(&self, renderer: &mut dyn PageRenderer, file_name: &Path) -> Result<()> {
+        let options = self.papercraft.options();
+        let texts_over_texture = options.texts_over_texture();
+
+        let content = Some(PageContent::NoTexts);
+        self.generate_pages(renderer, content, |page, pixbuf, texts, lines_by_island| {
+            let name = file_name_for_page(file_name, page);
+            let out = std::fs::File::create(name)?;
+            let mut out = std::io::BufWriter::new(out);
+
+            let page_size = Vector2::from(options.page_size);
+            let in_page = options.is_in_page_fn(page);
+
+            let mut png = Vec::new();
+            let mut cpng = std::io::Cursor::new(&mut png);
+            pixbuf.write_to(&mut cpng, image::ImageFormat::Png)?;
+
+            let mut all_page_cuts = Vec::new();
+
+            for (idx, (_, (lines, _))) in lines_by_island.iter().enumerate() {
+                if let Some(page_cuts) = cuts_to_page_cuts(lines.iter_cut(), &in_page) {
+                    all_page_cuts.push((idx, page_cuts));
+                };
+            }
+            writeln!(&mut out, r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>"#)?;
+            writeln!(
+                &mut out,
+                r#"<svg width="{0}mm" height="{1}mm" viewBox="0 0 {0} {1}" version="1.1" xmlns="http://www.w3.org/2000/svg" xmlns:inkscape="http://www.inkscape.org/namespaces/inkscape" xmlns:xlink="http://www.w3.org/1999/xlink">"#,
+                page_size.x, page_size.y
+            )?;
+
+            let write_layer_text = |out: &mut std::io::BufWriter<std::fs::File>| -> Result<()> {
+                if texts.is_empty() {
+                    return Ok(());
+                }
+                // begin layer Text
+                writeln!(out, r#"<g inkscape:label="Text" inkscape:groupmode="layer" id="Text">"#)?;
+                for text in texts {
+                    let basis2: cgmath::Basis2<f32> = Rotation2::from_angle(-text.angle);
+                    let pos = basis2.rotate_vector(text.pos);
+                    writeln!(out, r#"<text x="{}" y="{}" style="{}font-size:{};font-family:sans-serif;fill:#000000" transform="rotate({})">{}</text>"#,
+                        pos.x,
+                        pos.y,
+                        match text.align {
+                            TextAlign::Near => "",
+                            TextAlign::Center => "text-anchor:middle;",
+                            TextAlign::Far => "text-anchor:end;",
+                        },
+                        text.size,
+                        Deg::from(text.angle).0,
+                        text.text,
+                    )?;
+                }
+                writeln!(out, r#"</g>"#)?;
+                // end layer Text
+                Ok(())
+            };
+
+            if !texts_over_texture {
+                write_layer_text(&mut out)?;
+            }
+
+            // begin layer Background
+            writeln!(&mut out, r#"<g inkscape:label="Background" inkscape:groupmode="layer" id="Background">"#)?;
+            write!(
+                &mut out,
+                r#"<image width="{}" height="{}" preserveAspectRatio="none" xlink:href="data:image/png;base64,"#,
+                page_size.x, page_size.y)?;
+            {
+                use base64::prelude::*;
+                let mut b64png = base64::write::EncoderWriter::new(&mut out, &BASE64_STANDARD);
+                b64png.write_all(&png)?;
+                b64png.finish()?;
+            }
+            writeln!(&mut out, r#"" id="background" x="0" y="0" style="display:inline"/>"#)?;
+
+            writeln!(&mut out, r#"</g>"#)?;
+            // end layer Background
+
+            if texts_over_texture {
+                write_layer_text(&mut out)?;
+            }
+
+            // begin layer Cut
+            writeln!(&mut out, r#"<g inkscape:label="Cut" inkscape:groupmode="layer" id="Cut" style="display:none">"#)?;
+            for (idx, page_cut) in all_page_cuts {
+                writeln!(&mut out, r#"<path style="fill:none;stroke:#000000;stroke-width:1;stroke-linecap:butt;stroke-linejoin:miter" id="cut_{}" d=""#, idx)?;
+                write!(&mut out, r#"M "#)?;
+                let page_contour = cut_to_contour(page_cut);
+                for v in page_contour {
+                    writeln!(&mut out, r#"{},{}"#, v.x, v.y)?;
+                }
+                writeln!(&mut out, r#"z"#)?;
+                writeln!(&mut out, r#"" />"#)?;
+            }
+            // The slits for the tabs are cut too
+            for (idx, (_, (lines, extra))) in lines_by_island.iter().enumerate() {
+                let page_slots = lines
+                    .iter_crease(extra, EdgeDrawKind::Slot)
+                    .filter_map(|(a, b)| {
+                        let (is_in_a, a) = in_page(a.pos);
+                        let (is_in_b, b) = in_page(b.pos);
+                        (is_in_a || is_in_b).then_some((a, b))
+                    })
+                    .collect::<Vec<_>>();
+                if !page_slots.is_empty() {
+                    writeln!(&mut out, r#"<path style="fill:none;stroke:#000000;stroke-width:1;stroke-linecap:butt;stroke-linejoin:miter" id="slot_{}" d=""#, idx)?;
+                    for (a, b) in page_slots {
+                        writeln!(&mut out, r#"M {},{} {},{}"#, a.x, a.y, b.x, b.y)?;
+                    }
+                    writeln!(&mut out, r#"" />"#)?;
+                }
+            }
+            writeln!(&mut out, r#"</g>"#)?;
+            // end layer Cut
+
+            // begin layer Fold
+            writeln!(&mut out, r#"<g inkscape:label="Fold" inkscape:groupmode="layer" id="Fold" style="display:none">"#)?;
+            for fold_kind in [EdgeDrawKind::Mountain, EdgeDrawKind::Valley] {
+                writeln!(&mut out, r#"<g inkscape:label="{0}" inkscape:groupmode="layer" id="{0}">"#,
+                    if fold_kind == EdgeDrawKind::Mountain { "Mountain"} else { "Valley" })?;
+                for (idx, (_, (lines, extra))) in lines_by_island.iter().enumerate() {
+                    let creases = lines.iter_crease(extra, fold_kind);
+                    // each crease can be checked for bounds individually
+                    let page_creases = creases
+                        .filter_map(|(a, b)| {
+                            let (is_in_a, a) = in_page(a.pos);
+                            let (is_in_b, b) = in_page(b.pos);
+                            (is_in_a || is_in_b).then_some((a, b))
+                        })
+                        .collect::<Vec<_>>();
+                    if !page_creases.is_empty() {
+                        writeln!(&mut out, r#"<path style="fill:none;stroke:{1};stroke-width:1;stroke-linecap:butt;stroke-linejoin:miter" id="{2}_{0}" d=""#,
+                            idx,
+                            if fold_kind == EdgeDrawKind::Mountain  { "#ff0000" } else { "#0000ff" },
+                            if fold_kind == EdgeDrawKind::Mountain  { "foldm" } else { "foldv" }
+                        )?;
+                        for (a, b) in page_creases {
+                            writeln!(&mut out, r#"M {},{} {},{}"#, a.x, a.y, b.x, b.y)?;
+                        }
+                        writeln!(&mut out, r#"" />"#)?;
+                    }
+                }
+                writeln!(&mut out, r#"</g>"#)?;
+            }
+            writeln!(&mut out, r#"</g>"#)?;
+            // end layer Fold
+
+            writeln!(&mut out, r#"</svg>"#)?;
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    // DXF files have no images, only lines and texts, so nothing is rasterized
+    fn generate_dxf(&self, renderer: &mut dyn PageRenderer, file_name: &Path) -> Result<()> {
+        if self.papercraft.options().dxf_whole_canvas {
+            return self.generate_dxf_canvas(renderer, file_name);
+        }
+        let options = self.papercraft.options();
+        let page_size = Vector2::from(options.page_size);
+        let reg_marks = self.reg_marks();
+
+        self.generate_pages(renderer, None, |page, _pixbuf, texts, lines_by_island| {
+            let name = file_name_for_page(file_name, page);
+            let out = std::fs::File::create(name)?;
+            let mut out = std::io::BufWriter::new(out);
+
+            let in_page = options.is_in_page_fn(page);
+            // The Y axis points up
+            let to_dxf = |p: Vector2| Vector2::new(p.x, page_size.y - p.y);
+
+            dxf_begin(&mut out, Vector2::new(0.0, 0.0), page_size)?;
+            for (a, b) in &reg_marks {
+                dxf_rect(&mut out, "REGISTRATION", to_dxf(*a), to_dxf(*b))?;
+            }
+            for (_, (lines, extra)) in lines_by_island {
+                dxf_island(&mut out, lines, extra, &in_page, &to_dxf)?;
+            }
+            for text in texts {
+                dxf_text(&mut out, text, &to_dxf)?;
+            }
+            dxf_end(&mut out)?;
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    // All the selected pages in a single drawing, each one where it is in the paper.
+    // Only the islands that touch those pages are drawn, the same ones that get their texts.
+    fn generate_dxf_canvas(&self, renderer: &mut dyn PageRenderer, file_name: &Path) -> Result<()> {
+        let options = self.papercraft.options();
+        let page_size = Vector2::from(options.page_size);
+        let mut lines_by_island = PapercraftContext::lines_by_island(self.papercraft);
+        lines_by_island.retain(|(_, (lines, _))| {
+            self.pages.iter().any(|&page| {
+                let in_page = options.is_in_page_fn(page);
+                cuts_to_page_cuts(lines.iter_cut(), &in_page).is_some()
+            })
+        });
+
+        // Each island once, even if it is in several pages
+        let everywhere = |p: Vector2| (true, p);
+        let mut min = Vector2::new(0.0, 0.0);
+        let mut max = page_size;
+        for &page in &self.pages {
+            let pos = options.page_position(page);
+            max.x = max.x.max(pos.x + page_size.x);
+            max.y = max.y.max(pos.y + page_size.y);
+        }
+        for (_, (lines, _)) in &lines_by_island {
+            for (a, b) in lines.iter_cut() {
+                for p in [a.pos, b.pos] {
+                    min = Vector2::new(min.x.min(p.x), min.y.min(p.y));
+                    max = Vector2::new(max.x.max(p.x), max.y.max(p.y));
+                }
+            }
+        }
+        // The Y axis points up, with the bottom of the lowest page at 0
+        let bottom = max.y;
+        let to_dxf = |p: Vector2| Vector2::new(p.x, bottom - p.y);
+
+        // The texts come by page, the edge ids of an island that is in several pages are repeated
+        let mut texts: Vec<PrintableText> = Vec::new();
+        let mut seen = FxHashSet::default();
+        self.generate_pages(renderer, None, |page, _pixbuf, page_texts, _| {
+            let page_pos = options.page_position(page);
+            for text in page_texts {
+                let mut text = text.clone();
+                text.pos += page_pos;
+                let key = (
+                    text.text.clone(),
+                    (text.pos.x * 1000.0).round() as i64,
+                    (text.pos.y * 1000.0).round() as i64,
+                );
+                if seen.insert(key) {
+                    texts.push(text);
+                }
+            }
+            Ok(())
+        })?;
+
+        let out = std::fs::File::create(file_name)?;
+        let mut out = std::io::BufWriter::new(out);
+        dxf_begin(
+            &mut out,
+            to_dxf(Vector2::new(min.x, max.y)),
+            to_dxf(Vector2::new(max.x, min.y)),
+        )?;
+        // The outline of the pages, in a hidden layer, and the marks of each page
+        let reg_marks = self.reg_marks();
+        for &page in &self.pages {
+            let pos = options.page_position(page);
+            for (a, b) in &reg_marks {
+                dxf_rect(&mut out, "REGISTRATION", to_dxf(pos + a), to_dxf(pos + b))?;
+            }
+            let corners = [
+                pos,
+                pos + Vector2::new(page_size.x, 0.0),
+                pos + page_size,
+                pos + Vector2::new(0.0, page_size.y),
+            ];
+            for i in 0..4 {
+                dxf_line(
+                    &mut out,
+                    "PAGES",
+                    to_dxf(corners[i]),
+                    to_dxf(corners[(i + 1) % 4]),
+                )?;
+            }
+        }
+        for (_, (lines, extra)) in &lines_by_island {
+            dxf_island(&mut out, lines, extra, &everywhere, &to_dxf)?;
+        }
+        for text in &texts {
+            dxf_text(&mut out, text, &to_dxf)?;
+        }
+        dxf_end(&mut out)?;
+        Ok(())
+    }
+
+    // A plotter job for each page, to cut what has been printed with the registration marks.
+    // The cuts use pen 1 and, optionally, the creases are scored with pen 2.
+    fn generate_hpgl(&self, file_name: &Path) -> Result<()> {
+        let options = self.papercraft.options();
+        let page_size = Vector2::from(options.page_size);
+        let lines_by_island = PapercraftContext::lines_by_island(self.papercraft);
+        // The cutter finds the marks and measures from them: the origin is the outer corner of
+        // the bottom-left mark, with the Y axis pointing up
+        let (frame_min, frame_max) = registration_frame(options)
+            .ok_or_else(|| anyhow!("The margins are too small for the registration marks"))?;
+        let to_hpgl = |p: Vector2| {
+            let x = ((p.x - frame_min.x) * HPGL_UNITS_PER_MM).round() as i32;
+            let y = ((frame_max.y - p.y) * HPGL_UNITS_PER_MM).round() as i32;
+            (x, y)
+        };
+
+        for &page in &self.pages {
+            let name = file_name_for_page(file_name, page);
+            let out = std::fs::File::create(&name)?;
+            let mut out = std::io::BufWriter::new(out);
+            let in_page = options.is_in_page_fn(page);
+
+            writeln!(&mut out, "IN;")?;
+            if options.score_creases {
+                writeln!(&mut out, "SP2;")?;
+                for (_, (lines, extra)) in &lines_by_island {
+                    if cuts_to_page_cuts(lines.iter_cut(), &in_page).is_none() {
+                        continue;
+                    }
+                    for fold_kind in [EdgeDrawKind::Mountain, EdgeDrawKind::Valley] {
+                        for (a, b) in lines.iter_crease(extra, fold_kind) {
+                            // Scoring out of the page would mark the cutting mat
+                            let a = in_page(a.pos).1;
+                            let b = in_page(b.pos).1;
+                            let Some((a, b)) = clip_segment(a, b, Vector2::zero(), page_size)
+                            else {
+                                continue;
+                            };
+                            let (xa, ya) = to_hpgl(a);
+                            let (xb, yb) = to_hpgl(b);
+                            writeln!(&mut out, "PU{xa},{ya};PD{xb},{yb};")?;
+                        }
+                    }
+                }
+            }
+            // Cut after scoring, or the pieces could move
+            writeln!(&mut out, "SP1;")?;
+            for (_, (lines, extra)) in &lines_by_island {
+                let Some(page_cuts) = cuts_to_page_cuts(lines.iter_cut(), &in_page) else {
+                    continue;
+                };
+                // The slits first, while the piece is still held by the paper around it.
+                // Like the scores, nothing is cut out of the page.
+                for (a, b) in lines.iter_crease(extra, EdgeDrawKind::Slot) {
+                    let a = in_page(a.pos).1;
+                    let b = in_page(b.pos).1;
+                    let Some((a, b)) = clip_segment(a, b, Vector2::zero(), page_size) else {
+                        continue;
+                    };
+                    let (xa, ya) = to_hpgl(a);
+                    let (xb, yb) = to_hpgl(b);
+                    writeln!(&mut out, "PU{xa},{ya};PD{xb},{yb};")?;
+                }
+                let mut contour = cut_to_contour(page_cuts);
+                let Some(&first) = contour.first() else {
+                    continue;
+                };
+                contour.push(first);
+                // If the island crosses the page border the contour is cut in several pieces
+                for piece in clip_polyline(&contour, Vector2::zero(), page_size) {
+                    let (x, y) = to_hpgl(piece[0]);
+                    write!(&mut out, "PU{x},{y};PD")?;
+                    for (i, (x, y)) in piece[1..].iter().map(|p| to_hpgl(*p)).enumerate() {
+                        if i > 0 {
+                            write!(&mut out, ",")?;
+                        }
+                        write!(&mut out, "{x},{y}")?;
+                    }
+                    writeln!(&mut out, ";")?;
+                }
+            }
+            writeln!(&mut out, "PU;SP0;")?;
+        }
+        Ok(())
+    }
+
+    fn generate_png(&self, renderer: &mut dyn PageRenderer, file_name: &Path) -> Result<()> {
+        self.generate_pages(
+            renderer,
+            Some(PageContent::Full),
+            |page, pixbuf, _texts, _| {
+                let name = file_name_for_page(file_name, page);
+                let f = std::fs::File::create(name)?;
+                let mut f = std::io::BufWriter::new(f);
+                pixbuf.write_to(&mut f, image::ImageFormat::Png)?;
+                Ok(())
+            },
+        )?;
+        Ok(())
+    }
+
+    fn generate_pages<F>(
+        &self,
+        renderer: &mut dyn PageRenderer,
+        content: Option<PageContent>,
+        mut do_page_fn: F,
+    ) -> Result<()>
+    where
+        F: FnMut(
+            u32,
+            &DynamicImage,
+            &[PrintableText],
+            &[(IslandKey, (PaperDrawFaceArgs, PaperDrawFaceArgsExtra))],
+        ) -> Result<()>,
+    {
+        let options = self.papercraft.options();
+        let (_margin_top, margin_left, margin_right, margin_bottom) = options.margin;
+        let page_size_mm = Vector2::from(options.page_size);
+        let page_size_pixels = page_size_pixels(self.papercraft);
+
+        let mut pixbuf = image::RgbaImage::new(page_size_pixels.0, page_size_pixels.1);
+
+        let page_count = options.pages;
+        let mut texts = Vec::new();
+        let lines_by_island = PapercraftContext::lines_by_island(self.papercraft);
+        let reg_marks = self.reg_marks();
+
+        for &page in &self.pages {
+            // If there is nothing to render the pixbuf is left blank
+            if let Some(content) = content {
+                renderer.render_page(page, content, &mut pixbuf)?;
+                // The marks are the same in every page
+                let scale = Vector2::new(
+                    pixbuf.width() as f32 / page_size_mm.x,
+                    pixbuf.height() as f32 / page_size_mm.y,
+                );
+                for (a, b) in &reg_marks {
+                    let (x0, y0) = ((a.x * scale.x) as u32, (a.y * scale.y) as u32);
+                    let (x1, y1) = ((b.x * scale.x) as u32, (b.y * scale.y) as u32);
+                    for y in y0..y1.min(pixbuf.height()) {
+                        for x in x0..x1.min(pixbuf.width()) {
+                            pixbuf.put_pixel(x, y, image::Rgba([0, 0, 0, 0xff]));
+                        }
+                    }
+                }
+            }
+
+            let edge_id_font_size = options.edge_id_font_size * 25.4 / 72.0; // pt to mm
+            let edge_id_position = options.edge_id_position;
+
+            texts.clear();
+            if options.show_self_promotion {
+                let x = margin_left;
+                let y =
+                    (page_size_mm.y - margin_bottom + FONT_SIZE).min(page_size_mm.y - FONT_SIZE);
+                let text = String::from(signature());
+                texts.push(PrintableText {
+                    size: FONT_SIZE,
+                    pos: Vector2::new(x, y),
+                    angle: Rad(0.0),
+                    align: TextAlign::Near,
+                    text,
+                });
+            }
+            if options.show_page_number {
+                let x = page_size_mm.x - margin_right;
+                let y =
+                    (page_size_mm.y - margin_bottom + FONT_SIZE).min(page_size_mm.y - FONT_SIZE);
+                let text = format!("Page {}/{}", page + 1, page_count);
+                texts.push(PrintableText {
+                    size: FONT_SIZE,
+                    pos: Vector2::new(x, y),
+                    angle: Rad(0.0),
+                    align: TextAlign::Far,
+                    text,
+                });
+            }
+            if edge_id_position != EdgeIdPosition::None || options.thick_board {
+                let in_page = options.is_in_page_fn(page);
+                for (i_island, (lines, extra)) in &lines_by_island {
+                    let Some(page_cuts) = cuts_to_page_cuts(lines.iter_cut(), &in_page) else {
+                        continue;
+                    };
+                    // Edge ids
+                    for cut_idx in extra.cut_indices() {
+                        let text = cut_idx.text(self.papercraft);
+                        let pos =
+                            in_page(cut_idx.pos(self.font_text_line_scale * edge_id_font_size)).1;
+                        texts.push(PrintableText {
+                            size: edge_id_font_size,
+                            pos,
+                            angle: cut_idx.angle,
+                            align: TextAlign::Center,
+                            text,
+                        });
+                    }
+                    // Island ids, without edge ids there may be bevel angles only
+                    if edge_id_position == EdgeIdPosition::None {
+                        continue;
+                    }
+                    let pos = match edge_id_position {
+                        // On top
+                        EdgeIdPosition::None | EdgeIdPosition::Outside => {
+                            let top = page_cuts
+                                .iter()
+                                .min_by(|a, b| a.0.y.total_cmp(&b.0.y))
+                                .unwrap()
+                                .0;
+                            top - Vector2::new(0.0, edge_id_font_size)
+                        }
+                        // In the middle
+                        EdgeIdPosition::Inside => {
+                            let island = self.papercraft.island_by_key(*i_island).unwrap();
+                            let (flat_face, total_area) =
+                                self.papercraft.get_biggest_flat_face(island);
+                            // Compute the center of mass of the flat-face, that will be the
+                            // weighted mean of the centers of masses of each single face.
+                            let center: Vector2 = flat_face
+                                .iter()
+                                .map(|(i_face, area)| {
+                                    let vv: Vector2 =
+                                        lines.vertices_for_face(*i_face).into_iter().sum();
+                                    vv * *area
+                                })
+                                .sum();
+                            // Don't forget to divide the center of each triangle by 3!
+                            let center = center / total_area / 3.0;
+                            let center = in_page(center).1;
+                            center + Vector2::new(0.0, edge_id_font_size)
+                        }
+                    };
+                    if let Some(island) = self.papercraft.island_by_key(*i_island) {
+                        texts.push(PrintableText {
+                            size: 2.0 * edge_id_font_size,
+                            pos,
+                            angle: Rad(0.0),
+                            align: TextAlign::Center,
+                            text: String::from(island.name()),
+                        });
+                    }
+                }
+            }
+            let img = DynamicImage::from(pixbuf);
+            do_page_fn(page, &img, &texts, &lines_by_island)?;
+            // restore the
+            pixbuf = img.into();
+        }
+        Ok(())
+    }
+}
+
+// The size of each printed page, in pixels
+pub fn page_size_pixels(papercraft: &Papercraft) -> (u32, u32) {
+    let options = papercraft.options();
+    let resolution = options.resolution as f32;
+    let page_size_mm = Vector2::from(options.page_size);
+    let page_size_inches = page_size_mm / 25.4;
+    let page_size_pixels = page_size_inches * resolution;
+    (page_size_pixels.x as u32, page_size_pixels.y as u32)
+}
+
+// Parses a list of pages, such as "1,3-5", into 0-based page numbers.
+pub fn parse_page_list(text: &str, page_count: u32) -> Result<Vec<u32>> {
+    let mut pages = Vec::new();
+    for part in text.split(',') {
+        let part = part.trim();
+        if part.is_empty() {
+            continue;
+        }
+        let parse_page = |s: &str| -> Result<u32> {
+            let s = s.trim();
+            let page: u32 = s
+                .parse()
+                .map_err(|_| anyhow!("Invalid page number '{s}'"))?;
+            if page == 0 || page > page_count {
+                anyhow::bail!("Page {page} out of range, there are {page_count} pages");
+            }
+            Ok(page - 1)
+        };
+        match part.split_once('-') {
+            Some((from, to)) => {
+                let from = if from.trim().is_empty() {
+                    0
+                } else {
+                    parse_page(from)?
+                };
+                let to = if to.trim().is_empty() {
+                    page_count - 1
+                } else {
+                    parse_page(to)?
+                };
+                pages.extend(from..=to);
+            }
+            None => pages.push(parse_page(part)?),
+        }
+    }
+    pages.sort();
+    pages.dedup();
+    if pages.is_empty() {
+        anyhow::bail!("No pages selected");
+    }
+    Ok(pages)
+}
+
+pub fn file_name_for_page(file_name: &Path, page: u32) -> PathBuf {
+    if page == 0 {
+        return file_name.to_owned();
+    }
+    let ext = file_name.extension().unwrap_or_default();
+    let stem = file_name.file_stem().unwrap_or_default();
+    let stem = stem.to_string_lossy();
+    let stem = stem.strip_suffix("_1").unwrap_or(&stem);
+    let parent = file_name.parent().map(|p| p.to_owned()).unwrap_or_default();
+    let mut name = PathBuf::from(format!("{}_{}", stem, page + 1));
+    name.set_extension(ext);
+    parent.join(name)
+}
+
+pub fn cut_to_contour(mut cuts: Vec<(Vector2, Vector2)>) -> Vec<Vector2> {
+    // Order the vertices in a closed loop
+    let mut res = Vec::with_capacity(cuts.len());
+    while let Some(mut p) = cuts.pop() {
+        res.push(p.0);
+        while let Some((next, _)) = cuts
+            .iter()
+            .enumerate()
+            .map(|(idx, (v0, _))| (idx, v0.distance2(p.1)))
+            .min_by(|(_, a), (_, b)| f32::total_cmp(a, b))
+        {
+            p = cuts.swap_remove(next);
+            res.push(p.0);
+        }
+        // the last point should connect to the first
+    }
+    res
+}
+
+// Moves a closed contour outwards, the corners are mitered but sharp ones are limited
+fn offset_contour(contour: &[Vector2], d: f32) -> Vec<Vector2> {
+    const MITER_LIMIT: f32 = 4.0;
+
+    let n = contour.len();
+    if n < 3 || d <= 0.0 {
+        return contour.to_vec();
+    }
+    let area2: f32 = (0..n)
+        .map(|i| {
+            let (a, b) = (contour[i], contour[(i + 1) % n]);
+            a.x * b.y - b.x * a.y
+        })
+        .sum();
+    // The outer normal of the segment from `a` to `b`, that depends on the winding
+    let normal = |a: Vector2, b: Vector2| {
+        let v = b - a;
+        let len = v.magnitude();
+        if len == 0.0 {
+            return Vector2::zero();
+        }
+        let n = Vector2::new(v.y, -v.x) / len;
+        if area2 > 0.0 {
+            n
+        } else {
+            -n
+        }
+    };
+    (0..n)
+        .map(|i| {
+            let p = contour[i];
+            let n0 = normal(contour[(i + n - 1) % n], p);
+            let n1 = normal(p, contour[(i + 1) % n]);
+            let m = n0 + n1;
+            let m_len = m.magnitude();
+            if m_len < 1e-3 {
+                return p + n0 * d;
+            }
+            // The length of the miter is d / cos(angle / 2)
+            p + m / m_len * (d * (2.0 / m_len).min(MITER_LIMIT))
+        })
+        .collect()
+}
+
+// Plotter units are 0.025 mm
+const HPGL_UNITS_PER_MM: f32 = 40.0;
+
+// Print & cut registration marks, in mm
+const REG_MARK_SIZE: f32 = 5.0;
+const REG_MARK_MIN_SIZE: f32 = 2.0;
+const REG_MARK_LINE: f32 = 0.5;
+
+// The registration marks, as filled rectangles (min, max) in page coordinates.
+// They are at three corners of the printable area, just outside of it, so they never overlap
+// the pieces. Returns None if they do not fit in the margins.
+fn registration_mark_size(options: &PaperOptions) -> Option<f32> {
+    let (margin_top, margin_left, margin_right, margin_bottom) = options.margin;
+    let size = REG_MARK_SIZE
+        .min(margin_top)
+        .min(margin_left)
+        .min(margin_right)
+        .min(margin_bottom);
+    (size >= REG_MARK_MIN_SIZE).then_some(size)
+}
+
+// The rectangle limited by the outer corners of the marks, in page coordinates
+fn registration_frame(options: &PaperOptions) -> Option<(Vector2, Vector2)> {
+    let size = registration_mark_size(options)?;
+    let (margin_top, margin_left, margin_right, margin_bottom) = options.margin;
+    let page_size = Vector2::from(options.page_size);
+    Some((
+        Vector2::new(margin_left - size, margin_top - size),
+        Vector2::new(
+            page_size.x - margin_right + size,
+            page_size.y - margin_bottom + size,
+        ),
+    ))
+}
+
+fn registration_marks(options: &PaperOptions) -> Option<Vec<(Vector2, Vector2)>> {
+    let (margin_top, margin_left, margin_right, margin_bottom) = options.margin;
+    let size = registration_mark_size(options)?;
+    let page_size = Vector2::from(options.page_size);
+    let (x0, y0) = (margin_left - size, margin_top - size);
+    let x1 = page_size.x - margin_right;
+    let y1 = page_size.y - margin_bottom;
+    let marks = vec![
+        // Top-left: a filled square
+        (Vector2::new(x0, y0), Vector2::new(margin_left, margin_top)),
+        // Top-right: an L
+        (
+            Vector2::new(x1, y0),
+            Vector2::new(x1 + size, y0 + REG_MARK_LINE),
+        ),
+        (
+            Vector2::new(x1 + size - REG_MARK_LINE, y0),
+            Vector2::new(x1 + size, margin_top),
+        ),
+        // Bottom-left: another L
+        (
+            Vector2::new(x0, y1),
+            Vector2::new(x0 + REG_MARK_LINE, y1 + size),
+        ),
+        (
+            Vector2::new(x0, y1 + size - REG_MARK_LINE),
+            Vector2::new(margin_left, y1 + size),
+        ),
+    ];
+    Some(marks)
+}
+
+// Name and color of each layer. A negative color means the layer is hidden.
+static DXF_LAYERS: &[(&str, i32)] = &[
+    ("CUT", 7),
+    ("FLAPS", -3),
+    ("MOUNTAIN", 1),
+    ("VALLEY", 5),
+    ("MARKS", 7),
+    ("REGISTRATION", 7),
+    ("TEXT", 7),
+    ("PAGES", -8),
+];
+
+// The header, with the extents of the drawing, and the layers
+fn dxf_begin(out: &mut impl Write, ext_min: Vector2, ext_max: Vector2) -> Result<()> {
+    dxf_pair(out, 0, "SECTION")?;
+    dxf_pair(out, 2, "HEADER")?;
+    // R12 is the most widely supported version. It has no units, these are millimetres.
+    dxf_pair(out, 9, "$ACADVER")?;
+    dxf_pair(out, 1, "AC1009")?;
+    dxf_pair(out, 9, "$EXTMIN")?;
+    dxf_point(out, 0, ext_min)?;
+    dxf_pair(out, 9, "$EXTMAX")?;
+    dxf_point(out, 0, ext_max)?;
+    dxf_pair(out, 0, "ENDSEC")?;
+
+    dxf_pair(out, 0, "SECTION")?;
+    dxf_pair(out, 2, "TABLES")?;
+    dxf_pair(out, 0, "TABLE")?;
+    dxf_pair(out, 2, "LAYER")?;
+    dxf_pair(out, 70, DXF_LAYERS.len())?;
+    for (layer, color) in DXF_LAYERS {
+        dxf_pair(out, 0, "LAYER")?;
+        dxf_pair(out, 2, layer)?;
+        dxf_pair(out, 70, 0)?;
+        dxf_pair(out, 62, color)?;
+        dxf_pair(out, 6, "CONTINUOUS")?;
+    }
+    dxf_pair(out, 0, "ENDTAB")?;
+    dxf_pair(out, 0, "ENDSEC")?;
+
+    dxf_pair(out, 0, "SECTION")?;
+    dxf_pair(out, 2, "ENTITIES")?;
+    Ok(())
+}
+
+fn dxf_end(out: &mut impl Write) -> Result<()> {
+    dxf_pair(out, 0, "ENDSEC")?;
+    dxf_pair(out, 0, "EOF")?;
+    Ok(())
+}
+
+// The lines of an island, if it is in the page
+fn dxf_island(
+    out: &mut impl Write,
+    lines: &PaperDrawFaceArgs,
+    extra: &PaperDrawFaceArgsExtra,
+    in_page: &impl Fn(Vector2) -> (bool, Vector2),
+    to_dxf: &impl Fn(Vector2) -> Vector2,
+) -> Result<()> {
+    let Some(page_cuts) = cuts_to_page_cuts(lines.iter_cut(), in_page) else {
+        return Ok(());
+    };
+    // The cut contour is a closed polyline
+    dxf_pair(out, 0, "POLYLINE")?;
+    dxf_pair(out, 8, "CUT")?;
+    dxf_pair(out, 66, 1)?;
+    dxf_point(out, 0, Vector2::new(0.0, 0.0))?;
+    dxf_pair(out, 70, 1)?;
+    for v in cut_to_contour(page_cuts) {
+        dxf_pair(out, 0, "VERTEX")?;
+        dxf_pair(out, 8, "CUT")?;
+        dxf_point(out, 0, to_dxf(v))?;
+    }
+    dxf_pair(out, 0, "SEQEND")?;
+    dxf_pair(out, 8, "CUT")?;
+
+    // The flap outlines are already part of the cut, this layer is hidden by default
+    for line in lines.vertices_flap_edge.chunks_exact(2) {
+        let (is_in_a, a) = in_page(line[0].pos);
+        let (is_in_b, b) = in_page(line[1].pos);
+        if is_in_a || is_in_b {
+            dxf_line(out, "FLAPS", to_dxf(a), to_dxf(b))?;
+        }
+    }
+
+    for (fold_kind, layer) in [
+        (EdgeDrawKind::Mountain, "MOUNTAIN"),
+        (EdgeDrawKind::Valley, "VALLEY"),
+        (EdgeDrawKind::Slot, "CUT"),
+        (EdgeDrawKind::Tick, "MARKS"),
+    ] {
+        for (a, b) in lines.iter_crease(extra, fold_kind) {
+            let (is_in_a, a) = in_page(a.pos);
+            let (is_in_b, b) = in_page(b.pos);
+            if is_in_a || is_in_b {
+                dxf_line(out, layer, to_dxf(a), to_dxf(b))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// A filled rectangle, from two opposite corners
+fn dxf_rect(out: &mut impl Write, layer: &str, a: Vector2, b: Vector2) -> Result<()> {
+    dxf_pair(out, 0, "SOLID")?;
+    dxf_pair(out, 8, layer)?;
+    // The third and fourth corners go in zig-zag
+    dxf_point(out, 0, a)?;
+    dxf_point(out, 1, Vector2::new(b.x, a.y))?;
+    dxf_point(out, 2, Vector2::new(a.x, b.y))?;
+    dxf_point(out, 3, b)?;
+    Ok(())
+}
+
+fn dxf_text(
+    out: &mut impl Write,
+    text: &PrintableText,
+    to_dxf: &impl Fn(Vector2) -> Vector2,
+) -> Result<()> {
+    // 0: left, 1: center, 2: right
+    let align = match text.align {
+        TextAlign::Near => 0,
+        TextAlign::Center => 1,
+        TextAlign::Far => 2,
+    };
+    let pos = to_dxf(text.pos);
+    dxf_pair(out, 0, "TEXT")?;
+    dxf_pair(out, 8, "TEXT")?;
+    dxf_point(out, 0, pos)?;
+    dxf_pair(out, 40, text.size)?;
+    dxf_pair(out, 1, &text.text)?;
+    // Counter-clockwise, because of the flipped Y axis
+    let angle = (360.0 - Deg::from(text.angle).0) % 360.0;
+    dxf_pair(out, 50, angle)?;
+    if align != 0 {
+        // With any alignment other than left, the position is given by the second point
+        dxf_pair(out, 72, align)?;
+        dxf_point(out, 1, pos)?;
+    }
+    Ok(())
+}
+
+fn dxf_pair(out: &mut impl Write, code: u32, value: impl std::fmt::Display) -> Result<()> {
+    writeln!(out, "{code:>3}\n{value}")?;
+    Ok(())
+}
+
+// `n` is the index of the point, the group codes are 10/20/30, 11/21/31...
+fn dxf_point(out: &mut impl Write, n: u32, p: Vector2) -> Result<()> {
+    dxf_pair(out, 10 + n, p.x)?;
+    dxf_pair(out, 20 + n, p.y)?;
+    dxf_pair(out, 30 + n, 0.0)?;
+    Ok(())
+}
+
+fn dxf_line(out: &mut impl Write, layer: &str, a: Vector2, b: Vector2) -> Result<()> {
+    dxf_pair(out, 0, "LINE")?;
+    dxf_pair(out, 8, layer)?;
+    dxf_point(out, 0, a)?;
+    dxf_point(out, 1, b)?;
+    Ok(())
+}
+
+// Splits a dashed line into the visible segments, as parameters from 0.0 to 1.0.
+// The dash is visible for the first half of each unit, as with `frac_dash` 0.5.
+fn dash_segments(d0: f32, d1: f32) -> Vec<(f32, f32)> {
+    if d0 == d1 {
+        return if d0.rem_euclid(1.0) < 0.5 {
+            vec![(0.0, 1.0)]
+        } else {
+            Vec::new()
+        };
+    }
+    let (lo, hi) = (d0.min(d1), d0.max(d1));
+    let to_t = |d: f32| (d - d0) / (d1 - d0);
+    let mut res = Vec::new();
+    let mut k = lo.floor();
+    while k < hi {
+        let a = k.max(lo);
+        let b = (k + 0.5).min(hi);
+        if a < b {
+            let (ta, tb) = (to_t(a), to_t(b));
+            res.push((ta.min(tb), ta.max(tb)));
+        }
+        k += 1.0;
+    }
+    res
+}
+
+// Clips a segment to a rectangle, with the Liang-Barsky algorithm
+fn clip_segment(a: Vector2, b: Vector2, min: Vector2, max: Vector2) -> Option<(Vector2, Vector2)> {
+    let d = b - a;
+    let (mut t0, mut t1) = (0.0f32, 1.0f32);
+    for (p, q) in [
+        (-d.x, a.x - min.x),
+        (d.x, max.x - a.x),
+        (-d.y, a.y - min.y),
+        (d.y, max.y - a.y),
+    ] {
+        if p == 0.0 {
+            // Parallel to this side, and outside
+            if q < 0.0 {
+                return None;
+            }
+            continue;
+        }
+        let t = q / p;
+        if p < 0.0 {
+            t0 = t0.max(t);
+        } else {
+            t1 = t1.min(t);
+        }
+        if t0 > t1 {
+            return None;
+        }
+    }
+    Some((a + d * t0, a + d * t1))
+}
+
+// Clips an open polyline to a rectangle, the parts that are inside are returned as separate polylines
+fn clip_polyline(points: &[Vector2], min: Vector2, max: Vector2) -> Vec<Vec<Vector2>> {
+    let mut res: Vec<Vec<Vector2>> = Vec::new();
+    // If the previous segment was not clipped at its end, the next one continues the same piece
+    let mut connected = false;
+    for w in points.windows(2) {
+        let Some((a, b)) = clip_segment(w[0], w[1], min, max) else {
+            connected = false;
+            continue;
+        };
+        match res.last_mut() {
+            Some(piece) if connected && a == w[0] => piece.push(b),
+            _ => res.push(vec![a, b]),
+        }
+        connected = b == w[1];
+    }
+    res
+}
+
+pub fn cuts_to_page_cuts<'c>(
+    cuts: impl Iterator<Item = (&'c MVertex2DLine, &'c MVertex2DLine)>,
+    in_page: impl Fn(Vector2) -> (bool, Vector2),
+) -> Option<Vec<(Vector2, Vector2)>> {
+    let mut touching = false;
+    let page_cut = cuts
+        .map(|(v0, v1)| {
+            let (is_in_0, v0) = in_page(v0.pos);
+            let (is_in_1, v1) = in_page(v1.pos);
+            touching |= is_in_0 | is_in_1;
+            (v0, v1)
+        })
+        .collect::<Vec<_>>();
+    touching.then_some(page_cut)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clip_segment_to_page() {
+        let (min, max) = (Vector2::new(0.0, 0.0), Vector2::new(10.0, 10.0));
+        // Inside, unchanged
+        let (a, b) = (Vector2::new(1.0, 1.0), Vector2::new(9.0, 5.0));
+        assert_eq!(clip_segment(a, b, min, max), Some((a, b)));
+        // Crossing one side
+        let (a, b) = (Vector2::new(5.0, 5.0), Vector2::new(15.0, 5.0));
+        assert_eq!(
+            clip_segment(a, b, min, max),
+            Some((a, Vector2::new(10.0, 5.0)))
+        );
+        // Crossing the whole page
+        let (a, b) = (Vector2::new(-5.0, 5.0), Vector2::new(5.0, -5.0));
+        let (ca, cb) = clip_segment(a, b, min, max).unwrap();
+        assert!((ca - Vector2::new(0.0, 0.0)).magnitude() < 1e-5);
+        assert!((cb - Vector2::new(0.0, 0.0)).magnitude() < 1e-5);
+        // Outside
+        let (a, b) = (Vector2::new(11.0, 1.0), Vector2::new(12.0, 9.0));
+        assert_eq!(clip_segment(a, b, min, max), None);
+    }
+
+    #[test]
+    fn clip_polyline_to_page() {
+        let (min, max) = (Vector2::new(0.0, 0.0), Vector2::new(10.0, 10.0));
+        // A closed square that goes out of the right side of the page
+        let points =
+            [(5.0, 2.0), (15.0, 2.0), (15.0, 8.0), (5.0, 8.0), (5.0, 2.0)].map(Vector2::from);
+        let pieces = clip_polyline(&points, min, max);
+        assert_eq!(
+            pieces,
+            vec![
+                vec![Vector2::new(5.0, 2.0), Vector2::new(10.0, 2.0)],
+                vec![
+                    Vector2::new(10.0, 8.0),
+                    Vector2::new(5.0, 8.0),
+                    Vector2::new(5.0, 2.0),
+                ],
+            ]
+        );
+        // Inside, a single piece
+        let points = [(1.0, 1.0), (9.0, 1.0), (9.0, 9.0), (1.0, 1.0)].map(Vector2::from);
+        assert_eq!(clip_polyline(&points, min, max), vec![points.to_vec()]);
+    }
+
+    #[test]
+    fn registration_frame_contains_the_marks() {
+        let options = PaperOptions::default();
+        let (min, max) = registration_frame(&options).unwrap();
+        for (a, b) in registration_marks(&options).unwrap() {
+            for p in [a, b] {
+                assert!(p.x >= min.x && p.y >= min.y && p.x <= max.x && p.y <= max.y);
+            }
+        }
+        // The top-left and bottom-right marks touch the corners of the frame
+        let marks = registration_marks(&options).unwrap();
+        assert_eq!(marks[0].0, min);
+        assert_eq!(marks[2].1.x, max.x);
+        assert_eq!(marks[4].1.y, max.y);
+    }
+
+    #[test]
+    fn offset_contour_goes_outwards() {
+        let square = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)].map(Vector2::from);
+        let expected = [(-1.0, -1.0), (11.0, -1.0), (11.0, 11.0), (-1.0, 11.0)].map(Vector2::from);
+        // Any winding
+        for reversed in [false, true] {
+            let mut c = square.to_vec();
+            let mut e = expected.to_vec();
+            if reversed {
+                c.reverse();
+                e.reverse();
+            }
+            for (p, q) in offset_contour(&c, 1.0).into_iter().zip(e) {
+                assert!((p - q).magnitude() < 1e-5, "{p:?} {q:?}");
+            }
+        }
+        // A sharp corner is limited
+        let spike = [(0.0, 0.0), (100.0, 1.0), (0.0, 2.0)].map(Vector2::from);
+        let offset = offset_contour(&spike, 1.0);
+        assert!(offset[1].x > 100.0 && (offset[1] - spike[1]).magnitude() <= 4.0 + 1e-5);
+    }
+}
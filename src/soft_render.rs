@@ -0,0 +1,1022 @@
+/* A software rasterizer for the printable pages.
+ * It draws the same vertices as the GL renderer, but it does not need a GL context, so it can
+ * be used from the command line in a machine without a display.
+ *
+ * It tries to mimic what the GL renderer does: a 16x multisampled RGBA8 framebuffer, the same
+ * blending and the same texture filtering, including mipmaps. Everything is computed in a single
+ * thread in a fixed order, so the output is the same in every run.
+ *
+ * The texts use the same font as the GUI, rasterized here into an atlas, because the one from
+ * Dear ImGui is not available without a window.
+ */
+use anyhow::{anyhow, Result};
+use cgmath::prelude::*;
+use fxhash::FxHashMap;
+use image::RgbaImage;
+
+use crate::glr::Rgba;
+use crate::paper::{EdgeIdPosition, FlapStyle, MaterialIndex, Papercraft};
+use crate::printable::{PageContent, PageRenderer, PrintableText, TextAlign};
+use crate::ui::{PaperDrawFaceArgs, PapercraftContext};
+use crate::util_3d::{self, Matrix2, Matrix3, Point2, Vector2};
+use crate::util_gl::{MVertex2DColor, MVertex2DLine, MVertexText};
+use crate::{TextBuilder, FONT_TEXT_LINE_SCALE, KARLA_TTF};
+
+type Color = [f32; 4];
+
+// The samples are a regular grid of SAMPLE_GRID x SAMPLE_GRID inside each pixel
+const SAMPLE_GRID: u32 = 4;
+const SAMPLES: usize = (SAMPLE_GRID * SAMPLE_GRID) as usize;
+// The page is drawn in bands of this many rows, to keep the memory of the samples bounded
+const BAND_HEIGHT: u32 = 64;
+
+pub struct SoftRenderer<'a> {
+    papercraft: &'a Papercraft,
+    args: PaperDrawFaceArgs,
+    // The faces are drawn with the color attribute zeroed
+    face_vertices: Vec<MVertex2DColor>,
+    // None if the textures are not to be drawn
+    textures: Option<Textures>,
+    // The glyphs for `args.vertices_text`
+    font_atlas: RgbaImage,
+}
+
+impl<'a> SoftRenderer<'a> {
+    pub fn new(papercraft: &'a Papercraft) -> Result<SoftRenderer<'a>> {
+        // The same font size as in the GUI atlas
+        let options = papercraft.options();
+        let font_size = (options.edge_id_font_size * options.resolution as f32 / 72.0).min(350.0);
+        let text_builder = SoftTextBuilder::new(&KARLA_TTF, font_size)?;
+        let args = PapercraftContext::paper_build_args(papercraft, Some(&text_builder), true);
+        let face_vertices = args
+            .vertices
+            .iter()
+            .map(|v| MVertex2DColor {
+                pos: v.pos,
+                uv: v.uv,
+                mat: v.mat,
+                color: Rgba::new(0.0, 0.0, 0.0, 0.0),
+            })
+            .collect();
+        let textures = if papercraft.options().texture {
+            Textures::new(papercraft)
+        } else {
+            None
+        };
+        Ok(SoftRenderer {
+            papercraft,
+            args,
+            face_vertices,
+            textures,
+            font_atlas: text_builder.atlas,
+        })
+    }
+
+    fn texture_color(&self, mat: MaterialIndex, frag: &Fragment<MVertex2DColor>) -> Color {
+        match &self.textures {
+            Some(textures) => textures.sample(mat, frag.data.uv, frag.ddx.uv, frag.ddy.uv),
+            None => [1.0, 1.0, 1.0, 1.0],
+        }
+    }
+
+    fn draw(
+        &self,
+        canvas: &mut Canvas,
+        bins: &PageBins,
+        band: usize,
+        with_lines: bool,
+        with_texts: bool,
+    ) {
+        let options = self.papercraft.options();
+        let flap_style = options.flap_style;
+        let line_color = [0.0, 0.0, 0.0, 1.0];
+        let args = &self.args;
+
+        // Same order as in the GL renderer
+
+        // Texture bleed, under everything else
+        canvas.draw_triangles(&args.vertices_bleed, bins.bleed.band(band), |f| {
+            self.texture_color(f.data.mat, f)
+        });
+
+        // Texts
+        if with_texts
+            && options.edge_id_position == EdgeIdPosition::Outside
+            && !options.texts_over_texture()
+        {
+            canvas.draw_texts(&args.vertices_text, bins.text.band(band), &self.font_atlas);
+        }
+
+        // Line Flaps
+        if with_lines && flap_style != FlapStyle::None {
+            canvas.draw_lines(
+                &args.vertices_flap_edge,
+                bins.flap_edge.band(band),
+                line_color,
+            );
+        }
+
+        // Solid Flaps
+        if flap_style != FlapStyle::None && flap_style != FlapStyle::White {
+            canvas.draw_triangles(&args.vertices_flap, bins.flap.band(band), |f| {
+                let c = self.texture_color(f.data.mat, f);
+                mix_color(c, f.data.color)
+            });
+        }
+
+        // Borders
+        if with_lines {
+            canvas.draw_lines(
+                &args.vertices_edge_cut,
+                bins.edge_cut.band(band),
+                line_color,
+            );
+        }
+
+        // Textured faces
+        canvas.draw_triangles(&self.face_vertices, bins.faces.band(band), |f| {
+            self.texture_color(f.data.mat, f)
+        });
+
+        // Shadow Flaps
+        canvas.draw_triangles(
+            &args.vertices_shadow_flap,
+            bins.shadow_flap.band(band),
+            |f| mix_color([0.0, 0.0, 0.0, 0.0], f.data.color),
+        );
+
+        // Creases
+        if with_lines {
+            canvas.draw_lines(
+                &args.vertices_edge_crease,
+                bins.edge_crease.band(band),
+                line_color,
+            );
+        }
+
+        // Texts
+        if with_texts && options.texts_over_texture() {
+            canvas.draw_texts(&args.vertices_text, bins.text.band(band), &self.font_atlas);
+        }
+    }
+}
+
+// The primitives of a vertex array that touch each band of the page, so that every band
+// draws only its own
+struct Bins(Vec<Vec<u32>>);
+
+impl Bins {
+    // `ranges` is the vertical extent of each primitive, in pixels
+    fn new(n_bands: usize, ranges: impl Iterator<Item = (f32, f32)>) -> Bins {
+        let mut bins = vec![Vec::new(); n_bands];
+        for (i, (y0, y1)) in ranges.enumerate() {
+            if y1.is_nan() || y1 < 0.0 {
+                continue;
+            }
+            let b0 = (y0.max(0.0) / BAND_HEIGHT as f32) as usize;
+            let b1 = ((y1 / BAND_HEIGHT as f32) as usize).min(n_bands - 1);
+            for bin in bins.iter_mut().take(b1 + 1).skip(b0) {
+                bin.push(i as u32);
+            }
+        }
+        Bins(bins)
+    }
+    fn triangles<T>(n_bands: usize, mx: &Matrix3, vs: &[T], pos: impl Fn(&T) -> Vector2) -> Bins {
+        let ranges = vs.chunks_exact(3).map(|tri| {
+            let ys = [0, 1, 2].map(|i| transform(mx, pos(&tri[i])).y);
+            (ys[0].min(ys[1]).min(ys[2]), ys[0].max(ys[1]).max(ys[2]))
+        });
+        Bins::new(n_bands, ranges)
+    }
+    fn lines(n_bands: usize, mx: &Matrix3, vs: &[MVertex2DLine]) -> Bins {
+        // The quad of a line is a bit bigger than the line itself
+        let scale = mx.x.x.abs().max(mx.y.y.abs());
+        let ranges = vs.chunks_exact(2).map(|line| {
+            let (y0, y1) = (transform(mx, line[0].pos).y, transform(mx, line[1].pos).y);
+            let width = line
+                .iter()
+                .map(|v| v.width_left + v.width_right)
+                .sum::<f32>();
+            let margin = width * scale + 1.0;
+            (y0.min(y1) - margin, y0.max(y1) + margin)
+        });
+        Bins::new(n_bands, ranges)
+    }
+    fn band(&self, band: usize) -> &[u32] {
+        &self.0[band]
+    }
+}
+
+// The bins of every vertex array that is drawn
+struct PageBins {
+    bleed: Bins,
+    text: Bins,
+    flap_edge: Bins,
+    flap: Bins,
+    edge_cut: Bins,
+    faces: Bins,
+    shadow_flap: Bins,
+    edge_crease: Bins,
+}
+
+impl PageBins {
+    fn new(renderer: &SoftRenderer<'_>, n_bands: usize, mx: &Matrix3) -> PageBins {
+        let args = &renderer.args;
+        let tris = |vs: &[MVertex2DColor]| Bins::triangles(n_bands, mx, vs, |v| v.pos);
+        PageBins {
+            bleed: tris(&args.vertices_bleed),
+            text: Bins::triangles(n_bands, mx, &args.vertices_text, |v| v.pos),
+            flap_edge: Bins::lines(n_bands, mx, &args.vertices_flap_edge),
+            flap: tris(&args.vertices_flap),
+            edge_cut: Bins::lines(n_bands, mx, &args.vertices_edge_cut),
+            faces: tris(&renderer.face_vertices),
+            shadow_flap: tris(&args.vertices_shadow_flap),
+            edge_crease: Bins::lines(n_bands, mx, &args.vertices_edge_crease),
+        }
+    }
+}
+
+impl PageRenderer for SoftRenderer<'_> {
+    fn render_page(
+        &mut self,
+        page: u32,
+        content: PageContent,
+        pixbuf: &mut RgbaImage,
+    ) -> Result<()> {
+        let with_texts = content == PageContent::Full;
+        let with_lines = content != PageContent::TextureOnly;
+        let options = self.papercraft.options();
+        let page_pos = options.page_position(page);
+        let page_size_mm = Vector2::from(options.page_size);
+
+        // From paper coordinates in mm to pixels
+        let mx = Matrix3::from_nonuniform_scale(
+            pixbuf.width() as f32 / page_size_mm.x,
+            pixbuf.height() as f32 / page_size_mm.y,
+        ) * Matrix3::from_translation(-page_pos);
+
+        let n_bands = pixbuf.height().div_ceil(BAND_HEIGHT) as usize;
+        let bins = PageBins::new(self, n_bands, &mx);
+        let mut canvas = Canvas::new(pixbuf.width(), mx);
+        for band in 0..n_bands {
+            let y0 = band as u32 * BAND_HEIGHT;
+            canvas.reset(y0, BAND_HEIGHT.min(pixbuf.height() - y0));
+            self.draw(&mut canvas, &bins, band, with_lines, with_texts);
+            canvas.resolve(pixbuf);
+        }
+        Ok(())
+    }
+}
+
+// The same as the `paper_solid` shader: mix(c, vec4(color.rgb, 1.0), color.a)
+fn mix_color(c: Color, color: Rgba) -> Color {
+    let a = color.a;
+    [
+        c[0] * (1.0 - a) + color.r * a,
+        c[1] * (1.0 - a) + color.g * a,
+        c[2] * (1.0 - a) + color.b * a,
+        c[3] * (1.0 - a) + a,
+    ]
+}
+
+// A glyph in the atlas, the metrics are in pixels, relative to the top of the line
+struct Glyph {
+    p0: Vector2,
+    p1: Vector2,
+    uv0: Vector2,
+    uv1: Vector2,
+    advance: f32,
+}
+
+// Lays out the texts just like the GUI does with the Dear ImGui font
+struct SoftTextBuilder {
+    font_size: f32,
+    ascent: f32,
+    glyphs: FxHashMap<char, Glyph>,
+    // White, the glyphs are in the alpha channel
+    atlas: RgbaImage,
+}
+
+impl SoftTextBuilder {
+    fn new(ttf: &[u8], font_size: f32) -> Result<SoftTextBuilder> {
+        let face = ttf_parser::Face::parse(ttf, 0).map_err(|e| anyhow!("invalid font: {e}"))?;
+        // Same as stb_truetype's ScaleForPixelHeight
+        let scale = font_size / f32::from(face.ascender() - face.descender());
+        let ascent = (f32::from(face.ascender()) * scale).round();
+
+        // Basic Latin and Latin-1 Supplement, the default ranges of Dear ImGui
+        let mut shapes = Vec::new();
+        for c in (' '..='~').chain('\u{a0}'..='\u{ff}') {
+            let Some(id) = face.glyph_index(c) else {
+                continue;
+            };
+            let advance = f32::from(face.glyph_hor_advance(id).unwrap_or(0)) * scale;
+            let mut outline = GlyphOutline::new(scale);
+            let bbox = face.outline_glyph(id, &mut outline);
+            shapes.push((c, advance, bbox.map(|_| outline)));
+        }
+
+        // Pack the glyphs in rows, with a gap so that the linear filter does not mix them
+        const GAP: u32 = 2;
+        let atlas_width = (font_size.ceil() as u32 * 16).clamp(256, 4096);
+        let mut placed = Vec::with_capacity(shapes.len());
+        let (mut x, mut y, mut row_height) = (GAP, GAP, 0);
+        for (c, advance, outline) in shapes {
+            let Some(outline) = outline else {
+                placed.push((c, advance, None));
+                continue;
+            };
+            let (w, h) = outline.size();
+            if x + w + GAP > atlas_width {
+                x = GAP;
+                y += row_height + GAP;
+                row_height = 0;
+            }
+            placed.push((c, advance, Some((outline, x, y))));
+            x += w + GAP;
+            row_height = row_height.max(h);
+        }
+        let atlas_height = y + row_height + GAP;
+
+        let mut atlas = RgbaImage::from_pixel(
+            atlas_width,
+            atlas_height,
+            image::Rgba([0xff, 0xff, 0xff, 0]),
+        );
+        let atlas_size = Vector2::new(atlas_width as f32, atlas_height as f32);
+        let mut glyphs = FxHashMap::default();
+        for (c, advance, shape) in placed {
+            let glyph = match shape {
+                Some((outline, x, y)) => {
+                    let (w, h) = outline.size();
+                    outline.rasterize(|gx, gy, alpha| {
+                        atlas.get_pixel_mut(x + gx, y + gy).0[3] = alpha;
+                    });
+                    let uv0 = Vector2::new(x as f32, y as f32);
+                    let uv1 = uv0 + Vector2::new(w as f32, h as f32);
+                    let p0 = outline.bounds().0 + Vector2::new(0.0, ascent);
+                    Glyph {
+                        p0,
+                        p1: p0 + Vector2::new(w as f32, h as f32),
+                        uv0: Vector2::new(uv0.x / atlas_size.x, uv0.y / atlas_size.y),
+                        uv1: Vector2::new(uv1.x / atlas_size.x, uv1.y / atlas_size.y),
+                        advance,
+                    }
+                }
+                // Blanks have only the advance
+                None => Glyph {
+                    p0: Vector2::zero(),
+                    p1: Vector2::zero(),
+                    uv0: Vector2::zero(),
+                    uv1: Vector2::zero(),
+                    advance,
+                },
+            };
+            glyphs.insert(c, glyph);
+        }
+        Ok(SoftTextBuilder {
+            font_size,
+            ascent,
+            glyphs,
+            atlas,
+        })
+    }
+
+    // Unknown chars are drawn as '?', like Dear ImGui does
+    fn glyph(&self, c: char) -> Option<&Glyph> {
+        self.glyphs.get(&c).or_else(|| self.glyphs.get(&'?'))
+    }
+}
+
+impl TextBuilder for SoftTextBuilder {
+    fn font_text_line_scale(&self) -> f32 {
+        FONT_TEXT_LINE_SCALE
+    }
+    // The same quads as `TextHelper::make_text`
+    fn make_text(&self, pt: &PrintableText, vs: &mut Vec<MVertexText>) {
+        let width = pt
+            .text
+            .chars()
+            .filter_map(|c| self.glyph(c))
+            .map(|g| g.advance)
+            .sum::<f32>();
+        let x_offset = match pt.align {
+            TextAlign::Near => 0.0,
+            TextAlign::Center => -width / 2.0,
+            TextAlign::Far => -width,
+        };
+        let m = Matrix3::from_translation(pt.pos)
+            * Matrix3::from(Matrix2::from_angle(pt.angle))
+            * Matrix3::from_scale(pt.size / self.font_size)
+            * Matrix3::from_translation(Vector2::new(x_offset, -self.ascent));
+        let mut x = 0.0;
+        for c in pt.text.chars() {
+            let Some(g) = self.glyph(c) else {
+                continue;
+            };
+            if g.p0 != g.p1 {
+                let (p0, p1) = (g.p0 + Vector2::new(x, 0.0), g.p1 + Vector2::new(x, 0.0));
+                let q = [
+                    (p0, g.uv0),
+                    (Vector2::new(p1.x, p0.y), Vector2::new(g.uv1.x, g.uv0.y)),
+                    (Vector2::new(p0.x, p1.y), Vector2::new(g.uv0.x, g.uv1.y)),
+                    (Vector2::new(p0.x, p1.y), Vector2::new(g.uv0.x, g.uv1.y)),
+                    (Vector2::new(p1.x, p0.y), Vector2::new(g.uv1.x, g.uv0.y)),
+                    (p1, g.uv1),
+                ];
+                vs.extend(q.map(|(p, uv)| MVertexText {
+                    pos: m.transform_point(Point2::from_vec(p)).to_vec(),
+                    uv,
+                }));
+            }
+            x += g.advance;
+        }
+    }
+}
+
+// Collects the outline of a glyph as line segments, in pixels with the Y axis down
+struct GlyphOutline {
+    scale: f32,
+    segments: Vec<(Vector2, Vector2)>,
+    start: Vector2,
+    last: Vector2,
+}
+
+impl GlyphOutline {
+    // Curves are flattened with this many segments
+    const CURVE_STEPS: u32 = 8;
+
+    fn new(scale: f32) -> GlyphOutline {
+        GlyphOutline {
+            scale,
+            segments: Vec::new(),
+            start: Vector2::zero(),
+            last: Vector2::zero(),
+        }
+    }
+    fn point(&self, x: f32, y: f32) -> Vector2 {
+        Vector2::new(x * self.scale, -y * self.scale)
+    }
+    fn push(&mut self, p: Vector2) {
+        self.segments.push((self.last, p));
+        self.last = p;
+    }
+    // The bitmap of the glyph, relative to the pen position in the baseline, with a pixel of margin
+    fn bounds(&self) -> (Vector2, Vector2) {
+        let ps = self.segments.iter().flat_map(|&(a, b)| [a, b]);
+        let (min, max) = util_3d::bounding_box_2d(ps);
+        let min = Vector2::new(min.x.floor() - 1.0, min.y.floor() - 1.0);
+        let max = Vector2::new(max.x.ceil() + 1.0, max.y.ceil() + 1.0);
+        (min, max)
+    }
+    fn size(&self) -> (u32, u32) {
+        let (min, max) = self.bounds();
+        let d = max - min;
+        (d.x as u32, d.y as u32)
+    }
+    // Calls `f` with the coverage of each pixel, with the non-zero winding rule
+    fn rasterize(&self, mut f: impl FnMut(u32, u32, u8)) {
+        let (min, _) = self.bounds();
+        let (w, h) = self.size();
+        let mut crossings = Vec::new();
+        let mut coverage = vec![0u32; w as usize];
+        for y in 0..h {
+            coverage.fill(0);
+            for sy in 0..SAMPLE_GRID {
+                let ys = min.y + y as f32 + (sy as f32 + 0.5) / SAMPLE_GRID as f32;
+                crossings.clear();
+                for &(a, b) in &self.segments {
+                    let (dir, a, b) = if a.y <= b.y { (1, a, b) } else { (-1, b, a) };
+                    if ys < a.y || ys >= b.y {
+                        continue;
+                    }
+                    let x = a.x + (ys - a.y) / (b.y - a.y) * (b.x - a.x) - min.x;
+                    crossings.push((x, dir));
+                }
+                crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+                let mut winding = 0;
+                for pair in crossings.windows(2) {
+                    winding += pair[0].1;
+                    if winding == 0 {
+                        continue;
+                    }
+                    // Samples in [x0, x1)
+                    let to_sample = |x: f32| (x * SAMPLE_GRID as f32 - 0.5).ceil().max(0.0) as u32;
+                    let s0 = to_sample(pair[0].0);
+                    let s1 = to_sample(pair[1].0).min(w * SAMPLE_GRID);
+                    for s in s0..s1 {
+                        coverage[(s / SAMPLE_GRID) as usize] += 1;
+                    }
+                }
+            }
+            for (x, &c) in coverage.iter().enumerate() {
+                if c > 0 {
+                    let alpha = (c * 255 + SAMPLES as u32 / 2) / SAMPLES as u32;
+                    f(x as u32, y, alpha as u8);
+                }
+            }
+        }
+    }
+}
+
+impl ttf_parser::OutlineBuilder for GlyphOutline {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.start = self.point(x, y);
+        self.last = self.start;
+    }
+    fn line_to(&mut self, x: f32, y: f32) {
+        let p = self.point(x, y);
+        self.push(p);
+    }
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let (p0, p1, p2) = (self.last, self.point(x1, y1), self.point(x, y));
+        for i in 1..=Self::CURVE_STEPS {
+            let t = i as f32 / Self::CURVE_STEPS as f32;
+            let u = 1.0 - t;
+            self.push(p0 * (u * u) + p1 * (2.0 * u * t) + p2 * (t * t));
+        }
+    }
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let (p0, p1, p2, p3) = (
+            self.last,
+            self.point(x1, y1),
+            self.point(x2, y2),
+            self.point(x, y),
+        );
+        for i in 1..=Self::CURVE_STEPS {
+            let t = i as f32 / Self::CURVE_STEPS as f32;
+            let u = 1.0 - t;
+            self.push(
+                p0 * (u * u * u)
+                    + p1 * (3.0 * u * u * t)
+                    + p2 * (3.0 * u * t * t)
+                    + p3 * (t * t * t),
+            );
+        }
+    }
+    fn close(&mut self) {
+        if self.last != self.start {
+            let p = self.start;
+            self.push(p);
+        }
+    }
+}
+
+// The textures as the GL renderer sees them: all with the same size, with their mipmaps
+struct Textures {
+    width: u32,
+    height: u32,
+    filter: bool,
+    // One entry per material, and for each one the mipmap levels, starting with the full image
+    levels: Vec<Vec<RgbaImage>>,
+}
+
+impl Textures {
+    // Returns None if there are no images at all, just like the GL renderer
+    fn new(papercraft: &Papercraft) -> Option<Textures> {
+        let model = papercraft.model();
+        let images = model.textures().map(|tex| tex.pixbuf()).collect::<Vec<_>>();
+
+        let sizes = images
+            .iter()
+            .filter_map(|i| i.as_ref())
+            .map(|i| (i.width(), i.height()));
+        let width = sizes.clone().map(|(w, _)| w).max()?;
+        let height = sizes.map(|(_, h)| h).max()?;
+        let filter = papercraft.options().tex_filter;
+
+        let levels = images
+            .iter()
+            .map(|image| {
+                let image = match image {
+                    Some(image) if image.width() == width && image.height() == height => {
+                        image.to_rgba8()
+                    }
+                    Some(image) => image::imageops::resize(
+                        *image,
+                        width,
+                        height,
+                        image::imageops::FilterType::Triangle,
+                    ),
+                    // Missing images are drawn gray
+                    None => {
+                        RgbaImage::from_pixel(width, height, image::Rgba([0x80, 0x80, 0x80, 0xff]))
+                    }
+                };
+                let mut levels = vec![image];
+                // Without filter the mipmaps are never used
+                if filter {
+                    loop {
+                        let last = levels.last().unwrap();
+                        if last.width() == 1 && last.height() == 1 {
+                            break;
+                        }
+                        let next = half_image(last);
+                        levels.push(next);
+                    }
+                }
+                levels
+            })
+            .collect();
+        Some(Textures {
+            width,
+            height,
+            filter,
+            levels,
+        })
+    }
+
+    fn sample(&self, mat: MaterialIndex, uv: Vector2, ddx: Vector2, ddy: Vector2) -> Color {
+        let Some(levels) = self.levels.get(usize::from(mat)) else {
+            return [0.0, 0.0, 0.0, 1.0];
+        };
+        if !self.filter {
+            return sample_nearest(&levels[0], uv);
+        }
+        // Level of detail, computed as the GL spec says
+        let size = Vector2::new(self.width as f32, self.height as f32);
+        let rho_x = Vector2::new(ddx.x * size.x, ddx.y * size.y).magnitude();
+        let rho_y = Vector2::new(ddy.x * size.x, ddy.y * size.y).magnitude();
+        let lambda = rho_x.max(rho_y).log2();
+        // Magnification, or NaN if the derivatives are zero
+        if lambda.is_nan() || lambda <= 0.0 {
+            return sample_linear(&levels[0], uv);
+        }
+        let max_level = (levels.len() - 1) as f32;
+        let d = lambda.min(max_level);
+        let l0 = d.floor();
+        let l1 = (l0 + 1.0).min(max_level);
+        let c0 = sample_linear(&levels[l0 as usize], uv);
+        let c1 = sample_linear(&levels[l1 as usize], uv);
+        let t = d - l0;
+        [0, 1, 2, 3].map(|i| c0[i] * (1.0 - t) + c1[i] * t)
+    }
+}
+
+// Next mipmap level, with a simple box filter
+fn half_image(img: &RgbaImage) -> RgbaImage {
+    let (w, h) = img.dimensions();
+    let (w2, h2) = ((w / 2).max(1), (h / 2).max(1));
+    RgbaImage::from_fn(w2, h2, |x, y| {
+        let xs = [(2 * x).min(w - 1), (2 * x + 1).min(w - 1)];
+        let ys = [(2 * y).min(h - 1), (2 * y + 1).min(h - 1)];
+        let mut sum = [0u32; 4];
+        for y in ys {
+            for x in xs {
+                for (s, c) in sum.iter_mut().zip(img.get_pixel(x, y).0) {
+                    *s += u32::from(c);
+                }
+            }
+        }
+        image::Rgba(sum.map(|s| ((s + 2) / 4) as u8))
+    })
+}
+
+fn texel(img: &RgbaImage, x: i64, y: i64) -> Color {
+    // Textures repeat
+    let x = x.rem_euclid(i64::from(img.width())) as u32;
+    let y = y.rem_euclid(i64::from(img.height())) as u32;
+    img.get_pixel(x, y).0.map(|c| c as f32 / 255.0)
+}
+
+fn sample_nearest(img: &RgbaImage, uv: Vector2) -> Color {
+    let x = (uv.x * img.width() as f32).floor();
+    let y = (uv.y * img.height() as f32).floor();
+    texel(img, x as i64, y as i64)
+}
+
+fn sample_linear(img: &RgbaImage, uv: Vector2) -> Color {
+    let x = uv.x * img.width() as f32 - 0.5;
+    let y = uv.y * img.height() as f32 - 0.5;
+    let (x0, y0) = (x.floor(), y.floor());
+    let (a, b) = (x - x0, y - y0);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+    let c00 = texel(img, x0, y0);
+    let c10 = texel(img, x0 + 1, y0);
+    let c01 = texel(img, x0, y0 + 1);
+    let c11 = texel(img, x0 + 1, y0 + 1);
+    [0, 1, 2, 3].map(|i| {
+        (1.0 - a) * (1.0 - b) * c00[i]
+            + a * (1.0 - b) * c10[i]
+            + (1.0 - a) * b * c01[i]
+            + a * b * c11[i]
+    })
+}
+
+// A vertex ready to be rasterized, already in pixel coordinates
+#[derive(Copy, Clone)]
+struct RasterVertex<T> {
+    pos: Vector2,
+    data: T,
+}
+
+// What the shader gets: the interpolated values and their screen derivatives
+struct Fragment<T> {
+    data: T,
+    ddx: T,
+    ddy: T,
+}
+
+// Values that can be interpolated inside a triangle
+trait Interpolate: Copy {
+    fn interpolate(vs: [&Self; 3], w: [f32; 3]) -> Self;
+}
+
+impl Interpolate for MVertex2DColor {
+    fn interpolate(vs: [&Self; 3], w: [f32; 3]) -> Self {
+        let lerp = |f: fn(&Self) -> f32| w[0] * f(vs[0]) + w[1] * f(vs[1]) + w[2] * f(vs[2]);
+        MVertex2DColor {
+            pos: vs[0].pos * w[0] + vs[1].pos * w[1] + vs[2].pos * w[2],
+            uv: vs[0].uv * w[0] + vs[1].uv * w[1] + vs[2].uv * w[2],
+            // the material is flat
+            mat: vs[0].mat,
+            color: Rgba::new(
+                lerp(|v| v.color.r),
+                lerp(|v| v.color.g),
+                lerp(|v| v.color.b),
+                lerp(|v| v.color.a),
+            ),
+        }
+    }
+}
+
+impl Interpolate for MVertexText {
+    fn interpolate(vs: [&Self; 3], w: [f32; 3]) -> Self {
+        MVertexText {
+            pos: vs[0].pos * w[0] + vs[1].pos * w[1] + vs[2].pos * w[2],
+            uv: vs[0].uv * w[0] + vs[1].uv * w[1] + vs[2].uv * w[2],
+        }
+    }
+}
+
+impl Interpolate for f32 {
+    fn interpolate(vs: [&Self; 3], w: [f32; 3]) -> Self {
+        w[0] * vs[0] + w[1] * vs[1] + w[2] * vs[2]
+    }
+}
+
+// A band of rows of the page, with all the samples of each pixel
+struct Canvas {
+    width: u32,
+    y0: u32,
+    height: u32,
+    mx: Matrix3,
+    // RGBA8, just like the GL framebuffer
+    samples: Vec<[[u8; 4]; SAMPLES]>,
+    sample_pos: [Vector2; SAMPLES],
+}
+
+impl Canvas {
+    fn new(width: u32, mx: Matrix3) -> Canvas {
+        let sample_pos = std::array::from_fn(|i| {
+            let i = i as u32;
+            Vector2::new(
+                ((i % SAMPLE_GRID) as f32 + 0.5) / SAMPLE_GRID as f32,
+                ((i / SAMPLE_GRID) as f32 + 0.5) / SAMPLE_GRID as f32,
+            )
+        });
+        Canvas {
+            width,
+            y0: 0,
+            height: 0,
+            mx,
+            samples: Vec::new(),
+            sample_pos,
+        }
+    }
+
+    fn reset(&mut self, y0: u32, height: u32) {
+        self.y0 = y0;
+        self.height = height;
+        self.samples.clear();
+        self.samples
+            .resize(self.width as usize * height as usize, [[0; 4]; SAMPLES]);
+    }
+
+    // Just like the blit from the multisample buffer: the average of all the samples
+    fn resolve(&self, pixbuf: &mut RgbaImage) {
+        for (i, samples) in self.samples.iter().enumerate() {
+            let x = i as u32 % self.width;
+            let y = self.y0 + i as u32 / self.width;
+            let mut sum = [0u32; 4];
+            for s in samples {
+                for (sum, c) in sum.iter_mut().zip(s) {
+                    *sum += u32::from(*c);
+                }
+            }
+            let n = SAMPLES as u32;
+            pixbuf.put_pixel(x, y, image::Rgba(sum.map(|s| ((s + n / 2) / n) as u8)));
+        }
+    }
+
+    // Blending is SRC_ALPHA, ONE_MINUS_SRC_ALPHA for the color and ONE, ONE_MINUS_SRC_ALPHA for the alpha
+    fn blend(&mut self, x: u32, y: u32, mask: u32, src: Color) {
+        let src = src.map(|c| c.clamp(0.0, 1.0));
+        let a = src[3];
+        let pixel = &mut self.samples[((y - self.y0) * self.width + x) as usize];
+        for (i, dst) in pixel.iter_mut().enumerate() {
+            if mask & (1 << i) == 0 {
+                continue;
+            }
+            let d = dst.map(|c| c as f32 / 255.0);
+            let res = [
+                src[0] * a + d[0] * (1.0 - a),
+                src[1] * a + d[1] * (1.0 - a),
+                src[2] * a + d[2] * (1.0 - a),
+                a + d[3] * (1.0 - a),
+            ];
+            *dst = res.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+        }
+    }
+
+    fn transform(&self, p: Vector2) -> Vector2 {
+        transform(&self.mx, p)
+    }
+
+    // `prims` are the indices of the triangles to draw
+    fn draw_triangles(
+        &mut self,
+        vertices: &[MVertex2DColor],
+        prims: &[u32],
+        mut shader: impl FnMut(&Fragment<MVertex2DColor>) -> Color,
+    ) {
+        for &i in prims {
+            let tri = &vertices[3 * i as usize..][..3];
+            let tri = [0, 1, 2].map(|i| RasterVertex {
+                pos: self.transform(tri[i].pos),
+                data: tri[i],
+            });
+            self.raster_triangle(tri, &mut shader);
+        }
+    }
+
+    // The same as the `text` shader, black with the alpha of the font atlas
+    fn draw_texts(&mut self, vertices: &[MVertexText], prims: &[u32], atlas: &RgbaImage) {
+        for &i in prims {
+            let tri = &vertices[3 * i as usize..][..3];
+            let tri = [0, 1, 2].map(|i| RasterVertex {
+                pos: self.transform(tri[i].pos),
+                data: tri[i],
+            });
+            self.raster_triangle(tri, &mut |f: &Fragment<MVertexText>| {
+                let c = sample_linear(atlas, f.data.uv);
+                [0.0, 0.0, 0.0, c[3]]
+            });
+        }
+    }
+
+    // Lines are drawn as quads, the same as the `paper_line` geometry shader
+    fn draw_lines(&mut self, vertices: &[MVertex2DLine], prims: &[u32], color: Color) {
+        for &i in prims {
+            let line = &vertices[2 * i as usize..][..2];
+            let (v0, v1) = (&line[0], &line[1]);
+            let mut p0 = v0.pos;
+            let mut p1 = v1.pos;
+            let v = p1 - p0;
+            if v.magnitude2() == 0.0 {
+                continue;
+            }
+            let v = v.normalize();
+            let n = Vector2::new(v.y, -v.x);
+            let v = v * (v0.width_right + v0.width_left) / 2.0;
+            p0 -= v;
+            p1 += v;
+
+            let quad = [
+                (p0 + n * v0.width_right, v0.line_dash),
+                (p1 + n * v1.width_right, v1.line_dash),
+                (p0 - n * v0.width_left, v0.line_dash),
+                (p1 - n * v1.width_left, v1.line_dash),
+            ]
+            .map(|(pos, dash)| RasterVertex {
+                pos: self.transform(pos),
+                data: dash,
+            });
+            let mut shader = |f: &Fragment<f32>| {
+                // frac_dash is always 0.5 in the printable pages
+                if f.data.rem_euclid(1.0) < 0.5 {
+                    color
+                } else {
+                    [0.0; 4]
+                }
+            };
+            self.raster_triangle([quad[0], quad[1], quad[2]], &mut shader);
+            self.raster_triangle([quad[1], quad[2], quad[3]], &mut shader);
+        }
+    }
+
+    fn raster_triangle<T: Interpolate>(
+        &mut self,
+        tri: [RasterVertex<T>; 3],
+        shader: &mut impl FnMut(&Fragment<T>) -> Color,
+    ) {
+        let [a, b, c] = [tri[0].pos, tri[1].pos, tri[2].pos];
+        let band_y1 = (self.y0 + self.height) as f32;
+        let min_y = a.y.min(b.y).min(c.y);
+        let max_y = a.y.max(b.y).max(c.y);
+        // Most triangles are not in this band
+        if max_y < self.y0 as f32 || min_y > band_y1 {
+            return;
+        }
+        let area = edge_function(a, b, c);
+        if area == 0.0 || !area.is_finite() {
+            return;
+        }
+        let x0 = a.x.min(b.x).min(c.x).floor().max(0.0) as u32;
+        let y0 = (min_y.floor().max(0.0) as u32).max(self.y0);
+        let x1 = (a.x.max(b.x).max(c.x).ceil().max(0.0) as u32).min(self.width);
+        let y1 = (max_y.ceil().max(0.0) as u32).min(self.y0 + self.height);
+
+        // With a consistent rule for the samples exactly in an edge, triangles sharing that edge
+        // will not draw the same sample twice.
+        let top_left = |p: Vector2, q: Vector2| {
+            let (p, q) = if area > 0.0 { (p, q) } else { (q, p) };
+            (p.y == q.y && q.x < p.x) || q.y < p.y
+        };
+        let tl = [top_left(b, c), top_left(c, a), top_left(a, b)];
+        let weights = |p: Vector2| {
+            [
+                edge_function(b, c, p) / area,
+                edge_function(c, a, p) / area,
+                edge_function(a, b, p) / area,
+            ]
+        };
+
+        // The derivatives are the same in the whole triangle
+        let data = [&tri[0].data, &tri[1].data, &tri[2].data];
+        let dwdx = [(b.y - c.y) / area, (c.y - a.y) / area, (a.y - b.y) / area];
+        let dwdy = [(c.x - b.x) / area, (a.x - c.x) / area, (b.x - a.x) / area];
+        let ddx = T::interpolate(data, dwdx);
+        let ddy = T::interpolate(data, dwdy);
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let corner = Vector2::new(x as f32, y as f32);
+                let mut mask = 0;
+                for (i, s) in self.sample_pos.iter().enumerate() {
+                    let w = weights(corner + s);
+                    let inside = w
+                        .iter()
+                        .zip(&tl)
+                        .all(|(&w, &tl)| w > 0.0 || (w == 0.0 && tl));
+                    if inside {
+                        mask |= 1 << i;
+                    }
+                }
+                if mask == 0 {
+                    continue;
+                }
+                // The shader runs once per pixel, at its center, even if it is outside of the triangle
+                let w = weights(corner + Vector2::new(0.5, 0.5));
+                let frag = Fragment {
+                    data: T::interpolate(data, w),
+                    ddx,
+                    ddy,
+                };
+                let color = shader(&frag);
+                self.blend(x, y, mask, color);
+            }
+        }
+    }
+}
+
+fn transform(mx: &Matrix3, p: Vector2) -> Vector2 {
+    mx.transform_point(Point2::from_vec(p)).to_vec()
+}
+
+fn edge_function(a: Vector2, b: Vector2, p: Vector2) -> f32 {
+    (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paper::import::tests::cube;
+
+    fn render(papercraft: &Papercraft) -> RgbaImage {
+        let (w, h) = crate::printable::page_size_pixels(papercraft);
+        let mut pixbuf = RgbaImage::new(w, h);
+        let mut renderer = SoftRenderer::new(papercraft).unwrap();
+        renderer
+            .render_page(0, PageContent::Full, &mut pixbuf)
+            .unwrap();
+        pixbuf
+    }
+
+    #[test]
+    fn same_output_every_time() {
+        let mut papercraft = cube(1.0);
+        let mut options = papercraft.options().clone();
+        options.scale = 20.0;
+        options.resolution = 100;
+        papercraft.set_options(options, false);
+        papercraft.pack_islands(false);
+
+        let a = render(&papercraft);
+        let b = render(&papercraft);
+        // Something is drawn, but not everything
+        assert!(a.pixels().any(|p| p.0[3] != 0));
+        assert!(a.pixels().any(|p| p.0[3] == 0));
+        assert_eq!(a.as_raw(), b.as_raw());
+    }
+}
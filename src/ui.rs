@@ -1,4 +1,8 @@
-use std::{collections::HashMap, ops::Range};
+use std::{
+    collections::HashMap,
+    io::{Read, Seek, Write},
+    ops::Range,
+};
 /* Everything in this crate is public so that it can be freely used from main.rs */
 use std::ops::ControlFlow;
 
@@ -9,12 +13,15 @@ use easy_imgui_window::easy_imgui_renderer::{
 };
 use fxhash::{FxHashMap, FxHashSet};
 use image::DynamicImage;
+use serde::{Deserialize, Serialize};
 
+use crate::glr::{self, Rgba};
 use crate::paper::{
-    EdgeId, EdgeIdPosition, EdgeIndex, EdgeStatus, EdgeToggleFlapAction, Face, FaceIndex, FlapGeom,
-    FlapSide, FlapStyle, FoldStyle, IslandKey, JoinResult, MaterialIndex, Model, PaperOptions,
-    Papercraft,
+    AlignmentMarks, Conflict, Edge, EdgeId, EdgeIdPosition, EdgeIndex, EdgeStatus,
+    EdgeToggleFlapAction, Face, FaceIndex, FlapOverride, FlapSide, FlapStyle, FoldStyle, IslandKey,
+    JoinResult, MaterialIndex, Model, PaperOptions, Papercraft, PartIndex,
 };
+use crate::printable::{PrintableText, TextAlign};
 use crate::util_3d::{
     self, Matrix2, Matrix3, Matrix4, Point2, Point3, Quaternion, Vector2, Vector3,
 };
@@ -22,15 +29,13 @@ use crate::util_gl::{
     MStatus2D, MStatus3D, MVertex2D, MVertex2DColor, MVertex2DLine, MVertex3D, MVertex3DLine,
     MVertexText, MSTATUS_HI, MSTATUS_SEL, MSTATUS_UNSEL,
 };
-use crate::{
-    glr::{self, Rgba},
-    PrintableText, TextAlign,
-};
 use crate::{TextBuilder, FONT_SIZE};
 
 // In millimeters, these are not configurable, but they should be cut out, so they should not be visible anyways
 const FLAP_LINE_WIDTH: f32 = 0.2;
 const BORDER_LINE_WIDTH: f32 = 0.1;
+// Length of the alignment marks, into the face
+const TICK_LENGTH: f32 = 1.5;
 
 // In pixels
 const LINE_SEL_WIDTH: f32 = 5.0;
@@ -53,6 +58,7 @@ pub struct GLObjects {
     pub paper_vertices_flap_edge: glr::DynamicVertexArray<MVertex2DLine>,
     pub paper_vertices_edge_sel: glr::DynamicVertexArray<MVertex2DLine>,
     pub paper_vertices_shadow_flap: glr::DynamicVertexArray<MVertex2DColor>,
+    pub paper_vertices_bleed: glr::DynamicVertexArray<MVertex2DColor>,
     pub paper_text: glr::DynamicVertexArray<MVertexText>,
 
     // Maps a FaceIndex to the index into paper_vertices
@@ -60,6 +66,7 @@ pub struct GLObjects {
 
     pub paper_vertices_page: glr::DynamicVertexArray<MVertex2DColor>,
     pub paper_vertices_margin: glr::DynamicVertexArray<MVertex2DLine>,
+    pub paper_vertices_rubber_band: glr::DynamicVertexArray<MVertex2DLine>,
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -70,6 +77,18 @@ pub enum MouseMode {
     ReadOnly,
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum IslandAlign {
+    Left,
+    Right,
+    Top,
+    Bottom,
+    CenterH,
+    CenterV,
+    DistributeH,
+    DistributeV,
+}
+
 pub fn color_edge(mode: MouseMode) -> Rgba {
     match mode {
         MouseMode::Edge => Rgba::new(0.5, 0.5, 1.0, 1.0),
@@ -79,7 +98,7 @@ pub fn color_edge(mode: MouseMode) -> Rgba {
 }
 
 //UndoItem cannot store IslandKey, because they are dynamic, use the root of the island instead
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum UndoAction {
     IslandMove {
         i_root: FaceIndex,
@@ -90,6 +109,10 @@ pub enum UndoAction {
         i_edge: EdgeIndex,
         flap_side: FlapSide,
     },
+    FlapOverride {
+        i_edge: EdgeIndex,
+        flap_override: FlapOverride,
+    },
     EdgeCut {
         i_edge: EdgeIndex,
     },
@@ -100,9 +123,39 @@ pub enum UndoAction {
         options: PaperOptions,
         island_pos: FxHashMap<FaceIndex, (Rad<f32>, Vector2)>,
     },
+    ModelTransform {
+        mx: [[f32; 3]; 3],
+    },
     Modified,
 }
 
+impl UndoAction {
+    // A history loaded from a file may not match the model, and a wrong index would panic on undo
+    fn is_valid_for(&self, model: &Model) -> bool {
+        let face_ok = |i: FaceIndex| usize::from(i) < model.num_faces();
+        let edge_ok = |i: EdgeIndex| usize::from(i) < model.num_edges();
+        match self {
+            UndoAction::IslandMove { i_root, .. } => face_ok(*i_root),
+            UndoAction::FlapToggle { i_edge, .. }
+            | UndoAction::FlapOverride { i_edge, .. }
+            | UndoAction::EdgeCut { i_edge } => edge_ok(*i_edge),
+            UndoAction::EdgeJoin { join_result } => {
+                edge_ok(join_result.i_edge) && face_ok(join_result.prev_root)
+            }
+            UndoAction::DocConfig { island_pos, .. } => island_pos.keys().all(|&i| face_ok(i)),
+            UndoAction::ModelTransform { .. } | UndoAction::Modified => true,
+        }
+    }
+}
+
+// What is saved in the craft file, if enabled.
+// Generic so that it can be saved by reference and loaded by value.
+#[derive(Serialize, Deserialize)]
+struct UndoHistory<S> {
+    undo: S,
+    redo: S,
+}
+
 bitflags::bitflags! {
     #[derive(Copy, Clone)]
     pub struct RebuildFlags: u32 {
@@ -126,16 +179,23 @@ pub struct PapercraftContext {
     gl_objs: GLObjects,
 
     undo_stack: Vec<Vec<UndoAction>>,
+    redo_stack: Vec<Vec<UndoAction>>,
     pub modified: bool,
 
     // State
     selected_face: Option<FaceIndex>,
     selected_edges: Option<FxHashSet<EdgeIndex>>,
     selected_islands: Vec<IslandKey>,
+    // Edges whose flaps are being edited, picked with Ctrl-click in flap mode
+    selected_flaps: FxHashSet<EdgeIndex>,
     // Contains the UndoActions if these islands are to be moved, the actual grabbed islands are selected_islands
     grabbed_island: Option<Vec<UndoAction>>,
     last_cursor_pos: Vector2,
     rotation_center: Option<Vector2>,
+    // Corners of the selection rectangle, in paper coordinates, while it is being dragged
+    rubber_band: Option<(Vector2, Vector2)>,
+    // Parts of the model not drawn in the 3D view
+    hidden_parts: FxHashSet<PartIndex>,
 
     pub ui: UiSettings,
 }
@@ -291,19 +351,25 @@ pub enum ClickResult {
 pub enum EdgeDrawKind {
     Mountain,
     Valley,
+    // The slit for a tab-and-slot flap, it is drawn with the creases but it is cut
+    Slot,
+    // An alignment mark on a cut edge, it is only printed
+    Tick,
 }
 
 pub struct PaperDrawFaceArgs {
-    vertices: Vec<MVertex2D>,
-    vertices_edge_cut: Vec<MVertex2DLine>,
-    vertices_edge_crease: Vec<MVertex2DLine>,
-    vertices_flap: Vec<MVertex2DColor>,
-    vertices_flap_edge: Vec<MVertex2DLine>,
-    vertices_shadow_flap: Vec<MVertex2DColor>,
-    vertices_text: Vec<MVertexText>,
+    pub vertices: Vec<MVertex2D>,
+    pub vertices_edge_cut: Vec<MVertex2DLine>,
+    pub vertices_edge_crease: Vec<MVertex2DLine>,
+    pub vertices_flap: Vec<MVertex2DColor>,
+    pub vertices_flap_edge: Vec<MVertex2DLine>,
+    pub vertices_shadow_flap: Vec<MVertex2DColor>,
+    // The texture that goes beyond the cut edges
+    pub vertices_bleed: Vec<MVertex2DColor>,
+    pub vertices_text: Vec<MVertexText>,
 
     // Maps a FaceIndex to the index into vertices
-    face_index: Vec<u32>,
+    pub face_index: Vec<u32>,
 }
 
 // Complements PaperDrawFaceArgs for printable operations
@@ -323,6 +389,7 @@ impl PaperDrawFaceArgs {
             vertices_flap: Vec::new(),
             vertices_flap_edge: Vec::new(),
             vertices_shadow_flap: Vec::new(),
+            vertices_bleed: Vec::new(),
             face_index: vec![0; model.num_faces()],
             vertices_text: Vec::new(),
         }
@@ -376,7 +443,9 @@ pub struct CutIndex {
     pub dir: Vector2,
     pub angle: Rad<f32>,
     pub i_face_b: FaceIndex,
-    pub id: EdgeId,
+    pub id: Option<EdgeId>,
+    // Only in thick board mode
+    pub bevel: Option<Deg<f32>>,
     pub voffs: f32,
 }
 
@@ -385,24 +454,26 @@ impl CutIndex {
     /// (a, b): coordinates of the edge on paper
     /// n_flap: if there is a flap, a vector normal to the edge with the length of the flap width
     /// i_face_b: the face index of the _other _ face (_this_ face is not needed)
-    /// id: the edge id
+    /// id: the edge id, if any
+    /// bevel: the bevel angle of the edge, in thick board mode
     /// options: the PaperOptions
     fn new(
         a: Vector2,
         b: Vector2,
         n_flap: Option<Vector2>,
         i_face_b: FaceIndex,
-        id: EdgeId,
+        id: Option<EdgeId>,
+        bevel: Option<Deg<f32>>,
         options: &PaperOptions,
     ) -> CutIndex {
         let mut center = (a + b) / 2.0;
 
         // Where does the edge-id go?
         let voffs = match (options.edge_id_position, n_flap) {
-            // inside the face
-            (EdgeIdPosition::Inside, None) => -0.2,
+            // inside the face, also the bevel angles without edge-ids
+            (EdgeIdPosition::Inside | EdgeIdPosition::None, None) => -0.2,
             // in the flap, next to the face
-            (EdgeIdPosition::Inside, Some(_)) => 1.0,
+            (EdgeIdPosition::Inside | EdgeIdPosition::None, Some(_)) => 1.0,
             // outside the face
             (EdgeIdPosition::Outside, None) => 1.0,
             // outside the flap
@@ -410,8 +481,6 @@ impl CutIndex {
                 center += n;
                 1.0
             }
-            // should not happen, if pos is None it is filtered before getting here
-            (EdgeIdPosition::None, _) => 0.0,
         };
         let dir = (b - a).normalize();
         //let normal = Vector2::new(-dir.y, dir.x);
@@ -423,6 +492,7 @@ impl CutIndex {
             angle,
             i_face_b,
             id,
+            bevel,
             voffs,
         }
     }
@@ -430,6 +500,27 @@ impl CutIndex {
         let normal = Vector2::new(-self.dir.y, self.dir.x);
         self.center + font_size * self.voffs * normal
     }
+    pub fn text(&self, papercraft: &Papercraft) -> String {
+        let mut text = match self.id {
+            Some(id) => {
+                let i_island_b = papercraft.island_by_face(self.i_face_b);
+                let ii = papercraft
+                    .island_by_key(i_island_b)
+                    .map(|island_b| island_b.name())
+                    .unwrap_or("?");
+                format!("{}:{}", ii, id)
+            }
+            None => String::new(),
+        };
+        if let Some(bevel) = self.bevel {
+            if !text.is_empty() {
+                text.push(' ');
+            }
+            // The PDF fonts are ASCII only, so no degree sign
+            text += &format!("{:.0}deg", bevel.0);
+        }
+        text
+    }
 }
 
 pub enum UndoResult {
@@ -445,13 +536,14 @@ impl PapercraftContext {
     pub fn gl_objs(&self) -> &GLObjects {
         &self.gl_objs
     }
+    fn island_positions(&self) -> FxHashMap<FaceIndex, (Rad<f32>, Vector2)> {
+        self.papercraft()
+            .islands()
+            .map(|(_, island)| (island.root_face(), (island.rotation(), island.location())))
+            .collect()
+    }
     pub fn set_papercraft_options(&mut self, options: PaperOptions, push_undo_action: bool) {
-        let island_pos = push_undo_action.then(|| {
-            self.papercraft()
-                .islands()
-                .map(|(_, island)| (island.root_face(), (island.rotation(), island.location())))
-                .collect()
-        });
+        let island_pos = push_undo_action.then(|| self.island_positions());
 
         self.ui.show_textures = options.texture;
         if let Some(tex) = &self.gl_objs.textures {
@@ -462,24 +554,77 @@ impl PapercraftContext {
                 set_texture_filter(tex.gl(), options.tex_filter);
             }
         }
+        let thick_board = options.thick_board;
         let old_options = self
             .papercraft
             .set_options(options, /* relocate_pieces */ push_undo_action);
 
         if let Some(island_pos) = island_pos {
-            self.push_undo_action(vec![UndoAction::DocConfig {
-                options: old_options,
+            let mut undo_actions = vec![UndoAction::DocConfig {
+                options: old_options.clone(),
                 island_pos,
-            }]);
+            }];
+            // Entering the thick board mode splits every face into its own panel
+            if thick_board && !old_options.thick_board {
+                let cuts = self.papercraft.split_into_panels();
+                if !cuts.is_empty() {
+                    undo_actions.extend(
+                        cuts.into_iter()
+                            .map(|i_edge| UndoAction::EdgeCut { i_edge }),
+                    );
+                    undo_actions.extend(self.pack_islands(false));
+                }
+            }
+            self.push_undo_action(undo_actions);
         }
     }
+    pub fn load<R: Read + Seek>(r: R, gl: &GlContext) -> anyhow::Result<PapercraftContext> {
+        let (papercraft, undo_history) = Papercraft::load_with_undo_history(r)?;
+        let mut ctx = Self::from_papercraft(papercraft, gl)?;
+        if let Some(UndoHistory::<Vec<Vec<UndoAction>>> { undo, redo }) = undo_history {
+            // The saved file is the unmodified state, so the old Modified marks are meaningless
+            let clean = |stack: Vec<Vec<UndoAction>>| {
+                stack
+                    .into_iter()
+                    .map(|mut pack| {
+                        pack.retain(|a| !matches!(a, UndoAction::Modified));
+                        pack
+                    })
+                    .filter(|pack| !pack.is_empty())
+                    .collect()
+            };
+            let model = ctx.papercraft.model();
+            if undo
+                .iter()
+                .chain(&redo)
+                .flatten()
+                .all(|a| a.is_valid_for(model))
+            {
+                ctx.undo_stack = clean(undo);
+                ctx.redo_stack = clean(redo);
+            } else {
+                log::warn!("The undo history does not match the model, it is discarded");
+            }
+        }
+        Ok(ctx)
+    }
+    pub fn save<W: Write + Seek>(&self, w: W) -> anyhow::Result<()> {
+        let undo_history = self
+            .papercraft
+            .options()
+            .save_undo_history
+            .then_some(UndoHistory {
+                undo: &self.undo_stack,
+                redo: &self.redo_stack,
+            });
+        self.papercraft.save(w, undo_history.as_ref())
+    }
     pub fn from_papercraft(
         papercraft: Papercraft,
         gl: &GlContext,
     ) -> anyhow::Result<PapercraftContext> {
         // Compute the bounding box, then move to the center and scale to a standard size
-        let (v_min, v_max) =
-            util_3d::bounding_box_3d(papercraft.model().vertices().map(|(_, v)| v.pos()));
+        let (v_min, v_max) = papercraft.model().bounding_box();
         let size = (v_max.x - v_min.x)
             .max(v_max.y - v_min.y)
             .max(v_max.z - v_min.z);
@@ -497,14 +642,18 @@ impl PapercraftContext {
         Ok(PapercraftContext {
             papercraft,
             undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
             modified: false,
             gl_objs,
             selected_face: None,
             selected_edges: None,
             selected_islands: Vec::new(),
+            selected_flaps: FxHashSet::default(),
             grabbed_island: None,
             last_cursor_pos: Vector2::zero(),
             rotation_center: None,
+            rubber_band: None,
+            hidden_parts: FxHashSet::default(),
             ui: UiSettings {
                 mode: MouseMode::Face,
                 trans_scene,
@@ -555,40 +704,64 @@ impl PapercraftContext {
         p1.line_dash = p0.line_dash + line_dash;
     }
     fn paper_draw_face(
-        &self,
+        papercraft: &Papercraft,
         face: &Face,
         i_face: FaceIndex,
         m: &Matrix3,
         args: &mut PaperDrawFaceArgs,
-        mut flap_cache: Option<&mut Vec<(FaceIndex, FlapVertices)>>,
+        mut flap_cache: Option<&mut Vec<(FaceIndex, Vec<Vector2>)>>,
         mut extra: Option<&mut PaperDrawFaceArgsExtra>,
     ) {
         args.face_index[usize::from(i_face)] = args.vertices.len() as u32 / 3;
-        let options = self.papercraft.options();
+        let options = papercraft.options();
         let scale = options.scale;
         let flap_style = options.flap_style;
         let fold_line_width = options.fold_line_width;
 
-        for i_v in face.index_vertices() {
-            let v = &self.papercraft.model()[i_v];
-            let p = self
-                .papercraft
-                .model()
-                .face_plane(face)
-                .project(&v.pos(), scale);
-            let pos = m.transform_point(Point2::from_vec(p)).to_vec();
-
+        let plane = papercraft.model().face_plane(face);
+        let vs = face.index_vertices().map(|i_v| &papercraft.model()[i_v]);
+        let ps = vs.map(|v| plane.project(&v.pos(), scale));
+        // With paper thickness the vertices may be moved, then the UVs are interpolated
+        let ps_inset = papercraft.face_vertices_inset(face);
+        let mx_basis = Matrix2::from_cols(ps[1] - ps[0], ps[2] - ps[0]).invert();
+        let ps_paper = ps_inset.map(|p| m.transform_point(Point2::from_vec(p)).to_vec());
+        let uvs: [Vector2; 3] = std::array::from_fn(|i| match mx_basis {
+            Some(mx_basis) if ps_inset[i] != ps[i] => {
+                let vlocal = mx_basis * (ps_inset[i] - ps[0]);
+                let (uv0, uv1, uv2) = (vs[0].uv(), vs[1].uv(), vs[2].uv());
+                uv0 + vlocal.x * (uv1 - uv0) + vlocal.y * (uv2 - uv0)
+            }
+            _ => vs[i].uv(),
+        });
+        for (&pos, &uv) in ps_paper.iter().zip(&uvs) {
             args.vertices.push(MVertex2D {
                 pos,
-                uv: v.uv(),
+                uv,
                 mat: face.material(),
             });
         }
 
-        for (i_v0, i_v1, i_edge) in face.vertices_with_edges() {
-            let edge = &self.papercraft.model()[i_edge];
-            let edge_status = self.papercraft.edge_status(i_edge);
-            let edge_id = self.papercraft.edge_id(i_edge);
+        // The bleed continues the UV mapping of the face beyond its cut edges
+        let bleed = papercraft.texture_bleed();
+        let mx_paper = Matrix2::from_cols(ps_paper[1] - ps_paper[0], ps_paper[2] - ps_paper[0])
+            .invert()
+            .filter(|_| bleed > 0.0);
+        let bleed_vertex = |mx_paper: &Matrix2, pos: Vector2| {
+            let vlocal = mx_paper * (pos - ps_paper[0]);
+            MVertex2DColor {
+                pos,
+                uv: uvs[0] + vlocal.x * (uvs[1] - uvs[0]) + vlocal.y * (uvs[2] - uvs[0]),
+                mat: face.material(),
+                color: Rgba::new(1.0, 1.0, 1.0, 0.0),
+            }
+        };
+
+        for (i, i_edge) in face.index_edges().into_iter().enumerate() {
+            let edge = &papercraft.model()[i_edge];
+            let edge_status = papercraft.edge_status(i_edge);
+            let edge_id = papercraft.edge_id(i_edge);
+            // Each panel is cut at half the angle of the fold, so that they meet at the edge
+            let bevel = options.thick_board.then(|| Deg::from(edge.angle()) / 2.0);
 
             // `draw_flap`` references the adjacent face, and tells if it has to be drawn
             #[derive(Copy, Clone)]
@@ -641,14 +814,8 @@ impl PapercraftContext {
                 }
             };
 
-            let plane = self.papercraft.model().face_plane(face);
-            let v0 = &self.papercraft.model()[i_v0];
-            let p0 = plane.project(&v0.pos(), scale);
-            let pos0 = m.transform_point(Point2::from_vec(p0)).to_vec();
-
-            let v1 = &self.papercraft.model()[i_v1];
-            let p1 = plane.project(&v1.pos(), scale);
-            let pos1 = m.transform_point(Point2::from_vec(p1)).to_vec();
+            let pos0 = ps_paper[i];
+            let pos1 = ps_paper[(i + 1) % 3];
 
             //Dotted lines are drawn for negative 3d angles (valleys) if the edge is joined or
             //cut with a flap
@@ -773,100 +940,178 @@ impl PapercraftContext {
                 let mut v1 = MVertex2DLine { pos: pos1, ..v0 };
                 Self::make_dash_line(line_dash, v0, &mut v1);
                 args.vertices_edge_cut.extend_from_slice(&[v0, v1]);
-                if let (Some(extra), Some(edge_id), Some(i_face_b)) =
-                    (extra.as_mut(), edge_id, draw_flap.face())
-                {
-                    extra.cut_index.push(CutIndex::new(
-                        v0.pos, v1.pos, None, i_face_b, edge_id, options,
-                    ));
+
+                if let (Some(mx_paper), true) = (&mx_paper, v_len > 0.0) {
+                    let dir = v / v_len;
+                    let mut n = Vector2::new(-dir.y, dir.x);
+                    if n.dot(ps_paper[(i + 2) % 3] - pos0) > 0.0 {
+                        n = -n;
+                    }
+                    // A bit longer than the edge, to cover the corners
+                    let a = pos0 - dir * bleed;
+                    let b = pos1 + dir * bleed;
+                    let (a_out, b_out) = (a + n * bleed, b + n * bleed);
+                    args.vertices_bleed
+                        .extend([a, b, b_out, a, b_out, a_out].map(|p| bleed_vertex(mx_paper, p)));
+                }
+                if let (Some(extra), Some(i_face_b)) = (extra.as_mut(), draw_flap.face()) {
+                    if edge_id.is_some() || bevel.is_some() {
+                        extra.cut_index.push(CutIndex::new(
+                            v0.pos, v1.pos, None, i_face_b, edge_id, bevel, options,
+                        ));
+                    }
+                }
+            }
+
+            // The slit of a tab that is in the other face
+            if let (EdgeStatus::Cut(c), DrawFlap::Invisible(Some(i_face_b))) =
+                (edge_status, draw_flap)
+            {
+                if flap_style != FlapStyle::None && c != FlapSide::Hidden {
+                    let flap_geom =
+                        papercraft.flat_face_flap_dimensions(i_face_b, Some(i_face), i_edge);
+                    // The edge goes the other way around in the face with the flap
+                    if let Some((a, b)) = flap_geom.slot(pos1, pos0) {
+                        let v0 = MVertex2DLine {
+                            pos: a,
+                            line_dash: 0.0,
+                            width_left: fold_line_width / 2.0,
+                            width_right: fold_line_width / 2.0,
+                        };
+                        let v1 = MVertex2DLine { pos: b, ..v0 };
+                        args.vertices_edge_crease.extend_from_slice(&[v0, v1]);
+                        if let Some(extra) = extra.as_mut() {
+                            extra.crease_kind.push(EdgeDrawKind::Slot);
+                        }
+                    }
+                }
+            }
+
+            // Alignment marks, measured from the same end of the edge in both faces, so
+            // that the uneven ones tell the orientation
+            if matches!(edge_status, EdgeStatus::Cut(_))
+                && draw_flap.face().is_some()
+                && options.alignment_marks != AlignmentMarks::None
+                && v_len > 0.0
+            {
+                let (start, dir) = if edge.face_sign(i_face) {
+                    (pos1, -v / v_len)
+                } else {
+                    (pos0, v / v_len)
+                };
+                let mut n = Vector2::new(-dir.y, dir.x);
+                if n.dot(ps_paper[(i + 2) % 3] - start) < 0.0 {
+                    n = -n;
+                }
+                let fractions: &[f32] = match options.alignment_marks {
+                    AlignmentMarks::None => &[],
+                    AlignmentMarks::Midpoint => &[0.5],
+                    AlignmentMarks::Fractions => &[0.25, 0.5],
+                };
+                for f in fractions {
+                    let p = start + dir * (v_len * f);
+                    let v0 = MVertex2DLine {
+                        pos: p,
+                        line_dash: 0.0,
+                        width_left: fold_line_width / 2.0,
+                        width_right: fold_line_width / 2.0,
+                    };
+                    let v1 = MVertex2DLine {
+                        pos: p + n * TICK_LENGTH.min(v_len / 4.0),
+                        ..v0
+                    };
+                    args.vertices_edge_crease.extend_from_slice(&[v0, v1]);
+                    if let Some(extra) = extra.as_mut() {
+                        extra.crease_kind.push(EdgeDrawKind::Tick);
+                    }
                 }
             }
 
             // Draw the flap?
             if let DrawFlap::Visible(maybe_i_face_b) = draw_flap {
                 let flap_geom =
-                    self.papercraft
-                        .flat_face_flap_dimensions(i_face, maybe_i_face_b, i_edge);
-                let FlapGeom {
-                    tan_0,
-                    tan_1,
-                    width,
-                    triangular,
-                } = flap_geom;
-
-                let vn = v * (width / v_len);
-                let v_0 = vn * tan_0;
-                let v_1 = vn * tan_1;
-                let n = Vector2::new(-vn.y, vn.x);
-                let mut p = [
-                    MVertex2DLine {
-                        pos: pos0,
-                        line_dash: 0.0,
-                        width_left: FLAP_LINE_WIDTH,
-                        width_right: 0.0,
-                    },
-                    MVertex2DLine {
-                        pos: pos0 + n + v_1,
-                        line_dash: 0.0,
-                        width_left: FLAP_LINE_WIDTH,
-                        width_right: 0.0,
-                    },
-                    MVertex2DLine {
-                        pos: pos1 + n - v_0,
-                        line_dash: 0.0,
-                        width_left: FLAP_LINE_WIDTH,
-                        width_right: 0.0,
-                    },
-                    MVertex2DLine {
-                        pos: pos1,
+                    papercraft.flat_face_flap_dimensions(i_face, maybe_i_face_b, i_edge);
+                let outline = flap_geom.outline(pos0, pos1);
+                let mut p: Vec<_> = outline
+                    .iter()
+                    .map(|&pos| MVertex2DLine {
+                        pos,
                         line_dash: 0.0,
                         width_left: FLAP_LINE_WIDTH,
                         width_right: 0.0,
-                    },
-                ];
+                    })
+                    .collect();
                 // Weird flaps are drawn differently:
                 // * forced flap in a rim
                 if edge.faces().1.is_none() {
-                    Self::make_dash_line(5.0, p[0], &mut p[1]);
-                    Self::make_dash_line(5.0, p[1], &mut p[2]);
-                    Self::make_dash_line(5.0, p[2], &mut p[3]);
+                    for i in 1..p.len() {
+                        let prev = p[i - 1];
+                        Self::make_dash_line(5.0, prev, &mut p[i]);
+                    }
                 }
-                if triangular {
-                    //The unneeded vertex is actually [2], so remove that copying the [3] over
-                    p[2] = p[3];
-                    args.vertices_flap_edge
-                        .extend_from_slice(&[p[0], p[1], p[1], p[2]]);
-                } else {
-                    args.vertices_flap_edge
-                        .extend_from_slice(&[p[0], p[1], p[1], p[2], p[2], p[3]]);
-                };
-                if let (Some(extra), Some(edge_id), Some(i_face_b)) =
-                    (extra.as_mut(), edge_id, maybe_i_face_b)
-                {
-                    extra.cut_index.push(CutIndex::new(
-                        pos0,
-                        pos1,
-                        Some(n),
-                        i_face_b,
-                        edge_id,
-                        options,
-                    ));
+                for pp in p.windows(2) {
+                    args.vertices_flap_edge.extend_from_slice(pp);
+                }
+                let vn = v * (flap_geom.width / v_len);
+                let n = Vector2::new(-vn.y, vn.x);
+                if let (Some(extra), Some(i_face_b)) = (extra.as_mut(), maybe_i_face_b) {
+                    if edge_id.is_some() || bevel.is_some() {
+                        extra.cut_index.push(CutIndex::new(
+                            pos0,
+                            pos1,
+                            Some(n),
+                            i_face_b,
+                            edge_id,
+                            bevel,
+                            options,
+                        ));
+                    }
+                }
+
+                // The bleed goes beyond the cut of a textured flap, with the texture of the flap
+                let mut bleed_ps = Vec::new();
+                if flap_style == FlapStyle::Textured && bleed > 0.0 {
+                    // The outer side of the outline depends on its winding
+                    let area2: f32 = outline
+                        .iter()
+                        .zip(outline.iter().cycle().skip(1))
+                        .map(|(a, b)| a.x * b.y - b.x * a.y)
+                        .sum();
+                    for w in outline.windows(2) {
+                        let d = w[1] - w[0];
+                        let d_len = d.magnitude();
+                        if d_len == 0.0 {
+                            continue;
+                        }
+                        let dir = d / d_len;
+                        let n_out = if area2 > 0.0 {
+                            Vector2::new(dir.y, -dir.x)
+                        } else {
+                            Vector2::new(-dir.y, dir.x)
+                        };
+                        // A bit longer than the segment, to cover the corners
+                        let a = w[0] - dir * bleed;
+                        let b = w[1] + dir * bleed;
+                        let (a_out, b_out) = (a + n_out * bleed, b + n_out * bleed);
+                        bleed_ps.extend([a, b, b_out, a, b_out, a_out]);
+                    }
                 }
 
                 // Get material and geometry from adjacent face, if any
                 let geom_b; //Option<(mx_b_inv, i_face_b)>
                 let mat;
                 let uvs;
+                let bleed_uvs;
 
                 // helper function for the two cases below
-                let compute_uvs = |face_b: &Face, mx_b: &Matrix3| -> Vec<Vector2> {
+                let compute_uvs = |face_b: &Face, mx_b: &Matrix3, ps: &[Vector2]| -> Vec<Vector2> {
                     if flap_style == FlapStyle::White {
-                        vec![Vector2::zero(); 4]
+                        vec![Vector2::zero(); ps.len()]
                     } else {
                         //Now we have to compute the texture coordinates of `p` in the adjacent face
-                        let plane_b = self.papercraft.model().face_plane(face_b);
+                        let plane_b = papercraft.model().face_plane(face_b);
                         let vs_b = face_b.index_vertices().map(|v| {
-                            let v = &self.papercraft.model()[v];
+                            let v = &papercraft.model()[v];
                             let p = plane_b.project(&v.pos(), scale);
                             (v, p)
                         });
@@ -876,10 +1121,10 @@ impl PapercraftContext {
                         // mxx does both convertions at once, inverted
                         let mxx = (mx_b * Matrix3::from(mx_basis)).invert().unwrap();
 
-                        p.iter()
-                            .map(|px| {
+                        ps.iter()
+                            .map(|&px| {
                                 //vlocal is in edge-relative coordinates, that can be used to interpolate between UVs
-                                let vlocal = mxx.transform_point(Point2::from_vec(px.pos)).to_vec();
+                                let vlocal = mxx.transform_point(Point2::from_vec(px)).to_vec();
                                 let uv0 = vs_b[0].0.uv();
                                 let uv1 = vs_b[1].0.uv();
                                 let uv2 = vs_b[2].0.uv();
@@ -890,13 +1135,14 @@ impl PapercraftContext {
                 };
                 match maybe_i_face_b {
                     Some(i_face_b) => {
-                        let face_b = &self.papercraft.model()[i_face_b];
-                        let mx_b = m * self.papercraft.face_to_face_edge_matrix(edge, face, face_b);
+                        let face_b = &papercraft.model()[i_face_b];
+                        let mx_b = m * papercraft.face_to_face_edge_matrix(edge, face, face_b);
                         let mx_b_inv = mx_b.invert().unwrap();
                         // mx_b_inv converts from paper to local face_b coordinates
                         geom_b = Some((mx_b_inv, i_face_b));
                         mat = face_b.material();
-                        uvs = compute_uvs(face_b, &mx_b);
+                        uvs = compute_uvs(face_b, &mx_b, &outline);
+                        bleed_uvs = compute_uvs(face_b, &mx_b, &bleed_ps);
                     }
                     None => {
                         // There is no adjacent face to copy the texture from, so use the current
@@ -904,7 +1150,8 @@ impl PapercraftContext {
                         // N shadow flaps.
                         geom_b = None;
                         mat = face.material();
-                        uvs = compute_uvs(face, m);
+                        uvs = compute_uvs(face, m, &outline);
+                        bleed_uvs = compute_uvs(face, m, &bleed_ps);
                     }
                 }
                 let (root_alpha, tip_alpha) = match flap_style {
@@ -913,70 +1160,47 @@ impl PapercraftContext {
                     FlapStyle::White => (1.0, 1.0),
                     FlapStyle::None => (0.0, 0.0), //should not happen
                 };
-                let root_color = Rgba::new(1.0, 1.0, 1.0, root_alpha);
-                let tip_color = Rgba::new(1.0, 1.0, 1.0, tip_alpha);
-                if triangular {
-                    args.vertices_flap.push(MVertex2DColor {
-                        pos: p[0].pos,
-                        uv: uvs[0],
-                        mat,
-                        color: root_color,
-                    });
-                    args.vertices_flap.push(MVertex2DColor {
-                        pos: p[1].pos,
-                        uv: uvs[1],
-                        mat,
-                        color: tip_color,
-                    });
-                    args.vertices_flap.push(MVertex2DColor {
-                        pos: p[2].pos,
-                        uv: uvs[2],
-                        mat,
-                        color: root_color,
-                    });
-                } else {
-                    let pp = [
-                        MVertex2DColor {
-                            pos: p[0].pos,
-                            uv: uvs[0],
-                            mat,
-                            color: root_color,
-                        },
-                        MVertex2DColor {
-                            pos: p[1].pos,
-                            uv: uvs[1],
-                            mat,
-                            color: tip_color,
-                        },
-                        MVertex2DColor {
-                            pos: p[2].pos,
-                            uv: uvs[2],
-                            mat,
-                            color: tip_color,
-                        },
-                        MVertex2DColor {
-                            pos: p[3].pos,
-                            uv: uvs[3],
-                            mat,
-                            color: root_color,
-                        },
-                    ];
-                    args.vertices_flap
-                        .extend_from_slice(&[pp[0], pp[2], pp[1], pp[0], pp[3], pp[2]]);
-                }
-                if let (Some(flaps), Some((mx_b_inv, i_face_b))) = (&mut flap_cache, geom_b) {
-                    let mut flap_vs = if triangular {
-                        FlapVertices::Tri([p[0].pos, p[1].pos, p[2].pos])
+                // The alpha goes from the root to the tip of the flap, whatever its shape
+                let color = |pos: Vector2| {
+                    let h = if flap_geom.width > 0.0 {
+                        ((pos - pos0).dot(n) / n.magnitude2()).clamp(0.0, 1.0)
                     } else {
-                        FlapVertices::Quad([
-                            p[0].pos, p[2].pos, p[1].pos, p[0].pos, p[3].pos, p[2].pos,
-                        ])
+                        0.0
                     };
+                    Rgba::new(1.0, 1.0, 1.0, root_alpha + (tip_alpha - root_alpha) * h)
+                };
+                args.vertices_bleed
+                    .extend(
+                        bleed_ps
+                            .iter()
+                            .zip(&bleed_uvs)
+                            .map(|(&pos, &uv)| MVertex2DColor {
+                                pos,
+                                uv,
+                                mat,
+                                color: Rgba::new(1.0, 1.0, 1.0, 0.0),
+                            }),
+                    );
+                let tris = util_3d::tessellate_2d(&outline);
+                args.vertices_flap
+                    .extend(tris.iter().flatten().map(|&i| MVertex2DColor {
+                        pos: outline[i],
+                        uv: uvs[i],
+                        mat,
+                        color: color(outline[i]),
+                    }));
+                if let (Some(flaps), Some((mx_b_inv, i_face_b))) = (&mut flap_cache, geom_b) {
                     // Undo the mx_b transformation becase the shadow will be drawn over another
                     // face, the right matrix will be applied afterwards.
-                    for sp in flap_vs.iter_mut() {
-                        *sp = mx_b_inv.transform_point(Point2::from_vec(*sp)).to_vec();
-                    }
+                    let flap_vs = tris
+                        .iter()
+                        .flatten()
+                        .map(|&i| {
+                            mx_b_inv
+                                .transform_point(Point2::from_vec(outline[i]))
+                                .to_vec()
+                        })
+                        .collect();
                     flaps.push((i_face_b, flap_vs));
                 }
             }
@@ -984,17 +1208,49 @@ impl PapercraftContext {
     }
 
     fn paper_rebuild(&mut self, text_builder: &impl TextBuilder) {
-        let options = self.papercraft.options();
-        let mut args = PaperDrawFaceArgs::new(self.papercraft.model());
-        let mut edge_id_info =
-            if options.edge_id_position != EdgeIdPosition::None && self.ui.show_texts {
+        let args = Self::paper_build_args(&self.papercraft, Some(text_builder), self.ui.show_texts);
+
+        self.gl_objs.paper_vertices.set(args.vertices);
+        self.gl_objs
+            .paper_vertices_edge_cut
+            .set(args.vertices_edge_cut);
+        self.gl_objs
+            .paper_vertices_edge_crease
+            .set(args.vertices_edge_crease);
+        self.gl_objs.paper_vertices_flap.set(args.vertices_flap);
+        self.gl_objs
+            .paper_vertices_flap_edge
+            .set(args.vertices_flap_edge);
+        self.gl_objs.paper_face_index = args.face_index;
+        self.gl_objs
+            .paper_vertices_shadow_flap
+            .set(args.vertices_shadow_flap);
+        self.gl_objs.paper_vertices_bleed.set(args.vertices_bleed);
+        self.gl_objs.paper_text.set(args.vertices_text);
+    }
+
+    // Builds the vertices of the whole paper, it does not need a GL context.
+    // Without a `text_builder` no text is generated at all, the edge ids only if `show_texts`.
+    pub fn paper_build_args(
+        papercraft: &Papercraft,
+        text_builder: Option<&dyn TextBuilder>,
+        show_texts: bool,
+    ) -> PaperDrawFaceArgs {
+        let options = papercraft.options();
+        let mut args = PaperDrawFaceArgs::new(papercraft.model());
+        let mut edge_id_info = match text_builder {
+            Some(text_builder)
+                if (options.edge_id_position != EdgeIdPosition::None || options.thick_board)
+                    && show_texts =>
+            {
                 Some((
+                    text_builder,
                     PaperDrawFaceArgsExtra::default(),
                     slotmap::SecondaryMap::new(),
                 ))
-            } else {
-                None
-            };
+            }
+            _ => None,
+        };
 
         // Shadow flaps have to be drawn the the face adjacent to the one being drawn, but we do not
         // now its coordinates yet.
@@ -1006,23 +1262,24 @@ impl PapercraftContext {
         } else {
             None
         };
-        for (i_island, island) in self.papercraft.islands() {
+        for (i_island, island) in papercraft.islands() {
             let cut_before = args.cut_last_index();
-            self.papercraft.traverse_faces(island, |i_face, face, mx| {
+            papercraft.traverse_faces(island, |i_face, face, mx| {
                 if let Some((mx_face, _)) = &mut shadow_cache {
                     mx_face.insert(i_face, *mx);
                 }
-                self.paper_draw_face(
+                Self::paper_draw_face(
+                    papercraft,
                     face,
                     i_face,
                     mx,
                     &mut args,
                     shadow_cache.as_mut().map(|(_, t)| t),
-                    edge_id_info.as_mut().map(|(extra, _)| extra),
+                    edge_id_info.as_mut().map(|(_, extra, _)| extra),
                 );
                 ControlFlow::Continue(())
             });
-            if let Some((_, cuts)) = &mut edge_id_info {
+            if let Some((_, _, cuts)) = &mut edge_id_info {
                 let cut_next = args.cut_last_index();
                 cuts.insert(i_island, cut_before..cut_next);
             }
@@ -1049,18 +1306,12 @@ impl PapercraftContext {
         }
 
         // Draw the EdgeId?
-        if let Some((extra, cut_by_island)) = edge_id_info {
+        if let Some((text_builder, extra, cut_by_island)) = edge_id_info {
             let edge_id_font_size = options.edge_id_font_size * 25.4 / 72.0; // pt to mm
 
             // Edge ids
             for cut_idx in extra.cut_indices() {
-                let i_island_b = self.papercraft().island_by_face(cut_idx.i_face_b);
-                let ii = self
-                    .papercraft()
-                    .island_by_key(i_island_b)
-                    .map(|island_b| island_b.name())
-                    .unwrap_or("?");
-                let text = format!("{}:{}", ii, cut_idx.id);
+                let text = cut_idx.text(papercraft);
                 let pos = cut_idx.pos(text_builder.font_text_line_scale() * edge_id_font_size);
                 let t = PrintableText {
                     size: edge_id_font_size,
@@ -1072,8 +1323,11 @@ impl PapercraftContext {
                 text_builder.make_text(&t, &mut args.vertices_text);
             }
 
-            for (i_island, island) in self.papercraft().islands() {
-                // Island ids
+            // Island ids
+            let islands = papercraft
+                .islands()
+                .filter(|_| options.edge_id_position != EdgeIdPosition::None);
+            for (i_island, island) in islands {
                 let pos = match options.edge_id_position {
                     // On top (None should not happen)
                     EdgeIdPosition::None | EdgeIdPosition::Outside => {
@@ -1088,8 +1342,7 @@ impl PapercraftContext {
                     }
                     // In the middle
                     EdgeIdPosition::Inside => {
-                        let (flat_face, total_area) =
-                            self.papercraft().get_biggest_flat_face(island);
+                        let (flat_face, total_area) = papercraft.get_biggest_flat_face(island);
                         // Compute the center of mass of the flat-face, that will be the
                         // weighted mean of the centers of masses of each single face.
                         let center: Vector2 = flat_face
@@ -1104,7 +1357,7 @@ impl PapercraftContext {
                         center + Vector2::new(0.0, edge_id_font_size)
                     }
                 };
-                if let Some(island) = self.papercraft().island_by_key(i_island) {
+                if let Some(island) = papercraft.island_by_key(i_island) {
                     let t = PrintableText {
                         size: 2.0 * edge_id_font_size,
                         pos,
@@ -1118,7 +1371,9 @@ impl PapercraftContext {
         }
 
         //TODO PrintableTexts duplicated here and in generate_pages???
-        if options.show_self_promotion || options.show_page_number {
+        let page_texts =
+            text_builder.filter(|_| options.show_self_promotion || options.show_page_number);
+        if let Some(text_builder) = page_texts {
             let (_margin_top, margin_left, margin_right, margin_bottom) = options.margin;
             let page_size_mm = Vector2::from(options.page_size);
             let page_count = options.pages;
@@ -1155,22 +1410,7 @@ impl PapercraftContext {
             }
         }
 
-        self.gl_objs.paper_vertices.set(args.vertices);
-        self.gl_objs
-            .paper_vertices_edge_cut
-            .set(args.vertices_edge_cut);
-        self.gl_objs
-            .paper_vertices_edge_crease
-            .set(args.vertices_edge_crease);
-        self.gl_objs.paper_vertices_flap.set(args.vertices_flap);
-        self.gl_objs
-            .paper_vertices_flap_edge
-            .set(args.vertices_flap_edge);
-        self.gl_objs.paper_face_index = args.face_index;
-        self.gl_objs
-            .paper_vertices_shadow_flap
-            .set(args.vertices_shadow_flap);
-        self.gl_objs.paper_text.set(args.vertices_text);
+        args
     }
 
     fn pages_rebuild(&mut self) {
@@ -1254,10 +1494,59 @@ impl PapercraftContext {
         self.gl_objs.paper_vertices_margin.set(margin_vertices);
     }
 
+    pub fn is_part_visible(&self, i_part: PartIndex) -> bool {
+        !self.hidden_parts.contains(&i_part)
+    }
+    pub fn set_part_visible(&mut self, i_part: PartIndex, visible: bool) -> RebuildFlags {
+        if visible {
+            self.hidden_parts.remove(&i_part);
+        } else {
+            self.hidden_parts.insert(i_part);
+        }
+        self.scene_faces_rebuild();
+        RebuildFlags::SCENE_EDGE | RebuildFlags::SCENE_REDRAW
+    }
+    fn is_face_visible(&self, face: &Face) -> bool {
+        self.hidden_parts.is_empty() || self.is_part_visible(face.part())
+    }
+    // An edge is visible if any of its faces is
+    fn is_edge_visible(&self, edge: &Edge) -> bool {
+        let model = self.papercraft.model();
+        let (fa, fb) = edge.faces();
+        self.is_face_visible(&model[fa]) || fb.is_some_and(|fb| self.is_face_visible(&model[fb]))
+    }
+    // Hidden faces are collapsed into a point, so that the vertex buffer keeps its layout
+    fn scene_faces_rebuild(&mut self) {
+        let model = self.papercraft.model();
+        for (i_face, face) in model.faces() {
+            let visible = self.is_face_visible(face);
+            let vs = face.index_vertices();
+            for (i, i_v) in vs.into_iter().enumerate() {
+                let v = &model[i_v];
+                let gl_v = &mut self.gl_objs.vertices[3 * usize::from(i_face) + i];
+                gl_v.pos = model[if visible { i_v } else { vs[0] }].pos();
+                // The order of the vertices changes if the model is mirrored
+                gl_v.normal = v.normal();
+                gl_v.uv = v.uv();
+            }
+        }
+    }
+    // Returns the undo action, that is the inverse transformation
+    pub fn transform_model(&mut self, mx: &Matrix3) -> Vec<UndoAction> {
+        let Some(inv) = mx.invert() else {
+            return Vec::new();
+        };
+        self.papercraft.transform_model(mx);
+        self.scene_faces_rebuild();
+        vec![UndoAction::ModelTransform { mx: inv.into() }]
+    }
     fn scene_edge_rebuild(&mut self) {
         let mut edges_joint = Vec::new();
         let mut edges_cut = Vec::new();
         for (i_edge, edge) in self.papercraft.model().edges() {
+            if !self.is_edge_visible(edge) {
+                continue;
+            }
             let status = self.papercraft.edge_status(i_edge);
 
             let (edges, color);
@@ -1333,11 +1622,18 @@ impl PapercraftContext {
                 }
             }
         }
-        if let Some(i_sel_edges) = self.selected_edges.as_ref() {
+        if self.selected_edges.is_some() || !self.selected_flaps.is_empty() {
+            let i_sel_edges: FxHashSet<EdgeIndex> = self
+                .selected_edges
+                .iter()
+                .flatten()
+                .chain(&self.selected_flaps)
+                .copied()
+                .collect();
             let mut edges_sel_3d = Vec::with_capacity(2 * i_sel_edges.len());
             let mut edge_sel_2d = Vec::with_capacity(6 * i_sel_edges.len());
             let color = color_edge(self.ui.mode);
-            for &i_sel_edge in i_sel_edges {
+            for &i_sel_edge in &i_sel_edges {
                 let edge = &self.papercraft.model()[i_sel_edge];
                 let (p0, p1) = self.papercraft.model().edge_pos(edge);
                 edges_sel_3d.push(MVertex3DLine { pos: p0, color });
@@ -1418,6 +1714,28 @@ impl PapercraftContext {
             self.gl_objs.vertices_edge_sel.set(edges_sel_3d);
             self.gl_objs.paper_vertices_edge_sel.set(edge_sel_2d);
         }
+
+        let mut rubber_band = Vec::new();
+        if let Some((a, b)) = self.rubber_band {
+            // A thin dashed line, the same size at any zoom
+            let line_width = 0.5 / self.ui.trans_paper.mx[0][0];
+            let corners = [a, Vector2::new(b.x, a.y), b, Vector2::new(a.x, b.y)];
+            for (i, &c) in corners.iter().enumerate() {
+                let p0 = MVertex2DLine {
+                    pos: c,
+                    line_dash: 0.0,
+                    width_left: line_width,
+                    width_right: line_width,
+                };
+                let mut p1 = MVertex2DLine {
+                    pos: corners[(i + 1) % 4],
+                    ..p0
+                };
+                Self::make_dash_line(10.0 * line_width, p0, &mut p1);
+                rubber_band.extend_from_slice(&[p0, p1]);
+            }
+        }
+        self.gl_objs.paper_vertices_rubber_band.set(rubber_band);
     }
 
     #[must_use]
@@ -1435,6 +1753,10 @@ impl PapercraftContext {
                     self.selected_islands.clear();
                     island_changed = true;
                 }
+                if clicked && !add_to_sel && !self.selected_flaps.is_empty() {
+                    self.selected_flaps.clear();
+                    island_changed = true;
+                }
                 (None, None)
             }
             ClickResult::Face(i_face) => {
@@ -1536,6 +1858,37 @@ impl PapercraftContext {
         Some(undo_actions)
     }
 
+    #[must_use]
+    pub fn auto_unfold(&mut self) -> Vec<UndoAction> {
+        // Rejected joins are undone with a cut, that creates new island keys, so the selection is
+        // tracked by face instead
+        let selected_faces: Vec<_> = self
+            .selected_islands
+            .iter()
+            .filter_map(|&i_island| self.papercraft.island_by_key(i_island))
+            .map(|island| island.root_face())
+            .collect();
+        let joins = self.papercraft.auto_unfold();
+        self.selected_islands.clear();
+        for i_face in selected_faces {
+            let i_island = self.papercraft.island_by_face(i_face);
+            if !self.selected_islands.contains(&i_island) {
+                self.selected_islands.push(i_island);
+            }
+        }
+
+        // The undo actions are in order, so that they are undone in reverse
+        let mut undo_actions: Vec<_> = joins
+            .into_iter()
+            .map(|(_, join_result)| UndoAction::EdgeJoin { join_result })
+            .collect();
+        // The new islands will surely overlap, so repack everything
+        if !undo_actions.is_empty() {
+            undo_actions.extend(self.pack_islands(false));
+        }
+        undo_actions
+    }
+
     fn islands_renamed(&mut self, renames: &FxHashMap<IslandKey, JoinResult>) {
         for x in &mut self.selected_islands {
             while let Some(jr) = renames.get(x) {
@@ -1563,6 +1916,9 @@ impl PapercraftContext {
         //Faces has to be checked both in Edge and Face mode, because Edges can be hidden by a face.
         let mut hit_face = None;
         for (iface, face) in self.papercraft.model().faces() {
+            if !self.is_face_visible(face) {
+                continue;
+            }
             let tri = face
                 .index_vertices()
                 .map(|v| self.papercraft.model()[v].pos());
@@ -1585,6 +1941,9 @@ impl PapercraftContext {
 
         let mut hit_edge = None;
         for (i_edge, edge) in self.papercraft.model().edges() {
+            if !self.is_edge_visible(edge) {
+                continue;
+            }
             match (self.papercraft.edge_status(i_edge), mode) {
                 (EdgeStatus::Hidden, _) => continue,
                 (EdgeStatus::Joined, MouseMode::Flap) => continue,
@@ -1800,7 +2159,21 @@ impl PapercraftContext {
             | RebuildFlags::ISLANDS
     }
     #[must_use]
-    fn do_flap_action(&mut self, i_edge: EdgeIndex, shift_action: bool) -> RebuildFlags {
+    fn do_flap_action(
+        &mut self,
+        i_edge: EdgeIndex,
+        shift_action: bool,
+        add_to_sel: bool,
+    ) -> RebuildFlags {
+        if add_to_sel {
+            if !matches!(self.papercraft.edge_status(i_edge), EdgeStatus::Cut(_)) {
+                return RebuildFlags::empty();
+            }
+            if !self.selected_flaps.remove(&i_edge) {
+                self.selected_flaps.insert(i_edge);
+            }
+            return RebuildFlags::SELECTION;
+        }
         let action = if shift_action {
             EdgeToggleFlapAction::Hide
         } else {
@@ -1828,7 +2201,7 @@ impl PapercraftContext {
                 self.do_edge_action(i_edge, i_face, shift_action)
             }
             (MouseMode::Flap, ClickResult::Edge(i_edge, _)) => {
-                self.do_flap_action(i_edge, shift_action)
+                self.do_flap_action(i_edge, shift_action, add_to_sel)
             }
             (_, ClickResult::Face(f)) | (MouseMode::ReadOnly, ClickResult::Edge(_, Some(f))) => {
                 self.set_selection(ClickResult::Face(f), true, add_to_sel, false)
@@ -1857,6 +2230,11 @@ impl PapercraftContext {
         let delta = pos - self.last_cursor_pos;
         self.last_cursor_pos = pos;
 
+        if let Some((_, corner)) = &mut self.rubber_band {
+            *corner = self.ui.trans_paper.paper_click(size, pos);
+            return RebuildFlags::SELECTION;
+        }
+
         // Check if any island is to be moved
         match (
             self.selected_islands.is_empty(),
@@ -1950,7 +2328,7 @@ impl PapercraftContext {
                 self.do_edge_action(i_edge, i_face, shift_action)
             }
             (MouseMode::Flap, ClickResult::Edge(i_edge, _)) => {
-                self.do_flap_action(i_edge, shift_action)
+                self.do_flap_action(i_edge, shift_action, add_to_sel)
             }
             (_, ClickResult::Face(f)) | (MouseMode::ReadOnly, ClickResult::Edge(_, Some(f))) => {
                 let flags = self.set_selection(ClickResult::Face(f), true, add_to_sel, false);
@@ -1975,6 +2353,9 @@ impl PapercraftContext {
             }
             (_, ClickResult::None) => {
                 self.grabbed_island = None;
+                // Start a rubber band, the islands are selected when the button is released
+                let click = self.ui.trans_paper.paper_click(size, pos);
+                self.rubber_band = Some((click, click));
                 self.set_selection(ClickResult::None, true, add_to_sel, false)
             }
             _ => RebuildFlags::empty(),
@@ -2001,14 +2382,128 @@ impl PapercraftContext {
         alt_pressed: bool,
     ) -> RebuildFlags {
         self.last_cursor_pos = pos;
+        let mut flags = RebuildFlags::empty();
+        if let Some((a, b)) = self.rubber_band.take() {
+            flags |= self.rubber_band_select(a, b);
+        }
         let selection = self.paper_analyze_click(self.ui.mode, size, pos);
         self.rotation_center = None;
         self.grabbed_island = None;
-        self.set_selection(selection, false, false, alt_pressed)
+        flags | self.set_selection(selection, false, false, alt_pressed)
+    }
+    // Adds to the selection the islands fully inside the rectangle
+    fn rubber_band_select(&mut self, a: Vector2, b: Vector2) -> RebuildFlags {
+        let (min, max) = util_3d::bounding_box_2d([a, b]);
+        for (i_island, _) in self.papercraft.islands() {
+            if self.selected_islands.contains(&i_island) {
+                continue;
+            }
+            let (ia, ib) = self.papercraft.island_bounding_box(i_island);
+            if min.x <= ia.x && min.y <= ia.y && ib.x <= max.x && ib.y <= max.y {
+                self.selected_islands.push(i_island);
+            }
+        }
+        // Always rebuild, to remove the rectangle
+        RebuildFlags::SELECTION
+    }
+    pub fn selected_islands(&self) -> &[IslandKey] {
+        &self.selected_islands
+    }
+    #[must_use]
+    pub fn select_page_islands(&mut self, size: Vector2) -> RebuildFlags {
+        let options = self.papercraft.options();
+        // "This page" is the one of the selected islands, or else the one in the middle of the view
+        let pos = match self.selected_islands.first() {
+            Some(&i_island) => {
+                let (a, b) = self.papercraft.island_bounding_box(i_island);
+                (a + b) / 2.0
+            }
+            None => self.ui.trans_paper.paper_click(size, size / 2.0),
+        };
+        let Some(page) = options.page_of(pos) else {
+            return RebuildFlags::empty();
+        };
+        self.selected_islands = self
+            .papercraft
+            .islands()
+            .map(|(i_island, _)| i_island)
+            .filter(|&i_island| {
+                let (a, b) = self.papercraft.island_bounding_box(i_island);
+                options.page_of((a + b) / 2.0) == Some(page)
+            })
+            .collect();
+        RebuildFlags::SELECTION
+    }
+    #[must_use]
+    pub fn align_islands(&mut self, align: IslandAlign) -> Vec<UndoAction> {
+        let boxes: Vec<_> = self
+            .selected_islands
+            .iter()
+            .map(|&i_island| (i_island, self.papercraft.island_bounding_box(i_island)))
+            .collect();
+        if boxes.len() < 2 {
+            return Vec::new();
+        }
+        let (sel_a, sel_b) = util_3d::bounding_box_2d(boxes.iter().flat_map(|&(_, (a, b))| [a, b]));
+        let sel_center = (sel_a + sel_b) / 2.0;
+        let horz = |dx: f32| Vector2::new(dx, 0.0);
+        let vert = |dy: f32| Vector2::new(0.0, dy);
+        let mut moves: Vec<(IslandKey, Vector2)> = match align {
+            IslandAlign::Left => boxes
+                .iter()
+                .map(|&(i, (a, _))| (i, horz(sel_a.x - a.x)))
+                .collect(),
+            IslandAlign::Right => boxes
+                .iter()
+                .map(|&(i, (_, b))| (i, horz(sel_b.x - b.x)))
+                .collect(),
+            IslandAlign::Top => boxes
+                .iter()
+                .map(|&(i, (a, _))| (i, vert(sel_a.y - a.y)))
+                .collect(),
+            IslandAlign::Bottom => boxes
+                .iter()
+                .map(|&(i, (_, b))| (i, vert(sel_b.y - b.y)))
+                .collect(),
+            IslandAlign::CenterH => boxes
+                .iter()
+                .map(|&(i, (a, b))| (i, horz(sel_center.x - (a.x + b.x) / 2.0)))
+                .collect(),
+            IslandAlign::CenterV => boxes
+                .iter()
+                .map(|&(i, (a, b))| (i, vert(sel_center.y - (a.y + b.y) / 2.0)))
+                .collect(),
+            IslandAlign::DistributeH => distribute_gaps(&boxes, |v| v.x)
+                .into_iter()
+                .map(|(i, d)| (i, horz(d)))
+                .collect(),
+            IslandAlign::DistributeV => distribute_gaps(&boxes, |v| v.y)
+                .into_iter()
+                .map(|(i, d)| (i, vert(d)))
+                .collect(),
+        };
+        moves.retain(|(_, d)| d.magnitude2() > 0.0);
+
+        let undo_actions = moves
+            .iter()
+            .map(|&(i_island, _)| {
+                let island = self.papercraft.island_by_key(i_island).unwrap();
+                UndoAction::IslandMove {
+                    i_root: island.root_face(),
+                    prev_rot: island.rotation(),
+                    prev_loc: island.location(),
+                }
+            })
+            .collect();
+        for (i_island, delta) in moves {
+            let island = self.papercraft.island_by_key_mut(i_island).unwrap();
+            island.translate(delta);
+        }
+        undo_actions
     }
 
     #[must_use]
-    pub fn pack_islands(&mut self) -> Vec<UndoAction> {
+    pub fn pack_islands(&mut self, keep_pages: bool) -> Vec<UndoAction> {
         let undo_actions = self
             .papercraft
             .islands()
@@ -2018,13 +2513,56 @@ impl PapercraftContext {
                 prev_loc: island.location(),
             })
             .collect();
-        self.papercraft.pack_islands();
+        self.papercraft.pack_islands(keep_pages);
         undo_actions
     }
 
+    pub fn find_conflicts(&self) -> Vec<Conflict> {
+        self.papercraft.find_conflicts()
+    }
+    #[must_use]
+    pub fn show_conflict(&mut self, size: Vector2, conflict: &Conflict) -> RebuildFlags {
+        self.selected_face = None;
+        self.selected_edges = None;
+        self.selected_islands = vec![conflict.i_island];
+        if let Some((i_other, _)) = conflict.other {
+            if i_other != conflict.i_island {
+                self.selected_islands.push(i_other);
+            }
+        }
+
+        // Leave some room around the conflict, but not too much zoom for the tiny ones
+        let (a, b) = conflict.bbox;
+        let sz = (b - a) * 3.0;
+        let zoom = (size.x / sz.x).min(size.y / sz.y).clamp(0.5, 20.0);
+        let center = (a + b) / 2.0;
+        self.ui.trans_paper.mx = Matrix3::from_scale(zoom) * Matrix3::from_translation(-center);
+        self.rotation_center = None;
+        RebuildFlags::SELECTION | RebuildFlags::PAPER_REDRAW
+    }
+    // Selects a face or an edge of the model, such as those in the import report
+    #[must_use]
+    pub fn show_face(&mut self, i_face: FaceIndex) -> RebuildFlags {
+        self.selected_islands = vec![self.papercraft.island_by_face(i_face)];
+        self.selected_face = Some(i_face);
+        self.selected_edges = None;
+        RebuildFlags::SELECTION
+    }
+    #[must_use]
+    pub fn show_edge(&mut self, i_edge: EdgeIndex) -> RebuildFlags {
+        let (i_face, _) = self.papercraft.model()[i_edge].faces();
+        self.selected_islands = vec![self.papercraft.island_by_face(i_face)];
+        self.selected_face = None;
+        self.selected_edges = Some([i_edge].into_iter().collect());
+        RebuildFlags::SELECTION
+    }
+
     pub fn can_undo(&self) -> bool {
         !self.undo_stack.is_empty()
     }
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
     pub fn undo_action(&mut self) -> UndoResult {
         //Do not undo while grabbing or the stack will be messed up
         if self.grabbed_island.is_some() {
@@ -2035,8 +2573,36 @@ impl PapercraftContext {
             None => return UndoResult::False,
             Some(a) => a,
         };
+        let (res, redo_pack) = self.revert_action_pack(action_pack);
+        self.redo_stack.push(redo_pack);
+        res
+    }
+    pub fn redo_action(&mut self) -> UndoResult {
+        if self.grabbed_island.is_some() {
+            return UndoResult::False;
+        }
 
+        let action_pack = match self.redo_stack.pop() {
+            None => return UndoResult::False,
+            Some(a) => a,
+        };
+        let (res, undo_pack) = self.revert_action_pack(action_pack);
+        self.undo_stack.push(undo_pack);
+        res
+    }
+    // Applies the actions backwards, and returns the actions that will revert that.
+    // Undo and redo are the same thing, just with different stacks.
+    fn revert_action_pack(
+        &mut self,
+        action_pack: Vec<UndoAction>,
+    ) -> (UndoResult, Vec<UndoAction>) {
+        let was_modified = self.modified;
         let mut res = UndoResult::Model;
+        // The reverse actions are stored in the order they are done, so that when reverted
+        // backwards they are redone in the original order
+        let mut reverse = Vec::new();
+        // If the pack does not restore the unmodified state, then the model is now modified
+        self.modified = true;
 
         for action in action_pack.into_iter().rev() {
             match action {
@@ -2047,17 +2613,58 @@ impl PapercraftContext {
                 } => {
                     let i_island = self.papercraft.island_by_face(i_root);
                     let island = self.papercraft.island_by_key_mut(i_island).unwrap();
+                    reverse.push(UndoAction::IslandMove {
+                        i_root: island.root_face(),
+                        prev_rot: island.rotation(),
+                        prev_loc: island.location(),
+                    });
                     island.reset_transformation(i_root, prev_rot, prev_loc);
                 }
                 UndoAction::FlapToggle { i_edge, flap_side } => {
+                    if let EdgeStatus::Cut(prev_side) = self.papercraft.edge_status(i_edge) {
+                        reverse.push(UndoAction::FlapToggle {
+                            i_edge,
+                            flap_side: prev_side,
+                        });
+                    }
                     self.papercraft
                         .edge_toggle_flap(i_edge, EdgeToggleFlapAction::Set(flap_side));
                 }
+                UndoAction::FlapOverride {
+                    i_edge,
+                    flap_override,
+                } => {
+                    let prev = self.papercraft.set_flap_override(i_edge, flap_override);
+                    reverse.push(UndoAction::FlapOverride {
+                        i_edge,
+                        flap_override: prev,
+                    });
+                }
                 UndoAction::EdgeCut { i_edge } => {
-                    self.papercraft.edge_join(i_edge, None);
+                    let renames = self.papercraft.edge_join(i_edge, None);
+                    reverse.extend(
+                        renames
+                            .into_values()
+                            .map(|join_result| UndoAction::EdgeJoin { join_result }),
+                    );
                 }
                 UndoAction::EdgeJoin { join_result } => {
+                    // Joining again may not keep the same island in place, so move it back
+                    // after the join
+                    let i_island = self.papercraft.island_by_face(join_result.prev_root);
+                    let island = self.papercraft.island_by_key(i_island).unwrap();
+                    reverse.push(UndoAction::IslandMove {
+                        i_root: island.root_face(),
+                        prev_rot: island.rotation(),
+                        prev_loc: island.location(),
+                    });
+                    reverse.push(UndoAction::EdgeCut {
+                        i_edge: join_result.i_edge,
+                    });
+
                     self.papercraft.edge_cut(join_result.i_edge, None);
+                    self.papercraft
+                        .set_flap_override(join_result.i_edge, join_result.prev_flap_override);
                     let i_prev_island = self.papercraft.island_by_face(join_result.prev_root);
                     let island = self.papercraft.island_by_key_mut(i_prev_island).unwrap();
 
@@ -2071,6 +2678,10 @@ impl PapercraftContext {
                     options,
                     island_pos,
                 } => {
+                    reverse.push(UndoAction::DocConfig {
+                        options: self.papercraft.options().clone(),
+                        island_pos: self.island_positions(),
+                    });
                     for (i_root_face, (rot, loc)) in island_pos {
                         let i_island = self.papercraft.island_by_face(i_root_face);
                         let island = self.papercraft.island_by_key_mut(i_island).unwrap();
@@ -2078,12 +2689,19 @@ impl PapercraftContext {
                     }
                     res = UndoResult::ModelAndOptions(options);
                 }
+                UndoAction::ModelTransform { mx } => {
+                    let mx = Matrix3::from(mx);
+                    reverse.extend(self.transform_model(&mx));
+                }
                 UndoAction::Modified => {
                     self.modified = false;
                 }
             }
         }
-        res
+        if !was_modified {
+            reverse.push(UndoAction::Modified);
+        }
+        (res, reverse)
     }
     pub fn push_undo_action(&mut self, mut action: Vec<UndoAction>) {
         if action.is_empty() {
@@ -2094,19 +2712,69 @@ impl PapercraftContext {
             self.modified = true;
         }
         self.undo_stack.push(action);
+        // A new action invalidates the redo history
+        self.redo_stack.clear();
     }
     pub fn has_selected_edge(&self) -> bool {
-        self.selected_edges.is_some()
+        self.selected_edges.is_some() || !self.selected_flaps.is_empty()
+    }
+    // The selected edges that still have a flap to edit, sorted
+    pub fn selected_flaps(&self) -> Vec<EdgeIndex> {
+        let mut edges: Vec<_> = self
+            .selected_flaps
+            .iter()
+            .copied()
+            .filter(|&i_edge| matches!(self.papercraft.edge_status(i_edge), EdgeStatus::Cut(_)))
+            .collect();
+        edges.sort_by_key(|&i_edge| usize::from(i_edge));
+        edges
+    }
+    #[must_use]
+    pub fn clear_selected_flaps(&mut self) -> RebuildFlags {
+        self.selected_flaps.clear();
+        RebuildFlags::SELECTION
+    }
+    #[must_use]
+    pub fn set_flap_overrides(
+        &mut self,
+        edges: &[EdgeIndex],
+        flap_override: FlapOverride,
+    ) -> RebuildFlags {
+        let undo: Vec<_> = edges
+            .iter()
+            .filter_map(|&i_edge| {
+                let prev = self.papercraft.set_flap_override(i_edge, flap_override);
+                (prev != flap_override).then_some(UndoAction::FlapOverride {
+                    i_edge,
+                    flap_override: prev,
+                })
+            })
+            .collect();
+        if undo.is_empty() {
+            return RebuildFlags::empty();
+        }
+        self.push_undo_action(undo);
+        RebuildFlags::PAPER | RebuildFlags::SELECTION
     }
 
-    pub fn lines_by_island(&self) -> Vec<(IslandKey, (PaperDrawFaceArgs, PaperDrawFaceArgsExtra))> {
-        self.papercraft
+    pub fn lines_by_island(
+        papercraft: &Papercraft,
+    ) -> Vec<(IslandKey, (PaperDrawFaceArgs, PaperDrawFaceArgsExtra))> {
+        papercraft
             .islands()
             .map(|(id, island)| {
-                let mut args = PaperDrawFaceArgs::new(self.papercraft.model());
+                let mut args = PaperDrawFaceArgs::new(papercraft.model());
                 let mut extra = PaperDrawFaceArgsExtra::default();
-                self.papercraft.traverse_faces(island, |i_face, face, mx| {
-                    self.paper_draw_face(face, i_face, mx, &mut args, None, Some(&mut extra));
+                papercraft.traverse_faces(island, |i_face, face, mx| {
+                    Self::paper_draw_face(
+                        papercraft,
+                        face,
+                        i_face,
+                        mx,
+                        &mut args,
+                        None,
+                        Some(&mut extra),
+                    );
                     ControlFlow::Continue(())
                 });
                 (id, (args, extra))
@@ -2271,9 +2939,11 @@ impl GLObjects {
         let paper_vertices_flap_edge = glr::DynamicVertexArray::new(gl)?;
         let paper_vertices_edge_sel = glr::DynamicVertexArray::new(gl)?;
         let paper_vertices_shadow_flap = glr::DynamicVertexArray::new(gl)?;
+        let paper_vertices_bleed = glr::DynamicVertexArray::new(gl)?;
 
         let paper_vertices_page = glr::DynamicVertexArray::new(gl)?;
         let paper_vertices_margin = glr::DynamicVertexArray::new(gl)?;
+        let paper_vertices_rubber_band = glr::DynamicVertexArray::new(gl)?;
 
         let paper_text = glr::DynamicVertexArray::new(gl)?;
 
@@ -2293,40 +2963,45 @@ impl GLObjects {
             paper_vertices_flap_edge,
             paper_vertices_edge_sel,
             paper_vertices_shadow_flap,
+            paper_vertices_bleed,
 
             paper_face_index: Vec::new(),
 
             paper_vertices_page,
             paper_vertices_margin,
+            paper_vertices_rubber_band,
             paper_text,
         })
     }
 }
 
-pub fn signature() -> &'static str {
-    "Created with Papercraft. https://github.com/rodrigorc/papercraft"
-}
-
-enum FlapVertices {
-    Tri([Vector2; 3]),
-    Quad([Vector2; 6]),
+// Moves the boxes along one axis so that the gaps between them are all equal, keeping the
+// extent of the whole set. Returns how much each one is moved.
+fn distribute_gaps(
+    boxes: &[(IslandKey, (Vector2, Vector2))],
+    axis: impl Fn(Vector2) -> f32,
+) -> Vec<(IslandKey, f32)> {
+    let mut spans: Vec<_> = boxes
+        .iter()
+        .map(|&(i_island, (a, b))| (i_island, axis(a), axis(b)))
+        .collect();
+    spans.sort_by(|x, y| x.1.total_cmp(&y.1));
+    let start = spans.iter().map(|s| s.1).fold(f32::MAX, f32::min);
+    let end = spans.iter().map(|s| s.2).fold(f32::MIN, f32::max);
+    let total: f32 = spans.iter().map(|s| s.2 - s.1).sum();
+    let gap = (end - start - total) / (spans.len() - 1) as f32;
+
+    let mut pos = start;
+    spans
+        .into_iter()
+        .map(|(i_island, a, b)| {
+            let delta = pos - a;
+            pos += b - a + gap;
+            (i_island, delta)
+        })
+        .collect()
 }
 
-impl std::ops::Deref for FlapVertices {
-    type Target = [Vector2];
-
-    fn deref(&self) -> &Self::Target {
-        match self {
-            FlapVertices::Tri(s) => s,
-            FlapVertices::Quad(s) => s,
-        }
-    }
-}
-impl std::ops::DerefMut for FlapVertices {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        match self {
-            FlapVertices::Tri(s) => s,
-            FlapVertices::Quad(s) => s,
-        }
-    }
+pub fn signature() -> &'static str {
+    "Created with Papercraft. https://github.com/rodrigorc/papercraft"
 }
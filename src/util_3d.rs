@@ -78,17 +78,19 @@ pub fn tessellate(ps: &[Vector3]) -> (Vec<[usize; 3]>, Plane) {
         return (vec![[0, 1, 2]], plane);
     }
 
-    let mut res = Vec::with_capacity(ps.len() - 2);
-
     // Project every vertex into this plane
-    let mut ps = ps
-        .iter()
-        .enumerate()
-        .map(|(idx, p)| {
-            let p2 = plane.project(p, 1.0);
-            (idx, p2)
-        })
-        .collect::<Vec<_>>();
+    let ps = ps.iter().map(|p| plane.project(p, 1.0)).collect::<Vec<_>>();
+    (tessellate_2d(&ps), plane)
+}
+
+// Same as `tessellate` but for a polygon that is already in 2D, oriented as the projected ones:
+// the outside is to the left of each side.
+pub fn tessellate_2d(ps: &[Vector2]) -> Vec<[usize; 3]> {
+    if ps.len() < 3 {
+        return Vec::new();
+    }
+    let mut res = Vec::with_capacity(ps.len() - 2);
+    let mut ps = ps.iter().copied().enumerate().collect::<Vec<_>>();
 
     // Tessellate the 2D polygon using the "ear" method
     while ps.len() >= 3 {
@@ -123,7 +125,7 @@ pub fn tessellate(ps: &[Vector3]) -> (Vec<[usize; 3]>, Plane) {
         ps.remove(tri.1);
     }
 
-    (res, plane)
+    res
 }
 
 pub fn point_in_triangle(p: Vector2, tri: [Vector2; 3]) -> bool {
@@ -171,6 +173,36 @@ pub fn bounding_box_2d(vs: impl IntoIterator<Item = Vector2>) -> (Vector2, Vecto
     (a, b)
 }
 
+pub fn bounding_boxes_overlap(a: (Vector2, Vector2), b: (Vector2, Vector2)) -> bool {
+    a.0.x <= b.1.x && b.0.x <= a.1.x && a.0.y <= b.1.y && b.0.y <= a.1.y
+}
+
+// Separating axis theorem: two convex polygons do not overlap if there is a line between them.
+// Polygons that go into each other less than `epsilon` are just touching.
+pub fn convex_polygons_overlap(a: &[Vector2], b: &[Vector2], epsilon: f32) -> bool {
+    let project = |ps: &[Vector2], n: Vector2| {
+        ps.iter().fold((f32::MAX, f32::MIN), |(min, max), p| {
+            let d = p.dot(n);
+            (min.min(d), max.max(d))
+        })
+    };
+    for ps in [a, b] {
+        for (i, &p) in ps.iter().enumerate() {
+            let e = ps[(i + 1) % ps.len()] - p;
+            if e.magnitude2() == 0.0 {
+                continue;
+            }
+            let n = Vector2::new(-e.y, e.x).normalize();
+            let (min_a, max_a) = project(a, n);
+            let (min_b, max_b) = project(b, n);
+            if max_a <= min_b + epsilon || max_b <= min_a + epsilon {
+                return false;
+            }
+        }
+    }
+    true
+}
+
 pub fn ray_crosses_face(ray: (Vector3, Vector3), vs: &[Vector3; 3]) -> Option<f32> {
     // Möller-Trumbore algorithm
 